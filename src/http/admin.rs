@@ -0,0 +1,330 @@
+use std::{fmt::Write, time::Duration};
+
+use crate::http::{
+    errors::HttpError,
+    logging::{self, server_log, Level, LogFilter},
+    request::{id::RequestRef, HttpRequest, RequestId},
+    response::{HttpResponse, HttpStatusCode},
+    routes::{stats::StatsSnapshot, Params},
+    server,
+    writer::WrittenResponse,
+};
+
+/// Largest `/admin/logs` body sent; a longer dump is cut short rather than refused
+pub const MAX_LOGS_BYTES: usize = 4 * 1024 * 1024;
+
+/// Represents why an admin request was refused
+#[derive(Debug, PartialEq)]
+pub enum AdminError {
+    Disabled,         // No admin token is configured
+    Unauthorized,     // Missing or wrong bearer token
+    BadQuery(String), // A filter parameter could not be parsed
+}
+
+/// Checks the request's `Authorization: Bearer <token>` header against the configured token
+pub fn authorize(request: &HttpRequest, token: Option<&str>) -> Result<(), AdminError> {
+    let expected = token.ok_or(AdminError::Disabled)?;
+
+    let provided = request
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("Authorization"))
+        .and_then(|(_, value)| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .ok_or(AdminError::Unauthorized)?;
+
+    if constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+        Ok(())
+    } else {
+        Err(AdminError::Unauthorized)
+    }
+}
+
+/// Compares two byte strings without short-circuiting on the first mismatch
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Builds a log filter from the `level`, `request_id` (sequence number or token), and `limit` query parameters
+pub fn parse_log_filter(request: &HttpRequest) -> Result<LogFilter, AdminError> {
+    let level = request
+        .query_param("level")
+        .map(|raw| {
+            Level::parse(raw)
+                .ok_or_else(|| AdminError::BadQuery(format!("unknown level '{}'", raw)))
+        })
+        .transpose()?;
+
+    let req_id = request
+        .query_param("request_id")
+        .map(|raw| {
+            RequestRef::parse(raw)
+                .ok_or_else(|| AdminError::BadQuery(format!("invalid request_id '{}'", raw)))
+        })
+        .transpose()?;
+
+    let limit = request
+        .query_param("limit")
+        .map(|raw| {
+            raw.parse::<usize>()
+                .map_err(|_| AdminError::BadQuery(format!("invalid limit '{}'", raw)))
+        })
+        .transpose()?;
+
+    Ok(LogFilter {
+        level,
+        req_id,
+        limit,
+    })
+}
+
+/// Guard for admin routes: refuses requests without the configured bearer token
+pub fn require_token(
+    request: &HttpRequest,
+    _params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<(), HttpError> {
+    authorize(request, ctx.config().admin.token.as_deref()).map_err(|e| {
+        server_log!(Warn, req = req_id, "[admin] refused: {:?}", e);
+        HttpError::from(e)
+    })
+}
+
+/// Post-write hook for admin routes: records what each admin request was answered with, once sent
+pub fn audit(
+    request: &HttpRequest,
+    response: &WrittenResponse<'_>,
+    _ctx: &server::ServerContext,
+    req_id: RequestId,
+) {
+    server_log!(
+        Info,
+        req = req_id,
+        "[admin] {} {} answered {} ({} bytes)",
+        request.status_line.method,
+        request.status_line.path,
+        response.status,
+        response.body.byte_len()
+    );
+}
+
+/// Handler that returns recent log records from the in-memory ring buffer, oldest first
+pub fn logs_handler(
+    request: &HttpRequest,
+    _params: &Params,
+    _ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    server_log!(
+        Info,
+        req = req_id,
+        "[admin] logs query={:?}",
+        request.status_line.query
+    );
+
+    let filter = parse_log_filter(request)?;
+
+    let body: String = logging::recent(&filter)
+        .iter()
+        .map(|record| format!("{}\n", record))
+        .collect();
+
+    text_response(request, body)
+}
+
+/// Handler that lists every route's hit count and p50/p95/p99 latency, one route per line
+pub fn stats_handler(
+    request: &HttpRequest,
+    _params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    server_log!(Info, req = req_id, "[admin] stats");
+    text_response(request, format_stats(&ctx.router().stats()))
+}
+
+/// Lays out route statistics as aligned columns, latencies in milliseconds ("-" before any hit)
+fn format_stats(stats: &[(String, StatsSnapshot)]) -> String {
+    let width = stats
+        .iter()
+        .map(|(route, _)| route.len())
+        .chain(["route".len()])
+        .max()
+        .unwrap_or_default();
+    let millis = |latency: Option<Duration>| {
+        latency.map_or("-".to_string(), |d| {
+            format!("{:.3}", d.as_secs_f64() * 1000.0)
+        })
+    };
+
+    let mut out = format!(
+        "{:<width$} {:>8} {:>10} {:>10} {:>10}\n",
+        "route", "hits", "p50_ms", "p95_ms", "p99_ms"
+    );
+    for (route, snapshot) in stats {
+        let _ = writeln!(
+            out,
+            "{:<width$} {:>8} {:>10} {:>10} {:>10}",
+            route,
+            snapshot.hits,
+            millis(snapshot.p50),
+            millis(snapshot.p95),
+            millis(snapshot.p99)
+        );
+    }
+    out
+}
+
+/// Handler that evicts `?path=...` from the in-memory file cache, or every file without a path
+///
+/// The cache already notices edits through each file's modification time; purging covers content
+/// swapped in with its old timestamp preserved (e.g. `rsync -t` or `cp -p`), since cached files
+/// are tagged from their contents and the next request rereads and re-tags them, and frees the
+/// memory. A path whose file was deleted still has its entry evicted
+pub fn cache_purge_handler(
+    request: &HttpRequest,
+    _params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    let path = request.query_param("path");
+    server_log!(Info, req = req_id, "[admin] cache purge path={:?}", path);
+
+    let purged = match path {
+        None => ctx.file_cache().clear(),
+        Some(path) => {
+            let host = request.host();
+            // Entries are keyed by canonical path; a deleted file cannot be canonicalized, but
+            // its parent directory can, as for a file about to be written
+            let resolved = ctx
+                .resolve_path(host, path, server::AccessIntent::Read, req_id)
+                .or_else(|e| match e {
+                    server::ResolveError::NotFound => {
+                        ctx.resolve_path(host, path, server::AccessIntent::Write, req_id)
+                    }
+                    e => Err(e),
+                })
+                .map_err(|_| HttpError::new(HttpStatusCode::NotFound, "File not found"))?;
+            usize::from(ctx.file_cache().remove(resolved.path()))
+        }
+    };
+
+    text_response(request, format!("purged {} cached file(s)\n", purged))
+}
+
+/// Builds an uncacheable 200 plain-text response
+fn text_response(request: &HttpRequest, body: String) -> Result<HttpResponse, HttpError> {
+    Ok(HttpResponse::builder()
+        .version(request.status_line.version.clone())
+        .header("Cache-Control", "no-store")
+        .text_body(body)
+        .build()?)
+}
+
+impl From<AdminError> for HttpError {
+    fn from(error: AdminError) -> Self {
+        match error {
+            // Admin endpoints look like any other unknown route when disabled
+            AdminError::Disabled => HttpError::new(HttpStatusCode::NotFound, "Route not found"),
+            AdminError::Unauthorized => HttpError::new(
+                HttpStatusCode::Unauthorized,
+                "Missing or invalid admin token",
+            )
+            .with_header("WWW-Authenticate", "Bearer"),
+            AdminError::BadQuery(msg) => HttpError::new(HttpStatusCode::BadRequest, msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{
+        request::HttpRequest,
+        test_support::{TempRoot, TestClient},
+    };
+    use std::fs;
+
+    fn request(raw: &str) -> HttpRequest {
+        HttpRequest::parse(raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_authorize_requires_matching_bearer_token() {
+        let good = request("GET /admin/logs HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n");
+        let bad = request("GET /admin/logs HTTP/1.1\r\nauthorization: Bearer nope\r\n\r\n");
+        let missing = request("GET /admin/logs HTTP/1.1\r\n\r\n");
+
+        assert_eq!(authorize(&good, Some("s3cret")), Ok(()));
+        assert_eq!(
+            authorize(&bad, Some("s3cret")),
+            Err(AdminError::Unauthorized)
+        );
+        assert_eq!(
+            authorize(&missing, Some("s3cret")),
+            Err(AdminError::Unauthorized)
+        );
+        assert_eq!(authorize(&good, None), Err(AdminError::Disabled));
+    }
+
+    #[test]
+    fn test_parse_log_filter() {
+        let filter = parse_log_filter(&request(
+            "GET /admin/logs?level=warn&request_id=7&limit=20 HTTP/1.1\r\n\r\n",
+        ))
+        .unwrap();
+        assert_eq!(filter.level, Some(Level::Warn));
+        assert_eq!(filter.req_id, Some(RequestRef::Seq(7)));
+        assert_eq!(filter.limit, Some(20));
+
+        assert!(parse_log_filter(&request("GET /admin/logs?level=loud HTTP/1.1\r\n\r\n")).is_err());
+    }
+
+    #[test]
+    fn test_cache_purge_retags_swapped_content_and_evicts_deleted_files() {
+        let root = TempRoot::new("purge");
+        let page = root.write("page.html", "old!");
+        let modified = fs::metadata(&page).unwrap().modified().unwrap();
+        let mut config = root.config();
+        config.admin.token = Some("s3cret".to_string());
+        let ctx = server::ServerContext::from_config(config).unwrap();
+        let client = TestClient::new(&ctx);
+        let get = |if_none_match: &str| {
+            client.send(&request(&format!(
+                "GET /files/page.html HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n",
+                if_none_match
+            )))
+        };
+        let purge = || {
+            client
+                .send(&request(
+                    "POST /admin/cache/purge?path=page.html HTTP/1.1\r\n\
+                     Authorization: Bearer s3cret\r\nContent-Length: 0\r\n\r\n",
+                ))
+                .text()
+        };
+
+        let first = get("\"none\"");
+        let etag = first.header("ETag").unwrap().to_string();
+        assert_eq!(first.text(), "old!");
+
+        // Same size and timestamp: only a purge reveals the new content
+        fs::write(&page, "new!").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&page)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(get(&etag).status, HttpStatusCode::NotModified);
+        assert_eq!(purge(), "purged 1 cached file(s)\n");
+        let fresh = get(&etag);
+        assert_eq!(fresh.status, HttpStatusCode::Ok);
+        assert_eq!(fresh.text(), "new!");
+        assert_ne!(fresh.header("ETag").unwrap(), etag);
+
+        fs::remove_file(&page).unwrap();
+        assert_eq!(purge(), "purged 1 cached file(s)\n");
+    }
+}
@@ -0,0 +1,157 @@
+use std::{net::Shutdown, thread, time::Duration};
+
+use serde::Deserialize;
+
+use crate::http::{
+    errors::HttpErrorResponse,
+    logging::server_log,
+    random::sampled,
+    request::{HttpRequest, RequestId},
+    response::HttpStatusCode,
+    stream::ClientStream,
+    writer::{send_response, HttpWriter},
+};
+
+/// Fault injection rules, matched against the request path in order
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosConfig {
+    pub rules: Vec<ChaosRule>,
+}
+
+/// A single fault applied to requests whose path starts with `path`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChaosRule {
+    pub path: String,
+    #[serde(default)]
+    pub method: Option<String>,
+    pub fault: ChaosFault,
+    /// Percentage of matching requests affected, from 0 to 100
+    #[serde(default = "default_percent")]
+    pub percent: f64,
+    /// Delay applied by the `delay` fault
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+/// Supported fault kinds
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChaosFault {
+    Delay,              // Sleep for delay_ms, then handle normally
+    InternalError,      // Respond 500 without running the handler
+    ServiceUnavailable, // Respond 503 without running the handler
+    Truncate,           // Declare a body, send half of it, then close
+    Reset,              // Abort the connection with a TCP reset
+}
+
+/// What the connection loop should do after fault injection
+#[derive(Debug, PartialEq)]
+pub enum ChaosOutcome {
+    Continue, // Route the request normally
+    Closed,   // A fault was delivered and the connection must not be reused
+}
+
+fn default_percent() -> f64 {
+    100.0
+}
+
+impl ChaosConfig {
+    /// Checks rule values that deserialize fine but cannot be used
+    pub fn validate(&self) -> Result<(), String> {
+        for rule in &self.rules {
+            if !(0.0..=100.0).contains(&rule.percent) {
+                return Err(format!(
+                    "chaos rule for '{}': percent must be between 0 and 100",
+                    rule.path
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the first rule matching the request
+    fn matching_rule(&self, request: &HttpRequest) -> Option<&ChaosRule> {
+        self.rules.iter().find(|rule| {
+            request.status_line.path.starts_with(&rule.path)
+                && rule.method.as_ref().map_or(true, |m| {
+                    m.eq_ignore_ascii_case(&request.status_line.method.to_string())
+                })
+        })
+    }
+}
+
+/// Applies the first matching fault rule, if it is sampled for this request
+pub fn inject(
+    config: &ChaosConfig,
+    request: &HttpRequest,
+    stream: &mut dyn ClientStream,
+    req_id: RequestId,
+) -> ChaosOutcome {
+    let Some(rule) = config.matching_rule(request) else {
+        return ChaosOutcome::Continue;
+    };
+    if !sampled(rule.percent) {
+        return ChaosOutcome::Continue;
+    }
+
+    server_log!(
+        Warn,
+        req = req_id,
+        "[chaos] injecting {:?} for rule '{}'",
+        rule.fault,
+        rule.path
+    );
+
+    match rule.fault {
+        ChaosFault::Delay => {
+            thread::sleep(Duration::from_millis(rule.delay_ms));
+            ChaosOutcome::Continue
+        }
+        ChaosFault::InternalError | ChaosFault::ServiceUnavailable => {
+            let status = if rule.fault == ChaosFault::InternalError {
+                HttpStatusCode::InternalServerError
+            } else {
+                HttpStatusCode::ServiceUnavailable
+            };
+            let err_response = HttpErrorResponse::new(
+                status,
+                request.status_line.version.clone(),
+                "close",
+                request
+                    .headers
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case("Accept"))
+                    .map(|(_, value)| value.as_str()),
+                "Injected fault".to_string(),
+            );
+            send_response(stream, err_response, req_id).unwrap_or_else(|e| {
+                HttpWriter::log_writer_error(e, "chaos::inject - sending injected error", req_id);
+            });
+            let _ = stream.shutdown(Shutdown::Both);
+            ChaosOutcome::Closed
+        }
+        ChaosFault::Truncate => {
+            let body = "chaos: this body is cut short before Content-Length is reached";
+            let head = format!(
+                "{} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n",
+                request.status_line.version,
+                HttpStatusCode::Ok,
+                body.len()
+            );
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(&body.as_bytes()[..body.len() / 2]);
+            let _ = stream.flush();
+            let _ = stream.shutdown(Shutdown::Both);
+            ChaosOutcome::Closed
+        }
+        ChaosFault::Reset => {
+            // SO_LINGER with a zero timeout makes the close on drop send RST instead of FIN
+            if let Some(socket) = stream.tcp() {
+                let _ = socket2::SockRef::from(socket).set_linger(Some(Duration::ZERO));
+            }
+            ChaosOutcome::Closed
+        }
+    }
+}
@@ -0,0 +1,890 @@
+use std::{collections::BTreeMap, fmt, fs, io, path::Path, time::Duration};
+
+use serde::Deserialize;
+
+#[cfg(feature = "chaos")]
+use crate::http::chaos::ChaosConfig;
+#[cfg(feature = "otel")]
+use crate::http::otel::OtelConfig;
+use crate::http::{
+    files::cache::FileCacheConfig,
+    logging::{
+        access::AccessLogFormat,
+        console::DEFAULT_QUEUE_CAPACITY,
+        redact::{DEFAULT_REDACT_HEADERS, DEFAULT_REDACT_QUERY_PARAMS},
+        Level, LogFormat, DEFAULT_RING_CAPACITY,
+    },
+    metrics::MetricsConfig,
+    mirror::MirrorTarget,
+    quarantine::QuarantineConfig,
+    response::charset::Charset,
+    routes::{CompressionLevels, TrailingSlash},
+    server::FilenameRules,
+    shard::ShardConfig,
+    site_defaults::SiteDefaultsConfig,
+    uploads::UploadConfig,
+    well_known::WellKnownConfig,
+    writer::DEFAULT_MAX_HEADER_BYTES,
+};
+
+/// Default configuration file looked up in the working directory
+pub const DEFAULT_CONFIG_FILE: &str = "server.toml";
+
+const DEFAULT_ROOT: &str = "./www";
+const DEFAULT_BIND: &str = "127.0.0.1:4221";
+const DEFAULT_WORKERS: usize = 100;
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+const DEFAULT_CACHE_DIR: &str = "./cache";
+const DEFAULT_CRASH_REPORT_DIR: &str = "./crash-reports";
+
+/// 30 seconds is the default for most web servers, so we follow suit
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_HEADER_SECS: u64 = 10;
+const DEFAULT_BODY_SECS: u64 = 120;
+const DEFAULT_SHUTDOWN_SECS: u64 = 10;
+const DEFAULT_KEEP_ALIVE_SECS: u64 = 5;
+const DEFAULT_MAX_REQUESTS: u64 = 100;
+const DEFAULT_MAX_ERRORS: u32 = 10;
+const DEFAULT_BACKLOG: i32 = 1024;
+const DEFAULT_COMPRESSION_MIN_SIZE: usize = 1024;
+const DEFAULT_GZIP_LEVEL: u32 = 9;
+const DEFAULT_BROTLI_QUALITY: u32 = 5;
+const DEFAULT_BROTLI_WINDOW: u32 = 22;
+
+/// Top-level server configuration, usually loaded from `server.toml`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub root: String,
+    /// Per-host serving roots; requests for any other Host fall back to `root`
+    pub vhosts: BTreeMap<String, String>, // "example.com" -> "./site1"
+    /// Directories under the site root served at a URL prefix, nested paths included
+    pub static_files: BTreeMap<String, String>, // "/assets" -> "public/assets"
+    /// Files under the root rendered into complete responses at startup and sent byte for byte
+    pub raw_routes: BTreeMap<String, String>, // "/pixel.gif" -> "raw/pixel.gif"
+    /// Content-Types by file extension, added to or replacing the built-in ones
+    pub mime_types: BTreeMap<String, String>, // "glb" -> "model/gltf-binary"
+    /// Guess the type of files with no or an unknown extension from their first bytes
+    pub sniff_types: bool,
+    /// Pages under the root sent as the body of error responses, by status code or class,
+    /// added to or replacing the built-in 403.html, 404.html, 500.html, and 50x.html
+    pub error_pages: BTreeMap<String, String>, // "404" -> "missing.html", "5xx" -> ""
+    pub bind: Vec<String>, // ["127.0.0.1:4221", "[::1]:4221"]
+    pub workers: usize,
+    /// Open connections (active plus queued for a worker) beyond which new ones get 503
+    pub max_connections: usize,
+    /// Serialized size of a response's status line and headers beyond which a 500 is sent instead
+    pub max_response_header_bytes: usize,
+    pub cache_dir: String,
+    /// Whether `/user-agent/` matches `/user-agent`: "strict", "ignore", or "redirect"
+    pub trailing_slash: TrailingSlash,
+    /// Whose filename rules requested paths must follow: "platform" or "windows"
+    pub filename_rules: FilenameRules,
+    pub socket: SocketConfig,
+    pub timeouts: TimeoutConfig,
+    pub keep_alive: KeepAliveConfig,
+    pub compression: CompressionConfig,
+    pub charset: CharsetConfig,
+    pub cache_control: CacheControlConfig,
+    pub file_cache: FileCacheConfig,
+    pub logging: LoggingConfig,
+    pub admin: AdminConfig,
+    pub metrics: MetricsConfig,
+    pub uploads: UploadConfig,
+    pub well_known: WellKnownConfig,
+    pub site_defaults: SiteDefaultsConfig,
+    pub mirror: MirrorConfig,
+    pub quarantine: QuarantineConfig,
+    pub shard: ShardConfig,
+    #[cfg(feature = "chaos")]
+    pub chaos: ChaosConfig,
+    #[cfg(feature = "otel")]
+    pub otel: OtelConfig,
+}
+
+/// Options applied to listening and accepted sockets
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketConfig {
+    /// Disable Nagle's algorithm on accepted connections so small writes go out immediately
+    pub nodelay: bool,
+    /// Set SO_REUSEADDR on listeners so a restart can rebind while old connections sit in TIME_WAIT
+    pub reuse_address: bool,
+    /// Pending connections the kernel queues before the server accepts them
+    pub backlog: i32,
+}
+
+/// Socket and shutdown timeouts, in seconds
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    pub read_secs: u64,
+    pub write_secs: u64,
+    /// How long a client may take to send the complete header section, however slowly it trickles in
+    pub header_secs: u64,
+    /// How long a client may take to send the request body once the headers are in
+    pub body_secs: u64,
+    /// Overall limit on receiving a request, headers and body together (0 = only the limits above)
+    pub request_secs: u64,
+    /// How long in-flight requests may run after SIGINT/SIGTERM before the process exits
+    pub shutdown_secs: u64,
+}
+
+/// Persistent connection limits
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeepAliveConfig {
+    /// How long an idle connection may wait for its next request
+    pub idle_secs: u64,
+    /// Requests served on one connection before it is closed (0 = unlimited)
+    pub max_requests: u64,
+    /// Malformed or 4xx requests on one connection before it is closed (0 = unlimited)
+    pub max_errors: u32,
+}
+
+/// Response compression settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    /// Smallest body, in bytes, worth compressing
+    pub min_size: usize,
+    /// Per-MIME-type overrides of `min_size`; keys are "type/subtype" or "type/*"
+    pub min_size_by_type: BTreeMap<String, usize>, // "application/json" -> 256
+    /// Serve `app.js.br` or `app.js.gz` for `app.js` when one exists and the client accepts it
+    pub precompressed: bool,
+    /// Compress bodies streamed from disk as they are sent, in chunked framing, instead of
+    /// sending them as-is
+    pub streaming: bool,
+    /// Gzip and deflate effort: 0 stores without compressing; 1 to 9 search a larger window for
+    /// repeats, from 512 bytes up to the 32 KiB deflate allows, reached at 7
+    pub gzip_level: u32,
+    /// Brotli effort, 0 (fastest) to 11 (smallest)
+    pub brotli_quality: u32,
+    /// Brotli window as a power of two, 10 to 24; smaller windows use less memory per response
+    pub brotli_window: u32,
+}
+
+impl CompressionConfig {
+    /// Returns the threshold for a Content-Type: an exact match, then a "type/*" match, then `min_size`
+    pub fn min_size_for(&self, content_type: Option<&str>) -> usize {
+        let Some(mime) = content_type
+            .and_then(|ct| ct.split(';').next())
+            .map(|mime| mime.trim().to_ascii_lowercase())
+        else {
+            return self.min_size;
+        };
+        by_type(&self.min_size_by_type, &mime).map_or(self.min_size, |size| *size)
+    }
+
+    /// Returns the encoder settings
+    pub fn levels(&self) -> CompressionLevels {
+        CompressionLevels {
+            gzip: self.gzip_level,
+            brotli_quality: self.brotli_quality,
+            brotli_window: self.brotli_window,
+        }
+    }
+}
+
+/// Charset handling for text responses
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CharsetConfig {
+    /// Charset text files under the root are stored in; declared on them unless it is UTF-8
+    pub source: Charset,
+    /// Transcode text bodies to a charset the request's Accept-Charset prefers, when possible
+    pub negotiate: bool,
+}
+
+impl Default for CharsetConfig {
+    fn default() -> Self {
+        CharsetConfig {
+            source: Charset::Utf8,
+            negotiate: true,
+        }
+    }
+}
+
+/// Cache-Control values sent with served files; a file no rule matches gets no header
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheControlConfig {
+    /// Values by glob, where `*` matches any run of characters; a glob containing `/` is matched
+    /// against the request path, any other against the file name. The longest matching glob wins
+    pub by_path: BTreeMap<String, String>, // "*.css" -> "max-age=86400"
+    /// Values by MIME type, for files no glob matched; keys are "type/subtype" or "type/*"
+    pub by_type: BTreeMap<String, String>, // "image/*" -> "max-age=604800"
+    /// Value for files matching neither
+    pub default: Option<String>,
+}
+
+impl CacheControlConfig {
+    /// Returns the value for a file served at `path`: a glob match, then its type, then the default
+    pub fn value_for(&self, path: &str, file_name: &str, content_type: &str) -> Option<&str> {
+        self.by_path
+            .iter()
+            .filter(|(glob, _)| {
+                glob_matches(glob, if glob.contains('/') { path } else { file_name })
+            })
+            .max_by_key(|(glob, _)| glob.len())
+            .map(|(_, value)| value)
+            .or_else(|| {
+                let mime = content_type.split(';').next().unwrap_or_default().trim();
+                by_type(&self.by_type, &mime.to_ascii_lowercase())
+            })
+            .or(self.default.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// Looks up a lowercase MIME type in a map keyed by "type/subtype" or "type/*", exact match first
+fn by_type<'a, T>(map: &'a BTreeMap<String, T>, mime: &str) -> Option<&'a T> {
+    let wildcard = mime.split_once('/').map(|(kind, _)| format!("{}/*", kind));
+
+    map.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(mime))
+        .or_else(|| {
+            let wildcard = wildcard.as_deref()?;
+            map.iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(wildcard))
+        })
+        .map(|(_, value)| value)
+}
+
+/// Checks whether `text` matches `glob` in full, with `*` standing for any run of characters
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let (glob, text) = (glob.as_bytes(), text.as_bytes());
+    let (mut g, mut t) = (0, 0);
+    let mut star = None; // Position after the last `*`, and where in `text` it started matching
+    while t < text.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                g += 1;
+                star = Some((g, t));
+            }
+            Some(&c) if c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character and retry from there
+                Some((after, from)) => {
+                    g = after;
+                    t = from + 1;
+                    star = Some((after, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == b'*')
+}
+
+/// Console logging and crash report settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Least severe level that is logged: "error", "warn", "info", or "debug"
+    pub level: Level,
+    /// Console line format: "text", or "json" for one JSON object per record
+    pub format: LogFormat,
+    /// Log accepted and closed connections
+    pub connections: bool,
+    /// Directory that receives crash reports on panics and fatal errors
+    pub crash_report_dir: String,
+    /// Number of recent log records kept in memory for /admin/logs and crash reports
+    pub ring_capacity: usize,
+    /// Console lines buffered for the logger thread; beyond this they are dropped and counted.
+    /// 0 writes each line from the thread that logged it
+    pub queue_capacity: usize,
+    /// Headers whose values are masked in every log record (case-insensitive)
+    pub redact_headers: Vec<String>,
+    /// Query parameters whose values are masked in every log record (case-insensitive)
+    pub redact_query_params: Vec<String>,
+    /// Line written for each request: "off", "clf" for Common Log Format, or "ecs" for
+    /// Elastic Common Schema JSON
+    pub access_log: AccessLogFormat,
+    /// File the access log is appended to instead of stdout
+    pub access_log_file: Option<String>,
+}
+
+/// Admin API settings; the /admin endpoints are disabled unless a token is set
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Bearer token required in the Authorization header of admin requests
+    pub token: Option<String>,
+}
+
+/// Shadow traffic settings: copies a share of requests to a secondary backend
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MirrorConfig {
+    /// Plain-HTTP destination, e.g. "http://10.0.0.5:8080/shadow"
+    pub url: Option<String>,
+    /// Percentage of requests to mirror, from 0 to 100
+    pub percent: f64,
+}
+
+/// Represents an error that can occur while loading the configuration
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),   // File could not be read
+    Parse(String),   // File is not valid TOML or has unknown keys
+    Invalid(String), // Values parsed but are unusable
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "failed to read config: {}", e),
+            ConfigError::Parse(msg) => write!(f, "failed to parse config: {}", msg),
+            ConfigError::Invalid(msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            root: DEFAULT_ROOT.to_string(),
+            vhosts: BTreeMap::new(),
+            static_files: BTreeMap::new(),
+            raw_routes: BTreeMap::new(),
+            mime_types: BTreeMap::new(),
+            sniff_types: false,
+            error_pages: BTreeMap::new(),
+            bind: vec![DEFAULT_BIND.to_string()],
+            workers: DEFAULT_WORKERS,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            max_response_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            cache_dir: DEFAULT_CACHE_DIR.to_string(),
+            trailing_slash: TrailingSlash::default(),
+            filename_rules: FilenameRules::default(),
+            socket: SocketConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            keep_alive: KeepAliveConfig::default(),
+            compression: CompressionConfig::default(),
+            charset: CharsetConfig::default(),
+            cache_control: CacheControlConfig::default(),
+            file_cache: FileCacheConfig::default(),
+            logging: LoggingConfig::default(),
+            admin: AdminConfig::default(),
+            metrics: MetricsConfig::default(),
+            uploads: UploadConfig::default(),
+            well_known: WellKnownConfig::default(),
+            site_defaults: SiteDefaultsConfig::default(),
+            mirror: MirrorConfig::default(),
+            quarantine: QuarantineConfig::default(),
+            shard: ShardConfig::default(),
+            #[cfg(feature = "chaos")]
+            chaos: ChaosConfig::default(),
+            #[cfg(feature = "otel")]
+            otel: OtelConfig::default(),
+        }
+    }
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        SocketConfig {
+            nodelay: true,
+            reuse_address: true,
+            backlog: DEFAULT_BACKLOG,
+        }
+    }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            read_secs: DEFAULT_TIMEOUT_SECS,
+            write_secs: DEFAULT_TIMEOUT_SECS,
+            header_secs: DEFAULT_HEADER_SECS,
+            body_secs: DEFAULT_BODY_SECS,
+            request_secs: 0,
+            shutdown_secs: DEFAULT_SHUTDOWN_SECS,
+        }
+    }
+}
+
+impl Default for KeepAliveConfig {
+    fn default() -> Self {
+        KeepAliveConfig {
+            idle_secs: DEFAULT_KEEP_ALIVE_SECS,
+            max_requests: DEFAULT_MAX_REQUESTS,
+            max_errors: DEFAULT_MAX_ERRORS,
+        }
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: true,
+            min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+            min_size_by_type: BTreeMap::new(),
+            precompressed: true,
+            streaming: true,
+            gzip_level: DEFAULT_GZIP_LEVEL,
+            brotli_quality: DEFAULT_BROTLI_QUALITY,
+            brotli_window: DEFAULT_BROTLI_WINDOW,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: Level::Info,
+            format: LogFormat::Text,
+            connections: true,
+            crash_report_dir: DEFAULT_CRASH_REPORT_DIR.to_string(),
+            ring_capacity: DEFAULT_RING_CAPACITY,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            redact_headers: DEFAULT_REDACT_HEADERS.iter().map(|h| h.to_string()).collect(),
+            redact_query_params: DEFAULT_REDACT_QUERY_PARAMS
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            access_log: AccessLogFormat::Off,
+            access_log_file: None,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    /// Returns the socket read timeout
+    pub fn read(&self) -> Duration {
+        Duration::from_secs(self.read_secs)
+    }
+
+    /// Returns the socket write timeout
+    pub fn write(&self) -> Duration {
+        Duration::from_secs(self.write_secs)
+    }
+
+    /// Returns the deadline for receiving a request's full header section, within the overall limit
+    pub fn header(&self) -> Duration {
+        match self.request() {
+            Some(request) => Duration::from_secs(self.header_secs).min(request),
+            None => Duration::from_secs(self.header_secs),
+        }
+    }
+
+    /// Returns the deadline for receiving a request body, counted from the end of the headers
+    pub fn body(&self) -> Duration {
+        Duration::from_secs(self.body_secs)
+    }
+
+    /// Returns the overall deadline for receiving a request, counted from its first byte
+    pub fn request(&self) -> Option<Duration> {
+        (self.request_secs > 0).then(|| Duration::from_secs(self.request_secs))
+    }
+
+    /// Returns the connection draining deadline used during shutdown
+    pub fn shutdown(&self) -> Duration {
+        Duration::from_secs(self.shutdown_secs)
+    }
+}
+
+impl KeepAliveConfig {
+    /// Returns the idle timeout between requests
+    pub fn idle(&self) -> Duration {
+        Duration::from_secs(self.idle_secs)
+    }
+}
+
+impl ServerConfig {
+    /// Loads and validates a configuration file
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let raw = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::parse(&raw)
+    }
+
+    /// Parses and validates configuration from TOML text
+    pub fn parse(raw: &str) -> Result<Self, ConfigError> {
+        let config: ServerConfig =
+            toml::from_str(raw).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Returns a short, secret-free description of the configuration for diagnostics
+    pub fn summary(&self) -> String {
+        format!(
+            "root={} vhosts={} bind={:?} workers={} max_connections={} cache_dir={} nodelay={} \
+             backlog={} read_timeout={}s write_timeout={}s keep_alive={}s/{} requests compression={} \
+             compression_min_size={}{} file_cache={}/{} bytes file_cache_ttl={}ms/{} admin={} \
+             metrics={:?} upload_tokens={} mirror={} trailing_slash={:?} filename_rules={:?} shards={} \
+             charset={}{} sniff_types={}",
+            self.root,
+            self.vhosts.len(),
+            self.bind,
+            self.workers,
+            self.max_connections,
+            self.cache_dir,
+            self.socket.nodelay,
+            self.socket.backlog,
+            self.timeouts.read_secs,
+            self.timeouts.write_secs,
+            self.keep_alive.idle_secs,
+            self.keep_alive.max_requests,
+            self.compression.enabled,
+            self.compression.min_size,
+            self.compression
+                .min_size_by_type
+                .iter()
+                .map(|(mime, size)| format!(",{}={}", mime, size))
+                .collect::<String>(),
+            self.file_cache.max_entry_bytes,
+            self.file_cache.max_bytes,
+            self.file_cache.ttl_ms,
+            if self.file_cache.stale_while_revalidate {
+                format!("stale<={}ms", self.file_cache.max_stale_ms)
+            } else {
+                "inline".to_string()
+            },
+            self.admin.token.is_some(),
+            self.metrics.emitter,
+            self.uploads.secret.is_some(),
+            self.mirror.url.is_some(),
+            self.trailing_slash,
+            self.filename_rules,
+            self.shard.count,
+            self.charset.source.label(),
+            if self.charset.negotiate {
+                "+negotiate"
+            } else {
+                ""
+            },
+            self.sniff_types,
+        )
+    }
+
+    /// Replaces the listen addresses with a single one, keeping unspecified parts of the first address
+    pub fn override_listen(&mut self, host: Option<String>, port: Option<u16>) {
+        if host.is_none() && port.is_none() {
+            return;
+        }
+
+        let first = self
+            .bind
+            .first()
+            .map(|s| s.as_str())
+            .unwrap_or(DEFAULT_BIND);
+        let (current_host, current_port) = first.rsplit_once(':').unwrap_or((first, "4221"));
+
+        let host = host.unwrap_or_else(|| current_host.to_string());
+        let port = port.map_or_else(|| current_port.to_string(), |p| p.to_string());
+
+        self.bind = vec![format!("{}:{}", host, port)];
+    }
+
+    /// Adds or replaces a virtual host from a `host=dir` command line spec
+    pub fn add_vhost(&mut self, spec: &str) -> Result<(), ConfigError> {
+        let (host, root) = spec.split_once('=').ok_or_else(|| {
+            ConfigError::Invalid(format!("vhost '{}' must look like host=dir", spec))
+        })?;
+        self.vhosts
+            .insert(host.trim().to_string(), root.trim().to_string());
+        Ok(())
+    }
+
+    /// Checks values that deserialize fine but cannot be used
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.workers == 0 {
+            return Err(ConfigError::Invalid(
+                "workers must be greater than zero".to_string(),
+            ));
+        }
+        if self.max_connections == 0 {
+            return Err(ConfigError::Invalid(
+                "max_connections must be greater than zero".to_string(),
+            ));
+        }
+        if self.max_response_header_bytes == 0 {
+            return Err(ConfigError::Invalid(
+                "max_response_header_bytes must be greater than zero".to_string(),
+            ));
+        }
+        if self.bind.is_empty() {
+            return Err(ConfigError::Invalid(
+                "at least one bind address is required".to_string(),
+            ));
+        }
+        if self.socket.backlog <= 0 {
+            return Err(ConfigError::Invalid(
+                "socket.backlog must be greater than zero".to_string(),
+            ));
+        }
+        if self.timeouts.read_secs == 0
+            || self.timeouts.write_secs == 0
+            || self.timeouts.header_secs == 0
+            || self.timeouts.body_secs == 0
+            || self.keep_alive.idle_secs == 0
+        {
+            return Err(ConfigError::Invalid(
+                "timeouts must be greater than zero".to_string(),
+            ));
+        }
+        if self
+            .vhosts
+            .iter()
+            .any(|(host, root)| host.is_empty() || root.is_empty())
+        {
+            return Err(ConfigError::Invalid(
+                "vhosts need a non-empty host and directory".to_string(),
+            ));
+        }
+        if self.compression.gzip_level > 9
+            || self.compression.brotli_quality > 11
+            || !(10..=24).contains(&self.compression.brotli_window)
+        {
+            return Err(ConfigError::Invalid(
+                "compression levels must be gzip_level 0-9, brotli_quality 0-11, and brotli_window 10-24"
+                    .to_string(),
+            ));
+        }
+        if let Some(key) = self
+            .compression
+            .min_size_by_type
+            .keys()
+            .find(|key| !key.contains('/'))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "compression.min_size_by_type key '{}' must be a MIME type like text/html or text/*",
+                key
+            )));
+        }
+        if let Some((ext, _)) = self.mime_types.iter().find(|(ext, mime)| {
+            ext.trim_start_matches('.').is_empty()
+                || ext.contains('/')
+                || !mime.contains('/')
+                || mime.chars().any(char::is_control)
+        }) {
+            return Err(ConfigError::Invalid(format!(
+                "mime_types entry '{}' must map an extension to a MIME type like application/wasm",
+                ext
+            )));
+        }
+        if let Some((key, _)) = self.error_pages.iter().find(|(key, page)| {
+            let status = key
+                .parse::<u16>()
+                .is_ok_and(|code| (400..600).contains(&code));
+            !(status || matches!(key.as_str(), "4xx" | "5xx"))
+                || page.contains(['/', '\\'])
+                || page.as_str() == ".."
+        }) {
+            return Err(ConfigError::Invalid(format!(
+                "error_pages key '{}' must be a status like 404 or a class like 5xx, mapped to a \
+                 file name at the top of the root",
+                key
+            )));
+        }
+        if let Some(key) = self
+            .cache_control
+            .by_type
+            .keys()
+            .find(|key| !key.contains('/'))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "cache_control.by_type key '{}' must be a MIME type like text/css or image/*",
+                key
+            )));
+        }
+        if self
+            .cache_control
+            .by_path
+            .values()
+            .chain(self.cache_control.by_type.values())
+            .chain(&self.cache_control.default)
+            .any(|value| value.is_empty() || value.chars().any(char::is_control))
+        {
+            return Err(ConfigError::Invalid(
+                "cache_control values must be non-empty, without control characters".to_string(),
+            ));
+        }
+        if self.logging.ring_capacity == 0 {
+            return Err(ConfigError::Invalid(
+                "logging.ring_capacity must be greater than zero".to_string(),
+            ));
+        }
+        if self.admin.token.as_deref().is_some_and(str::is_empty) {
+            return Err(ConfigError::Invalid(
+                "admin.token must not be empty".to_string(),
+            ));
+        }
+        if self.uploads.secret.as_deref().is_some_and(str::is_empty) {
+            return Err(ConfigError::Invalid(
+                "uploads.secret must not be empty".to_string(),
+            ));
+        }
+        if self.uploads.token_ttl_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "uploads.token_ttl_secs must be greater than zero".to_string(),
+            ));
+        }
+        self.well_known.validate().map_err(ConfigError::Invalid)?;
+        if self.quarantine.dir.as_deref().is_some_and(str::is_empty) {
+            return Err(ConfigError::Invalid(
+                "quarantine.dir must not be empty".to_string(),
+            ));
+        }
+        if !(0.0..=100.0).contains(&self.mirror.percent) {
+            return Err(ConfigError::Invalid(
+                "mirror.percent must be between 0 and 100".to_string(),
+            ));
+        }
+        if let Some(url) = &self.mirror.url {
+            MirrorTarget::parse(url).map_err(ConfigError::Invalid)?;
+        }
+        #[cfg(feature = "chaos")]
+        self.chaos.validate().map_err(ConfigError::Invalid)?;
+        #[cfg(feature = "otel")]
+        self.otel.validate().map_err(ConfigError::Invalid)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_partial_config_uses_defaults() {
+        let config =
+            ServerConfig::parse("root = \"./site\"\nworkers = 8\n\n[timeouts]\nread_secs = 5\n")
+                .unwrap();
+
+        assert_eq!(config.root, "./site");
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.bind, vec![DEFAULT_BIND.to_string()]);
+        assert_eq!(config.timeouts.read_secs, 5);
+        assert_eq!(config.timeouts.write_secs, DEFAULT_TIMEOUT_SECS);
+        assert!(config.compression.enabled);
+        assert_eq!(config.logging.level, Level::Info);
+
+        let config = ServerConfig::parse("[logging]\nlevel = \"warning\"\n").unwrap();
+        assert_eq!(config.logging.level, Level::Warn);
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_keys_and_invalid_values() {
+        assert!(matches!(
+            ServerConfig::parse("wrokers = 8\n"),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            ServerConfig::parse("workers = 0\n"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            ServerConfig::parse("[logging]\nlevel = \"verbose\"\n"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_override_listen_keeps_unspecified_parts() {
+        let mut config = ServerConfig::parse("bind = [\"0.0.0.0:8080\", \"[::]:8080\"]\n").unwrap();
+
+        config.override_listen(None, Some(9000));
+        assert_eq!(config.bind, vec!["0.0.0.0:9000".to_string()]);
+
+        config.override_listen(Some("127.0.0.1".to_string()), None);
+        assert_eq!(config.bind, vec!["127.0.0.1:9000".to_string()]);
+    }
+
+    #[test]
+    fn test_cli_vhosts_extend_config_file_vhosts() {
+        let mut config = ServerConfig::parse("[vhosts]\n\"example.com\" = \"./site1\"\n").unwrap();
+
+        config.add_vhost("other.org=./site2").unwrap();
+        config.add_vhost("example.com=./site3").unwrap();
+        assert_eq!(config.vhosts.len(), 2);
+        assert_eq!(config.vhosts["example.com"], "./site3");
+        assert_eq!(config.vhosts["other.org"], "./site2");
+
+        assert!(config.add_vhost("no-directory").is_err());
+        config.add_vhost("=./site4").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_compression_min_size_per_mime_type() {
+        let config = ServerConfig::parse(
+            "[compression]\nmin_size = 2048\n\n[compression.min_size_by_type]\n\
+             \"text/*\" = 512\n\"application/json\" = 128\n",
+        )
+        .unwrap();
+        let compression = &config.compression;
+
+        assert_eq!(compression.min_size_for(None), 2048);
+        assert_eq!(compression.min_size_for(Some("image/svg+xml")), 2048);
+        assert_eq!(
+            compression.min_size_for(Some("text/html; charset=utf-8")),
+            512
+        );
+        assert_eq!(compression.min_size_for(Some("Application/JSON")), 128);
+
+        assert!(matches!(
+            ServerConfig::parse("[compression.min_size_by_type]\ntext = 1\n"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            ServerConfig::parse("[mime_types]\nwasm = \"wasm\"\n"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(ServerConfig::parse("[mime_types]\nwasm = \"application/wasm\"\n").is_ok());
+        assert!(matches!(
+            ServerConfig::parse("[error_pages]\n302 = \"moved.html\"\n"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            ServerConfig::parse("[error_pages]\n404 = \"../404.html\"\n"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(ServerConfig::parse("[error_pages]\n403 = \"\"\n4xx = \"oops.html\"\n").is_ok());
+    }
+
+    #[test]
+    fn test_cache_control_prefers_longest_glob_then_type() {
+        let config = ServerConfig::parse(
+            "[cache_control]\ndefault = \"no-cache\"\n\n[cache_control.by_path]\n\
+             \"*.css\" = \"max-age=86400\"\n\"/assets/*.css\" = \"max-age=31536000, immutable\"\n\n\
+             [cache_control.by_type]\n\"image/*\" = \"max-age=604800\"\n",
+        )
+        .unwrap();
+        let cache_control = &config.cache_control;
+
+        assert_eq!(
+            cache_control.value_for("/site.css", "site.css", "text/css"),
+            Some("max-age=86400")
+        );
+        assert_eq!(
+            cache_control.value_for("/assets/app.css", "app.css", "text/css"),
+            Some("max-age=31536000, immutable")
+        );
+        assert_eq!(
+            cache_control.value_for("/img/cat.png", "cat.png", "image/png"),
+            Some("max-age=604800")
+        );
+        assert_eq!(
+            cache_control.value_for("/logo.svg", "logo.svg", "image/svg+xml; charset=utf-8"),
+            Some("max-age=604800")
+        );
+        assert_eq!(
+            cache_control.value_for("/docs/", "index.html", "text/html"),
+            Some("no-cache")
+        );
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("*.css", "site.css.map"));
+
+        assert!(matches!(
+            ServerConfig::parse("[cache_control]\ndefault = \"a\\r\\nSet-Cookie: x\"\n"),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    fmt::Write as _,
+    fs, panic,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{fence, AtomicU64, AtomicUsize, Ordering},
+        OnceLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::http::{
+    config::ServerConfig,
+    logging::{self, server_log},
+    request::RequestId,
+};
+
+/// Number of in-flight requests the crash report can list
+const ACTIVE_SLOTS: usize = 256;
+
+/// Slot state of a free slot; a filled slot holds the request's sequence number plus one
+const FREE: u64 = 0;
+
+/// Slot state while a request is being written into the slot
+const CLAIMED: u64 = u64::MAX;
+
+/// Requests currently being handled, reported if the process crashes
+static ACTIVE: [Slot; ACTIVE_SLOTS] = [const { Slot::new() }; ACTIVE_SLOTS];
+
+/// In-flight requests that found every slot taken
+static UNTRACKED: AtomicUsize = AtomicUsize::new(0);
+
+/// Where reports go and what configuration they describe, set once at startup
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+struct Reporter {
+    dir: PathBuf,
+    config_summary: String,
+}
+
+/// One in-flight request, written by its worker and read without locks by the crash reporter
+struct Slot {
+    state: AtomicU64, // FREE, CLAIMED, or the sequence number plus one
+    token_high: AtomicU64,
+    token_low: AtomicU64,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Slot {
+            state: AtomicU64::new(FREE),
+            token_high: AtomicU64::new(0),
+            token_low: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads the slot's request, or None if it is free, being written, or changed mid-read
+    fn read(&self) -> Option<RequestId> {
+        let state = self.state.load(Ordering::Acquire);
+        if state == FREE || state == CLAIMED {
+            return None;
+        }
+        let token = (u128::from(self.token_high.load(Ordering::Relaxed)) << 64)
+            | u128::from(self.token_low.load(Ordering::Relaxed));
+        fence(Ordering::Acquire);
+        (self.state.load(Ordering::Relaxed) == state).then(|| RequestId::from_raw(state - 1, token))
+    }
+}
+
+/// Marks a request as in flight for as long as the guard lives
+pub struct ActiveRequest(Option<usize>); // Index into ACTIVE, or None when untracked
+
+impl ActiveRequest {
+    /// Registers `req_id` as in flight, starting the slot search at its sequence number so
+    /// concurrent workers rarely contend for the same slot
+    pub fn track(req_id: RequestId) -> Self {
+        let (seq, token) = req_id.to_raw();
+        let start = seq as usize % ACTIVE_SLOTS;
+        for offset in 0..ACTIVE_SLOTS {
+            let index = (start + offset) % ACTIVE_SLOTS;
+            let slot = &ACTIVE[index];
+            if slot
+                .state
+                .compare_exchange(FREE, CLAIMED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                fence(Ordering::Release);
+                slot.token_high
+                    .store((token >> 64) as u64, Ordering::Relaxed);
+                slot.token_low.store(token as u64, Ordering::Relaxed);
+                slot.state.store(seq + 1, Ordering::Release);
+                return ActiveRequest(Some(index));
+            }
+        }
+        UNTRACKED.fetch_add(1, Ordering::Relaxed);
+        ActiveRequest(None)
+    }
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        match self.0 {
+            Some(index) => ACTIVE[index].state.store(FREE, Ordering::Release),
+            None => {
+                UNTRACKED.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Enables crash reports and installs a panic hook that writes one before the default hook runs
+pub fn install(config: &ServerConfig) {
+    let _ = REPORTER.set(Reporter {
+        dir: PathBuf::from(&config.logging.crash_report_dir),
+        config_summary: config.summary(),
+    });
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        write_report(&format!("panic: {}", info));
+        logging::flush();
+        default_hook(info);
+    }));
+}
+
+/// Logs a fatal error, writes a crash report, and exits
+pub fn fatal(message: &str) -> ! {
+    server_log!(Error, "{}", message);
+    logging::flush();
+    write_report(message);
+    process::exit(1);
+}
+
+/// Lists in-flight requests as "seq token", one per line in sequence order
+fn format_active() -> String {
+    let mut active: Vec<RequestId> = ACTIVE.iter().filter_map(Slot::read).collect();
+    active.sort();
+    let mut lines: Vec<String> = active
+        .iter()
+        .map(|id| format!("{} {}", id.seq(), id.token()))
+        .collect();
+    let untracked = UNTRACKED.load(Ordering::Relaxed);
+    if untracked > 0 {
+        lines.push(format!("({} more not tracked)", untracked));
+    }
+    lines.join("\n")
+}
+
+/// Writes a crash report file, returning its path; never blocks on locks held by the crashing thread
+fn write_report(reason: &str) -> Option<PathBuf> {
+    REPORTER.get()?.write(reason)
+}
+
+impl Reporter {
+    /// Writes one report into `dir`
+    fn write(&self, reason: &str) -> Option<PathBuf> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut report = String::new();
+        let _ = writeln!(
+            report,
+            "{} {} crash report",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        );
+        let _ = writeln!(report, "time: {}", now.as_secs());
+        let _ = writeln!(report, "pid: {}", process::id());
+        let _ = writeln!(report, "reason: {}", reason);
+        let _ = writeln!(report, "\n[config]\n{}", self.config_summary);
+
+        let _ = writeln!(report, "\n[active requests]\n{}", format_active());
+
+        let _ = writeln!(report, "\n[recent log]");
+        for record in logging::try_recent() {
+            let _ = writeln!(report, "{}", record);
+        }
+
+        let path = self
+            .dir
+            .join(format!("crash-{}-{}.txt", now.as_secs(), process::id()));
+        let written = fs::create_dir_all(&self.dir).and_then(|()| fs::write(&path, report));
+        match written {
+            Ok(()) => {
+                eprintln!("Crash report written to {}", path.display());
+                Some(path)
+            }
+            Err(e) => {
+                eprintln!("Failed to write crash report {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{request::RequestIdGenerator, test_support::TempRoot};
+
+    #[test]
+    fn test_report_lists_config_and_active_requests() {
+        let root = TempRoot::new("crash-report");
+        let reporter = Reporter {
+            dir: root.join("crashes"),
+            config_summary: root.config().summary(),
+        };
+        let ids = RequestIdGenerator::new();
+        let (finished, active) = (ids.next(), ids.next());
+        drop(ActiveRequest::track(finished));
+        let _guard = ActiveRequest::track(active);
+
+        let path = reporter.write("panic: test").unwrap();
+        assert!(path.starts_with(root.join("crashes")));
+        let report = fs::read_to_string(&path).unwrap();
+
+        assert!(report.contains("reason: panic: test"));
+        let sections = ["[config]", "[active requests]", "[recent log]"].map(|s| report.find(s));
+        assert!(sections
+            .windows(2)
+            .all(|pair| pair[0] < pair[1] && pair[0].is_some()));
+        assert!(report.contains(&format!("\n{} {}\n", active.seq(), active.token())));
+        assert!(!report.contains(&finished.token()));
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Weekday names starting from Thursday, the weekday of 1970-01-01
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A UTC calendar date and time of day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Civil {
+    pub year: i64,
+    pub month: u32, // 1 to 12
+    pub day: u32,   // 1 to 31
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millis: u32,
+    weekday: usize, // Index into WEEKDAYS
+}
+
+impl Civil {
+    /// Returns the three-letter English month name, e.g. "Feb"
+    pub fn month_name(&self) -> &'static str {
+        MONTHS[self.month as usize - 1]
+    }
+}
+
+/// Splits a time into its UTC calendar date and time of day; times before 1970 read as 1970
+pub fn civil(time: SystemTime) -> Civil {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01, after Howard Hinnant's days_from_civil inverse
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    Civil {
+        year,
+        month: month as u32,
+        day: day as u32,
+        hour: (secs_of_day / 3600) as u32,
+        minute: (secs_of_day % 3600 / 60) as u32,
+        second: (secs_of_day % 60) as u32,
+        millis: since_epoch.subsec_millis(),
+        weekday: (days % 7) as usize,
+    }
+}
+
+/// Formats a time as an HTTP-date in IMF-fixdate form, e.g. Sun, 06 Nov 1994 08:49:37 GMT
+pub fn http_date(time: SystemTime) -> String {
+    let c = civil(time);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[c.weekday],
+        c.day,
+        c.month_name(),
+        c.year,
+        c.hour,
+        c.minute,
+        c.second
+    )
+}
+
+/// Formats a time as an RFC 3339 UTC timestamp with milliseconds, e.g. 2024-05-01T12:00:00.250Z
+pub fn rfc3339(time: SystemTime) -> String {
+    let c = civil(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        c.year, c.month, c.day, c.hour, c.minute, c.second, c.millis
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_http_date_uses_imf_fixdate() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(http_date(at(784_111_777)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date(at(0)), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(
+            http_date(at(1_709_210_096)),
+            "Thu, 29 Feb 2024 12:34:56 GMT"
+        );
+    }
+}
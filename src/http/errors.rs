@@ -1,9 +1,50 @@
 use crate::http::{
-    request::{HttpVersion},
+    json,
+    request::{HttpRequest, HttpVersion, RequestId},
     response::{self, ContentNegotiable},
     writer::{HttpBody, HttpWritable},
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Write};
+
+/// Content-Type of RFC 9457 problem details, sent to clients that prefer JSON
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Represents a failed request; the router turns it into an error response
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: response::HttpStatusCode,
+    pub message: String,
+    pub headers: HashMap<String, String>, // Extra headers, e.g. WWW-Authenticate
+}
+
+impl HttpError {
+    /// Creates an error with the given status and client-facing message
+    pub fn new(status: response::HttpStatusCode, message: impl Into<String>) -> Self {
+        HttpError {
+            status,
+            message: message.into(),
+            headers: HashMap::new(),
+        }
+    }
+
+    /// Adds a header to the error response
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+/// Appends the request's token to a server error message, so a user reporting it can quote the id
+pub fn with_reference(
+    status: &response::HttpStatusCode,
+    message: String,
+    req_id: RequestId,
+) -> String {
+    if status.code() < 500 {
+        return message;
+    }
+    format!("{} (reference {})", message, req_id.token())
+}
 
 /// Represents an HTTP error response
 pub struct HttpErrorResponse {
@@ -24,6 +65,7 @@ impl ContentNegotiable for HttpErrorResponse {
         let content_text = match content {
             HttpBody::Text(text) => text,
             HttpBody::Binary(bin) => String::from_utf8_lossy(&bin).to_string(),
+            HttpBody::Stream(stream) => String::from_utf8_lossy(&stream.read_to_vec()).to_string(),
         };
 
         HttpErrorResponse::new(
@@ -90,14 +132,76 @@ impl HttpWritable for HttpErrorResponse {
 }
 
 impl HttpErrorResponse {
+    /// Replaces the body with a site's custom HTML error page, when it has one
+    pub fn with_page(mut self, page: Option<String>) -> HttpErrorResponse {
+        if let Some(page) = page {
+            self.headers.insert(
+                "Content-Type".to_string(),
+                response::HttpContentType::Html.to_string(),
+            );
+            self.headers
+                .insert("content-length".to_string(), page.len().to_string());
+            self.body = Some(HttpBody::Text(page));
+        }
+        self
+    }
+
     /// Creates a new HttpErrorResponse based on the status code, accept header, and message
+    ///
+    /// Recoverable errors keep an HTTP/1.1 connection alive unless the client asked to close it;
+    /// protocol errors always announce `Connection: close`
     pub fn new(
         status_code: response::HttpStatusCode,
         version: HttpVersion,
-        _connection_header: &str,
+        connection_header: &str,
         accept_header: Option<&str>,
         message: String,
     ) -> HttpErrorResponse {
+        Self::render(
+            status_code,
+            version,
+            connection_header,
+            accept_header,
+            message,
+            None,
+        )
+    }
+
+    /// Creates the error response for a parsed request, whose path becomes the `instance` of
+    /// JSON problem details
+    pub fn for_request(
+        status_code: response::HttpStatusCode,
+        request: &HttpRequest,
+        message: String,
+    ) -> HttpErrorResponse {
+        let header = |name: &str| {
+            request
+                .headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+        Self::render(
+            status_code,
+            request.status_line.version.clone(),
+            header("Connection").unwrap_or_default(),
+            header("Accept"),
+            message,
+            Some(&request.status_line.path),
+        )
+    }
+
+    fn render(
+        status_code: response::HttpStatusCode,
+        version: HttpVersion,
+        connection_header: &str,
+        accept_header: Option<&str>,
+        message: String,
+        instance: Option<&str>,
+    ) -> HttpErrorResponse {
+        let keep_alive = version == HttpVersion::Http1_1
+            && !connection_header.eq_ignore_ascii_case("close")
+            && !status_code.closes_connection();
         let status_line = response::ResponseStatusLine {
             version,
             status: status_code.clone(),
@@ -112,10 +216,7 @@ impl HttpErrorResponse {
             response::HttpContentType::Html => {
                 format!("<h1>{}</h1><p>{}</p>", status_code, message)
             }
-            response::HttpContentType::Json => format!(
-                r#"{{"error": "{}", "code": {}}}"#,
-                message, status_code as u16
-            ),
+            response::HttpContentType::Json => problem_json(&status_code, &message, instance),
             response::HttpContentType::PlainText => message,
             response::HttpContentType::OctetStream => String::new(),
         };
@@ -126,18 +227,23 @@ impl HttpErrorResponse {
             Some(HttpBody::Text(body_text))
         };
 
-        let headers = HashMap::from([
-            ("Content-Type".to_string(), accepted_type.to_string()),
+        let content_type = match accepted_type {
+            response::HttpContentType::Json => PROBLEM_JSON.to_string(),
+            _ => accepted_type.to_string(),
+        };
+        let mut headers = HashMap::from([
+            ("Content-Type".to_string(), content_type),
             (
                 "content-length".to_string(),
                 body.as_ref()
-                    .map_or("0".to_string(), |b| match b {
-                        HttpBody::Text(t) => t.len().to_string(),
-                        HttpBody::Binary(bin) => bin.len().to_string(),
-                    }),
+                    .map_or("0".to_string(), |b| b.byte_len().to_string()),
+            ),
+            (
+                "Connection".to_string(),
+                if keep_alive { "keep-alive" } else { "close" }.to_string(),
             ),
-            ("Connection".to_string(), "close".to_string()),
         ]);
+        response::append_vary(&mut headers, "Accept");
 
         HttpErrorResponse {
             status_line,
@@ -146,3 +252,87 @@ impl HttpErrorResponse {
         }
     }
 }
+
+/// Formats RFC 9457 problem details; the `about:blank` type means the status alone says what
+/// went wrong, so the title is its reason phrase
+fn problem_json(status: &response::HttpStatusCode, detail: &str, instance: Option<&str>) -> String {
+    let mut body = format!(
+        "{{\"type\":\"about:blank\",\"title\":{},\"status\":{}",
+        json::quote(status.reason()),
+        status.code()
+    );
+    if !detail.is_empty() {
+        let _ = write!(body, ",\"detail\":{}", json::quote(detail));
+    }
+    if let Some(instance) = instance {
+        let _ = write!(body, ",\"instance\":{}", json::quote(instance));
+    }
+    body.push('}');
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::response::HttpStatusCode;
+
+    #[test]
+    fn test_json_clients_get_problem_details() {
+        let request =
+            HttpRequest::parse(b"GET /files/a.txt HTTP/1.1\r\nAccept: application/json\r\n\r\n")
+                .unwrap();
+        let response = HttpErrorResponse::for_request(
+            HttpStatusCode::NotFound,
+            &request,
+            "No \"a.txt\" here".to_string(),
+        );
+        assert_eq!(response.headers["Content-Type"], "application/problem+json");
+        assert_eq!(
+            response.body().to_string(),
+            "{\"type\":\"about:blank\",\"title\":\"Not Found\",\"status\":404,\
+             \"detail\":\"No \\\"a.txt\\\" here\",\"instance\":\"/files/a.txt\"}"
+        );
+    }
+
+    #[test]
+    fn test_request_headers_are_matched_case_insensitively() {
+        let request = HttpRequest::parse(
+            b"GET /a HTTP/1.1\r\naccept: application/json\r\nCONNECTION: close\r\n\r\n",
+        )
+        .unwrap();
+        let response =
+            HttpErrorResponse::for_request(HttpStatusCode::NotFound, &request, String::new());
+        assert_eq!(response.headers["Content-Type"], "application/problem+json");
+        assert_eq!(response.headers["Connection"], "close");
+    }
+
+    #[test]
+    fn test_only_protocol_errors_close_the_connection() {
+        let connection = |status, version, header| {
+            HttpErrorResponse::new(status, version, header, None, String::new()).headers
+                ["Connection"]
+                .clone()
+        };
+
+        assert_eq!(
+            connection(HttpStatusCode::NotFound, HttpVersion::Http1_1, ""),
+            "keep-alive"
+        );
+        assert_eq!(
+            connection(HttpStatusCode::NotFound, HttpVersion::Http1_1, "Close"),
+            "close"
+        );
+        assert_eq!(
+            connection(HttpStatusCode::NotFound, HttpVersion::Http1_0, ""),
+            "close"
+        );
+        assert_eq!(
+            connection(
+                HttpStatusCode::BadRequest,
+                HttpVersion::Http1_1,
+                "keep-alive"
+            ),
+            "close"
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::Deserialize;
+
+use crate::http::{files::etag, logging::server_log};
+
+const DEFAULT_MAX_BYTES: usize = 32 * 1024 * 1024;
+const DEFAULT_MAX_ENTRY_BYTES: usize = 1024 * 1024;
+const DEFAULT_MAX_STALE_MS: u64 = 5_000;
+
+/// In-memory file cache settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileCacheConfig {
+    /// Total bytes of file content kept in memory (0 disables the cache)
+    pub max_bytes: usize,
+    /// Files larger than this are always read from disk
+    pub max_entry_bytes: usize,
+    /// How long an entry is served without checking the file (0 checks on every request)
+    pub ttl_ms: u64,
+    /// Serve entries past their TTL immediately and recheck the file in the background
+    pub stale_while_revalidate: bool,
+    /// How far past its TTL an entry may still be served stale; older entries are checked inline
+    pub max_stale_ms: u64,
+}
+
+impl Default for FileCacheConfig {
+    fn default() -> Self {
+        FileCacheConfig {
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_entry_bytes: DEFAULT_MAX_ENTRY_BYTES,
+            ttl_ms: 0,
+            stale_while_revalidate: false,
+            max_stale_ms: DEFAULT_MAX_STALE_MS,
+        }
+    }
+}
+
+/// Keeps the contents of small, frequently served files in memory
+///
+/// Entries are keyed by the canonical path `resolve_path` returns; when the cache is full, the
+/// least recently served one is evicted first. An entry is only used while the file's size and modification time still match what was read;
+/// changed files are reloaded from disk. Within the TTL an entry is served without checking, and
+/// in stale-while-revalidate mode a stale entry is served while a background thread checks it.
+/// Clones share the same entries
+#[derive(Debug, Clone)]
+pub struct FileCache {
+    max_bytes: usize,
+    max_entry_bytes: usize,
+    ttl: Duration,
+    max_stale: Option<Duration>, // Set in stale-while-revalidate mode
+    state: Arc<Mutex<CacheState>>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<PathBuf, Entry>,
+    recency: BTreeMap<u64, PathBuf>, // Last-use tick -> path; the first is evicted next
+    tick: u64,
+    used: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    bytes: Arc<[u8]>,
+    etag: Arc<str>, // Derived from `bytes`, so it changes whenever they are reread differently
+    modified: SystemTime,
+    checked: Instant,   // When the file was last compared against this entry
+    revalidating: bool, // A background check is already running
+    last_used: u64,     // Its key in `recency`
+}
+
+/// Contents served from the cache, with their ETag
+struct Cached {
+    bytes: Arc<[u8]>,
+    etag: Arc<str>,
+}
+
+impl From<&Entry> for Cached {
+    fn from(entry: &Entry) -> Self {
+        Cached {
+            bytes: Arc::clone(&entry.bytes),
+            etag: Arc::clone(&entry.etag),
+        }
+    }
+}
+
+impl CacheState {
+    /// Marks an entry as just served, moving it to the back of the eviction order
+    fn touch(&mut self, path: &Path) {
+        let Some(entry) = self.entries.get_mut(path) else {
+            return;
+        };
+        self.tick += 1;
+        self.recency.remove(&entry.last_used);
+        entry.last_used = self.tick;
+        self.recency.insert(self.tick, path.to_path_buf());
+    }
+
+    fn remove(&mut self, path: &Path) -> Option<Entry> {
+        let entry = self.entries.remove(path)?;
+        self.recency.remove(&entry.last_used);
+        self.used -= entry.bytes.len();
+        Some(entry)
+    }
+}
+
+impl FileCache {
+    /// Creates an empty cache
+    pub fn new(config: &FileCacheConfig) -> Self {
+        FileCache {
+            max_bytes: config.max_bytes,
+            max_entry_bytes: config.max_entry_bytes.min(config.max_bytes),
+            ttl: Duration::from_millis(config.ttl_ms),
+            max_stale: config
+                .stale_while_revalidate
+                .then(|| Duration::from_millis(config.max_stale_ms)),
+            state: Arc::new(Mutex::new(CacheState::default())),
+        }
+    }
+
+    /// Returns a file's contents from memory, reading and caching them first if needed
+    ///
+    /// Returns `Ok(None)` for files too large to cache, which the caller should read from disk
+    pub fn load(&self, path: &Path) -> io::Result<Option<Arc<[u8]>>> {
+        Ok(self.load_tagged(path)?.map(|cached| cached.bytes))
+    }
+
+    /// Returns the ETag of a file's cached contents, reading and caching them first if needed
+    ///
+    /// Returns `Ok(None)` for files too large to cache, which are tagged from their metadata
+    pub fn etag(&self, path: &Path) -> io::Result<Option<String>> {
+        Ok(self
+            .load_tagged(path)?
+            .map(|cached| cached.etag.to_string()))
+    }
+
+    fn load_tagged(&self, path: &Path) -> io::Result<Option<Cached>> {
+        if let Some(cached) = self.lookup(path) {
+            return Ok(Some(cached));
+        }
+        self.revalidate(path)
+    }
+
+    /// Returns an entry that can be served without checking the file: one within its TTL, or a
+    /// stale one within `max_stale`, which also starts a background check
+    fn lookup(&self, path: &Path) -> Option<Cached> {
+        let mut state = self.lock();
+        state.touch(path);
+        let entry = state.entries.get_mut(path)?;
+        let age = entry.checked.elapsed();
+        if age < self.ttl {
+            return Some(Cached::from(&*entry));
+        }
+        if age >= self.ttl + self.max_stale? {
+            return None;
+        }
+
+        if !entry.revalidating {
+            entry.revalidating = true;
+            let (cache, path) = (self.clone(), path.to_path_buf());
+            thread::spawn(move || {
+                if let Err(e) = cache.revalidate(&path) {
+                    server_log!(Debug, "[file-cache] revalidating {:?} failed: {}", path, e);
+                }
+            });
+        }
+        Some(Cached::from(&*entry))
+    }
+
+    /// Checks the file against its entry, rereading it if it changed
+    fn revalidate(&self, path: &Path) -> io::Result<Option<Cached>> {
+        let result = self.check(path);
+        if !matches!(result, Ok(Some(_))) {
+            // Missing, unreadable, or grown too large: never serve the old entry again
+            self.remove(path);
+        }
+        result
+    }
+
+    fn check(&self, path: &Path) -> io::Result<Option<Cached>> {
+        let metadata = fs::metadata(path)?;
+        if metadata.len() > self.max_entry_bytes as u64 {
+            return Ok(None);
+        }
+        let modified = metadata.modified()?;
+
+        let mut state = self.lock();
+        if let Some(entry) = state.entries.get_mut(path) {
+            if entry.modified == modified && entry.bytes.len() as u64 == metadata.len() {
+                entry.checked = Instant::now();
+                entry.revalidating = false;
+                return Ok(Some(Cached::from(&*entry)));
+            }
+        }
+        drop(state);
+
+        // Read and hash without holding the lock so a slow disk never blocks hits on other files
+        let bytes: Arc<[u8]> = fs::read(path)?.into();
+        let cached = Cached {
+            etag: etag::content_etag(&bytes).into(),
+            bytes,
+        };
+        if cached.bytes.len() <= self.max_entry_bytes {
+            self.insert(path, &cached, modified);
+        }
+        Ok(Some(cached))
+    }
+
+    /// Drops a file's cached contents, e.g. after it was written; returns whether it was cached
+    pub fn remove(&self, path: &Path) -> bool {
+        self.lock().remove(path).is_some()
+    }
+
+    /// Drops every cached file, returning how many there were
+    pub fn clear(&self) -> usize {
+        let mut state = self.lock();
+        let count = state.entries.len();
+        *state = CacheState::default();
+        count
+    }
+
+    fn insert(&self, path: &Path, cached: &Cached, modified: SystemTime) {
+        let bytes = Arc::clone(&cached.bytes);
+        let mut state = self.lock();
+        state.remove(path);
+
+        while state.used + bytes.len() > self.max_bytes {
+            let Some(coldest) = state.recency.values().next().cloned() else {
+                break;
+            };
+            state.remove(&coldest);
+        }
+
+        state.tick += 1;
+        let last_used = state.tick;
+        state.used += bytes.len();
+        state.recency.insert(last_used, path.to_path_buf());
+        state.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                bytes,
+                etag: Arc::clone(&cached.etag),
+                modified,
+                checked: Instant::now(),
+                revalidating: false,
+                last_used,
+            },
+        );
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        // A panic mid-update can at worst leave a stale entry, which the next stat check replaces
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::test_support::TempRoot;
+
+    #[test]
+    fn test_cache_reloads_changed_files_and_evicts_oldest() {
+        let dir = TempRoot::new("cache");
+        let (a, b) = (dir.write("a.txt", "aaaa"), dir.write("b.txt", "bbbb"));
+
+        let cache = FileCache::new(&FileCacheConfig {
+            max_bytes: 6,
+            max_entry_bytes: 6,
+            ..Default::default()
+        });
+        assert_eq!(&*cache.load(&a).unwrap().unwrap(), b"aaaa");
+        fs::write(&a, "aaaaa").unwrap();
+        assert_eq!(&*cache.load(&a).unwrap().unwrap(), b"aaaaa");
+
+        assert_eq!(&*cache.load(&b).unwrap().unwrap(), b"bbbb");
+        assert!(!cache.lock().entries.contains_key(&a));
+        assert_eq!(cache.lock().used, 4);
+
+        fs::write(&a, "too large").unwrap();
+        assert!(cache.load(&a).unwrap().is_none());
+
+        assert!(cache.remove(&b));
+        assert!(!cache.remove(&b));
+        cache.load(&b).unwrap();
+        assert_eq!(cache.clear(), 1);
+        assert_eq!(cache.lock().used, 0);
+    }
+
+    #[test]
+    fn test_least_recently_served_is_evicted_first() {
+        let dir = TempRoot::new("lru");
+        let [a, b, c] = ["a.css", "b.css", "c.css"].map(|name| dir.write(name, "1234"));
+
+        let cache = FileCache::new(&FileCacheConfig {
+            max_bytes: 8,
+            max_entry_bytes: 4,
+            ..Default::default()
+        });
+        cache.load(&a).unwrap();
+        cache.load(&b).unwrap();
+        cache.load(&a).unwrap();
+        cache.load(&c).unwrap();
+
+        let state = cache.lock();
+        assert!(state.entries.contains_key(&a));
+        assert!(!state.entries.contains_key(&b));
+        assert_eq!(state.recency.len(), 2);
+        assert_eq!(state.used, 8);
+    }
+
+    #[test]
+    fn test_stale_entries_are_served_while_revalidating() {
+        let dir = TempRoot::new("swr");
+        let path = dir.write("page.html", "old");
+
+        let cache = FileCache::new(&FileCacheConfig {
+            stale_while_revalidate: true,
+            max_stale_ms: 60_000,
+            ..Default::default()
+        });
+        assert_eq!(&*cache.load(&path).unwrap().unwrap(), b"old");
+        fs::write(&path, "newer").unwrap();
+        assert_eq!(&*cache.load(&path).unwrap().unwrap(), b"old");
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while &*cache.load(&path).unwrap().unwrap() != b"newer" {
+            assert!(
+                Instant::now() < deadline,
+                "background revalidation never finished"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{fs::Metadata, time::UNIX_EPOCH};
+
+use crate::http::shard::fnv1a;
+
+/// Builds a weak ETag from a file's size and modification time, without reading its contents
+///
+/// Weak because a file rewritten within the same timestamp keeps its tag; returns `None` when the
+/// platform reports no modification time
+pub fn weak_etag(metadata: &Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "W/\"{:x}-{:x}.{:x}\"",
+        metadata.len(),
+        modified.as_secs(),
+        modified.subsec_nanos()
+    ))
+}
+
+/// Builds a weak ETag from a file's contents, for files small enough to be held in memory
+///
+/// Unlike `weak_etag`, content rewritten with its old size and timestamp gets a new tag once it
+/// is reread
+pub fn content_etag(bytes: &[u8]) -> String {
+    format!("W/\"{:x}-{:016x}\"", bytes.len(), fnv1a(bytes))
+}
+
+/// Checks whether an If-None-Match header names `etag`, using the weak comparison RFC 9110
+/// requires for it: `W/` prefixes are ignored and `*` matches any tag
+pub fn none_match(header: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    header.trim() == "*" || header.split(',').any(|tag| opaque(tag) == opaque(etag))
+}
+
+/// Derives the ETag of a compressed variant, e.g. `W/"1a-5f.0"` -> `W/"1a-5f.0-gzip"`
+pub fn for_encoding(etag: &str, encoding: &str) -> String {
+    match etag.strip_suffix('"') {
+        Some(open) => format!("{}-{}\"", open, encoding),
+        None => format!("{}-{}", etag, encoding),
+    }
+}
+
+/// Rewrites the tags of `encoding`'s variant in an If-None-Match header back to the tags they
+/// were derived from; returns `None` when no tag belongs to that variant
+pub fn strip_encoding(header: &str, encoding: &str) -> Option<String> {
+    let suffix = format!("-{}\"", encoding);
+    let mut found = false;
+    let tags: Vec<String> = header
+        .split(',')
+        .map(|tag| match tag.trim().strip_suffix(&suffix) {
+            Some(open) => {
+                found = true;
+                format!("{}\"", open)
+            }
+            None => tag.trim().to_string(),
+        })
+        .collect();
+    found.then(|| tags.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_if_none_match_uses_weak_comparison() {
+        let etag = "W/\"1a-5f.0\"";
+        assert!(none_match("W/\"1a-5f.0\"", etag));
+        assert!(none_match("\"other\", \"1a-5f.0\"", etag));
+        assert!(none_match(" * ", etag));
+        assert!(!none_match("W/\"1a-5f.1\"", etag));
+        assert!(!none_match("", etag));
+    }
+
+    #[test]
+    fn test_encoding_variants_map_back_to_their_base_tag() {
+        let gzip = for_encoding("W/\"1a-5f.0\"", "gzip");
+        assert_eq!(gzip, "W/\"1a-5f.0-gzip\"");
+        assert_eq!(
+            strip_encoding(&format!("\"x\", {}", gzip), "gzip").as_deref(),
+            Some("\"x\", W/\"1a-5f.0\"")
+        );
+        assert_eq!(strip_encoding(&gzip, "deflate"), None);
+    }
+}
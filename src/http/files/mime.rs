@@ -1,25 +1,264 @@
-const TEXT_EXTENSIONS: &[&str] = &["txt", "html", "json", "js", "css", "xml"];
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::Read,
+    path::Path,
+    sync::OnceLock,
+};
 
-/// Returns true if the given file extension is a text extension.
+use crate::http::response::charset::{self, Charset};
+
+/// Archive and font types whose contents are already compressed
+const COMPRESSED_TYPES: &[&str] = &[
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/vnd.rar",
+    "application/zstd",
+    "font/woff",
+    "font/woff2",
+];
+
+/// Returns true if the given file extension is a text extension, judged by its registered type.
 pub fn is_text_extension(extension: &str) -> bool {
-    TEXT_EXTENSIONS.contains(&extension.to_lowercase().as_str())
-}
-
-/// Returns the MIME type for a given file extension.
-pub fn mime_type_from_extension(extension: &str) -> &str {
-    match extension.to_lowercase().as_str() {
-        "txt" => "text/plain",
-        "html" => "text/html",
-        "json" => "application/json",
-        "js" => "application/javascript",
-        "css" => "text/css",
-        "xml" => "application/xml",
-        "mp4" => "video/mp4",
-        "webm" => "video/webm",
-        "jpg" => "image/jpeg",
-        "png" => "image/png",
-        "jpeg" => "image/jpeg",
-        "gif" => "image/gif",
-        _ => "application/octet-stream",
+    lookup(extension).is_some_and(charset::is_text)
+}
+
+/// Types served for common extensions; `[mime_types]` in the config adds to and overrides these
+const DEFAULT_TYPES: &[(&str, &str)] = &[
+    ("txt", "text/plain; charset=utf-8"),
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("json", "application/json; charset=utf-8"),
+    ("js", "application/javascript; charset=utf-8"),
+    ("mjs", "application/javascript; charset=utf-8"),
+    ("map", "application/json; charset=utf-8"),
+    ("xml", "application/xml; charset=utf-8"),
+    ("webmanifest", "application/manifest+json; charset=utf-8"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("m3u8", "application/vnd.apple.mpegurl"),
+    ("ts", "video/mp2t"),
+    ("m4s", "video/iso.segment"),
+    ("aac", "audio/aac"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+];
+
+/// Type sent for extensions the registry does not know
+pub const FALLBACK_TYPE: &str = "application/octet-stream";
+
+/// Leading bytes `sniff_file` reads
+const SNIFF_BYTES: u64 = 512;
+
+/// File signatures recognised by `sniff`, checked in order
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+];
+
+static REGISTRY: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Builds the extension registry from the defaults plus `extra`, whose entries win; only the
+/// first call takes effect
+///
+/// Text types in `extra` that name no charset are declared UTF-8, like the defaults
+pub fn init(extra: &BTreeMap<String, String>) {
+    let _ = REGISTRY.set(registry(extra));
+}
+
+fn registry(extra: &BTreeMap<String, String>) -> HashMap<String, String> {
+    let mut types: HashMap<String, String> = DEFAULT_TYPES
+        .iter()
+        .map(|(ext, mime)| (ext.to_string(), mime.to_string()))
+        .collect();
+    for (ext, mime) in extra {
+        let mime = if charset::is_text(mime) && charset::declared(mime).is_none() {
+            charset::with_charset(mime, Charset::Utf8)
+        } else {
+            mime.clone()
+        };
+        types.insert(ext.trim_start_matches('.').to_ascii_lowercase(), mime);
+    }
+    types
+}
+
+/// Returns the MIME type for a given file extension, with `charset=utf-8` on text types.
+pub fn mime_type_from_extension(extension: &str) -> &'static str {
+    lookup(extension).unwrap_or(FALLBACK_TYPE)
+}
+
+/// Returns the MIME type registered for an extension, or `None` for an unknown one
+pub fn lookup(extension: &str) -> Option<&'static str> {
+    let extension = extension.to_ascii_lowercase();
+    match REGISTRY.get() {
+        Some(types) => types.get(&extension).map(String::as_str),
+        None => DEFAULT_TYPES
+            .iter()
+            .find(|(ext, _)| *ext == extension)
+            .map(|(_, mime)| *mime),
+    }
+}
+
+/// Guesses a Content-Type from a file's first bytes: a known signature, or UTF-8 text without
+/// binary control characters
+///
+/// Returns `None` when neither fits, including for an empty file
+pub fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    if let Some((_, mime)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+    {
+        return Some(mime);
+    }
+    if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        return Some("image/webp");
+    }
+
+    // A multi-byte character may be cut off where the sample ends
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return None,
+    };
+    let binary = text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'));
+    (!text.is_empty() && !binary).then_some("text/plain; charset=utf-8")
+}
+
+/// Reads the start of a file and sniffs its type; `None` when it cannot be read or recognised
+pub fn sniff_file(path: &Path) -> Option<&'static str> {
+    let mut sample = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(SNIFF_BYTES)
+        .read_to_end(&mut sample)
+        .ok()?;
+    sniff(&sample)
+}
+
+/// Returns true for Content-Types whose bodies are compressed already, such as images, audio,
+/// video, and archives, so encoding them again costs CPU and saves nothing
+pub fn is_compressed_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match mime.split_once('/') {
+        Some(("image", "svg+xml" | "bmp")) => false, // Text and raw pixels shrink well
+        Some(("image" | "audio" | "video", _)) => true,
+        _ => COMPRESSED_TYPES.contains(&mime.as_str()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_types_are_recognised() {
+        for compressed in [
+            "image/jpeg",
+            "video/mp4",
+            "audio/mpeg",
+            "application/zip",
+            "font/woff2",
+        ] {
+            assert!(is_compressed_type(compressed), "{}", compressed);
+        }
+        assert!(is_compressed_type("Image/PNG; charset=binary"));
+        for text in [
+            "text/html",
+            "application/json",
+            "image/svg+xml",
+            "application/octet-stream",
+        ] {
+            assert!(!is_compressed_type(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_registry_extends_and_overrides_the_defaults() {
+        let extra = BTreeMap::from([
+            (".GLB".to_string(), "model/gltf-binary".to_string()),
+            ("md".to_string(), "text/x-markdown".to_string()),
+        ]);
+        let types = registry(&extra);
+        assert_eq!(types["glb"], "model/gltf-binary");
+        assert_eq!(types["md"], "text/x-markdown; charset=utf-8");
+        assert_eq!(types["wasm"], "application/wasm");
+
+        assert_eq!(mime_type_from_extension("WOFF2"), "font/woff2");
+        assert_eq!(mime_type_from_extension("unknown"), FALLBACK_TYPE);
+    }
+
+    #[test]
+    fn test_sniff_recognises_signatures_and_text() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0\0\x10JFIF"), Some("image/jpeg"));
+        assert_eq!(sniff(b"GIF89a\x01\0"), Some("image/gif"));
+        assert_eq!(sniff(b"%PDF-1.7\n"), Some("application/pdf"));
+        assert_eq!(
+            sniff(b"\x7fELF\x02\x01\x01"),
+            Some("application/x-executable")
+        );
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WEBPVP8 "), Some("image/webp"));
+
+        assert_eq!(
+            sniff("héllo\r\n\tworld".as_bytes()),
+            Some("text/plain; charset=utf-8")
+        );
+        // Cut in the middle of "é"
+        assert_eq!(
+            sniff(&"café".as_bytes()[..4]),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(sniff(b"abc\0def"), None);
+        assert_eq!(sniff(b"\xff\xfe\x00a"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn test_text_extensions_follow_the_registry() {
+        for text in ["txt", "HTM", "csv", "md", "mjs", "map", "webmanifest", "svg"] {
+            assert!(is_text_extension(text), "{}", text);
+        }
+        for binary in ["png", "wasm", "zip", "unknown"] {
+            assert!(!is_text_extension(binary), "{}", binary);
+        }
     }
 }
@@ -1,3 +1,9 @@
+pub mod cache;
+pub mod etag;
 pub mod mime;
+pub mod playlist;
+pub mod precompressed;
 pub mod reader;
 pub mod types;
+#[cfg(feature = "thumbnails")]
+pub mod thumbnail;
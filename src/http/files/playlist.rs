@@ -0,0 +1,70 @@
+use std::{fmt::Write, fs, io, path::Path};
+
+/// Segment duration (in seconds) advertised when the real duration is unknown
+pub const DEFAULT_SEGMENT_DURATION: f32 = 10.0;
+
+/// Name under which the generated playlist is served inside a segment directory
+pub const PLAYLIST_NAME: &str = "index.m3u8";
+
+/// Extensions recognized as HLS media segments
+const SEGMENT_EXTENSIONS: &[&str] = &["ts", "m4s", "aac", "mp3"];
+
+/// Returns true if the given file name looks like an HLS media segment
+pub fn is_segment(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SEGMENT_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Generates a VOD m3u8 playlist listing every segment in `dir`, in natural order
+pub fn generate_playlist(dir: &Path, segment_duration: f32) -> io::Result<String> {
+    let mut segments: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| is_segment(name))
+        .collect();
+
+    if segments.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "directory contains no media segments",
+        ));
+    }
+
+    segments.sort_by_key(|name| natural_key(name));
+
+    let mut playlist = String::new();
+    // Writing to a String cannot fail
+    let _ = writeln!(playlist, "#EXTM3U");
+    let _ = writeln!(playlist, "#EXT-X-VERSION:3");
+    let _ = writeln!(
+        playlist,
+        "#EXT-X-TARGETDURATION:{}",
+        segment_duration.ceil() as u32
+    );
+    let _ = writeln!(playlist, "#EXT-X-MEDIA-SEQUENCE:0");
+    let _ = writeln!(playlist, "#EXT-X-PLAYLIST-TYPE:VOD");
+    for segment in &segments {
+        let _ = writeln!(playlist, "#EXTINF:{:.3},", segment_duration);
+        let _ = writeln!(playlist, "{}", segment);
+    }
+    let _ = writeln!(playlist, "#EXT-X-ENDLIST");
+
+    Ok(playlist)
+}
+
+/// Splits a name into its non-numeric prefix and trailing number so `seg10` sorts after `seg9`
+fn natural_key(name: &str) -> (String, u64, String) {
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let digits_start = stem.len()
+        - stem
+            .chars()
+            .rev()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+    let number = stem[digits_start..].parse::<u64>().unwrap_or(0);
+
+    (stem[..digits_start].to_string(), number, name.to_string())
+}
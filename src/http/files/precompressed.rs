@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+
+use crate::http::routes::HttpEncoding;
+
+/// Content codings a file may be stored precompressed in, with the suffix of each sibling
+const VARIANTS: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+/// What a file's precompressed siblings offer one request
+#[derive(Debug, Default)]
+pub struct Lookup {
+    /// Whether any sibling exists, so the response depends on Accept-Encoding
+    pub varies: bool,
+    /// The sibling to send instead of the file, and its Content-Encoding
+    pub chosen: Option<(PathBuf, &'static str)>,
+}
+
+/// Looks for `app.js.br` and `app.js.gz` next to `app.js`, choosing the one the client
+/// prefers among those it accepts
+pub fn lookup(path: &Path, accept_encoding: Option<&str>) -> Lookup {
+    let siblings: Vec<(&'static str, PathBuf)> = VARIANTS
+        .iter()
+        .map(|&(coding, suffix)| {
+            let mut sibling = path.as_os_str().to_owned();
+            sibling.push(suffix);
+            (coding, PathBuf::from(sibling))
+        })
+        .filter(|(_, sibling)| sibling.is_file())
+        .collect();
+
+    let chosen = accept_encoding
+        .map(HttpEncoding::parse_accept_encoding)
+        .unwrap_or_default()
+        .into_iter()
+        .find_map(|(encoding, _)| {
+            let coding = match encoding {
+                HttpEncoding::Brotli => "br",
+                HttpEncoding::Gzip => "gzip",
+                HttpEncoding::Deflate | HttpEncoding::Identity => return None,
+            };
+            siblings.iter().find(|(c, _)| *c == coding)
+        })
+        .map(|(coding, sibling)| (sibling.clone(), *coding));
+
+    Lookup {
+        varies: !siblings.is_empty(),
+        chosen,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::test_support::TempRoot;
+
+    #[test]
+    fn test_lookup_picks_the_preferred_existing_sibling() {
+        let dir = TempRoot::new("precompressed");
+        let app = dir.write("app.js", "x");
+        dir.write("app.js.gz", "gz");
+
+        let chosen = |accept| lookup(&app, accept).chosen.map(|(_, coding)| coding);
+        assert_eq!(chosen(Some("br, gzip;q=0.5")), Some("gzip"));
+        assert_eq!(chosen(Some("deflate")), None);
+        assert_eq!(chosen(None), None);
+        assert!(lookup(&app, None).varies);
+
+        dir.write("app.js.br", "br");
+        assert_eq!(chosen(Some("gzip;q=0.5, br")), Some("br"));
+        assert_eq!(chosen(Some("gzip, br;q=0")), Some("gzip"));
+
+        assert!(!lookup(&dir.join("other.js"), Some("gzip")).varies);
+    }
+}
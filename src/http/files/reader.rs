@@ -1,14 +1,19 @@
 use super::{
+    cache::FileCache,
     mime::is_text_extension,
     types::{FileReadError, FileReadRequest, FileReadResult},
 };
-use crate::http::writer::HttpBody;
+use crate::http::writer::{BodyStream, HttpBody};
 use std::{
     fs::{self, File},
     io::{Read, Seek, SeekFrom},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
+/// Files and ranges at least this large are streamed from disk rather than read into memory
+pub const STREAM_MIN_SIZE: u64 = 1024 * 1024;
+
 /// Defines a trait for reading files.
 trait FileReader {
     /// Reads the file and returns its content as an HttpBody.
@@ -24,31 +29,115 @@ pub struct FullFileReader {
 impl FileReader for FullFileReader {
     fn read(&self) -> Result<HttpBody, FileReadError> {
         let read_bytes = fs::read(&self.path).map_err(FileReadError::NotFound)?;
-        let file_ext = self.path.extension().and_then(|ext| ext.to_str());
-        match file_ext {
-            Some(ext) => {
-                if is_text_extension(ext) {
-                    match String::from_utf8(read_bytes) {
-                        Ok(text) => Ok(HttpBody::Text(text)),
-                        Err(e) => Ok(HttpBody::Binary(e.into_bytes())),
-                    }
-                } else {
-                    Ok(HttpBody::Binary(read_bytes))
-                }
-            }
-            None => Ok(HttpBody::Binary(read_bytes)),
+        Ok(body_from_bytes(&self.path, read_bytes))
+    }
+}
+
+/// Wraps file contents as text for text extensions that hold valid UTF-8, and as binary otherwise
+fn body_from_bytes(path: &Path, bytes: Vec<u8>) -> HttpBody {
+    let file_ext = path.extension().and_then(|ext| ext.to_str());
+    match file_ext {
+        Some(ext) if is_text_extension(ext) => match String::from_utf8(bytes) {
+            Ok(text) => HttpBody::Text(text),
+            Err(e) => HttpBody::Binary(e.into_bytes()),
+        },
+        _ => HttpBody::Binary(bytes),
+    }
+}
+
+/// A range of a cached file, sent straight from the cached bytes without copying them
+struct CachedRange {
+    bytes: Arc<[u8]>,
+    start: usize,
+    end: usize, // Exclusive
+}
+
+impl AsRef<[u8]> for CachedRange {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[self.start..self.end]
+    }
+}
+
+/// Represents a reader that streams part of a file from disk, one read per chunk
+#[cfg(not(feature = "mmap"))]
+pub struct StreamFileReader {
+    path: PathBuf,
+    start: u64, // First byte sent
+    len: u64,
+}
+
+#[cfg(not(feature = "mmap"))]
+impl FileReader for StreamFileReader {
+    fn read(&self) -> Result<HttpBody, FileReadError> {
+        let mut file = File::open(&self.path).map_err(FileReadError::NotFound)?;
+        file.seek(SeekFrom::Start(self.start))
+            .map_err(FileReadError::IoError)?;
+        Ok(HttpBody::Stream(BodyStream::from_file(file, self.len)))
+    }
+}
+
+/// Represents a reader that maps part of a file into memory, so it is written to the socket
+/// straight from the page cache rather than copied through a read buffer
+#[cfg(feature = "mmap")]
+pub struct MmapFileReader {
+    path: PathBuf,
+    start: u64, // First byte sent
+    len: u64,
+}
+
+#[cfg(feature = "mmap")]
+impl FileReader for MmapFileReader {
+    fn read(&self) -> Result<HttpBody, FileReadError> {
+        let file = File::open(&self.path).map_err(FileReadError::NotFound)?;
+        // SAFETY: the map is read-only and dropped once the response is written. If another
+        // process truncates the file meanwhile, touching the lost pages raises SIGBUS, so files
+        // must be replaced by rename rather than rewritten in place while this feature is on
+        let map = unsafe {
+            memmap2::MmapOptions::new()
+                .offset(self.start)
+                .len(self.len as usize)
+                .map(&file)
         }
+        .map_err(FileReadError::IoError)?;
+        Ok(HttpBody::Stream(BodyStream::from_bytes(map)))
     }
 }
 
+/// Reader for files and ranges of at least `STREAM_MIN_SIZE` bytes
+#[cfg(not(feature = "mmap"))]
+type LargeFileReader = StreamFileReader;
+#[cfg(feature = "mmap")]
+type LargeFileReader = MmapFileReader;
+
 /// Reads a file with range support and returns metadata
-pub fn read_file_with_range(request: FileReadRequest) -> Result<FileReadResult, FileReadError> {
+///
+/// Files small enough for the cache are served from memory, ranges included; larger files are
+/// read from disk, seeking straight to the requested range, and streamed once they reach
+/// `STREAM_MIN_SIZE` (from a memory map with the `mmap` feature)
+pub fn read_file_with_range(
+    request: FileReadRequest,
+    cache: &FileCache,
+) -> Result<FileReadResult, FileReadError> {
     match request {
         FileReadRequest::Full(path) => {
-            let file_reader = FullFileReader { path };
-            let body = file_reader.read()?;
+            let body = match cache.load(&path).map_err(FileReadError::NotFound)? {
+                Some(bytes) => HttpBody::Stream(BodyStream::from_bytes(bytes)),
+                None => {
+                    let len = fs::metadata(&path).map_err(FileReadError::NotFound)?.len();
+                    if len >= STREAM_MIN_SIZE {
+                        LargeFileReader {
+                            path,
+                            start: 0,
+                            len,
+                        }
+                        .read()?
+                    } else {
+                        FullFileReader { path }.read()?
+                    }
+                }
+            };
             let total_size = body.byte_len() as u64;
-            
+
             Ok(FileReadResult {
                 body,
                 total_size,
@@ -56,24 +145,41 @@ pub fn read_file_with_range(request: FileReadRequest) -> Result<FileReadResult,
             })
         }
         FileReadRequest::Range(path, range) => {
-            let metadata = fs::metadata(&path).map_err(FileReadError::IoError)?;
-            let file_size = metadata.len();
+            let cached = cache.load(&path).map_err(FileReadError::IoError)?;
+            let file_size = match &cached {
+                Some(bytes) => bytes.len() as u64,
+                None => fs::metadata(&path).map_err(FileReadError::IoError)?.len(),
+            };
 
-            if file_size == 0 {
-                return Err(FileReadError::InvalidRange);
-            }
+            let (start, end) = range
+                .resolve(file_size)
+                .ok_or(FileReadError::InvalidRange(file_size))?;
 
-            let start = range.start;
-            let end = range.end.unwrap_or(file_size - 1);
+            if let Some(bytes) = cached {
+                return Ok(FileReadResult {
+                    body: HttpBody::Stream(BodyStream::from_bytes(CachedRange {
+                        bytes,
+                        start: start as usize,
+                        end: end as usize + 1,
+                    })),
+                    total_size: file_size,
+                    range: Some((start, end)),
+                });
+            }
 
-            if start > end || end >= file_size {
-                return Err(FileReadError::InvalidRange);
+            let len = end - start + 1;
+            if len >= STREAM_MIN_SIZE {
+                return Ok(FileReadResult {
+                    body: LargeFileReader { path, start, len }.read()?,
+                    total_size: file_size,
+                    range: Some((start, end)),
+                });
             }
 
             let mut file = File::open(&path).map_err(FileReadError::IoError)?;
             file.seek(SeekFrom::Start(start))
                 .map_err(FileReadError::IoError)?;
-            let mut buffer = vec![0; (end - start + 1) as usize];
+            let mut buffer = vec![0; len as usize];
             file.read_exact(&mut buffer)
                 .map_err(FileReadError::IoError)?;
 
@@ -85,3 +191,41 @@ pub fn read_file_with_range(request: FileReadRequest) -> Result<FileReadResult,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{
+        files::{cache::FileCacheConfig, types::ByteRange},
+        test_support::TempRoot,
+    };
+
+    #[test]
+    fn test_large_files_and_ranges_are_streamed() {
+        let root = TempRoot::new("large");
+        let content: Vec<u8> = (0..2 * STREAM_MIN_SIZE).map(|i| (i % 251) as u8).collect();
+        let path = root.write("large.bin", &content);
+        let cache = FileCache::new(&FileCacheConfig::default());
+
+        let full = read_file_with_range(FileReadRequest::Full(path.clone()), &cache).unwrap();
+        let HttpBody::Stream(stream) = full.body else {
+            panic!("expected a streamed body");
+        };
+        assert_eq!(stream.read_to_vec(), content);
+
+        let range = ByteRange {
+            start: Some(3),
+            end: Some(STREAM_MIN_SIZE + 2),
+        };
+        let partial = read_file_with_range(FileReadRequest::Range(path.clone(), range), &cache);
+        let partial = partial.unwrap();
+        assert_eq!(partial.range, Some((3, STREAM_MIN_SIZE + 2)));
+        let HttpBody::Stream(stream) = partial.body else {
+            panic!("expected a streamed body");
+        };
+        assert_eq!(
+            stream.read_to_vec(),
+            &content[3..STREAM_MIN_SIZE as usize + 3]
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::UNIX_EPOCH,
+};
+
+use image::{imageops::FilterType, ImageFormat};
+
+/// Largest thumbnail width (in pixels) that can be requested
+pub const MAX_THUMBNAIL_WIDTH: u32 = 2048;
+
+/// Counter that keeps temporary names unique between concurrent misses in this process
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Represents an error that can occur while generating a thumbnail
+#[derive(Debug)]
+pub enum ThumbnailError {
+    InvalidWidth,        // Width missing, zero, or above MAX_THUMBNAIL_WIDTH
+    UnsupportedFormat,   // Extension is not a decodable image format
+    Decode(String),      // Source image could not be decoded
+    IoError(io::Error),  // Reading the source or writing the cache failed
+}
+
+impl From<io::Error> for ThumbnailError {
+    fn from(error: io::Error) -> Self {
+        ThumbnailError::IoError(error)
+    }
+}
+
+/// Parses the `w` query parameter into a validated thumbnail width
+pub fn parse_width(raw: Option<&str>) -> Result<u32, ThumbnailError> {
+    match raw.and_then(|w| w.parse::<u32>().ok()) {
+        Some(width) if width > 0 && width <= MAX_THUMBNAIL_WIDTH => Ok(width),
+        _ => Err(ThumbnailError::InvalidWidth),
+    }
+}
+
+/// Returns a cached thumbnail for `source`, generating it under `cache_dir` on a miss
+pub fn thumbnail_path(
+    source: &Path,
+    cache_dir: &Path,
+    width: u32,
+) -> Result<PathBuf, ThumbnailError> {
+    let format = source
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(ImageFormat::from_extension)
+        .ok_or(ThumbnailError::UnsupportedFormat)?;
+    let extension = format
+        .extensions_str()
+        .first()
+        .copied()
+        .ok_or(ThumbnailError::UnsupportedFormat)?;
+
+    let cached = cache_dir.join(format!("{}.{}", cache_key(source, width)?, extension));
+    if cached.is_file() {
+        return Ok(cached);
+    }
+
+    let image = image::open(source).map_err(|e| ThumbnailError::Decode(e.to_string()))?;
+    let thumbnail = if image.width() <= width {
+        image
+    } else {
+        let height = (u64::from(image.height()) * u64::from(width) / u64::from(image.width()))
+            .max(1) as u32;
+        image.resize_exact(width, height, FilterType::Triangle)
+    };
+
+    fs::create_dir_all(cache_dir)?;
+
+    // Write to a unique temporary name first so concurrent readers never see a partial
+    // file and concurrent misses for the same thumbnail never write into each other
+    let temp = cached.with_extension(format!(
+        "{}.{}-{}.tmp",
+        extension,
+        process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    if let Err(e) = thumbnail.save_with_format(&temp, format) {
+        let _ = fs::remove_file(&temp);
+        return Err(ThumbnailError::Decode(e.to_string()));
+    }
+    if let Err(e) = fs::rename(&temp, &cached) {
+        let _ = fs::remove_file(&temp);
+        return Err(e.into());
+    }
+
+    Ok(cached)
+}
+
+/// Builds a cache key from the canonical source path, its modification time, and the width
+fn cache_key(source: &Path, width: u32) -> Result<String, ThumbnailError> {
+    let modified = fs::metadata(source)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    modified.hash(&mut hasher);
+
+    Ok(format!("{:016x}-w{}", hasher.finish(), width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::test_support::TempRoot;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_misses_leave_one_thumbnail_and_no_temp_files() {
+        let root = TempRoot::new("thumbnail-race");
+        let source = root.join("photo.png");
+        image::RgbImage::new(64, 32).save(&source).unwrap();
+        let cache_dir = root.join("cache");
+
+        let paths: Vec<PathBuf> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| thumbnail_path(&source, &cache_dir, 16).unwrap()))
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+
+        assert!(paths.windows(2).all(|pair| pair[0] == pair[1]));
+        assert_eq!(image::image_dimensions(&paths[0]).unwrap(), (16, 8));
+        let entries: Vec<_> = fs::read_dir(&cache_dir).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+    }
+}
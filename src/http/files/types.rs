@@ -4,29 +4,48 @@ use std::{io, path};
 /// Represents a byte range for partial file reads
 #[derive(Debug, Clone)]
 pub struct ByteRange {
-    pub start: u64,
-    pub end: Option<u64>, // None means "to end of file"
+    pub start: Option<u64>, // None means a suffix range: the last `end` bytes
+    pub end: Option<u64>,   // None means "to end of file"
 }
 
 impl ByteRange {
-    /// Parses a Range header value like "bytes=0-999" or "bytes=1000-"
+    /// Parses a Range header value like "bytes=0-999", "bytes=1000-", or "bytes=-500"
     pub fn from_header(range_header: &str) -> Option<ByteRange> {
-        if let Some(range) = range_header.strip_prefix("bytes=") {
-            if let Some((start, end)) = range.split_once('-') {
-                if let Ok(start) = start.parse::<u64>() {
-                    if let Ok(end) = end.parse::<u64>() {
-                        return Some(ByteRange {
-                            start,
-                            end: Some(end),
-                        });
-                    } else if end.is_empty() {
-                        return Some(ByteRange { start, end: None });
-                    }
-                }
-            }
+        let (start, end) = range_header.strip_prefix("bytes=")?.split_once('-')?;
+        if start.is_empty() {
+            return Some(ByteRange {
+                start: None,
+                end: Some(end.parse::<u64>().ok()?),
+            });
         }
 
-        None
+        let start = start.parse::<u64>().ok()?;
+        if end.is_empty() {
+            return Some(ByteRange {
+                start: Some(start),
+                end: None,
+            });
+        }
+        // A range ending before it starts is invalid, so the header is ignored
+        let end = end.parse::<u64>().ok().filter(|&end| end >= start)?;
+        Some(ByteRange {
+            start: Some(start),
+            end: Some(end),
+        })
+    }
+
+    /// Returns the inclusive (start, end) offsets this range covers in a file of `file_size`
+    /// bytes, or None if it covers none of it; an end past the file is cut to its last byte
+    pub fn resolve(&self, file_size: u64) -> Option<(u64, u64)> {
+        let last = file_size.checked_sub(1)?;
+        let (start, end) = match (self.start, self.end) {
+            // A suffix longer than the file covers all of it
+            (None, Some(suffix)) if suffix > 0 => (file_size.saturating_sub(suffix), last),
+            (None, _) => return None,
+            (Some(start), end) => (start, end.map_or(last, |end| end.min(last))),
+        };
+
+        (start <= end).then_some((start, end))
     }
 }
 
@@ -51,5 +70,25 @@ pub enum FileReadError {
     PermissionDenied,    // Access issues
     RangeNotImplemented, // Not implemented yet
     IoError(io::Error),  // Unexpected I/O errors
-    InvalidRange,        // Range exceeds file size
+    InvalidRange(u64),   // Range lies outside a file of this many bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suffix_ranges_cover_the_end_of_the_file() {
+        let resolve = |header: &str, size| ByteRange::from_header(header)?.resolve(size);
+
+        assert_eq!(resolve("bytes=-500", 2000), Some((1500, 1999)));
+        assert_eq!(resolve("bytes=-5000", 2000), Some((0, 1999)));
+        assert_eq!(resolve("bytes=-0", 2000), None);
+        assert_eq!(resolve("bytes=-500", 0), None);
+        assert_eq!(resolve("bytes=100-", 2000), Some((100, 1999)));
+        assert_eq!(resolve("bytes=0-5000", 2000), Some((0, 1999)));
+        assert_eq!(resolve("bytes=2000-", 2000), None);
+        assert!(ByteRange::from_header("bytes=5-2").is_none());
+        assert!(ByteRange::from_header("bytes=-").is_none());
+    }
 }
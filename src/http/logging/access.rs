@@ -0,0 +1,245 @@
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, LineWriter, Write},
+    net::SocketAddr,
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+
+use super::{console, redact, Level};
+use crate::http::{
+    date, json,
+    request::{HttpRequest, RequestId},
+    response::HttpStatusCode,
+    writer::WriteReport,
+};
+
+/// ECS release whose field names the `ecs` format follows
+const ECS_VERSION: &str = "8.11.0";
+
+static FORMAT: OnceLock<AccessLogFormat> = OnceLock::new();
+static FILE: OnceLock<Mutex<LineWriter<File>>> = OnceLock::new();
+
+/// How each served request is recorded in the access log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    #[default]
+    Off,
+    Clf, // Common Log Format, as written by Apache and read by goaccess or awstats
+    Ecs, // One JSON object per line with Elastic Common Schema field names
+}
+
+/// Selects the access log format and, when `file` is given, opens it for appending so lines go
+/// there instead of stdout; only the first call takes effect
+pub fn init(format: AccessLogFormat, file: Option<&str>) -> io::Result<()> {
+    if FORMAT.set(format).is_err() || format == AccessLogFormat::Off {
+        return Ok(());
+    }
+    if let Some(path) = file {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let _ = FILE.set(Mutex::new(LineWriter::new(file)));
+    }
+    Ok(())
+}
+
+/// Writes the access log line for a request that was answered with `status`
+///
+/// `status` is the one the handler chose, even when `report` shows the response was cut short
+pub fn log(
+    request: &HttpRequest,
+    peer: Option<SocketAddr>,
+    status: &HttpStatusCode,
+    report: &WriteReport,
+    duration: Duration,
+    req_id: RequestId,
+) {
+    let line = match FORMAT.get().copied().unwrap_or_default() {
+        AccessLogFormat::Off => return,
+        AccessLogFormat::Clf => clf_line(SystemTime::now(), peer, request, status, report),
+        AccessLogFormat::Ecs => {
+            ecs_line(SystemTime::now(), request, status, report, duration, req_id)
+        }
+    };
+    match FILE.get() {
+        Some(file) => {
+            // A poisoned lock only means another thread panicked mid-write; the file is still usable
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = writeln!(file, "{}", line);
+        }
+        None => console::write_waiting(Level::Info, line),
+    }
+}
+
+/// Formats one request in Common Log Format:
+/// `host - - [29/Feb/2024:12:34:56 +0000] "GET /a.txt HTTP/1.1" 200 245`
+///
+/// The host is "-" when the client is not on a socket. Bytes count what the socket accepted,
+/// head included, and are "-" when nothing was sent. Secret query parameters are redacted
+fn clf_line(
+    timestamp: SystemTime,
+    peer: Option<SocketAddr>,
+    request: &HttpRequest,
+    status: &HttpStatusCode,
+    report: &WriteReport,
+) -> String {
+    let line = &request.status_line;
+    let mut target = line.path.clone();
+    if let Some(query) = &line.query {
+        let _ = write!(target, "?{}", query);
+    }
+
+    let c = date::civil(timestamp);
+    let mut out = String::new();
+    match peer {
+        Some(peer) => {
+            let _ = write!(out, "{}", peer.ip());
+        }
+        None => out.push('-'),
+    }
+    let _ = write!(
+        out,
+        " - - [{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000] \"{} {} {}\" {} ",
+        c.day,
+        c.month_name(),
+        c.year,
+        c.hour,
+        c.minute,
+        c.second,
+        line.method,
+        escape(&redact(&target)),
+        line.version,
+        status.code()
+    );
+    match report.bytes {
+        0 => out.push('-'),
+        bytes => {
+            let _ = write!(out, "{}", bytes);
+        }
+    }
+    out
+}
+
+/// Escapes quotes, backslashes, and control characters the way Apache does, so a request
+/// target cannot break out of its quoted field
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => {
+                let _ = write!(out, "\\x{:02x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Formats one request as an ECS document; the delivery outcome goes under `labels`, ECS's
+/// place for custom keywords
+fn ecs_line(
+    timestamp: SystemTime,
+    request: &HttpRequest,
+    status: &HttpStatusCode,
+    report: &WriteReport,
+    duration: Duration,
+    req_id: RequestId,
+) -> String {
+    let version = request.status_line.version.to_string();
+    let mut line = String::new();
+    let _ = write!(
+        line,
+        "{{\"@timestamp\":\"{}\",\"ecs\":{{\"version\":\"{}\"}},\"event\":{{\"duration\":{}}},\
+         \"http\":{{\"request\":{{\"id\":{},\"method\":{}}},\"response\":{{\"bytes\":{},\
+         \"status_code\":{}}},\"version\":{}}},\"labels\":{{\"delivery\":\"{}\"}},\
+         \"url\":{{\"path\":{}}}}}",
+        date::rfc3339(timestamp),
+        ECS_VERSION,
+        duration.as_nanos(),
+        json::quote(&req_id.token()),
+        json::quote(&request.status_line.method.to_string()),
+        report.bytes,
+        status.code(),
+        json::quote(version.trim_start_matches("HTTP/")),
+        report.delivery,
+        json::quote(&request.status_line.path),
+    );
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{request::RequestIdGenerator, writer::WriterError};
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_ecs_line_uses_ecs_field_names() {
+        let request = HttpRequest::parse(b"GET /files/a\"b.txt HTTP/1.1\r\n\r\n").unwrap();
+        let req_id = RequestIdGenerator::new().next();
+        let timestamp = UNIX_EPOCH + Duration::from_millis(1_709_210_096_250);
+
+        let aborted = WriteReport::new(
+            4096,
+            Err(WriterError::IoError(io::ErrorKind::BrokenPipe.into())),
+        );
+
+        let line = ecs_line(
+            timestamp,
+            &request,
+            &HttpStatusCode::NotFound,
+            &aborted,
+            Duration::from_micros(1500),
+            req_id,
+        );
+        assert_eq!(
+            line,
+            format!(
+                "{{\"@timestamp\":\"2024-02-29T12:34:56.250Z\",\"ecs\":{{\"version\":\"8.11.0\"}},\
+                 \"event\":{{\"duration\":1500000}},\"http\":{{\"request\":{{\"id\":\"{}\",\
+                 \"method\":\"GET\"}},\"response\":{{\"bytes\":4096,\"status_code\":404}},\
+                 \"version\":\"1.1\"}},\"labels\":{{\"delivery\":\"truncated-client-abort\"}},\
+                 \"url\":{{\"path\":\"/files/a\\\"b.txt\"}}}}",
+                req_id.token()
+            )
+        );
+    }
+
+    #[test]
+    fn test_clf_line_matches_common_log_format() {
+        let request =
+            HttpRequest::parse(b"GET /a\"b.txt?w=200&token=abc HTTP/1.1\r\n\r\n").unwrap();
+        let timestamp = UNIX_EPOCH + Duration::from_millis(1_709_210_096_250);
+        let peer = "192.0.2.7:51234".parse().ok();
+
+        let sent = WriteReport::new(245, Ok(()));
+        assert_eq!(
+            clf_line(timestamp, peer, &request, &HttpStatusCode::Ok, &sent),
+            "192.0.2.7 - - [29/Feb/2024:12:34:56 +0000] \
+             \"GET /a\\\"b.txt?w=200&token=[REDACTED] HTTP/1.1\" 200 245"
+        );
+
+        let nothing = WriteReport::new(
+            0,
+            Err(WriterError::IoError(io::ErrorKind::BrokenPipe.into())),
+        );
+        assert_eq!(
+            clf_line(
+                timestamp,
+                None,
+                &request,
+                &HttpStatusCode::NotFound,
+                &nothing
+            ),
+            "- - - [29/Feb/2024:12:34:56 +0000] \
+             \"GET /a\\\"b.txt?w=200&token=[REDACTED] HTTP/1.1\" 404 -"
+        );
+    }
+}
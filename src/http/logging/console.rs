@@ -0,0 +1,172 @@
+use std::{
+    io::{self, BufWriter, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SendError, SyncSender, TrySendError},
+        OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::Level;
+
+/// Console lines that may wait for the logger thread when no capacity is configured
+pub const DEFAULT_QUEUE_CAPACITY: usize = 8192;
+
+/// Longest `flush` waits for the logger thread to write what is queued
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+static QUEUE: OnceLock<SyncSender<Message>> = OnceLock::new();
+
+/// Lines discarded because the queue was full, not yet reported
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// What the logger thread receives
+enum Message {
+    Line(Level, String),
+    Flush(mpsc::Sender<()>), // Acknowledged once everything before it is written
+}
+
+/// Starts the logger thread; until then, or with a capacity of 0, callers write their own lines
+pub fn start(capacity: usize) {
+    if capacity == 0 || QUEUE.get().is_some() {
+        return;
+    }
+
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    let spawned = thread::Builder::new()
+        .name("logger".to_string())
+        .spawn(move || run(receiver, &DROPPED, io::stdout(), io::stderr()));
+    if spawned.is_ok() {
+        let _ = QUEUE.set(sender);
+    }
+}
+
+/// Hands a line to the logger thread without waiting; when the queue is full the line is
+/// dropped and counted instead of blocking the request that logged it
+pub fn write(level: Level, line: String) {
+    let Some(queue) = QUEUE.get() else {
+        return write_direct(level, &line);
+    };
+    match queue.try_send(Message::Line(level, line)) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+        // The logger thread is gone, so nothing else will write this
+        Err(TrySendError::Disconnected(Message::Line(level, line))) => write_direct(level, &line),
+        Err(TrySendError::Disconnected(Message::Flush(_))) => {}
+    }
+}
+
+/// Hands a line to the logger thread, waiting for room when the queue is full; for lines that
+/// must not be lost, such as access log entries
+pub fn write_waiting(level: Level, line: String) {
+    match QUEUE.get() {
+        Some(queue) => send_waiting(queue, level, line),
+        None => write_direct(level, &line),
+    }
+}
+
+fn send_waiting(queue: &SyncSender<Message>, level: Level, line: String) {
+    // The logger thread is gone, so nothing else will write this
+    if let Err(SendError(Message::Line(level, line))) = queue.send(Message::Line(level, line)) {
+        write_direct(level, &line);
+    }
+}
+
+/// Waits, up to a bound, until every line queued so far has been written
+pub fn flush() {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    let (done, written) = mpsc::channel();
+    if queue.send(Message::Flush(done)).is_ok() {
+        let _ = written.recv_timeout(FLUSH_TIMEOUT);
+    }
+}
+
+fn write_direct(level: Level, line: &str) {
+    match level {
+        Level::Error | Level::Warn => eprintln!("{}", line),
+        Level::Info | Level::Debug => println!("{}", line),
+    }
+}
+
+/// Writes queued lines in batches, flushing the console only once the queue runs dry
+fn run<O: Write, E: Write>(
+    receiver: Receiver<Message>,
+    dropped: &AtomicU64,
+    out: O,
+    err: E,
+) -> (O, E) {
+    let mut out = BufWriter::new(out);
+    let mut err = BufWriter::new(err);
+
+    while let Ok(first) = receiver.recv() {
+        for message in std::iter::once(first).chain(receiver.try_iter()) {
+            match message {
+                Message::Line(Level::Error | Level::Warn, line) => {
+                    let _ = writeln!(err, "{}", line);
+                }
+                Message::Line(Level::Info | Level::Debug, line) => {
+                    let _ = writeln!(out, "{}", line);
+                }
+                Message::Flush(done) => {
+                    let _ = (out.flush(), err.flush());
+                    let _ = done.send(());
+                }
+            }
+        }
+
+        let lost = dropped.swap(0, Ordering::Relaxed);
+        if lost > 0 {
+            let _ = writeln!(err, "{} log lines dropped: logging queue full", lost);
+        }
+        let _ = (out.flush(), err.flush());
+    }
+
+    let _ = (out.flush(), err.flush());
+    (out.into_parts().0, err.into_parts().0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_go_to_their_stream_and_drops_are_reported() {
+        let (sender, receiver) = mpsc::sync_channel(4);
+        let dropped = AtomicU64::new(2);
+        sender
+            .send(Message::Line(Level::Info, "served".to_string()))
+            .unwrap();
+        sender
+            .send(Message::Line(Level::Warn, "slow".to_string()))
+            .unwrap();
+        drop(sender);
+
+        let (out, err) = run(receiver, &dropped, Vec::new(), Vec::new());
+        assert_eq!(String::from_utf8(out).unwrap(), "served\n");
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            "slow\n2 log lines dropped: logging queue full\n"
+        );
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_waiting_lines_are_kept_when_the_queue_is_full() {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        sender
+            .send(Message::Line(Level::Info, "first".to_string()))
+            .unwrap();
+        let waiting =
+            thread::spawn(move || send_waiting(&sender, Level::Info, "second".to_string()));
+
+        let (out, _) = run(receiver, &AtomicU64::new(0), Vec::new(), Vec::new());
+        waiting.join().unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "first\nsecond\n");
+    }
+}
@@ -0,0 +1,503 @@
+pub mod access;
+pub mod console;
+pub mod redact;
+
+use std::{
+    borrow::Cow,
+    fmt::{self, Write},
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use arc_swap::ArcSwapOption;
+use serde::Deserialize;
+
+use crate::http::{
+    config::LoggingConfig,
+    date, json,
+    request::id::{RequestId, RequestRef},
+};
+use redact::Redactor;
+
+/// Number of recent log records kept in memory when no capacity is configured
+pub const DEFAULT_RING_CAPACITY: usize = 1000;
+
+static RING: OnceLock<LogRing> = OnceLock::new();
+static REDACTOR: OnceLock<Redactor> = OnceLock::new();
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+static MAX_LEVEL: OnceLock<Level> = OnceLock::new();
+
+/// How log records are written to the console
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text, // "1709210096.250 INFO  [request 3] GET /a.txt"
+    Json, // One JSON object per line, with the record's fields as keys
+}
+
+/// Severity of a log record
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    #[serde(alias = "warning")]
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl Level {
+    /// Parses a level name such as "warn" or "ERROR"
+    pub fn parse(name: &str) -> Option<Level> {
+        match name.to_ascii_lowercase().as_str() {
+            "error" => Some(Level::Error),
+            "warn" | "warning" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+}
+
+impl Level {
+    /// Returns the lowercase level name used in JSON records, e.g. "warn"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Error => write!(f, "ERROR"),
+            Level::Warn => write!(f, "WARN"),
+            Level::Info => write!(f, "INFO"),
+            Level::Debug => write!(f, "DEBUG"),
+        }
+    }
+}
+
+/// A single log event
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub timestamp: SystemTime,
+    pub level: Level,
+    pub req_id: Option<RequestId>,
+    pub message: String,
+    pub fields: Fields,
+}
+
+/// Structured details of the events a log pipeline filters on; each is left out when `None`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fields {
+    pub event: Option<&'static str>, // "request", "response", "parse_error", or "writer_error"
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub status: Option<u16>,
+    pub duration: Option<Duration>,
+    pub bytes: Option<u64>, // Bytes the socket accepted, head included
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_epoch = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        write!(
+            f,
+            "{}.{:03} {:<5} ",
+            since_epoch.as_secs(),
+            since_epoch.subsec_millis(),
+            self.level
+        )?;
+        write_message(f, self.req_id, &self.message)
+    }
+}
+
+/// Writes "[request N] message", or "[request N][tag] message" when the message starts with a tag
+fn write_message(f: &mut dyn fmt::Write, req_id: Option<RequestId>, message: &str) -> fmt::Result {
+    match req_id {
+        Some(id) if message.starts_with('[') => write!(f, "[request {}]{}", id.seq(), message),
+        Some(id) => write!(f, "[request {}] {}", id.seq(), message),
+        None => write!(f, "{}", message),
+    }
+}
+
+/// Returns whether records at `level` are kept; `server_log!` checks this before formatting
+pub fn enabled(level: Level) -> bool {
+    level <= MAX_LEVEL.get().copied().unwrap_or_default()
+}
+
+/// Emits a log record to the console and keeps it in the in-memory ring buffer
+///
+/// Console output goes through the logger thread once `init` has started it, so a slow
+/// terminal never holds up a request
+///
+/// Secrets are redacted here, before the message reaches any output, so no log site can leak them
+pub fn record(level: Level, req_id: Option<RequestId>, message: String) {
+    record_fields(level, req_id, message, Fields::default());
+}
+
+/// Emits a log record carrying structured fields, which JSON output writes as their own keys
+pub fn record_fields(level: Level, req_id: Option<RequestId>, message: String, fields: Fields) {
+    let message = match redactor().redact(&message) {
+        Cow::Borrowed(_) => message,
+        Cow::Owned(redacted) => redacted,
+    };
+    let record = Record {
+        timestamp: SystemTime::now(),
+        level,
+        req_id,
+        message,
+        fields,
+    };
+
+    let line = match FORMAT.get().copied().unwrap_or_default() {
+        LogFormat::Text => {
+            let mut line = String::new();
+            let _ = write_message(&mut line, req_id, &record.message);
+            line
+        }
+        LogFormat::Json => json_line(&record),
+    };
+    console::write(level, line);
+
+    ring().push(record);
+}
+
+/// Formats a record as a single-line JSON object, e.g.
+/// `{"time":"2024-02-29T12:34:56.250Z","level":"info","request_id":"...","event":"response",
+/// "status":200,"message":"..."}`
+fn json_line(record: &Record) -> String {
+    let mut line = format!(
+        "{{\"time\":\"{}\",\"level\":\"{}\"",
+        date::rfc3339(record.timestamp),
+        record.level.name()
+    );
+    if let Some(id) = record.req_id {
+        let _ = write!(
+            line,
+            ",\"request_id\":{},\"seq\":{}",
+            json::quote(&id.token()),
+            id.seq()
+        );
+    }
+
+    let fields = &record.fields;
+    if let Some(event) = fields.event {
+        let _ = write!(line, ",\"event\":{}", json::quote(event));
+    }
+    if let Some(method) = &fields.method {
+        let _ = write!(line, ",\"method\":{}", json::quote(method));
+    }
+    if let Some(path) = &fields.path {
+        let _ = write!(line, ",\"path\":{}", json::quote(path));
+    }
+    if let Some(status) = fields.status {
+        let _ = write!(line, ",\"status\":{}", status);
+    }
+    if let Some(duration) = fields.duration {
+        let _ = write!(
+            line,
+            ",\"duration_ms\":{:.3}",
+            duration.as_secs_f64() * 1000.0
+        );
+    }
+    if let Some(bytes) = fields.bytes {
+        let _ = write!(line, ",\"bytes\":{}", bytes);
+    }
+
+    let _ = write!(line, ",\"message\":{}}}", json::quote(&record.message));
+    line
+}
+
+/// Applies the logging configuration; only the first call before any record is logged takes effect
+///
+/// Fails when the access log file cannot be opened
+pub fn init(config: &LoggingConfig) -> io::Result<()> {
+    let _ = RING.set(LogRing::new(config.ring_capacity));
+    let _ = REDACTOR.set(Redactor::new(
+        &config.redact_headers,
+        &config.redact_query_params,
+    ));
+    let _ = FORMAT.set(config.format);
+    let _ = MAX_LEVEL.set(config.level);
+    console::start(config.queue_capacity);
+    access::init(config.access_log, config.access_log_file.as_deref())
+}
+
+/// Writes out console lines still queued for the logger thread; call before the process exits
+pub fn flush() {
+    console::flush();
+}
+
+/// Returns the most recent log records, oldest first, that match the filter
+pub fn recent(filter: &LogFilter) -> Vec<Record> {
+    ring().snapshot(filter)
+}
+
+/// Returns a snapshot of the most recent log records, oldest first
+///
+/// Never blocks, so it is safe to call from a panic hook
+pub fn try_recent() -> Vec<Record> {
+    ring().snapshot(&LogFilter::default())
+}
+
+fn ring() -> &'static LogRing {
+    RING.get_or_init(|| LogRing::new(DEFAULT_RING_CAPACITY))
+}
+
+/// Masks secret header and query parameter values in text that bypasses `record`
+pub fn redact(text: &str) -> Cow<'_, str> {
+    redactor().redact(text)
+}
+
+fn redactor() -> &'static Redactor {
+    REDACTOR.get_or_init(|| {
+        let defaults = LoggingConfig::default();
+        Redactor::new(&defaults.redact_headers, &defaults.redact_query_params)
+    })
+}
+
+/// Narrows a snapshot of the ring buffer
+#[derive(Debug, Default)]
+pub struct LogFilter {
+    /// Keep records at this level or more severe
+    pub level: Option<Level>,
+    /// Keep records tagged with this request, by sequence number or token
+    pub req_id: Option<RequestRef>,
+    /// Keep only the newest N matching records
+    pub limit: Option<usize>,
+}
+
+impl LogFilter {
+    fn matches(&self, record: &Record) -> bool {
+        self.level.map_or(true, |level| record.level <= level)
+            && self
+                .req_id
+                .map_or(true, |id| record.req_id.is_some_and(|r| r.is(&id)))
+    }
+}
+
+/// A ring slot holding the record's sequence number alongside the record
+type Slot = ArcSwapOption<(u64, Record)>;
+
+/// Fixed-size, lock-free ring of log records
+///
+/// Writers claim a slot with an atomic cursor and publish into it with an atomic pointer swap,
+/// and readers load whatever each slot points to, so neither logging nor a snapshot ever waits
+struct LogRing {
+    next: AtomicU64,
+    slots: Box<[Slot]>,
+}
+
+impl LogRing {
+    fn new(capacity: usize) -> Self {
+        LogRing {
+            next: AtomicU64::new(0),
+            slots: (0..capacity.max(1)).map(|_| Slot::empty()).collect(),
+        }
+    }
+
+    fn push(&self, record: Record) {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let index = (seq % self.slots.len() as u64) as usize;
+        let entry = Arc::new((seq, record));
+
+        // A writer that lapped a slower one must not be overwritten by the older record
+        self.slots[index].rcu(|current| match current {
+            Some(current) if current.0 > seq => Some(Arc::clone(current)),
+            _ => Some(Arc::clone(&entry)),
+        });
+    }
+
+    fn snapshot(&self, filter: &LogFilter) -> Vec<Record> {
+        let mut records: Vec<Arc<(u64, Record)>> = self
+            .slots
+            .iter()
+            .filter_map(|slot| slot.load_full())
+            .filter(|entry| filter.matches(&entry.1))
+            .collect();
+
+        records.sort_by_key(|entry| entry.0);
+        if let Some(limit) = filter.limit {
+            records.drain(..records.len().saturating_sub(limit));
+        }
+        records.into_iter().map(|entry| entry.1.clone()).collect()
+    }
+}
+
+/// Logs a formatted message at the given level, optionally tagged with a request id
+///
+/// `server_log!(Info, req = req_id, "[file] served {}", name)`
+///
+/// `fields = Fields { .. }` after the request id attaches structured fields for JSON output.
+/// Nothing is formatted when the level is filtered out by `logging.level`
+#[macro_export]
+macro_rules! server_log {
+    ($level:ident, req = $req_id:expr, fields = $fields:expr, $($arg:tt)+) => {
+        if $crate::http::logging::enabled($crate::http::logging::Level::$level) {
+            $crate::http::logging::record_fields(
+                $crate::http::logging::Level::$level,
+                Some($req_id),
+                format!($($arg)+),
+                $fields,
+            )
+        }
+    };
+    ($level:ident, req = $req_id:expr, $($arg:tt)+) => {
+        if $crate::http::logging::enabled($crate::http::logging::Level::$level) {
+            $crate::http::logging::record(
+                $crate::http::logging::Level::$level,
+                Some($req_id),
+                format!($($arg)+),
+            )
+        }
+    };
+    ($level:ident, $($arg:tt)+) => {
+        if $crate::http::logging::enabled($crate::http::logging::Level::$level) {
+            $crate::http::logging::record(
+                $crate::http::logging::Level::$level,
+                None,
+                format!($($arg)+),
+            )
+        }
+    };
+}
+
+pub use crate::server_log;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::RequestIdGenerator;
+
+    fn record(level: Level, req_id: Option<RequestId>, message: &str) -> Record {
+        Record {
+            timestamp: SystemTime::now(),
+            level,
+            req_id,
+            message: message.to_string(),
+            fields: Fields::default(),
+        }
+    }
+
+    #[test]
+    fn test_ring_keeps_newest_records_in_order() {
+        let ring = LogRing::new(3);
+        for i in 0..5 {
+            ring.push(record(Level::Info, None, &i.to_string()));
+        }
+
+        let messages: Vec<String> = ring
+            .snapshot(&LogFilter::default())
+            .into_iter()
+            .map(|r| r.message)
+            .collect();
+        assert_eq!(messages, vec!["2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_ring_keeps_the_newest_records_of_concurrent_writers() {
+        let ring = LogRing::new(64);
+        std::thread::scope(|scope| {
+            for writer in 0..4 {
+                let ring = &ring;
+                scope.spawn(move || {
+                    for i in 0..500 {
+                        ring.push(record(Level::Info, None, &format!("{}-{}", writer, i)));
+                    }
+                });
+            }
+            // Snapshots taken mid-write see whole records
+            scope.spawn(|| {
+                for _ in 0..50 {
+                    for record in ring.snapshot(&LogFilter::default()) {
+                        assert!(record.message.contains('-'), "{}", record.message);
+                    }
+                }
+            });
+        });
+
+        let records = ring.snapshot(&LogFilter::default());
+        assert_eq!(records.len(), 64);
+        let mut messages: Vec<&str> = records.iter().map(|r| r.message.as_str()).collect();
+        messages.sort_unstable();
+        messages.dedup();
+        assert_eq!(messages.len(), 64);
+    }
+
+    #[test]
+    fn test_ring_filters_by_level_request_and_limit() {
+        let ids = RequestIdGenerator::new();
+        let (first, second) = (ids.next(), ids.next());
+        let ring = LogRing::new(10);
+        ring.push(record(Level::Debug, Some(first), "a"));
+        ring.push(record(Level::Warn, Some(first), "b"));
+        ring.push(record(Level::Error, Some(second), "c"));
+        ring.push(record(Level::Error, Some(first), "d"));
+
+        let filter = LogFilter {
+            level: Some(Level::Warn),
+            req_id: RequestRef::parse(&first.token()),
+            limit: None,
+        };
+        let messages: Vec<String> = ring
+            .snapshot(&filter)
+            .into_iter()
+            .map(|r| r.message)
+            .collect();
+        assert_eq!(messages, vec!["b", "d"]);
+
+        let filter = LogFilter {
+            limit: Some(1),
+            ..LogFilter::default()
+        };
+        assert_eq!(ring.snapshot(&filter)[0].message, "d");
+    }
+
+    #[test]
+    fn test_json_line_writes_fields_as_keys() {
+        let req_id = RequestIdGenerator::new().next();
+        let mut response = record(Level::Info, Some(req_id), "200 OK answered \"a\"");
+        response.timestamp = UNIX_EPOCH + Duration::from_millis(1_709_210_096_250);
+        response.fields = Fields {
+            event: Some("response"),
+            method: Some("GET".to_string()),
+            path: Some("/a.txt".to_string()),
+            status: Some(200),
+            duration: Some(Duration::from_micros(1500)),
+            bytes: Some(245),
+        };
+        assert_eq!(
+            json_line(&response),
+            format!(
+                "{{\"time\":\"2024-02-29T12:34:56.250Z\",\"level\":\"info\",\"request_id\":\"{}\",\
+                 \"seq\":{},\"event\":\"response\",\"method\":\"GET\",\"path\":\"/a.txt\",\
+                 \"status\":200,\"duration_ms\":1.500,\"bytes\":245,\
+                 \"message\":\"200 OK answered \\\"a\\\"\"}}",
+                req_id.token(),
+                req_id.seq()
+            )
+        );
+
+        let mut plain = record(Level::Warn, None, "starting");
+        plain.timestamp = UNIX_EPOCH;
+        assert_eq!(
+            json_line(&plain),
+            "{\"time\":\"1970-01-01T00:00:00.000Z\",\"level\":\"warn\",\"message\":\"starting\"}"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use std::borrow::Cow;
+
+/// Replacement text for redacted values
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Headers whose values are redacted when no list is configured
+pub const DEFAULT_REDACT_HEADERS: &[&str] = &[
+    "Authorization",
+    "Proxy-Authorization",
+    "Cookie",
+    "Set-Cookie",
+    "X-Api-Key",
+    "X-Upload-Token",
+];
+
+/// Query parameters whose values are redacted when no list is configured
+pub const DEFAULT_REDACT_QUERY_PARAMS: &[&str] = &[
+    "token",
+    "access_token",
+    "api_key",
+    "apikey",
+    "key",
+    "password",
+    "secret",
+    "signature",
+    "sig",
+];
+
+/// Masks secret header and query parameter values inside free-form log messages
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    headers: Vec<String>,      // Lowercased header names
+    query_params: Vec<String>, // Lowercased parameter names
+}
+
+impl Redactor {
+    /// Creates a redactor for the given header and query parameter names (case-insensitive)
+    pub fn new(headers: &[String], query_params: &[String]) -> Self {
+        Redactor {
+            headers: headers.iter().map(|h| h.to_ascii_lowercase()).collect(),
+            query_params: query_params
+                .iter()
+                .map(|q| q.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Returns the message with every matching value replaced by `[REDACTED]`
+    ///
+    /// Recognizes `Name: value` and `"Name": "value"` (as printed by `{:?}` on a header map)
+    /// for headers, and `name=value` (as in `?name=value&...`) for query parameters
+    pub fn redact<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        // ASCII lowercasing keeps byte offsets identical to the original message
+        let lower = message.to_ascii_lowercase();
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+        for name in &self.headers {
+            for (start, _) in lower.match_indices(name.as_str()) {
+                if let Some(range) = header_value(&lower, start, name.len()) {
+                    ranges.push(range);
+                }
+            }
+        }
+        for name in &self.query_params {
+            for (start, _) in lower.match_indices(name.as_str()) {
+                if let Some(range) = query_value(&lower, start, name.len()) {
+                    ranges.push(range);
+                }
+            }
+        }
+
+        if ranges.is_empty() {
+            return Cow::Borrowed(message);
+        }
+
+        ranges.sort_unstable();
+        let mut out = String::with_capacity(message.len());
+        let mut cursor = 0;
+        for (start, end) in ranges {
+            if start < cursor {
+                // Overlaps a range that was already redacted
+                cursor = cursor.max(end);
+                continue;
+            }
+            out.push_str(&message[cursor..start]);
+            out.push_str(REDACTED);
+            cursor = end;
+        }
+        out.push_str(&message[cursor..]);
+        Cow::Owned(out)
+    }
+}
+
+/// Finds the value following a header name at `start`, if the name is used as a header there
+fn header_value(lower: &str, start: usize, len: usize) -> Option<(usize, usize)> {
+    let bytes = lower.as_bytes();
+    if start > 0 && is_name_byte(bytes[start - 1]) {
+        return None;
+    }
+
+    let mut i = start + len;
+    if bytes.get(i) == Some(&b'"') {
+        i += 1;
+    }
+    if bytes.get(i) != Some(&b':') {
+        return None;
+    }
+    i += 1;
+    while bytes.get(i) == Some(&b' ') {
+        i += 1;
+    }
+
+    let quoted = bytes.get(i) == Some(&b'"');
+    if quoted {
+        i += 1;
+    }
+    let end = bytes[i..]
+        .iter()
+        .position(|&b| {
+            if quoted {
+                b == b'"'
+            } else {
+                b == b'\r' || b == b'\n'
+            }
+        })
+        .map_or(bytes.len(), |offset| i + offset);
+
+    (end > i).then_some((i, end))
+}
+
+/// Finds the value of a query parameter whose name starts at `start`
+fn query_value(lower: &str, start: usize, len: usize) -> Option<(usize, usize)> {
+    let bytes = lower.as_bytes();
+    if start > 0 && is_name_byte(bytes[start - 1]) {
+        return None;
+    }
+
+    let i = start + len;
+    if bytes.get(i) != Some(&b'=') {
+        return None;
+    }
+    let value_start = i + 1;
+    let end = bytes[value_start..]
+        .iter()
+        .position(|&b| matches!(b, b'&' | b'#' | b'"' | b'\'' | b')') || b.is_ascii_whitespace())
+        .map_or(bytes.len(), |offset| value_start + offset);
+
+    (end > value_start).then_some((value_start, end))
+}
+
+/// Checks whether a byte can be part of a header name
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor() -> Redactor {
+        let headers: Vec<String> = DEFAULT_REDACT_HEADERS
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        let params: Vec<String> = DEFAULT_REDACT_QUERY_PARAMS
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        Redactor::new(&headers, &params)
+    }
+
+    #[test]
+    fn test_redacts_headers_in_plain_and_debug_form() {
+        let redactor = redactor();
+
+        assert_eq!(
+            redactor.redact("authorization: Bearer abc123"),
+            "authorization: [REDACTED]"
+        );
+        assert_eq!(
+            redactor.redact(r#"headers={"Cookie": "sid=1", "Accept": "*/*"}"#),
+            r#"headers={"Cookie": "[REDACTED]", "Accept": "*/*"}"#
+        );
+        // A name that merely contains a secret header name is left alone
+        assert_eq!(
+            redactor.redact("X-Cookie-Policy: strict"),
+            "X-Cookie-Policy: strict"
+        );
+    }
+
+    #[test]
+    fn test_redacts_query_params() {
+        let redactor = redactor();
+
+        assert_eq!(
+            redactor.redact(r#"query=Some("token=s3cret&w=200&sig=ff") path=/a"#),
+            r#"query=Some("token=[REDACTED]&w=200&sig=[REDACTED]") path=/a"#
+        );
+        assert_eq!(
+            redactor.redact("GET /files/a?monkey=1"),
+            "GET /files/a?monkey=1"
+        );
+        assert!(matches!(
+            redactor.redact("nothing to hide"),
+            Cow::Borrowed(_)
+        ));
+    }
+}
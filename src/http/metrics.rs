@@ -0,0 +1,583 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::UdpSocket,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::http::{
+    errors::HttpError,
+    logging::server_log,
+    request::{HttpMethod, HttpRequest, RequestId},
+    response::{HttpResponse, HttpStatusCode},
+    routes::{CompressionOutcome, Params, SkipReason},
+    server,
+};
+
+const DEFAULT_STATSD_ADDR: &str = "127.0.0.1:8125";
+const DEFAULT_PREFIX: &str = "rusttp";
+
+/// Route label for requests no route matched
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Upper bounds, in seconds, of the request duration histogram buckets
+const DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Content-Type of the Prometheus text exposition format
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+static COUNTERS: [AtomicU64; Counter::ALL.len()] =
+    [const { AtomicU64::new(0) }; Counter::ALL.len()];
+static REQUEST_TIME_COUNT: AtomicU64 = AtomicU64::new(0);
+static REQUEST_TIME_MICROS: AtomicU64 = AtomicU64::new(0);
+static COMPRESSION_BYTES_IN: AtomicU64 = AtomicU64::new(0);
+static COMPRESSION_BYTES_OUT: AtomicU64 = AtomicU64::new(0);
+/// Requests per duration bucket, not cumulative; the extra last bucket holds the slower ones
+static DURATION_COUNTS: [AtomicU64; DURATION_BUCKETS.len() + 1] =
+    [const { AtomicU64::new(0) }; DURATION_BUCKETS.len() + 1];
+static IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+static ROUTES: Mutex<BTreeMap<RouteKey, RouteCounts>> = Mutex::new(BTreeMap::new());
+static SINK: OnceLock<StatsdSink> = OnceLock::new();
+
+/// Route pattern, method, and status a response is counted under
+type RouteKey = (String, String, u16);
+
+/// Responses sent under one route, method, and status
+#[derive(Debug, Clone, Copy, Default)]
+struct RouteCounts {
+    responses: u64,
+    bytes: u64, // Bytes the socket accepted, head included
+}
+
+/// Where metrics are pushed in addition to the in-process counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsEmitter {
+    #[default]
+    None, // Keep counters in process only
+    Statsd,    // Plain statsd: dimensions are folded into the metric name
+    Dogstatsd, // DogStatsD: dimensions are sent as tags
+}
+
+/// Metrics settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub emitter: MetricsEmitter,
+    /// UDP address of the statsd agent
+    pub statsd_addr: String,
+    /// Prepended to every metric name, e.g. "rusttp.requests"
+    pub prefix: String,
+    /// Extra DogStatsD tags sent with every metric, e.g. ["env:prod"]
+    pub tags: Vec<String>,
+    /// Serve the metrics in Prometheus text format at GET /metrics
+    pub prometheus: bool,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            emitter: MetricsEmitter::None,
+            statsd_addr: DEFAULT_STATSD_ADDR.to_string(),
+            prefix: DEFAULT_PREFIX.to_string(),
+            tags: Vec::new(),
+            prometheus: false,
+        }
+    }
+}
+
+/// Monotonic counters tracked by the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Counter {
+    ConnectionsAccepted,
+    ConnectionsRejected, // Turned away at the connection limit
+    ConnectionsReaped,   // Closed by the reaper after idling past the keep-alive timeout
+    Requests,
+    Responses2xx,
+    Responses3xx,
+    Responses4xx,
+    Responses5xx,
+    Compressed,
+    CompressedStream,        // Compressed while streamed from disk
+    CompressionDisabled,     // Compression turned off in config
+    CompressionNotAccepted,  // Client accepts no supported encoding
+    CompressionBelowMinimum, // Body too small to be worth compressing
+    CompressionStreamed,     // Body streamed from disk, sent as-is
+    CompressionPartial,      // Range response, sent as-is
+    CompressionEncoded,      // Body already encoded, e.g. a precompressed file
+    CompressionType,         // Content-Type already compressed, e.g. an image
+    CompressionRefused,      // Client forbids identity and accepts no supported encoding
+}
+
+impl Counter {
+    pub const ALL: [Counter; 18] = [
+        Counter::ConnectionsAccepted,
+        Counter::ConnectionsRejected,
+        Counter::ConnectionsReaped,
+        Counter::Requests,
+        Counter::Responses2xx,
+        Counter::Responses3xx,
+        Counter::Responses4xx,
+        Counter::Responses5xx,
+        Counter::Compressed,
+        Counter::CompressedStream,
+        Counter::CompressionDisabled,
+        Counter::CompressionNotAccepted,
+        Counter::CompressionBelowMinimum,
+        Counter::CompressionStreamed,
+        Counter::CompressionPartial,
+        Counter::CompressionEncoded,
+        Counter::CompressionType,
+        Counter::CompressionRefused,
+    ];
+
+    /// Returns the counter for a response status
+    pub fn for_status(status: &HttpStatusCode) -> Counter {
+        match status.code() {
+            300..=399 => Counter::Responses3xx,
+            400..=499 => Counter::Responses4xx,
+            500..=599 => Counter::Responses5xx,
+            _ => Counter::Responses2xx,
+        }
+    }
+
+    /// Returns the metric name and optional dimension, e.g. ("responses", Some("status:4xx"))
+    pub fn name(&self) -> (&'static str, Option<&'static str>) {
+        match self {
+            Counter::ConnectionsAccepted => ("connections.accepted", None),
+            Counter::ConnectionsRejected => ("connections.rejected", None),
+            Counter::ConnectionsReaped => ("connections.reaped", None),
+            Counter::Requests => ("requests", None),
+            Counter::Responses2xx => ("responses", Some("status:2xx")),
+            Counter::Responses3xx => ("responses", Some("status:3xx")),
+            Counter::Responses4xx => ("responses", Some("status:4xx")),
+            Counter::Responses5xx => ("responses", Some("status:5xx")),
+            Counter::Compressed => ("compression", Some("outcome:compressed")),
+            Counter::CompressedStream => ("compression", Some("outcome:compressed_stream")),
+            Counter::CompressionDisabled => ("compression", Some("outcome:disabled")),
+            Counter::CompressionNotAccepted => ("compression", Some("outcome:not_accepted")),
+            Counter::CompressionBelowMinimum => ("compression", Some("outcome:below_minimum")),
+            Counter::CompressionStreamed => ("compression", Some("outcome:streamed")),
+            Counter::CompressionPartial => ("compression", Some("outcome:partial")),
+            Counter::CompressionEncoded => ("compression", Some("outcome:encoded")),
+            Counter::CompressionType => ("compression", Some("outcome:compressed_type")),
+            Counter::CompressionRefused => ("compression", Some("outcome:not_acceptable")),
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Connected UDP socket plus the formatting settings for each packet
+struct StatsdSink {
+    socket: UdpSocket,
+    emitter: MetricsEmitter,
+    prefix: String,
+    tags: Vec<String>,
+}
+
+impl StatsdSink {
+    fn send(&self, name: &str, dimension: Option<&str>, value: &str, kind: &str) {
+        let line = format_line(
+            self.emitter,
+            &self.prefix,
+            name,
+            dimension,
+            value,
+            kind,
+            &self.tags,
+        );
+        // Metrics are best effort; a missing agent must never affect request handling
+        let _ = self.socket.send(line.as_bytes());
+    }
+}
+
+/// Connects the configured statsd emitter; without this call metrics stay in process
+pub fn init(config: &MetricsConfig) {
+    if config.emitter == MetricsEmitter::None {
+        return;
+    }
+
+    let socket = UdpSocket::bind("0.0.0.0:0").and_then(|socket| {
+        socket.connect(&config.statsd_addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    });
+
+    match socket {
+        Ok(socket) => {
+            let _ = SINK.set(StatsdSink {
+                socket,
+                emitter: config.emitter,
+                prefix: config.prefix.clone(),
+                tags: config.tags.clone(),
+            });
+            server_log!(Info, "Sending metrics to statsd at {}", config.statsd_addr);
+        }
+        Err(e) => server_log!(
+            Error,
+            "Failed to connect statsd emitter to {}: {}",
+            config.statsd_addr,
+            e
+        ),
+    }
+}
+
+/// Increments a counter by one
+pub fn incr(counter: Counter) {
+    COUNTERS[counter.index()].fetch_add(1, Ordering::Relaxed);
+
+    if let Some(sink) = SINK.get() {
+        let (name, dimension) = counter.name();
+        sink.send(name, dimension, "1", "c");
+    }
+}
+
+/// Records how long a request took, from parsed headers to the end of the response
+pub fn time_request(elapsed: Duration) {
+    REQUEST_TIME_COUNT.fetch_add(1, Ordering::Relaxed);
+    REQUEST_TIME_MICROS.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    let bucket = DURATION_BUCKETS
+        .iter()
+        .position(|&bound| elapsed.as_secs_f64() <= bound)
+        .unwrap_or(DURATION_BUCKETS.len());
+    DURATION_COUNTS[bucket].fetch_add(1, Ordering::Relaxed);
+
+    if let Some(sink) = SINK.get() {
+        let millis = format!("{:.3}", elapsed.as_secs_f64() * 1000.0);
+        sink.send("request.duration", None, &millis, "ms");
+    }
+}
+
+/// Counts a routed response under its route pattern, method, and status, with the bytes sent
+pub fn record_response(route: &str, method: &HttpMethod, status: &HttpStatusCode, bytes: u64) {
+    let key = (route.to_string(), method.to_string(), status.code());
+    // A poisoned lock only means another thread panicked mid-update; the counts are still usable
+    let mut routes = ROUTES.lock().unwrap_or_else(|e| e.into_inner());
+    let counts = routes.entry(key).or_default();
+    counts.responses += 1;
+    counts.bytes += bytes;
+}
+
+/// Counts a request as in flight until dropped
+pub struct InFlight(());
+
+impl InFlight {
+    pub fn start() -> Self {
+        IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+        InFlight(())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts a compression decision; buffered compressed responses also add their sizes before and
+/// after
+pub fn record_compression(outcome: &CompressionOutcome) {
+    match outcome {
+        CompressionOutcome::Compressed {
+            original,
+            compressed,
+            ..
+        } => {
+            incr(Counter::Compressed);
+            COMPRESSION_BYTES_IN.fetch_add(*original as u64, Ordering::Relaxed);
+            COMPRESSION_BYTES_OUT.fetch_add(*compressed as u64, Ordering::Relaxed);
+
+            if let Some(sink) = SINK.get() {
+                sink.send("compression.bytes_in", None, &original.to_string(), "c");
+                sink.send("compression.bytes_out", None, &compressed.to_string(), "c");
+            }
+        }
+        // Only counted: bytes_out would never be known in time to pair with bytes_in
+        CompressionOutcome::Streaming { .. } => incr(Counter::CompressedStream),
+        CompressionOutcome::Skipped { reason, .. } => incr(match reason {
+            SkipReason::Disabled => Counter::CompressionDisabled,
+            SkipReason::NotAccepted => Counter::CompressionNotAccepted,
+            SkipReason::BelowMinimum => Counter::CompressionBelowMinimum,
+            SkipReason::Streamed => Counter::CompressionStreamed,
+            SkipReason::Partial => Counter::CompressionPartial,
+            SkipReason::Encoded => Counter::CompressionEncoded,
+            SkipReason::CompressedType => Counter::CompressionType,
+        }),
+        CompressionOutcome::NotAcceptable => incr(Counter::CompressionRefused),
+    }
+}
+
+/// Handler for GET /metrics: every counter, the request duration histogram, and the connection
+/// gauges in Prometheus text format
+pub fn prometheus_handler(
+    request: &HttpRequest,
+    _params: &Params,
+    ctx: &server::ServerContext,
+    _req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    let config = ctx.config();
+    let prefix = &config.metrics.prefix;
+    let mut text = prometheus_text(prefix);
+
+    let gauges = [
+        (
+            "requests_in_flight",
+            IN_FLIGHT.load(Ordering::Relaxed) as usize,
+        ),
+        ("connections_open", ctx.open_connections()),
+        ("connections_limit", config.max_connections),
+    ];
+    for (name, value) in gauges.into_iter().chain(pool_gauges(ctx)) {
+        let name = metric_name(prefix, name);
+        let _ = writeln!(text, "# TYPE {} gauge\n{} {}", name, name, value);
+    }
+
+    Ok(HttpResponse::builder()
+        .version(request.status_line.version.clone())
+        .header("Content-Type", PROMETHEUS_CONTENT_TYPE)
+        .header("Cache-Control", "no-store")
+        .text_body(text)
+        .build()?)
+}
+
+/// Thread pool gauges; each open connection holds a pool thread until it closes, and the rest
+/// wait in the pool's queue
+#[cfg(not(any(feature = "async", feature = "event-loop")))]
+fn pool_gauges(ctx: &server::ServerContext) -> Vec<(&'static str, usize)> {
+    let (open, workers) = (ctx.open_connections(), ctx.config().workers);
+    vec![
+        ("pool_workers", workers),
+        ("pool_busy_workers", open.min(workers)),
+        ("pool_queued_connections", open.saturating_sub(workers)),
+    ]
+}
+
+/// The async runtime and the event loop serve connections without a fixed pool
+#[cfg(any(feature = "async", feature = "event-loop"))]
+fn pool_gauges(_ctx: &server::ServerContext) -> Vec<(&'static str, usize)> {
+    Vec::new()
+}
+
+/// Formats the process-wide counters and the duration histogram in Prometheus text format
+fn prometheus_text(prefix: &str) -> String {
+    let mut text = String::new();
+
+    let mut previous = None;
+    for counter in Counter::ALL {
+        let (name, dimension) = counter.name();
+        let full_name = metric_name(prefix, &format!("{}_total", name));
+        if previous != Some(name) {
+            let _ = writeln!(text, "# TYPE {} counter", full_name);
+            previous = Some(name);
+        }
+        let value = COUNTERS[counter.index()].load(Ordering::Relaxed);
+        match dimension.and_then(|d| d.split_once(':')) {
+            Some((label, value_label)) => {
+                let _ = writeln!(
+                    text,
+                    "{}{{{}={}}} {}",
+                    full_name,
+                    label,
+                    label_value(value_label),
+                    value
+                );
+            }
+            None => {
+                let _ = writeln!(text, "{} {}", full_name, value);
+            }
+        }
+    }
+    for (name, total) in [
+        ("compression_bytes_in_total", &COMPRESSION_BYTES_IN),
+        ("compression_bytes_out_total", &COMPRESSION_BYTES_OUT),
+    ] {
+        let name = metric_name(prefix, name);
+        let total = total.load(Ordering::Relaxed);
+        let _ = writeln!(text, "# TYPE {} counter\n{} {}", name, name, total);
+    }
+
+    // Both families list every route; each family's lines must stay together
+    let routes = ROUTES.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let responses = metric_name(prefix, "route_responses_total");
+    let bytes = metric_name(prefix, "route_response_bytes_total");
+    let mut bytes_text = format!("# TYPE {} counter\n", bytes);
+    let _ = writeln!(text, "# TYPE {} counter", responses);
+    for ((route, method, status), counts) in &routes {
+        let labels = format!(
+            "route={},method={},status=\"{}\"",
+            label_value(route),
+            label_value(method),
+            status
+        );
+        let _ = writeln!(text, "{}{{{}}} {}", responses, labels, counts.responses);
+        let _ = writeln!(bytes_text, "{}{{{}}} {}", bytes, labels, counts.bytes);
+    }
+    text.push_str(&bytes_text);
+
+    let name = metric_name(prefix, "request_duration_seconds");
+    let _ = writeln!(text, "# TYPE {} histogram", name);
+    let mut cumulative = 0;
+    for (i, count) in DURATION_COUNTS.iter().enumerate() {
+        cumulative += count.load(Ordering::Relaxed);
+        let bound = DURATION_BUCKETS
+            .get(i)
+            .map_or("+Inf".to_string(), |b| b.to_string());
+        let _ = writeln!(text, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+    }
+    let sum = REQUEST_TIME_MICROS.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+    let _ = writeln!(text, "{}_sum {}\n{}_count {}", name, sum, name, cumulative);
+
+    text
+}
+
+/// Builds a Prometheus metric name: the prefix and name joined by `_`, with any character
+/// Prometheus does not allow in a name (such as the `.` statsd names use) replaced by `_`
+fn metric_name(prefix: &str, name: &str) -> String {
+    let joined = if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}_{}", prefix, name)
+    };
+    joined
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Quotes a Prometheus label value, escaping backslashes, quotes, and newlines
+fn label_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+/// Formats one statsd packet, folding the dimension into the name unless tags are supported
+fn format_line(
+    emitter: MetricsEmitter,
+    prefix: &str,
+    name: &str,
+    dimension: Option<&str>,
+    value: &str,
+    kind: &str,
+    tags: &[String],
+) -> String {
+    let mut line = if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    };
+
+    match emitter {
+        MetricsEmitter::Dogstatsd => {
+            line.push_str(&format!(":{}|{}", value, kind));
+            let all_tags: Vec<&str> = dimension
+                .into_iter()
+                .chain(tags.iter().map(|t| t.as_str()))
+                .collect();
+            if !all_tags.is_empty() {
+                line.push_str("|#");
+                line.push_str(&all_tags.join(","));
+            }
+        }
+        MetricsEmitter::Statsd | MetricsEmitter::None => {
+            if let Some((_, suffix)) = dimension.and_then(|d| d.split_once(':')) {
+                line.push('.');
+                line.push_str(suffix);
+            }
+            line.push_str(&format!(":{}|{}", value, kind));
+        }
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_line_per_emitter() {
+        let tags = vec!["env:prod".to_string()];
+
+        assert_eq!(
+            format_line(
+                MetricsEmitter::Statsd,
+                "rusttp",
+                "responses",
+                Some("status:4xx"),
+                "1",
+                "c",
+                &tags
+            ),
+            "rusttp.responses.4xx:1|c"
+        );
+        assert_eq!(
+            format_line(
+                MetricsEmitter::Dogstatsd,
+                "rusttp",
+                "responses",
+                Some("status:4xx"),
+                "1",
+                "c",
+                &tags
+            ),
+            "rusttp.responses:1|c|#status:4xx,env:prod"
+        );
+        assert_eq!(
+            format_line(
+                MetricsEmitter::Dogstatsd,
+                "",
+                "request.duration",
+                None,
+                "1.500",
+                "ms",
+                &[]
+            ),
+            "request.duration:1.500|ms"
+        );
+    }
+
+    #[test]
+    fn test_prometheus_text_lists_routes_and_histogram() {
+        let route = "/metrics-test/\"{name}\"";
+        record_response(route, &HttpMethod::Get, &HttpStatusCode::NotFound, 120);
+        record_response(route, &HttpMethod::Get, &HttpStatusCode::NotFound, 80);
+        time_request(Duration::from_millis(20));
+
+        let text = prometheus_text("rusttp");
+        let labels = "route=\"/metrics-test/\\\"{name}\\\"\",method=\"GET\",status=\"404\"";
+        assert!(text.contains(&format!("rusttp_route_responses_total{{{}}} 2\n", labels)));
+        assert!(text.contains(&format!(
+            "rusttp_route_response_bytes_total{{{}}} 200\n",
+            labels
+        )));
+        assert!(text.contains(
+            "# TYPE rusttp_responses_total counter\nrusttp_responses_total{status=\"2xx\"}"
+        ));
+        assert!(text.contains("# TYPE rusttp_request_duration_seconds histogram\n"));
+        assert!(text.contains("rusttp_request_duration_seconds_bucket{le=\"+Inf\"}"));
+        assert_eq!(
+            metric_name("my.app", "connections.accepted_total"),
+            "my_app_connections_accepted_total"
+        );
+    }
+}
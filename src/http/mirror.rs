@@ -0,0 +1,244 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::http::{
+    config::MirrorConfig,
+    logging::server_log,
+    random::sampled,
+    request::{HttpRequest, RequestId},
+};
+
+/// Upper bound on mirror requests in flight; extra samples are dropped rather than queued
+const MAX_IN_FLIGHT: usize = 32;
+
+/// Connect, read, and write timeout for the mirror backend
+const MIRROR_TIMEOUT: Duration = Duration::from_secs(2);
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Request headers that carry credentials and are never copied to the mirror
+const CREDENTIAL_HEADERS: [&str; 3] = ["Authorization", "Cookie", "Proxy-Authorization"];
+
+/// Hop-by-hop and framing headers that are rewritten rather than copied to the mirror
+const HOP_BY_HOP_HEADERS: [&str; 10] = [
+    "Connection",
+    "Content-Length",
+    "Expect",
+    "Host",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// A configured mirror: its destination, parsed once, and the share of requests it receives
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    target: Arc<MirrorTarget>,
+    percent: f64,
+}
+
+impl Mirror {
+    /// Builds the mirror from its settings, or returns None when no url is configured
+    pub fn from_config(config: &MirrorConfig) -> Result<Option<Self>, String> {
+        let Some(url) = &config.url else {
+            return Ok(None);
+        };
+        Ok(Some(Mirror {
+            target: Arc::new(MirrorTarget::parse(url)?),
+            percent: config.percent,
+        }))
+    }
+}
+
+/// Parsed `http://host[:port][/prefix]` mirror destination
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirrorTarget {
+    pub host: String,
+    pub port: u16,
+    pub prefix: String,
+}
+
+impl MirrorTarget {
+    /// Parses a plain-HTTP mirror URL
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("mirror url '{}' must start with http://", url))?;
+
+        let (authority, prefix) = match rest.find('/') {
+            Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| format!("mirror url '{}' has an invalid port", url))?,
+            ),
+            None => (authority, 80),
+        };
+
+        if host.is_empty() {
+            return Err(format!("mirror url '{}' is missing a host", url));
+        }
+
+        Ok(MirrorTarget {
+            host: host.to_string(),
+            port,
+            prefix: prefix.to_string(),
+        })
+    }
+}
+
+/// Copies a sampled share of requests to the mirror backend on a background thread
+pub fn maybe_mirror(mirror: Option<&Mirror>, request: &HttpRequest, req_id: RequestId) {
+    let Some(mirror) = mirror else {
+        return;
+    };
+    if !sampled(mirror.percent) {
+        return;
+    }
+
+    if IN_FLIGHT.fetch_add(1, Ordering::AcqRel) >= MAX_IN_FLIGHT {
+        IN_FLIGHT.fetch_sub(1, Ordering::AcqRel);
+        server_log!(
+            Warn,
+            req = req_id,
+            "[mirror] dropped: too many mirror requests in flight"
+        );
+        return;
+    }
+
+    let target = Arc::clone(&mirror.target);
+    let payload = serialize(request, &target);
+    thread::spawn(move || {
+        if let Err(e) = send(&target, &payload) {
+            server_log!(
+                Warn,
+                req = req_id,
+                "[mirror] failed to mirror to {}:{}: {}",
+                target.host,
+                target.port,
+                e
+            );
+        }
+        IN_FLIGHT.fetch_sub(1, Ordering::AcqRel);
+    });
+}
+
+/// Rebuilds the request for the mirror, rewriting the target path and framing headers and
+/// leaving out credentials
+fn serialize(request: &HttpRequest, target: &MirrorTarget) -> Vec<u8> {
+    let mut out = format!(
+        "{} {}{}",
+        request.status_line.method, target.prefix, request.status_line.path
+    );
+    if let Some(query) = &request.status_line.query {
+        out.push('?');
+        out.push_str(query);
+    }
+    out.push_str(" HTTP/1.1\r\n");
+
+    for (key, value) in &request.headers {
+        let skipped = CREDENTIAL_HEADERS
+            .iter()
+            .chain(HOP_BY_HOP_HEADERS.iter())
+            .any(|name| key.eq_ignore_ascii_case(name));
+        if !skipped {
+            out.push_str(&format!("{}: {}\r\n", key, value));
+        }
+    }
+    let body = request
+        .body
+        .as_ref()
+        .map_or(&[][..], |body| body.as_bytes());
+    out.push_str(&format!("Host: {}:{}\r\n", target.host, target.port));
+    if !body.is_empty() {
+        out.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    out.push_str("Connection: close\r\n\r\n");
+
+    let mut bytes = out.into_bytes();
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/// Sends the payload and drains the response so the backend sees a well-behaved client
+fn send(target: &MirrorTarget, payload: &[u8]) -> io::Result<()> {
+    let addr = (target.host.as_str(), target.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "mirror host did not resolve"))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, MIRROR_TIMEOUT)?;
+    stream.set_read_timeout(Some(MIRROR_TIMEOUT))?;
+    stream.set_write_timeout(Some(MIRROR_TIMEOUT))?;
+
+    stream.write_all(payload)?;
+    stream.flush()?;
+
+    io::copy(&mut stream.take(1024 * 1024), &mut io::sink())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mirror_target() {
+        assert_eq!(
+            MirrorTarget::parse("http://10.0.0.5:8080/shadow/").unwrap(),
+            MirrorTarget {
+                host: "10.0.0.5".to_string(),
+                port: 8080,
+                prefix: "/shadow".to_string(),
+            }
+        );
+        assert_eq!(MirrorTarget::parse("http://backend").unwrap().port, 80);
+        assert!(MirrorTarget::parse("https://backend").is_err());
+        assert!(MirrorTarget::parse("http://backend:http").is_err());
+    }
+
+    #[test]
+    fn test_serialize_strips_credentials_and_reframes_the_body() {
+        let request = HttpRequest::parse(
+            b"POST /upload?x=1 HTTP/1.1\r\nHost: example.com\r\nauthorization: Bearer secret\r\n\
+              Cookie: session=1\r\nTransfer-Encoding: chunked\r\nContent-Length: 99\r\n\
+              X-Trace: abc\r\n\r\nhello",
+        )
+        .unwrap();
+        let target = MirrorTarget::parse("http://10.0.0.5:8080/shadow").unwrap();
+        let payload = String::from_utf8(serialize(&request, &target)).unwrap();
+        let (head, body) = payload.split_once("\r\n\r\n").unwrap();
+
+        assert!(head.starts_with("POST /shadow/upload?x=1 HTTP/1.1\r\n"));
+        assert!(head.contains("X-Trace: abc"));
+        assert!(head.contains("Host: 10.0.0.5:8080"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        for stripped in [
+            "authorization",
+            "cookie",
+            "transfer-encoding",
+            "content-length: 99",
+        ] {
+            assert!(
+                !head.to_ascii_lowercase().contains(stripped),
+                "{}",
+                stripped
+            );
+        }
+    }
+}
@@ -1,7 +1,28 @@
+pub mod admin;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod config;
+pub mod crash;
+pub mod date;
 pub mod errors;
+pub mod json;
 pub mod request;
 pub mod response;
 pub mod routes;
 pub mod server;
 pub mod writer;
 pub mod files;
+pub mod logging;
+pub mod metrics;
+pub mod mirror;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod quarantine;
+pub mod random;
+pub mod shard;
+pub mod site_defaults;
+pub mod stream;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod uploads;
+pub mod well_known;
@@ -0,0 +1,614 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        OnceLock,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+
+use crate::http::{
+    json, logging::server_log, random::random_u64, request::HttpRequest, response::HttpStatusCode,
+};
+
+/// Connect, read, and write timeout for the OTLP collector
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest `flush` waits for the exporter thread to send what is queued
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Instrumentation scope reported with every span
+const SCOPE_NAME: &str = "rusttp";
+
+static EXPORTER: OnceLock<SyncSender<Message>> = OnceLock::new();
+
+/// Spans discarded because the export queue was full, not yet reported
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// OpenTelemetry trace export settings; spans are only recorded once an endpoint is set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtelConfig {
+    /// OTLP/HTTP traces endpoint of a collector, e.g. "http://127.0.0.1:4318/v1/traces"
+    pub endpoint: Option<String>,
+    /// Reported as the `service.name` resource attribute
+    pub service_name: String,
+    /// Finished spans waiting for export; beyond this they are dropped and counted
+    pub queue_capacity: usize,
+    /// Most spans sent in one export request
+    pub batch_size: usize,
+    /// Longest a finished span waits before it is exported
+    pub export_interval_ms: u64,
+}
+
+impl Default for OtelConfig {
+    fn default() -> Self {
+        OtelConfig {
+            endpoint: None,
+            service_name: "rusttp".to_string(),
+            queue_capacity: 2048,
+            batch_size: 512,
+            export_interval_ms: 5000,
+        }
+    }
+}
+
+impl OtelConfig {
+    /// Checks values that deserialize fine but cannot be used
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(endpoint) = &self.endpoint {
+            Endpoint::parse(endpoint)?;
+        }
+        if self.queue_capacity == 0 || self.batch_size == 0 {
+            return Err("otel queue_capacity and batch_size must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Parsed `http://host[:port][/path]` collector endpoint
+#[derive(Debug, Clone, PartialEq)]
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    /// Parses a plain-HTTP OTLP endpoint; the path defaults to /v1/traces
+    fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("otel endpoint '{}' must start with http://", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/v1/traces"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| format!("otel endpoint '{}' has an invalid port", url))?,
+            ),
+            None => (authority, 4318),
+        };
+        if host.is_empty() {
+            return Err(format!("otel endpoint '{}' is missing a host", url));
+        }
+
+        Ok(Endpoint {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// What the exporter thread receives
+enum Message {
+    Span(SpanData),
+    Flush(mpsc::Sender<()>), // Acknowledged once everything before it is exported
+}
+
+/// Identifies a span within a trace
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpanContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+}
+
+impl SpanContext {
+    /// Starts a new trace
+    fn root() -> Self {
+        SpanContext {
+            trace_id: u128::from(random_u64()) << 64 | u128::from(random_u64()),
+            span_id: random_u64(),
+        }
+    }
+
+    /// Starts a span in the same trace
+    fn child(&self) -> Self {
+        SpanContext {
+            trace_id: self.trace_id,
+            span_id: random_u64(),
+        }
+    }
+}
+
+/// Parses a W3C `traceparent` header, e.g. `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`
+fn parse_traceparent(value: &str) -> Option<SpanContext> {
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, span_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    if version.len() != 2 || version == "ff" || trace_id.len() != 32 || span_id.len() != 16 {
+        return None;
+    }
+    if flags.len() != 2 || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    u8::from_str_radix(version, 16).ok()?;
+    u8::from_str_radix(flags, 16).ok()?;
+
+    let context = SpanContext {
+        trace_id: u128::from_str_radix(trace_id, 16).ok()?,
+        span_id: u64::from_str_radix(span_id, 16).ok()?,
+    };
+    (context.trace_id != 0 && context.span_id != 0).then_some(context)
+}
+
+/// OTLP span kinds
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SpanKind {
+    Internal = 1,
+    Server = 2,
+}
+
+/// An attribute value; OTLP has more types, these are the ones the server records
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Int(i64),
+}
+
+/// A finished span, ready for export
+#[derive(Debug, Clone, PartialEq)]
+struct SpanData {
+    name: String,
+    kind: SpanKind,
+    context: SpanContext,
+    parent: Option<u64>, // Span id of the parent, in the same trace
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, Value)>,
+    error: bool, // Reported with status code ERROR
+}
+
+/// Starts the exporter thread when an endpoint is configured; without this call no span is
+/// recorded
+pub fn init(config: &OtelConfig) {
+    let Some(url) = &config.endpoint else {
+        return;
+    };
+    if EXPORTER.get().is_some() {
+        return;
+    }
+    // Validated with the rest of the configuration
+    let Ok(endpoint) = Endpoint::parse(url) else {
+        return;
+    };
+
+    let (sender, receiver) = mpsc::sync_channel(config.queue_capacity);
+    let config = config.clone();
+    let spawned = thread::Builder::new()
+        .name("otel-exporter".to_string())
+        .spawn(move || run(receiver, endpoint, config));
+    match spawned {
+        Ok(_) => {
+            let _ = EXPORTER.set(sender);
+            server_log!(Info, "Exporting traces to {}", url);
+        }
+        Err(e) => server_log!(Error, "Failed to start the trace exporter: {}", e),
+    }
+}
+
+/// Waits, up to a bound, until every span finished so far has been exported
+pub fn flush() {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    let (done, exported) = mpsc::channel();
+    if exporter.send(Message::Flush(done)).is_ok() {
+        let _ = exported.recv_timeout(FLUSH_TIMEOUT);
+    }
+}
+
+/// Queues a finished span without waiting; when the queue is full the span is dropped and counted
+fn export(span: SpanData) {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    if let Err(TrySendError::Full(_)) = exporter.try_send(Message::Span(span)) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The span covering a connection from accept until it closes; requests on it are its children
+///
+/// `Default` starts the span, and dropping it ends and exports it
+#[derive(Debug)]
+pub struct ConnectionSpan {
+    context: SpanContext,
+    start: SystemTime,
+    peer: Option<SocketAddr>,
+    requests: u64,
+}
+
+impl Default for ConnectionSpan {
+    fn default() -> Self {
+        ConnectionSpan {
+            context: SpanContext::root(),
+            start: SystemTime::now(),
+            peer: None,
+            requests: 0,
+        }
+    }
+}
+
+impl Drop for ConnectionSpan {
+    fn drop(&mut self) {
+        if EXPORTER.get().is_none() {
+            return;
+        }
+        let mut attributes = peer_attributes(self.peer);
+        attributes.push((
+            "rusttp.connection.requests",
+            Value::Int(self.requests as i64),
+        ));
+        export(SpanData {
+            name: "connection".to_string(),
+            kind: SpanKind::Internal,
+            context: self.context,
+            parent: None,
+            start: self.start,
+            end: SystemTime::now(),
+            attributes,
+            error: false,
+        });
+    }
+}
+
+/// The span covering one request, from parsed headers until its response has been written
+pub struct RequestSpan {
+    context: SpanContext,
+    parent: u64,
+    start: SystemTime,
+    method: String,
+    path: String,
+    peer: Option<SocketAddr>,
+}
+
+impl RequestSpan {
+    /// Starts a request span under the connection's span, or under the caller's span when the
+    /// request carries a valid `traceparent` header
+    pub fn start(
+        connection: &mut ConnectionSpan,
+        request: &HttpRequest,
+        peer: Option<SocketAddr>,
+    ) -> Self {
+        connection.requests += 1;
+        if connection.peer.is_none() {
+            connection.peer = peer;
+        }
+
+        let caller = request
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("traceparent"))
+            .and_then(|(_, value)| parse_traceparent(value));
+        let parent = caller.unwrap_or(connection.context);
+        RequestSpan {
+            context: parent.child(),
+            parent: parent.span_id,
+            start: SystemTime::now(),
+            method: request.status_line.method.to_string(),
+            path: request.status_line.path.clone(),
+            peer,
+        }
+    }
+
+    /// Ends the span with the response's outcome; `route` is the matched route pattern, if any
+    pub fn end(self, status: &HttpStatusCode, bytes: u64, route: Option<&str>) {
+        if EXPORTER.get().is_none() {
+            return;
+        }
+
+        // HTTP server spans are named after the route, never the raw path, to keep names few
+        let name = match route {
+            Some(route) => format!("{} {}", self.method, route),
+            None => self.method.clone(),
+        };
+        let mut attributes = vec![
+            ("http.request.method", Value::Str(self.method)),
+            ("url.path", Value::Str(self.path)),
+            (
+                "http.response.status_code",
+                Value::Int(i64::from(status.code())),
+            ),
+            ("http.response.size", Value::Int(bytes as i64)),
+        ];
+        if let Some(route) = route {
+            attributes.push(("http.route", Value::Str(route.to_string())));
+        }
+        attributes.extend(peer_attributes(self.peer));
+
+        export(SpanData {
+            name,
+            kind: SpanKind::Server,
+            context: self.context,
+            parent: Some(self.parent),
+            start: self.start,
+            end: SystemTime::now(),
+            attributes,
+            error: status.code() >= 500,
+        });
+    }
+}
+
+/// Client address attributes, following the OpenTelemetry network conventions
+fn peer_attributes(peer: Option<SocketAddr>) -> Vec<(&'static str, Value)> {
+    peer.map_or_else(Vec::new, |peer| {
+        vec![
+            ("network.peer.address", Value::Str(peer.ip().to_string())),
+            ("network.peer.port", Value::Int(i64::from(peer.port()))),
+        ]
+    })
+}
+
+/// Exporter thread: batches spans and posts them when a batch fills or the interval passes
+fn run(receiver: Receiver<Message>, endpoint: Endpoint, config: OtelConfig) {
+    let interval = Duration::from_millis(config.export_interval_ms);
+    let mut batch = Vec::new();
+    let mut deadline = Instant::now() + interval;
+    let mut failing = false;
+
+    loop {
+        let message = receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        let flush = match message {
+            Ok(Message::Span(span)) => {
+                batch.push(span);
+                None
+            }
+            Ok(Message::Flush(done)) => Some(done),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                send_batch(&endpoint, &config, &mut batch, &mut failing);
+                return;
+            }
+        };
+
+        if flush.is_some() || batch.len() >= config.batch_size || Instant::now() >= deadline {
+            send_batch(&endpoint, &config, &mut batch, &mut failing);
+            deadline = Instant::now() + interval;
+        }
+        if let Some(done) = flush {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Posts a batch, logging only when exports start or stop failing so a down collector cannot
+/// flood the log
+fn send_batch(
+    endpoint: &Endpoint,
+    config: &OtelConfig,
+    batch: &mut Vec<SpanData>,
+    failing: &mut bool,
+) {
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        server_log!(Warn, "[otel] dropped {} spans: export queue full", dropped);
+    }
+    if batch.is_empty() {
+        return;
+    }
+
+    let body = encode(&config.service_name, batch);
+    match post(endpoint, &body) {
+        Ok(()) if *failing => {
+            *failing = false;
+            server_log!(
+                Info,
+                "[otel] exporting to {}:{} again",
+                endpoint.host,
+                endpoint.port
+            );
+        }
+        Ok(()) => {}
+        Err(e) if !*failing => {
+            *failing = true;
+            server_log!(
+                Warn,
+                "[otel] failed to export {} spans to {}:{}: {}",
+                batch.len(),
+                endpoint.host,
+                endpoint.port,
+                e
+            );
+        }
+        Err(_) => {}
+    }
+    batch.clear();
+}
+
+/// Sends one OTLP/HTTP JSON request and checks for a 2xx answer
+fn post(endpoint: &Endpoint, body: &str) -> io::Result<()> {
+    let addr = (endpoint.host.as_str(), endpoint.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "otel host did not resolve"))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, EXPORT_TIMEOUT)?;
+    stream.set_read_timeout(Some(EXPORT_TIMEOUT))?;
+    stream.set_write_timeout(Some(EXPORT_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        endpoint.path,
+        endpoint.host,
+        endpoint.port,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    match status_line.split(' ').nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "collector answered {:?}",
+            status_line.trim_end()
+        ))),
+    }
+}
+
+/// Encodes spans as an OTLP `ExportTraceServiceRequest` in the protobuf JSON mapping
+fn encode(service_name: &str, spans: &[SpanData]) -> String {
+    let mut out = format!(
+        "{{\"resourceSpans\":[{{\"resource\":{{\"attributes\":[{}]}},\
+         \"scopeSpans\":[{{\"scope\":{{\"name\":{},\"version\":{}}},\"spans\":[",
+        attribute("service.name", &Value::Str(service_name.to_string())),
+        json::quote(SCOPE_NAME),
+        json::quote(env!("CARGO_PKG_VERSION")),
+    );
+    for (i, span) in spans.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(
+            out,
+            "{{\"traceId\":\"{:032x}\",\"spanId\":\"{:016x}\",",
+            span.context.trace_id, span.context.span_id
+        );
+        if let Some(parent) = span.parent {
+            let _ = write!(out, "\"parentSpanId\":\"{:016x}\",", parent);
+        }
+        let attributes: Vec<String> = span
+            .attributes
+            .iter()
+            .map(|(key, value)| attribute(key, value))
+            .collect();
+        let _ = write!(
+            out,
+            "\"name\":{},\"kind\":{},\"startTimeUnixNano\":\"{}\",\"endTimeUnixNano\":\"{}\",\
+             \"attributes\":[{}]",
+            json::quote(&span.name),
+            span.kind as u8,
+            unix_nanos(span.start),
+            unix_nanos(span.end),
+            attributes.join(",")
+        );
+        if span.error {
+            out.push_str(",\"status\":{\"code\":2}");
+        }
+        out.push('}');
+    }
+    out.push_str("]}]}]}");
+    out
+}
+
+/// Encodes one key-value attribute; 64-bit integers are strings in the protobuf JSON mapping
+fn attribute(key: &str, value: &Value) -> String {
+    let value = match value {
+        Value::Str(s) => format!("{{\"stringValue\":{}}}", json::quote(s)),
+        Value::Int(n) => format!("{{\"intValue\":\"{}\"}}", n),
+    };
+    format!("{{\"key\":{},\"value\":{}}}", json::quote(key), value)
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_traceparent_and_endpoint() {
+        assert_eq!(
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some(SpanContext {
+                trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736,
+                span_id: 0x00f067aa0ba902b7,
+            })
+        );
+        assert_eq!(
+            parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(
+            parse_traceparent("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(parse_traceparent("00-abc-def-01"), None);
+
+        assert_eq!(
+            Endpoint::parse("http://collector:4318/v1/traces").unwrap(),
+            Endpoint {
+                host: "collector".to_string(),
+                port: 4318,
+                path: "/v1/traces".to_string(),
+            }
+        );
+        assert_eq!(
+            Endpoint::parse("http://127.0.0.1").unwrap().path,
+            "/v1/traces"
+        );
+        assert!(Endpoint::parse("https://collector").is_err());
+    }
+
+    #[test]
+    fn test_encode_follows_otlp_json() {
+        let start = UNIX_EPOCH + Duration::from_millis(1_709_210_096_250);
+        let span = SpanData {
+            name: "GET /files/{filename..}".to_string(),
+            kind: SpanKind::Server,
+            context: SpanContext {
+                trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736,
+                span_id: 0xab,
+            },
+            parent: Some(0x00f067aa0ba902b7),
+            start,
+            end: start + Duration::from_micros(1500),
+            attributes: vec![
+                ("http.request.method", Value::Str("GET".to_string())),
+                ("http.response.status_code", Value::Int(503)),
+            ],
+            error: true,
+        };
+
+        assert_eq!(
+            encode("web", &[span]),
+            "{\"resourceSpans\":[{\"resource\":{\"attributes\":[{\"key\":\"service.name\",\
+             \"value\":{\"stringValue\":\"web\"}}]},\"scopeSpans\":[{\"scope\":{\"name\":\
+             \"rusttp\",\"version\":\"0.1.0\"},\"spans\":[{\"traceId\":\
+             \"4bf92f3577b34da6a3ce929d0e0e4736\",\"spanId\":\"00000000000000ab\",\
+             \"parentSpanId\":\"00f067aa0ba902b7\",\"name\":\"GET /files/{filename..}\",\
+             \"kind\":2,\"startTimeUnixNano\":\"1709210096250000000\",\
+             \"endTimeUnixNano\":\"1709210096251500000\",\"attributes\":[{\"key\":\
+             \"http.request.method\",\"value\":{\"stringValue\":\"GET\"}},{\"key\":\
+             \"http.response.status_code\",\"value\":{\"intValue\":\"503\"}}],\
+             \"status\":{\"code\":2}}]}]}]}"
+        );
+    }
+}
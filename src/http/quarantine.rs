@@ -0,0 +1,144 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::http::{logging::server_log, request::RequestId};
+
+const DEFAULT_MAX_BYTES: usize = 16 * 1024;
+const DEFAULT_PER_MINUTE: u32 = 10;
+
+/// Length of the window `per_minute` is counted over
+const WINDOW: Duration = Duration::from_secs(60);
+
+static QUARANTINE: OnceLock<Quarantine> = OnceLock::new();
+
+/// Quarantine settings; malformed requests are only dumped when a directory is set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuarantineConfig {
+    /// Directory that receives the raw bytes of requests that failed to parse
+    pub dir: Option<String>,
+    /// Bytes kept from each request; the rest is dropped
+    pub max_bytes: usize,
+    /// Requests dumped per minute at most, so a flood of garbage cannot fill the disk
+    pub per_minute: u32,
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> Self {
+        QuarantineConfig {
+            dir: None,
+            max_bytes: DEFAULT_MAX_BYTES,
+            per_minute: DEFAULT_PER_MINUTE,
+        }
+    }
+}
+
+struct Quarantine {
+    dir: PathBuf,
+    max_bytes: usize,
+    limit: Mutex<RateLimit>,
+}
+
+/// Fixed-window counter allowing `per_window` events per window
+struct RateLimit {
+    per_window: u32,
+    window_start: Instant,
+    used: u32,
+}
+
+impl RateLimit {
+    fn new(per_window: u32, now: Instant) -> Self {
+        RateLimit {
+            per_window,
+            window_start: now,
+            used: 0,
+        }
+    }
+
+    /// Counts an event at `now`, returning false if the current window is used up
+    fn allow(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.used = 0;
+        }
+        if self.used >= self.per_window {
+            return false;
+        }
+        self.used += 1;
+        true
+    }
+}
+
+/// Enables the quarantine directory; without this call nothing is written
+pub fn init(config: &QuarantineConfig) {
+    let Some(dir) = &config.dir else {
+        return;
+    };
+
+    let _ = QUARANTINE.set(Quarantine {
+        dir: PathBuf::from(dir),
+        max_bytes: config.max_bytes,
+        limit: Mutex::new(RateLimit::new(config.per_minute, Instant::now())),
+    });
+    server_log!(Info, "Quarantining malformed requests in {}", dir);
+}
+
+/// Writes the raw bytes of a request that failed to parse, returning the file they went to
+pub fn store(request_bytes: &[u8], req_id: RequestId) -> Option<PathBuf> {
+    let quarantine = QUARANTINE.get()?;
+    let allowed = quarantine
+        .limit
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .allow(Instant::now());
+    if !allowed {
+        server_log!(
+            Debug,
+            req = req_id,
+            "[quarantine] rate limit reached, not saving request"
+        );
+        return None;
+    }
+
+    // The token is unique across restarts, so earlier dumps are never overwritten
+    let path = quarantine
+        .dir
+        .join(format!("request-{}.bin", req_id.token()));
+    let kept = &request_bytes[..request_bytes.len().min(quarantine.max_bytes)];
+
+    match fs::create_dir_all(&quarantine.dir).and_then(|()| fs::write(&path, kept)) {
+        Ok(()) => Some(path),
+        Err(e) => {
+            server_log!(
+                Error,
+                req = req_id,
+                "[quarantine] failed to write {}: {}",
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_resets_each_window() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(2, start);
+
+        assert!(limit.allow(start));
+        assert!(limit.allow(start + Duration::from_secs(1)));
+        assert!(!limit.allow(start + Duration::from_secs(59)));
+        assert!(limit.allow(start + WINDOW));
+    }
+}
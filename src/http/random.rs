@@ -0,0 +1,34 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+/// Returns a random u64; RandomState is seeded randomly per instance, which is enough
+/// for sampling and non-cryptographic identifiers
+pub fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Returns true for roughly `percent`% of calls
+pub fn sampled(percent: f64) -> bool {
+    if percent <= 0.0 {
+        return false;
+    }
+    if percent >= 100.0 {
+        return true;
+    }
+
+    let roll = random_u64() % 10_000;
+    (roll as f64) < percent * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampling_bounds() {
+        assert!(!sampled(0.0));
+        assert!(sampled(100.0));
+    }
+}
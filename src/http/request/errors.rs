@@ -10,6 +10,7 @@ pub struct ParseError {
     pub status: HttpStatusCode,
     pub version: HttpVersion,
     pub headers: HashMap<String, String>,
+    pub detail: Option<&'static str>, // Told to the client instead of a generic message
 }
 
 impl fmt::Display for ParseError {
@@ -0,0 +1,118 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::http::random::random_u64;
+
+/// Response header carrying the request's unique token
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Hex digits in a token: 12 for the millisecond timestamp, 16 for the random part
+const TOKEN_LEN: usize = 28;
+
+/// Identifies a request by its per-process sequence number and a globally unique token
+///
+/// The sequence number keeps log lines short and ordered; the token is what leaves the process,
+/// in the `X-Request-Id` header and in error references, so ids from restarts and replicas never collide
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RequestId {
+    seq: u64,
+    token: u128, // Milliseconds since the epoch in the high bits, random in the low 64
+}
+
+impl RequestId {
+    /// Returns the per-process sequence number shown in log lines
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Returns the unique token as lowercase hex
+    pub fn token(&self) -> String {
+        format!("{:0width$x}", self.token, width = TOKEN_LEN)
+    }
+
+    /// Splits the id into its sequence number and raw token, for storing in atomics
+    pub fn to_raw(&self) -> (u64, u128) {
+        (self.seq, self.token)
+    }
+
+    /// Rebuilds an id from the parts `to_raw` returned
+    pub fn from_raw(seq: u64, token: u128) -> Self {
+        RequestId { seq, token }
+    }
+
+    /// Checks whether this id is the one a sequence number or token refers to
+    pub fn is(&self, reference: &RequestRef) -> bool {
+        match reference {
+            RequestRef::Seq(seq) => self.seq == *seq,
+            RequestRef::Token(token) => self.token == *token,
+        }
+    }
+}
+
+/// A request id as an operator would quote it, from a log line or a response header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestRef {
+    Seq(u64),    // "42"
+    Token(u128), // "0192f3a4b5c6d7e8f9a0b1c2d3e4"
+}
+
+impl RequestRef {
+    /// Parses a sequence number, or a token in the form sent in `X-Request-Id`
+    pub fn parse(raw: &str) -> Option<Self> {
+        if let Ok(seq) = raw.parse::<u64>() {
+            return Some(RequestRef::Seq(seq));
+        }
+        if raw.len() != TOKEN_LEN {
+            return None;
+        }
+        u128::from_str_radix(raw, 16).ok().map(RequestRef::Token)
+    }
+}
+
+/// Hands out request ids; cloned contexts share one generator so sequence numbers stay unique
+#[derive(Debug, Default)]
+pub struct RequestIdGenerator {
+    next: AtomicU64,
+}
+
+impl RequestIdGenerator {
+    /// Creates a generator whose sequence starts at 0
+    pub fn new() -> Self {
+        RequestIdGenerator::default()
+    }
+
+    /// Returns the next id
+    pub fn next(&self) -> RequestId {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        RequestId {
+            seq: self.next.fetch_add(1, Ordering::Relaxed),
+            token: ((millis as u128 & 0xffff_ffff_ffff) << 64) | random_u64() as u128,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_are_sequential_with_unique_tokens() {
+        let ids = RequestIdGenerator::new();
+        let (first, second) = (ids.next(), ids.next());
+
+        assert_eq!((first.seq(), second.seq()), (0, 1));
+        assert_ne!(first.token(), second.token());
+        assert_eq!(first.token().len(), TOKEN_LEN);
+
+        assert!(first.is(&RequestRef::parse("0").unwrap()));
+        assert!(first.is(&RequestRef::parse(&first.token()).unwrap()));
+        assert!(!second.is(&RequestRef::parse(&first.token()).unwrap()));
+        assert_eq!(RequestRef::parse("abc"), None);
+    }
+}
@@ -1,6 +1,8 @@
 pub mod errors;
+pub mod id;
 pub mod parser;
 pub mod types;
 
+pub use id::{RequestId, RequestIdGenerator};
 pub use parser::HttpRequest;
 pub use types::{HttpMethod, HttpVersion};
\ No newline at end of file
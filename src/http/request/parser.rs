@@ -15,11 +15,11 @@ pub struct HttpRequest {
 
 impl fmt::Display for HttpRequest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{} {} {}\r\n",
-            self.status_line.method, self.status_line.path, self.status_line.version
-        )?;
+        write!(f, "{} {}", self.status_line.method, self.status_line.path)?;
+        if let Some(query) = &self.status_line.query {
+            write!(f, "?{}", query)?;
+        }
+        write!(f, " {}\r\n", self.status_line.version)?;
 
         let mut headers: Vec<_> = self.headers.iter().collect();
         headers.sort_by_key(|(key, _)| *key);
@@ -46,6 +46,16 @@ impl HttpRequest {
                 status: HttpStatusCode::BadRequest,
                 version: HttpVersion::Http1_0,
                 headers: HashMap::new(),
+                detail: None,
+            });
+        }
+
+        if Self::is_simple_request(request) {
+            return Err(ParseError {
+                status: HttpStatusCode::HttpVersionNotSupported,
+                version: HttpVersion::Http1_1,
+                headers: HashMap::new(),
+                detail: Some("HTTP/0.9 is not supported; use HTTP/1.0 or HTTP/1.1"),
             });
         }
 
@@ -53,6 +63,7 @@ impl HttpRequest {
             status: HttpStatusCode::BadRequest,
             version: HttpVersion::Http1_0,
             headers: HashMap::new(),
+            detail: None,
         })?;
 
         let (header_bytes, body_bytes) = request.split_at(boundary);
@@ -72,6 +83,7 @@ impl HttpRequest {
                     status: HttpStatusCode::BadRequest,
                     version: HttpVersion::Http1_0,
                     headers,
+                    detail: None,
                 });
             }
         }
@@ -87,17 +99,36 @@ impl HttpRequest {
                 status: HttpStatusCode::BadRequest,
                 version: HttpVersion::Http1_0,
                 headers,
+                detail: None,
             });
         }
 
-        let parsed_version = match request_line[2] {
-            "HTTP/1.0" => HttpVersion::Http1_0,
-            "HTTP/1.1" => HttpVersion::Http1_1,
-            _ => {
+        // The connection preface of a client speaking HTTP/2 without negotiating it first
+        if request_line == ["PRI", "*", "HTTP/2.0"] {
+            return Err(ParseError {
+                status: HttpStatusCode::BadRequest,
+                version: HttpVersion::Http1_1,
+                headers,
+                detail: Some("HTTP/2 is not supported on this port; use HTTP/1.1"),
+            });
+        }
+
+        let parsed_version = match parse_version(request_line[2]) {
+            Ok(version) => version,
+            Err(HttpStatusCode::HttpVersionNotSupported) => {
                 return Err(ParseError {
-                    status: HttpStatusCode::BadRequest,
+                    status: HttpStatusCode::HttpVersionNotSupported,
+                    version: HttpVersion::Http1_1,
+                    headers,
+                    detail: Some("Only HTTP/1.0 and HTTP/1.1 are supported"),
+                })
+            }
+            Err(status) => {
+                return Err(ParseError {
+                    status,
                     version: HttpVersion::Http1_0,
                     headers,
+                    detail: None,
                 })
             }
         };
@@ -112,15 +143,20 @@ impl HttpRequest {
                     status: HttpStatusCode::MethodNotAllowed,
                     version: parsed_version,
                     headers,
+                    detail: None,
                 })
             }
         };
 
-        let path = request_line[1].to_string();
+        let (path, query) = match request_line[1].split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (request_line[1].to_string(), None),
+        };
 
         let status_line = RequestStatusLine {
             method: method.clone(),
-            path: path.clone(),
+            path,
+            query,
             version: parsed_version.clone(),
         };
 
@@ -138,6 +174,37 @@ impl HttpRequest {
         Ok(request)
     }
 
+    /// Checks whether the bytes are an HTTP/0.9 simple request: a single line of just a method and
+    /// a path, which never gets the blank line that ends an HTTP/1.x head
+    pub fn is_simple_request(bytes: &[u8]) -> bool {
+        let Some(line) = bytes
+            .strip_suffix(b"\r\n")
+            .or_else(|| bytes.strip_suffix(b"\n"))
+        else {
+            return false;
+        };
+        !line.contains(&b'\n') && String::from_utf8_lossy(line).split_whitespace().count() == 2
+    }
+
+    /// Returns the first value of a query string parameter, e.g. `w` in `?w=200`
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.status_line
+            .query
+            .as_deref()?
+            .split('&')
+            .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns the Host header, matched case-insensitively
+    pub fn host(&self) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("Host"))
+            .map(|(_, value)| value.as_str())
+    }
+
     /// Locates the boundary between headers and body in raw HTTP request bytes
     fn find_boundary(bytes: &[u8]) -> Option<usize> {
         bytes.windows(4).position(|window| window == b"\r\n\r\n")
@@ -152,6 +219,27 @@ impl HttpRequest {
     }
 }
 
+/// Reads an `HTTP/major.minor` version token
+///
+/// Later 1.x minors are served as 1.1, which RFC 9112 says they understand. A well-formed version
+/// with another major gets 505; anything else is malformed
+fn parse_version(token: &str) -> Result<HttpVersion, HttpStatusCode> {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let Some(number) = token.strip_prefix("HTTP/") else {
+        return Err(HttpStatusCode::BadRequest);
+    };
+    match number.split_once('.') {
+        Some(("1", "0")) => Ok(HttpVersion::Http1_0),
+        Some(("1", minor)) if digits(minor) => Ok(HttpVersion::Http1_1),
+        Some((major, minor)) if digits(major) && digits(minor) => {
+            Err(HttpStatusCode::HttpVersionNotSupported)
+        }
+        // "HTTP/2" and "HTTP/3", as those versions write themselves
+        None if digits(number) && number != "1" => Err(HttpStatusCode::HttpVersionNotSupported),
+        _ => Err(HttpStatusCode::BadRequest),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -166,7 +254,7 @@ mod tests {
 
         assert_eq!(request.status_line.method, HttpMethod::Get);
         assert_eq!(request.status_line.path, "/");
-        assert_eq!(request.status_line.version, HttpVersion::Http1_0);
+        assert_eq!(request.status_line.version, HttpVersion::Http1_1);
         assert_eq!(request.headers.get("Host").unwrap(), "localhost");
         assert_eq!(request.headers.get("User-Agent").unwrap(), "curl/7.64.1");
         assert_eq!(request.headers.get("Accept").unwrap(), "*/*");
@@ -184,6 +272,7 @@ mod tests {
                 status: HttpStatusCode::MethodNotAllowed,
                 version: HttpVersion::Http1_1,
                 headers: HashMap::from([("Host".to_string(), "localhost".to_string())]),
+                detail: None,
             }
         );
     }
@@ -196,13 +285,48 @@ mod tests {
         assert_eq!(
             result.unwrap_err(),
             ParseError {
-                status: HttpStatusCode::BadRequest,
-                version: HttpVersion::Http1_0,
+                status: HttpStatusCode::HttpVersionNotSupported,
+                version: HttpVersion::Http1_1,
                 headers: HashMap::from([("Host".to_string(), "localhost".to_string())]),
+                detail: Some("Only HTTP/1.0 and HTTP/1.1 are supported"),
             }
         );
     }
 
+    #[test]
+    fn test_parse_version_wire_forms() {
+        let status = |raw: &[u8]| HttpRequest::parse(raw).map(|r| r.status_line.version);
+
+        assert_eq!(status(b"GET / HTTP/1.2\r\n\r\n"), Ok(HttpVersion::Http1_1));
+        for unsupported in [&b"GET / HTTP/3\r\n\r\n"[..], b"GET / HTTP/0.9\r\n\r\n"] {
+            assert_eq!(
+                status(unsupported).unwrap_err().status,
+                HttpStatusCode::HttpVersionNotSupported
+            );
+        }
+        for malformed in [
+            &b"GET / HTTP/x\r\n\r\n"[..],
+            b"GET / HTTP/1\r\n\r\n",
+            b"GET / FTP/1.1\r\n\r\n",
+        ] {
+            assert_eq!(
+                status(malformed).unwrap_err().status,
+                HttpStatusCode::BadRequest
+            );
+        }
+
+        // HTTP/0.9 sends one line and waits, so the head must count as complete without a blank line
+        assert!(HttpRequest::is_simple_request(b"GET /index.html\r\n"));
+        assert!(!HttpRequest::is_simple_request(b"GET /index.html"));
+        assert!(!HttpRequest::is_simple_request(b"GET / HTTP/1.1\r\n"));
+        let simple = HttpRequest::parse(b"GET /index.html\r\n").unwrap_err();
+        assert_eq!(simple.status, HttpStatusCode::HttpVersionNotSupported);
+
+        let preface = HttpRequest::parse(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").unwrap_err();
+        assert_eq!(preface.status, HttpStatusCode::BadRequest);
+        assert!(preface.detail.unwrap().contains("HTTP/2"));
+    }
+
     #[test]
     fn test_parse_invalid_target() {
         let request_bytes = b"GET /noexist HTTP/1.1\r\nHost: localhost\r\n\r\n";
@@ -222,6 +346,7 @@ mod tests {
                 status: HttpStatusCode::BadRequest,
                 version: HttpVersion::Http1_0,
                 headers: HashMap::from([("Host".to_string(), "localhost".to_string())]),
+                detail: None,
             }
         );
     }
@@ -237,6 +362,7 @@ mod tests {
                 status: HttpStatusCode::BadRequest,
                 version: HttpVersion::Http1_0,
                 headers: HashMap::new(),
+                detail: None,
             }
         );
     }
@@ -249,10 +375,23 @@ mod tests {
 
         assert_eq!(request.status_line.method, HttpMethod::Get);
         assert_eq!(request.status_line.path, "/");
-        assert_eq!(request.status_line.version, HttpVersion::Http1_0);
+        assert_eq!(request.status_line.version, HttpVersion::Http1_1);
         assert!(request.headers.is_empty());
     }
 
+    #[test]
+    fn test_parse_query_string() {
+        let request_bytes = b"GET /thumb/cat.png?w=200&fit HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        let request = HttpRequest::parse(request_bytes).unwrap();
+
+        assert_eq!(request.status_line.path, "/thumb/cat.png");
+        assert_eq!(request.status_line.query.as_deref(), Some("w=200&fit"));
+        assert_eq!(request.query_param("w"), Some("200"));
+        assert_eq!(request.query_param("fit"), Some(""));
+        assert_eq!(request.query_param("h"), None);
+    }
+
     #[test]
     fn test_http_method_display() {
         let methods: Vec<HttpMethod> = vec![
@@ -285,7 +424,8 @@ mod tests {
         let status_line = RequestStatusLine {
             method: HttpMethod::Get,
             path: "/".to_string(),
-            version: HttpVersion::Http1_0,
+            query: None,
+            version: HttpVersion::Http1_1,
         };
 
         let request = HttpRequest {
@@ -307,7 +447,8 @@ mod tests {
         let status_line = RequestStatusLine {
             method: HttpMethod::Get,
             path: "/".to_string(),
-            version: HttpVersion::Http1_0,
+            query: None,
+            version: HttpVersion::Http1_1,
         };
 
         let request = HttpRequest {
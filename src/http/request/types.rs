@@ -40,6 +40,7 @@ impl fmt::Display for HttpVersion {
 #[derive(Debug, Clone)]
 pub struct RequestStatusLine {
     pub method: HttpMethod,
-    pub path: String,
+    pub path: String,          // "/thumb/cat.png"
+    pub query: Option<String>, // "w=200"
     pub version: HttpVersion,
 }
\ No newline at end of file
@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 use std::fmt;
 
-use super::types::ResponseStatusLine;
-use crate::http::writer::{HttpBody, HttpWritable};
+use super::types::{HttpContentType, HttpStatusCode, ResponseStatusLine};
+use crate::http::{
+    errors::HttpError,
+    request::HttpVersion,
+    writer::{validate_header, HttpBody, HttpWritable},
+};
 
 /// Represents an HTTP response
 #[derive(Debug, Clone)]
@@ -46,7 +50,7 @@ impl fmt::Display for HttpResponse {
         if let Some(body) = &self.body {
             write!(f, "{}", body)?;
         }
-        
+
         Ok(())
     }
 }
@@ -64,4 +68,237 @@ impl HttpResponse {
             body,
         }
     }
+
+    /// Starts building a response: 200 OK over HTTP/1.1 with no headers or body
+    pub fn builder() -> HttpResponseBuilder {
+        HttpResponseBuilder {
+            version: HttpVersion::Http1_1,
+            status: HttpStatusCode::Ok,
+            headers: HashMap::new(),
+            body: None,
+        }
+    }
+
+    /// Copies any writable response, such as a compressed or error response, into an HttpResponse
+    pub fn from_writable<T: HttpWritable>(response: T) -> Self {
+        HttpResponse {
+            status_line: response.status_line().clone(),
+            headers: response.headers(),
+            body: Some(response.body()),
+        }
+    }
+
+    /// Creates a redirect to `location` with a short plain-text body
+    ///
+    /// 301 and 308 stay cacheable by default, as permanent moves should; every other status is
+    /// sent with `Cache-Control: no-cache` so clients ask again before following it
+    pub fn redirect(status: HttpStatusCode, location: &str) -> Self {
+        let body = HttpBody::Text(format!("Redirecting to {}\n", location));
+        let mut headers = HashMap::from([
+            ("Location".to_string(), location.to_string()),
+            (
+                "Content-Type".to_string(),
+                HttpContentType::PlainText.to_string(),
+            ),
+            ("Content-Length".to_string(), body.byte_len().to_string()),
+        ]);
+        if !matches!(
+            status,
+            HttpStatusCode::MovedPermanently | HttpStatusCode::PermanentRedirect
+        ) {
+            headers.insert("Cache-Control".to_string(), "no-cache".to_string());
+        }
+        let status_line = ResponseStatusLine {
+            version: HttpVersion::Http1_1,
+            status,
+        };
+        HttpResponse::new(status_line, headers, Some(body))
+    }
+
+    /// Creates a 308 redirect, which clients follow with the same method and body
+    pub fn permanent_redirect(location: &str) -> Self {
+        Self::redirect(HttpStatusCode::PermanentRedirect, location)
+    }
+
+    /// Creates a 303 redirect, which clients follow with a GET, e.g. after a form POST
+    pub fn see_other(location: &str) -> Self {
+        Self::redirect(HttpStatusCode::SeeOther, location)
+    }
+}
+
+/// Assembles an HttpResponse one part at a time; `build` adds Content-Length and checks the result
+#[derive(Debug)]
+pub struct HttpResponseBuilder {
+    version: HttpVersion,
+    status: HttpStatusCode,
+    headers: HashMap<String, String>,
+    body: Option<HttpBody>,
+}
+
+impl HttpResponseBuilder {
+    /// Sets the status code
+    pub fn status(mut self, status: HttpStatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Sets the HTTP version, usually the one the request was sent with
+    pub fn version(mut self, version: HttpVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets a header, replacing any value set under the same name in another case
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers
+            .retain(|key, _| !key.eq_ignore_ascii_case(name));
+        self.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Sets a text body, sent as plain text unless a Content-Type header says otherwise
+    pub fn text_body(mut self, text: impl Into<String>) -> Self {
+        if !self.has_header("Content-Type") {
+            self.headers.insert(
+                "Content-Type".to_string(),
+                HttpContentType::PlainText.to_string(),
+            );
+        }
+        self.body = Some(HttpBody::Text(text.into()));
+        self
+    }
+
+    /// Sets the body as is; pair it with a Content-Type header
+    pub fn body(mut self, body: HttpBody) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    /// Finishes the response, setting Content-Length from the body unless it is sent chunked
+    ///
+    /// Fails when a header could not be sent as written or the status forbids the body
+    pub fn build(mut self) -> Result<HttpResponse, BuildError> {
+        if let Some((name, _)) = self
+            .headers
+            .iter()
+            .find(|(name, value)| validate_header(name, value).is_err())
+        {
+            return Err(BuildError::InvalidHeader(name.clone()));
+        }
+        let body_len = self.body.as_ref().map_or(0, HttpBody::byte_len);
+        if !self.status.allows_body() {
+            if body_len > 0 {
+                return Err(BuildError::BodyNotAllowed(self.status));
+            }
+        } else if !self.has_header("Transfer-Encoding") {
+            self.headers
+                .retain(|key, _| !key.eq_ignore_ascii_case("Content-Length"));
+            self.headers
+                .insert("Content-Length".to_string(), body_len.to_string());
+        }
+
+        let status_line = ResponseStatusLine {
+            version: self.version,
+            status: self.status,
+        };
+        Ok(HttpResponse::new(status_line, self.headers, self.body))
+    }
+
+    fn has_header(&self, name: &str) -> bool {
+        self.headers
+            .keys()
+            .any(|key| key.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Represents why a built response was refused
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+    InvalidHeader(String), // Name is not a token, or the value holds CR, LF, or NUL
+    BodyNotAllowed(HttpStatusCode), // 1xx, 204, and 304 responses never carry a body
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::InvalidHeader(name) => write!(f, "header {:?} cannot be sent", name),
+            BuildError::BodyNotAllowed(status) => write!(f, "a {} response has no body", status),
+        }
+    }
+}
+
+impl From<BuildError> for HttpError {
+    fn from(error: BuildError) -> Self {
+        HttpError::new(HttpStatusCode::InternalServerError, error.to_string())
+    }
+}
+
+/// Adds `field` to a response's `Vary` header, keeping the fields already listed there
+pub fn append_vary(headers: &mut HashMap<String, String>, field: &str) {
+    let existing = headers
+        .keys()
+        .find(|key| key.eq_ignore_ascii_case("Vary"))
+        .cloned();
+    let Some(key) = existing else {
+        headers.insert("Vary".to_string(), field.to_string());
+        return;
+    };
+    let value = headers.entry(key).or_default();
+    let listed = value
+        .split(',')
+        .map(str::trim)
+        .any(|listed| listed == "*" || listed.eq_ignore_ascii_case(field));
+    if !listed {
+        if !value.trim().is_empty() {
+            value.push_str(", ");
+        }
+        value.push_str(field);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_sets_content_length_and_rejects_bad_responses() {
+        let response = HttpResponse::builder()
+            .header("X-Foo", "bar")
+            .header("content-length", "99")
+            .text_body("hi")
+            .build()
+            .unwrap();
+        assert_eq!(response.status_line.status, HttpStatusCode::Ok);
+        assert_eq!(response.headers["X-Foo"], "bar");
+        assert_eq!(response.headers["Content-Length"], "2");
+        assert!(!response.headers.contains_key("content-length"));
+        assert_eq!(
+            response.headers["Content-Type"],
+            "text/plain; charset=utf-8"
+        );
+
+        let empty = HttpResponse::builder()
+            .status(HttpStatusCode::NoContent)
+            .build()
+            .unwrap();
+        assert!(!empty.headers.contains_key("Content-Length"));
+
+        assert_eq!(
+            HttpResponse::builder()
+                .status(HttpStatusCode::NotModified)
+                .text_body("stale")
+                .build()
+                .unwrap_err(),
+            BuildError::BodyNotAllowed(HttpStatusCode::NotModified)
+        );
+        for (name, value) in [("X-Foo", "a\r\nSet-Cookie: b"), ("Bad Name", "a")] {
+            assert_eq!(
+                HttpResponse::builder()
+                    .header(name, value)
+                    .build()
+                    .unwrap_err(),
+                BuildError::InvalidHeader(name.to_string())
+            );
+        }
+    }
 }
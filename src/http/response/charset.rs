@@ -0,0 +1,196 @@
+use serde::Deserialize;
+
+/// Character encodings text bodies can be stored in and sent as
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum Charset {
+    #[default]
+    #[serde(rename = "utf-8", alias = "utf8")]
+    Utf8,
+    #[serde(rename = "iso-8859-1", alias = "latin1")]
+    Latin1,
+    #[serde(rename = "us-ascii", alias = "ascii")]
+    Ascii,
+}
+
+/// Charsets a body may be transcoded to, preferred in this order when the client ranks them equally
+const SUPPORTED_CHARSETS: [Charset; 3] = [Charset::Utf8, Charset::Latin1, Charset::Ascii];
+
+impl Charset {
+    /// Returns the name sent in a Content-Type `charset` parameter
+    pub fn label(&self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            Charset::Latin1 => "iso-8859-1",
+            Charset::Ascii => "us-ascii",
+        }
+    }
+
+    /// Looks up a charset by any of its common names, case-insensitively
+    pub fn from_label(label: &str) -> Option<Charset> {
+        match label.trim().trim_matches('"').to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Charset::Utf8),
+            "iso-8859-1" | "iso_8859-1" | "latin1" | "l1" => Some(Charset::Latin1),
+            "us-ascii" | "ascii" => Some(Charset::Ascii),
+            _ => None,
+        }
+    }
+
+    /// Decodes text stored in this charset, or `None` when the bytes are not valid in it
+    pub fn decode(&self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Charset::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+            Charset::Latin1 => Some(bytes.iter().map(|&b| char::from(b)).collect()),
+            Charset::Ascii => bytes
+                .is_ascii()
+                .then(|| bytes.iter().map(|&b| char::from(b)).collect()),
+        }
+    }
+
+    /// Encodes text in this charset, or `None` when it holds a character the charset lacks
+    pub fn encode(&self, text: &str) -> Option<Vec<u8>> {
+        let limit = match self {
+            Charset::Utf8 => return Some(text.as_bytes().to_vec()),
+            Charset::Latin1 => 0xFF,
+            Charset::Ascii => 0x7F,
+        };
+        text.chars()
+            .map(|c| {
+                u8::try_from(u32::from(c))
+                    .ok()
+                    .filter(|&b| u32::from(b) <= limit)
+            })
+            .collect()
+    }
+
+    /// Re-encodes a body stored in this charset as `target`
+    pub fn transcode(&self, bytes: &[u8], target: Charset) -> Option<Vec<u8>> {
+        target.encode(&self.decode(bytes)?)
+    }
+}
+
+/// Returns whether a Content-Type carries text whose charset matters: `text/*`, JSON, JavaScript,
+/// and XML, `+json` and `+xml` types included
+pub fn is_text(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json" | "application/javascript" | "application/xml"
+        )
+}
+
+/// Returns the charset a Content-Type declares, e.g. Latin-1 for `text/html; charset=ISO-8859-1`
+pub fn declared(content_type: &str) -> Option<Charset> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Charset::from_label(value))
+}
+
+/// Returns a Content-Type with its `charset` parameter set to `charset`, replacing any other
+pub fn with_charset(content_type: &str, charset: Charset) -> String {
+    let mut parts = content_type.split(';').map(str::trim);
+    let mime = parts.next().unwrap_or_default();
+    let params = parts.filter(|param| {
+        !param
+            .split_once('=')
+            .is_some_and(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+    });
+    std::iter::once(mime.to_string())
+        .chain(params.map(str::to_string))
+        .chain(std::iter::once(format!("charset={}", charset.label())))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Picks the charset to send text in from an Accept-Charset header, or `None` when it accepts
+/// none this server can produce
+///
+/// Charsets the header does not list take the quality of `*`, or 0 without one. Ties go to
+/// `current`, the charset the body is already in, so nothing is transcoded needlessly
+pub fn negotiate(accept_charset: Option<&str>, current: Charset) -> Option<Charset> {
+    let Some(header) = accept_charset else {
+        return Some(current);
+    };
+    let ranked: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let name = parts.next().filter(|name| !name.is_empty())?;
+            let quality = parts
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map_or(1.0, |(_, q)| q.trim().parse::<f32>().unwrap_or(1.0));
+            Some((name, quality))
+        })
+        .collect();
+    let quality = |charset: Charset| {
+        ranked
+            .iter()
+            .find(|(name, _)| Charset::from_label(name) == Some(charset))
+            .or_else(|| ranked.iter().find(|(name, _)| *name == "*"))
+            .map_or(0.0, |(_, q)| *q)
+    };
+
+    std::iter::once(current)
+        .chain(SUPPORTED_CHARSETS)
+        .map(|charset| (charset, quality(charset)))
+        .filter(|(_, q)| *q > 0.0)
+        .fold(None, |best: Option<(Charset, f32)>, candidate| match best {
+            Some((_, q)) if q >= candidate.1 => best,
+            _ => Some(candidate),
+        })
+        .map(|(charset, _)| charset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_and_transcode() {
+        let utf8 = Charset::Utf8;
+        assert_eq!(negotiate(None, Charset::Latin1), Some(Charset::Latin1));
+        assert_eq!(
+            negotiate(Some("iso-8859-1, utf-8;q=0.5"), utf8),
+            Some(Charset::Latin1)
+        );
+        assert_eq!(negotiate(Some("ISO-8859-1, *"), utf8), Some(utf8));
+        assert_eq!(
+            negotiate(Some("us-ascii;q=0.2, *;q=0.1"), utf8),
+            Some(Charset::Ascii)
+        );
+        assert_eq!(negotiate(Some("koi8-r"), utf8), None);
+        assert_eq!(negotiate(Some("*, utf-8;q=0"), utf8), Some(Charset::Latin1));
+
+        assert_eq!(
+            utf8.transcode("café".as_bytes(), Charset::Latin1),
+            Some(b"caf\xe9".to_vec())
+        );
+        assert_eq!(
+            Charset::Latin1.transcode(b"caf\xe9", utf8),
+            Some("café".as_bytes().to_vec())
+        );
+        assert_eq!(utf8.transcode("café".as_bytes(), Charset::Ascii), None);
+        assert_eq!(utf8.transcode(b"\xff", Charset::Latin1), None);
+
+        assert_eq!(
+            declared("text/html; Charset=\"ISO-8859-1\""),
+            Some(Charset::Latin1)
+        );
+        assert_eq!(
+            with_charset("text/plain; charset=utf-8; format=flowed", Charset::Latin1),
+            "text/plain; format=flowed; charset=iso-8859-1"
+        );
+        assert!(is_text("application/ld+json") && !is_text("image/png"));
+    }
+}
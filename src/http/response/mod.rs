@@ -1,7 +1,8 @@
 pub mod builder;
+pub mod charset;
 pub mod negotiation;
 pub mod types;
 
-pub use builder::HttpResponse;
+pub use builder::{append_vary, HttpResponse};
 pub use negotiation::ContentNegotiable;
 pub use types::{HttpContentType, HttpStatusCode, ResponseStatusLine};
\ No newline at end of file
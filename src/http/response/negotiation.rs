@@ -1,12 +1,21 @@
 use std::collections::HashMap;
 use std::path::Path;
 
-use super::builder::HttpResponse;
+use super::builder::{append_vary, HttpResponse};
 use super::types::{HttpContentType, HttpStatusCode, ResponseStatusLine};
 use crate::http::files::mime::mime_type_from_extension;
+use crate::http::json;
 use crate::http::request::HttpVersion;
 use crate::http::writer::types::HttpBody;
 
+/// Types a negotiated response can take, in the order preferred when the client ranks them equally
+const SUPPORTED_TYPES: [HttpContentType; 4] = [
+    HttpContentType::PlainText,
+    HttpContentType::Html,
+    HttpContentType::Json,
+    HttpContentType::OctetStream,
+];
+
 /// Trait for content negotiation.
 pub trait ContentNegotiable {
     /// Negotiates on a per-file basis
@@ -68,11 +77,11 @@ impl ContentNegotiable for HttpResponse {
         ]);
 
         let body = match content {
-            HttpBody::Binary(data) => data,
-            HttpBody::Text(text) => text.as_bytes().to_vec(),
+            HttpBody::Text(text) => HttpBody::Binary(text.into_bytes()),
+            body => body,
         };
 
-        HttpResponse::new(status_line, headers, Some(HttpBody::Binary(body)))
+        HttpResponse::new(status_line, headers, Some(body))
     }
 
     fn for_file_error(
@@ -82,7 +91,7 @@ impl ContentNegotiable for HttpResponse {
         _filename: &str,
         content: String,
     ) -> Self {
-        let content_type = "text/plain";
+        let content_type = HttpContentType::PlainText;
 
         let status_line = ResponseStatusLine {
             version,
@@ -120,9 +129,9 @@ impl ContentNegotiable for HttpResponse {
                 status_code, content
             ))),
             HttpContentType::Json => Some(HttpBody::Text(format!(
-                r#"{{"message": "{}", "code": {}}}"#,
-                content,
-                status_code.clone() as u16
+                r#"{{"message": {}, "code": {}}}"#,
+                json::quote(&content),
+                status_code.code()
             ))),
             HttpContentType::PlainText => Some(HttpBody::Text(content)),
             HttpContentType::OctetStream => None,
@@ -131,6 +140,7 @@ impl ContentNegotiable for HttpResponse {
         let mut headers = HashMap::new();
 
         headers.insert("Content-Type".to_string(), accepted_type.to_string());
+        append_vary(&mut headers, "Accept");
 
         let connection_value = if connection_header.eq_ignore_ascii_case("close") {
             "close"
@@ -159,3 +169,113 @@ impl ContentNegotiable for HttpResponse {
         HttpResponse::new(status_line, headers, body)
     }
 }
+
+/// One media range from an Accept header, e.g. `text/*;q=0.5`
+struct MediaRange<'a> {
+    kind: &'a str,    // `*` for any type
+    subtype: &'a str, // `*` for any subtype
+    quality: f32,
+}
+
+impl MediaRange<'_> {
+    /// Returns how precisely this range names `media_type`: 2 for an exact match, 1 for
+    /// `type/*`, 0 for `*/*`, or `None` when it does not cover it
+    fn specificity(&self, media_type: &str) -> Option<u8> {
+        let (kind, subtype) = media_type.split_once('/')?;
+        match (self.kind, self.subtype) {
+            ("*", "*") => Some(0),
+            (k, "*") if k.eq_ignore_ascii_case(kind) => Some(1),
+            (k, s) if k.eq_ignore_ascii_case(kind) && s.eq_ignore_ascii_case(subtype) => Some(2),
+            _ => None,
+        }
+    }
+}
+
+/// Splits an Accept header into media ranges, skipping malformed entries
+fn media_ranges(accept: &str) -> Vec<MediaRange<'_>> {
+    accept
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let (kind, subtype) = parts.next()?.split_once('/')?;
+            if kind.is_empty() || subtype.is_empty() || (kind == "*" && subtype != "*") {
+                return None;
+            }
+            let quality = parts
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map_or(1.0, |(_, q)| q.trim().parse::<f32>().unwrap_or(1.0))
+                .clamp(0.0, 1.0);
+            Some(MediaRange {
+                kind,
+                subtype,
+                quality,
+            })
+        })
+        .collect()
+}
+
+/// Picks the supported type the Accept header ranks highest, or `None` when it accepts none
+///
+/// Each type takes the quality of the most specific range covering it, so
+/// `text/*;q=0.2, text/html` prefers HTML; ties go to the range listed first, then to the
+/// order the types are supported in: plain text, HTML, JSON, then binary
+pub fn preferred_type(accept: &str) -> Option<HttpContentType> {
+    let ranges = media_ranges(accept);
+    let mut best: Option<(HttpContentType, f32, u8, usize)> = None;
+
+    for content_type in SUPPORTED_TYPES {
+        let media_type = content_type.media_type();
+        let matched = ranges
+            .iter()
+            .enumerate()
+            .filter_map(|(index, range)| Some((range.specificity(media_type)?, index, range)))
+            .max_by_key(|&(specificity, index, _)| (specificity, std::cmp::Reverse(index)));
+        let Some((specificity, index, range)) = matched else {
+            continue;
+        };
+        if range.quality <= 0.0 {
+            continue;
+        }
+        let better = best.map_or(true, |(_, quality, best_specificity, best_index)| {
+            (range.quality, specificity, std::cmp::Reverse(index))
+                > (quality, best_specificity, std::cmp::Reverse(best_index))
+        });
+        if better {
+            best = Some((content_type, range.quality, specificity, index));
+        }
+    }
+
+    best.map(|(content_type, ..)| content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preferred_type_weighs_ranges_by_quality_and_specificity() {
+        let cases = [
+            (
+                "text/html,application/json;q=0.9,*/*;q=0.8",
+                Some(HttpContentType::Html),
+            ),
+            (
+                "text/html;q=0.5, application/json",
+                Some(HttpContentType::Json),
+            ),
+            ("application/json, text/html", Some(HttpContentType::Json)),
+            ("*/*", Some(HttpContentType::PlainText)),
+            ("text/*;q=0.2, text/html", Some(HttpContentType::Html)),
+            ("application/*", Some(HttpContentType::Json)),
+            ("*/*;q=0.1, text/plain;q=0", Some(HttpContentType::Html)),
+            ("TEXT/HTML; Q=0.7", Some(HttpContentType::Html)),
+            ("image/png", None),
+            ("text/*;q=0, application/*;q=0", None),
+            ("", None),
+        ];
+        for (accept, expected) in cases {
+            assert_eq!(preferred_type(accept), expected, "{}", accept);
+        }
+    }
+}
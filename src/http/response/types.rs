@@ -1,9 +1,11 @@
 #![allow(dead_code)]
 use std::fmt;
 
+use super::negotiation;
 use crate::http::request::HttpVersion;
 
 /// Represents common HTTP content types
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum HttpContentType {
     Html,
     Json,
@@ -12,25 +14,28 @@ pub enum HttpContentType {
 }
 
 impl HttpContentType {
-    /// Returns HttpContentType from Accept header string
-    pub fn from_accept_header(type_str: &str) -> Self {
-        match type_str {
-            "text/html" => HttpContentType::Html,
-            "application/json" => HttpContentType::Json,
-            "text/plain" => HttpContentType::PlainText,
-            "application/octet-stream" => HttpContentType::OctetStream,
-            _ => HttpContentType::PlainText, // default to plain text
+    /// Returns the bare media type, without parameters, e.g. `text/html`
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            HttpContentType::Html => "text/html",
+            HttpContentType::Json => "application/json",
+            HttpContentType::PlainText => "text/plain",
+            HttpContentType::OctetStream => "application/octet-stream",
         }
     }
+
+    /// Returns the type the Accept header ranks highest, or plain text when it accepts none
+    pub fn from_accept_header(accept: &str) -> Self {
+        negotiation::preferred_type(accept).unwrap_or(HttpContentType::PlainText)
+    }
 }
 
+/// Formats the Content-Type header value, declaring UTF-8 on text types
 impl fmt::Display for HttpContentType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            HttpContentType::Html => write!(f, "text/html"),
-            HttpContentType::Json => write!(f, "application/json"),
-            HttpContentType::PlainText => write!(f, "text/plain"),
-            HttpContentType::OctetStream => write!(f, "application/octet-stream"),
+            HttpContentType::OctetStream => write!(f, "{}", self.media_type()),
+            _ => write!(f, "{}; charset=utf-8", self.media_type()),
         }
     }
 }
@@ -38,34 +43,197 @@ impl fmt::Display for HttpContentType {
 /// HTTP response status codes
 #[derive(Debug, Clone, PartialEq)]
 pub enum HttpStatusCode {
-    Ok = 200,
-    Created = 201,
-    NoContent = 204,
-    PartialContent = 206,
-    BadRequest = 400,
-    Forbidden = 403,
-    NotFound = 404,
-    MethodNotAllowed = 405,
-    InternalServerError = 500,
-    NotImplemented = 501,
+    Continue,
+    EarlyHints,
+    Ok,
+    Created,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
+    Conflict,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    ServiceUnavailable,
+    HttpVersionNotSupported,
+    Custom(u16, &'static str), // Any other code, with its reason phrase
 }
 
+/// Statuses with a variant of their own, which `From<u16>` returns in place of `Custom`
+const NAMED_STATUSES: [HttpStatusCode; 30] = [
+    HttpStatusCode::Continue,
+    HttpStatusCode::EarlyHints,
+    HttpStatusCode::Ok,
+    HttpStatusCode::Created,
+    HttpStatusCode::NoContent,
+    HttpStatusCode::PartialContent,
+    HttpStatusCode::MovedPermanently,
+    HttpStatusCode::Found,
+    HttpStatusCode::SeeOther,
+    HttpStatusCode::NotModified,
+    HttpStatusCode::TemporaryRedirect,
+    HttpStatusCode::PermanentRedirect,
+    HttpStatusCode::BadRequest,
+    HttpStatusCode::Unauthorized,
+    HttpStatusCode::Forbidden,
+    HttpStatusCode::NotFound,
+    HttpStatusCode::MethodNotAllowed,
+    HttpStatusCode::NotAcceptable,
+    HttpStatusCode::RequestTimeout,
+    HttpStatusCode::Conflict,
+    HttpStatusCode::PreconditionFailed,
+    HttpStatusCode::PayloadTooLarge,
+    HttpStatusCode::UnsupportedMediaType,
+    HttpStatusCode::RangeNotSatisfiable,
+    HttpStatusCode::TooManyRequests,
+    HttpStatusCode::RequestHeaderFieldsTooLarge,
+    HttpStatusCode::InternalServerError,
+    HttpStatusCode::NotImplemented,
+    HttpStatusCode::ServiceUnavailable,
+    HttpStatusCode::HttpVersionNotSupported,
+];
+
 /// Formats HttpStatus for display
 impl fmt::Display for HttpStatusCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // An empty reason phrase still keeps the space after the code, as the status line requires
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+/// Builds the status for a numeric code, with the standard reason phrase for codes that have
+/// no variant of their own
+impl From<u16> for HttpStatusCode {
+    fn from(code: u16) -> Self {
+        NAMED_STATUSES
+            .into_iter()
+            .find(|status| status.code() == code)
+            .unwrap_or(HttpStatusCode::Custom(code, standard_reason(code)))
+    }
+}
+
+/// Returns the IANA-registered reason phrase for a code, or an empty one for unregistered codes
+fn standard_reason(code: u16) -> &'static str {
+    match code {
+        101 => "Switching Protocols",
+        102 => "Processing",
+        202 => "Accepted",
+        203 => "Non-Authoritative Information",
+        205 => "Reset Content",
+        207 => "Multi-Status",
+        208 => "Already Reported",
+        226 => "IM Used",
+        300 => "Multiple Choices",
+        305 => "Use Proxy",
+        402 => "Payment Required",
+        407 => "Proxy Authentication Required",
+        410 => "Gone",
+        411 => "Length Required",
+        414 => "URI Too Long",
+        417 => "Expectation Failed",
+        418 => "I'm a teapot",
+        421 => "Misdirected Request",
+        422 => "Unprocessable Content",
+        423 => "Locked",
+        424 => "Failed Dependency",
+        425 => "Too Early",
+        426 => "Upgrade Required",
+        428 => "Precondition Required",
+        451 => "Unavailable For Legal Reasons",
+        502 => "Bad Gateway",
+        504 => "Gateway Timeout",
+        506 => "Variant Also Negotiates",
+        507 => "Insufficient Storage",
+        508 => "Loop Detected",
+        510 => "Not Extended",
+        511 => "Network Authentication Required",
+        _ => "",
+    }
+}
+
+impl HttpStatusCode {
+    /// Returns the numeric status code, e.g. 404
+    pub fn code(&self) -> u16 {
+        self.parts().0
+    }
+
+    /// Returns the reason phrase sent after the code, e.g. `Not Found`
+    pub fn reason(&self) -> &'static str {
+        self.parts().1
+    }
+
+    fn parts(&self) -> (u16, &'static str) {
         match self {
-            HttpStatusCode::Ok => write!(f, "200 OK"),
-            HttpStatusCode::NotFound => write!(f, "404 Not Found"),
-            HttpStatusCode::BadRequest => write!(f, "400 Bad Request"),
-            HttpStatusCode::MethodNotAllowed => write!(f, "405 Method Not Allowed"),
-            HttpStatusCode::Created => write!(f, "201 Created"),
-            HttpStatusCode::NoContent => write!(f, "204 No Content"),
-            HttpStatusCode::PartialContent => write!(f, "206 Partial Content"),
-            HttpStatusCode::InternalServerError => write!(f, "500 Internal Server Error"),
-            HttpStatusCode::Forbidden => write!(f, "403 Forbidden"),
-            HttpStatusCode::NotImplemented => write!(f, "501 Not Implemented"),
+            HttpStatusCode::Continue => (100, "Continue"),
+            HttpStatusCode::EarlyHints => (103, "Early Hints"),
+            HttpStatusCode::Ok => (200, "OK"),
+            HttpStatusCode::Created => (201, "Created"),
+            HttpStatusCode::NoContent => (204, "No Content"),
+            HttpStatusCode::PartialContent => (206, "Partial Content"),
+            HttpStatusCode::MovedPermanently => (301, "Moved Permanently"),
+            HttpStatusCode::Found => (302, "Found"),
+            HttpStatusCode::SeeOther => (303, "See Other"),
+            HttpStatusCode::NotModified => (304, "Not Modified"),
+            HttpStatusCode::TemporaryRedirect => (307, "Temporary Redirect"),
+            HttpStatusCode::PermanentRedirect => (308, "Permanent Redirect"),
+            HttpStatusCode::BadRequest => (400, "Bad Request"),
+            HttpStatusCode::Unauthorized => (401, "Unauthorized"),
+            HttpStatusCode::Forbidden => (403, "Forbidden"),
+            HttpStatusCode::NotFound => (404, "Not Found"),
+            HttpStatusCode::MethodNotAllowed => (405, "Method Not Allowed"),
+            HttpStatusCode::NotAcceptable => (406, "Not Acceptable"),
+            HttpStatusCode::RequestTimeout => (408, "Request Timeout"),
+            HttpStatusCode::Conflict => (409, "Conflict"),
+            HttpStatusCode::PreconditionFailed => (412, "Precondition Failed"),
+            HttpStatusCode::PayloadTooLarge => (413, "Payload Too Large"),
+            HttpStatusCode::UnsupportedMediaType => (415, "Unsupported Media Type"),
+            HttpStatusCode::RangeNotSatisfiable => (416, "Range Not Satisfiable"),
+            HttpStatusCode::TooManyRequests => (429, "Too Many Requests"),
+            HttpStatusCode::RequestHeaderFieldsTooLarge => (431, "Request Header Fields Too Large"),
+            HttpStatusCode::InternalServerError => (500, "Internal Server Error"),
+            HttpStatusCode::NotImplemented => (501, "Not Implemented"),
+            HttpStatusCode::ServiceUnavailable => (503, "Service Unavailable"),
+            HttpStatusCode::HttpVersionNotSupported => (505, "HTTP Version Not Supported"),
+            HttpStatusCode::Custom(code, reason) => (*code, reason),
         }
     }
+
+    /// Checks whether a response with this status may carry a body; informational, 204, and 304
+    /// responses never do, and need no Content-Length
+    pub fn allows_body(&self) -> bool {
+        !matches!(self.code(), 100..=199 | 204 | 304)
+    }
+
+    /// Checks whether this status reports a protocol error, after which the connection's
+    /// remaining bytes cannot be trusted to frame another request
+    pub fn closes_connection(&self) -> bool {
+        matches!(
+            self,
+            HttpStatusCode::BadRequest
+                | HttpStatusCode::RequestTimeout
+                | HttpStatusCode::PayloadTooLarge
+                | HttpStatusCode::RequestHeaderFieldsTooLarge
+                | HttpStatusCode::NotImplemented
+                | HttpStatusCode::HttpVersionNotSupported
+        )
+    }
 }
 
 /// Status line of an HTTP response
@@ -74,3 +242,26 @@ pub struct ResponseStatusLine {
     pub version: HttpVersion,
     pub status: HttpStatusCode,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_from_code_renders_any_code() {
+        assert_eq!(HttpStatusCode::from(404), HttpStatusCode::NotFound);
+        assert_eq!(
+            HttpStatusCode::from(418),
+            HttpStatusCode::Custom(418, "I'm a teapot")
+        );
+        assert_eq!(HttpStatusCode::from(429), HttpStatusCode::TooManyRequests);
+        assert_eq!(
+            HttpStatusCode::from(431).to_string(),
+            "431 Request Header Fields Too Large"
+        );
+        assert_eq!(HttpStatusCode::from(599).to_string(), "599 ");
+        assert_eq!(HttpStatusCode::Custom(299, "Fine").to_string(), "299 Fine");
+        assert_eq!(HttpStatusCode::Forbidden.code(), 403);
+        assert!(!HttpStatusCode::from(103).allows_body());
+    }
+}
@@ -0,0 +1,818 @@
+use std::collections::HashMap;
+
+use crate::http::{
+    errors::HttpError,
+    files::{etag, mime, reader::STREAM_MIN_SIZE},
+    logging::server_log,
+    metrics,
+    request::{HttpRequest, HttpVersion, RequestId},
+    response::{append_vary, charset, HttpResponse, HttpStatusCode},
+    server::ServerContext,
+    writer::{BodyStream, HttpBody, WrittenResponse},
+};
+
+use super::{CompressionMiddleware, CompressionOutcome, Handler, HttpEncoding, Params, SkipReason};
+
+/// Signature shared by route middleware; it runs the rest of the chain by calling `next.run`
+///
+/// A middleware may return early without calling `next` (e.g. to refuse a request), or inspect
+/// and replace the response `next` returns
+pub type Middleware = fn(
+    request: &HttpRequest,
+    params: &Params,
+    ctx: &ServerContext,
+    req_id: RequestId,
+    next: Next<'_>,
+) -> Result<HttpResponse, HttpError>;
+
+/// Signature of a route guard: a check on the request head that runs before any middleware
+///
+/// Guards see the request before its body is read, so a refusal reaches a client sending
+/// `Expect: 100-continue` before it uploads anything; they run again once the body has arrived
+pub type Guard = fn(
+    request: &HttpRequest,
+    params: &Params,
+    ctx: &ServerContext,
+    req_id: RequestId,
+) -> Result<(), HttpError>;
+
+/// Signature of a post-write hook: it sees the response once it has been written in full
+///
+/// Hooks run in the order they were attached and cannot change what was sent, which suits
+/// response caching, mirroring, or checksumming; a response that failed to write runs none
+pub type AfterWrite = fn(
+    request: &HttpRequest,
+    response: &WrittenResponse<'_>,
+    ctx: &ServerContext,
+    req_id: RequestId,
+);
+
+/// A route's cap on the body it sends, for handlers whose output grows with data on disk
+#[derive(Debug, Clone, Copy)]
+pub struct ResponseLimit {
+    max_bytes: usize,
+    over: OverLimit,
+}
+
+/// What becomes of a response larger than its route's limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverLimit {
+    Abort,    // Replaced with a 500
+    Truncate, // Cut to the limit and flagged with a Warning header
+}
+
+impl ResponseLimit {
+    /// Limit of `max_bytes` of body, with `over` deciding the fate of larger responses
+    pub fn new(max_bytes: usize, over: OverLimit) -> Self {
+        ResponseLimit { max_bytes, over }
+    }
+
+    /// Passes a response within the limit through, and aborts or cuts down one that is over
+    ///
+    /// Partial and compressed responses are always aborted: cutting them would contradict the
+    /// Content-Range or leave a stream the client cannot decode
+    pub fn enforce(
+        &self,
+        mut response: HttpResponse,
+        req_id: RequestId,
+    ) -> Result<HttpResponse, HttpError> {
+        let size = response.body.as_ref().map_or(0, HttpBody::byte_len);
+        if size <= self.max_bytes {
+            return Ok(response);
+        }
+        let uncuttable = response.status_line.status == HttpStatusCode::PartialContent
+            || header(&response.headers, "Content-Encoding").is_some();
+        if self.over == OverLimit::Abort || uncuttable {
+            server_log!(
+                Warn,
+                req = req_id,
+                "Response of {} bytes exceeds the route's limit of {}, aborting",
+                size,
+                self.max_bytes
+            );
+            return Err(HttpError::new(
+                HttpStatusCode::InternalServerError,
+                "Response too large",
+            ));
+        }
+
+        server_log!(
+            Warn,
+            req = req_id,
+            "Response of {} bytes exceeds the route's limit of {}, truncating",
+            size,
+            self.max_bytes
+        );
+        let Some(body) = response.body.as_mut() else {
+            return Ok(response);
+        };
+        body.truncate(self.max_bytes);
+        let sent = body.byte_len();
+        // The tag names the full representation, which the client no longer gets
+        response
+            .headers
+            .retain(|key, _| !key.eq_ignore_ascii_case("ETag"));
+        if let Some((_, length)) = response
+            .headers
+            .iter_mut()
+            .find(|(key, _)| key.eq_ignore_ascii_case("Content-Length"))
+        {
+            *length = sent.to_string();
+        }
+        response.headers.insert(
+            "Warning".to_string(),
+            format!("199 - \"Response truncated to {} of {} bytes\"", sent, size),
+        );
+        Ok(response)
+    }
+}
+
+/// The rest of a route's middleware chain, ending in its handler
+pub struct Next<'a> {
+    middleware: &'a [Middleware],
+    handler: Handler,
+    limit: Option<ResponseLimit>, // Applied to the handler's response, before any middleware sees it
+}
+
+impl<'a> Next<'a> {
+    /// Creates a chain that runs `middleware` in order, outermost first, then `handler`
+    pub fn new(middleware: &'a [Middleware], handler: Handler) -> Self {
+        Next {
+            middleware,
+            handler,
+            limit: None,
+        }
+    }
+
+    /// Holds the handler's response to `limit`, if any
+    pub fn limit(mut self, limit: Option<ResponseLimit>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Runs the next middleware, or the handler once the chain is exhausted
+    pub fn run(
+        self,
+        request: &HttpRequest,
+        params: &Params,
+        ctx: &ServerContext,
+        req_id: RequestId,
+    ) -> Result<HttpResponse, HttpError> {
+        match self.middleware.split_first() {
+            Some((first, rest)) => {
+                let next = Next::new(rest, self.handler).limit(self.limit);
+                first(request, params, ctx, req_id, next)
+            }
+            None => {
+                let response = (self.handler)(request, params, ctx, req_id)?;
+                match self.limit {
+                    Some(limit) => limit.enforce(response, req_id),
+                    None => Ok(response),
+                }
+            }
+        }
+    }
+}
+
+/// Compresses the response according to the request's Accept-Encoding, if compression is enabled
+///
+/// A compressed response's ETag gets the encoding appended, so a cache never matches it to another
+/// encoding; revalidations of that tag reach the handler as its uncompressed original
+pub fn compress(
+    request: &HttpRequest,
+    params: &Params,
+    ctx: &ServerContext,
+    req_id: RequestId,
+    next: Next<'_>,
+) -> Result<HttpResponse, HttpError> {
+    let accept_encoding = header(&request.headers, "Accept-Encoding");
+    let encoding = HttpEncoding::negotiate(accept_encoding)
+        .unwrap_or(HttpEncoding::Identity)
+        .to_string();
+    let revalidation = ctx
+        .config()
+        .compression
+        .enabled
+        .then(|| header(&request.headers, "If-None-Match"))
+        .flatten()
+        .and_then(|tags| etag::strip_encoding(tags, &encoding))
+        .map(|tags| {
+            let mut request = request.clone();
+            request
+                .headers
+                .retain(|key, _| !key.eq_ignore_ascii_case("If-None-Match"));
+            request.headers.insert("If-None-Match".to_string(), tags);
+            request
+        });
+    let mut response = next.run(
+        revalidation.as_ref().unwrap_or(request),
+        params,
+        ctx,
+        req_id,
+    )?;
+
+    if !ctx.config().compression.enabled {
+        let size = response.body.as_ref().map_or(0, HttpBody::byte_len);
+        report(
+            &CompressionOutcome::Skipped {
+                reason: SkipReason::Disabled,
+                size,
+            },
+            req_id,
+        );
+        return Ok(response);
+    }
+    if response.status_line.status == HttpStatusCode::NotModified {
+        if revalidation.is_some() {
+            tag_variant(&mut response.headers, &encoding);
+        }
+        append_vary(&mut response.headers, "Accept-Encoding");
+        return Ok(response);
+    }
+
+    // A range counts bytes of the uncompressed body, an encoded body, such as a precompressed
+    // file, must not be encoded twice, and JPEGs or zips would not shrink
+    let passthrough = if response.status_line.status == HttpStatusCode::PartialContent {
+        Some(SkipReason::Partial)
+    } else if header(&response.headers, "Content-Encoding").is_some() {
+        Some(SkipReason::Encoded)
+    } else if header(&response.headers, "Content-Type").is_some_and(mime::is_compressed_type) {
+        Some(SkipReason::CompressedType)
+    } else {
+        None
+    };
+    if let Some(reason) = passthrough {
+        let size = response.body.as_ref().map_or(0, HttpBody::byte_len);
+        report(&CompressionOutcome::Skipped { reason, size }, req_id);
+        append_vary(&mut response.headers, "Accept-Encoding");
+        return Ok(response);
+    }
+
+    let content_type = header(&response.headers, "Content-Type");
+    let min_size = ctx.config().compression.min_size_for(content_type);
+    if let Some(HttpBody::Stream(stream)) = &response.body {
+        // Cached files arrive as streams over their shared bytes: they are compressed whole like
+        // other bodies, and only copied out of the cache when they are actually compressed
+        if stream.byte_len() < STREAM_MIN_SIZE {
+            let size = stream.byte_len() as usize;
+            let skipped = match HttpEncoding::negotiate(accept_encoding) {
+                Some(HttpEncoding::Identity) => Some(SkipReason::NotAccepted),
+                Some(_) if size < min_size => Some(SkipReason::BelowMinimum),
+                _ => None,
+            };
+            if let Some(reason) = skipped {
+                report(&CompressionOutcome::Skipped { reason, size }, req_id);
+                append_vary(&mut response.headers, "Accept-Encoding");
+                return Ok(response);
+            }
+        } else {
+            let stream = stream.clone();
+            let outcome = compress_stream(request, &mut response, stream, ctx, min_size);
+            report(&outcome, req_id);
+            if outcome == CompressionOutcome::NotAcceptable {
+                return Err(not_acceptable());
+            }
+            if let CompressionOutcome::Streaming { encoding, .. } = &outcome {
+                tag_variant(&mut response.headers, encoding);
+            }
+            append_vary(&mut response.headers, "Accept-Encoding");
+            return Ok(response);
+        }
+    }
+
+    let levels = ctx.config().compression.levels();
+    let compressed = CompressionMiddleware::apply(response, accept_encoding, min_size, levels);
+    let outcome = compressed.outcome().clone();
+    report(&outcome, req_id);
+    if outcome == CompressionOutcome::NotAcceptable {
+        return Err(not_acceptable());
+    }
+
+    let mut response = HttpResponse::from_writable(compressed);
+    if let CompressionOutcome::Compressed { encoding, .. } = &outcome {
+        tag_variant(&mut response.headers, encoding);
+    }
+    Ok(response)
+}
+
+/// Sends a text body in the charset the request's Accept-Charset prefers, when that is not the
+/// one it is in
+///
+/// Bodies are taken to be UTF-8 unless their Content-Type declares otherwise. Text the preferred
+/// charset cannot represent, ranges, and bodies streamed from disk are sent as they are. A weak
+/// ETag still holds for the transcoded text; a strong one, promising identical bytes, is dropped
+pub fn charset(
+    request: &HttpRequest,
+    params: &Params,
+    ctx: &ServerContext,
+    req_id: RequestId,
+    next: Next<'_>,
+) -> Result<HttpResponse, HttpError> {
+    let mut response = next.run(request, params, ctx, req_id)?;
+    let Some(content_type) = header(&response.headers, "Content-Type")
+        .filter(|content_type| charset::is_text(content_type))
+        .map(str::to_string)
+    else {
+        return Ok(response);
+    };
+    if !ctx.config().charset.negotiate {
+        return Ok(response);
+    }
+    append_vary(&mut response.headers, "Accept-Charset");
+
+    let source = charset::declared(&content_type).unwrap_or_default();
+    let accept_charset = header(&request.headers, "Accept-Charset");
+    let target = match charset::negotiate(accept_charset, source) {
+        Some(target) if target != source => target,
+        _ => return Ok(response),
+    };
+    if response.status_line.status == HttpStatusCode::PartialContent
+        || header(&response.headers, "Content-Encoding").is_some()
+    {
+        return Ok(response);
+    }
+    let cached;
+    let body = match &response.body {
+        Some(HttpBody::Text(text)) => text.as_bytes(),
+        Some(HttpBody::Binary(bytes)) => bytes.as_slice(),
+        // Streams this small are cached files held in memory, not files read from disk
+        Some(HttpBody::Stream(stream)) if stream.byte_len() < STREAM_MIN_SIZE => {
+            cached = stream.read_to_vec();
+            cached.as_slice()
+        }
+        _ => return Ok(response),
+    };
+    let Some(transcoded) = source.transcode(body, target) else {
+        server_log!(
+            Debug,
+            req = req_id,
+            "[charset] body does not fit {}, sent as {}",
+            target.label(),
+            source.label()
+        );
+        return Ok(response);
+    };
+
+    server_log!(
+        Debug,
+        req = req_id,
+        "[charset] {} -> {}",
+        source.label(),
+        target.label()
+    );
+    let framed_by_length = header(&response.headers, "Content-Length").is_some();
+    response.headers.retain(|key, value| {
+        !key.eq_ignore_ascii_case("Content-Type")
+            && !key.eq_ignore_ascii_case("Content-Length")
+            && (!key.eq_ignore_ascii_case("ETag") || value.starts_with("W/"))
+    });
+    response.headers.insert(
+        "Content-Type".to_string(),
+        charset::with_charset(&content_type, target),
+    );
+    if framed_by_length {
+        response
+            .headers
+            .insert("Content-Length".to_string(), transcoded.len().to_string());
+    }
+    response.body = Some(HttpBody::Binary(transcoded));
+    Ok(response)
+}
+
+/// Switches a streamed body to be compressed as it is sent, when the client can take it
+///
+/// Without a length to announce, the response needs chunked framing, so HTTP/1.0 clients get the
+/// body as-is
+fn compress_stream(
+    request: &HttpRequest,
+    response: &mut HttpResponse,
+    stream: BodyStream,
+    ctx: &ServerContext,
+    min_size: usize,
+) -> CompressionOutcome {
+    let size = stream.byte_len() as usize;
+    let accept_encoding = header(&request.headers, "Accept-Encoding");
+    let Some(encoding) = HttpEncoding::negotiate(accept_encoding) else {
+        return CompressionOutcome::NotAcceptable;
+    };
+
+    let skipped = if !ctx.config().compression.streaming
+        || request.status_line.version != HttpVersion::Http1_1
+    {
+        Some(SkipReason::Streamed)
+    } else if matches!(encoding, HttpEncoding::Identity) {
+        Some(SkipReason::NotAccepted)
+    } else if size < min_size {
+        Some(SkipReason::BelowMinimum)
+    } else {
+        None
+    };
+    if let Some(reason) = skipped {
+        return CompressionOutcome::Skipped { reason, size };
+    }
+
+    let levels = ctx.config().compression.levels();
+    response.body = Some(HttpBody::Stream(stream.encoded(encoding.clone(), levels)));
+    response
+        .headers
+        .retain(|key, _| !key.eq_ignore_ascii_case("Content-Length"));
+    response
+        .headers
+        .insert("Content-Encoding".to_string(), encoding.to_string());
+    response
+        .headers
+        .insert("Transfer-Encoding".to_string(), "chunked".to_string());
+    CompressionOutcome::Streaming {
+        encoding: encoding.to_string(),
+        original: size,
+    }
+}
+
+/// Refuses a client that forbids an unencoded body but accepts none of the supported encodings
+fn not_acceptable() -> HttpError {
+    HttpError::new(
+        HttpStatusCode::NotAcceptable,
+        "None of the encodings in Accept-Encoding are supported",
+    )
+    .with_header("Vary", "Accept-Encoding")
+}
+
+/// Gives a compressed variant its own ETag, derived from the uncompressed one
+fn tag_variant(headers: &mut HashMap<String, String>, encoding: &str) {
+    if let Some((_, tag)) = headers
+        .iter_mut()
+        .find(|(key, _)| key.eq_ignore_ascii_case("ETag"))
+    {
+        *tag = etag::for_encoding(tag, encoding);
+    }
+}
+
+/// Looks up a header by name, case-insensitively
+fn header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Logs and counts a compression decision
+fn report(outcome: &CompressionOutcome, req_id: RequestId) {
+    match outcome {
+        CompressionOutcome::Compressed {
+            encoding,
+            original,
+            compressed,
+        } => server_log!(
+            Debug,
+            req = req_id,
+            "[compression] {} {} -> {} bytes",
+            encoding,
+            original,
+            compressed
+        ),
+        CompressionOutcome::Streaming { encoding, original } => server_log!(
+            Debug,
+            req = req_id,
+            "[compression] {} {} bytes as they stream",
+            encoding,
+            original
+        ),
+        CompressionOutcome::Skipped { reason, size } => server_log!(
+            Debug,
+            req = req_id,
+            "[compression] skipped ({}), {} bytes",
+            reason,
+            size
+        ),
+        CompressionOutcome::NotAcceptable => server_log!(
+            Debug,
+            req = req_id,
+            "[compression] no acceptable encoding, refused with 406"
+        ),
+    }
+    metrics::record_compression(outcome);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{
+        request::HttpVersion,
+        response::{ContentNegotiable, HttpStatusCode, ResponseStatusLine},
+        test_support::{TempRoot, TestClient},
+    };
+    use std::collections::HashMap;
+
+    fn handler(
+        _request: &HttpRequest,
+        _params: &Params,
+        _ctx: &ServerContext,
+        _req_id: RequestId,
+    ) -> Result<HttpResponse, HttpError> {
+        let status_line = ResponseStatusLine {
+            version: HttpVersion::Http1_1,
+            status: HttpStatusCode::Ok,
+        };
+        let headers = HashMap::from([
+            ("X-Trace".to_string(), "handler".to_string()),
+            ("Content-Length".to_string(), "0".to_string()),
+        ]);
+        Ok(HttpResponse::new(status_line, headers, None))
+    }
+
+    fn outer(
+        request: &HttpRequest,
+        params: &Params,
+        ctx: &ServerContext,
+        req_id: RequestId,
+        next: Next<'_>,
+    ) -> Result<HttpResponse, HttpError> {
+        let mut response = next.run(request, params, ctx, req_id)?;
+        let trace = format!("outer({})", response.headers["X-Trace"]);
+        response.headers.insert("X-Trace".to_string(), trace);
+        Ok(response)
+    }
+
+    fn inner(
+        request: &HttpRequest,
+        params: &Params,
+        ctx: &ServerContext,
+        req_id: RequestId,
+        next: Next<'_>,
+    ) -> Result<HttpResponse, HttpError> {
+        let mut response = next.run(request, params, ctx, req_id)?;
+        let trace = format!("inner({})", response.headers["X-Trace"]);
+        response.headers.insert("X-Trace".to_string(), trace);
+        Ok(response)
+    }
+
+    /// Serves a fixed, compressible body tagged `W/"v1"`, honoring If-None-Match
+    fn tagged_handler(
+        request: &HttpRequest,
+        _params: &Params,
+        _ctx: &ServerContext,
+        _req_id: RequestId,
+    ) -> Result<HttpResponse, HttpError> {
+        let fresh = header(&request.headers, "If-None-Match")
+            .is_some_and(|tags| etag::none_match(tags, "W/\"v1\""));
+        let status = if fresh {
+            HttpStatusCode::NotModified
+        } else {
+            HttpStatusCode::Ok
+        };
+        let body = (!fresh).then(|| HttpBody::Text("abc".repeat(1000)));
+        let headers = HashMap::from([
+            ("ETag".to_string(), "W/\"v1\"".to_string()),
+            ("Content-Type".to_string(), "text/plain".to_string()),
+        ]);
+        let status_line = ResponseStatusLine {
+            version: HttpVersion::Http1_1,
+            status,
+        };
+        Ok(HttpResponse::new(status_line, headers, body))
+    }
+
+    #[test]
+    fn test_compressed_variants_get_their_own_etag() {
+        let ctx = TempRoot::new("etag-variants").context();
+        let get = |headers: &str| {
+            let raw = format!("GET / HTTP/1.1\r\n{}\r\n", headers);
+            let request = HttpRequest::parse(raw.as_bytes()).unwrap();
+            Next::new(&[compress], tagged_handler)
+                .run(&request, &Params::new(), &ctx, ctx.next_request_id())
+                .unwrap()
+        };
+        let status_and_tag = |response: HttpResponse| {
+            (
+                response.status_line.status,
+                response.headers["ETag"].clone(),
+            )
+        };
+
+        let gzip = get("Accept-Encoding: gzip\r\n");
+        assert_eq!(gzip.headers["Vary"], "Accept-Encoding");
+        assert_eq!(
+            status_and_tag(gzip),
+            (HttpStatusCode::Ok, "W/\"v1-gzip\"".to_string())
+        );
+        assert_eq!(
+            status_and_tag(get(
+                "Accept-Encoding: gzip\r\nIf-None-Match: W/\"v1-gzip\"\r\n"
+            )),
+            (HttpStatusCode::NotModified, "W/\"v1-gzip\"".to_string())
+        );
+        // A gzip tag must not let an identity client keep a gzip body, nor the reverse
+        assert_eq!(
+            status_and_tag(get("If-None-Match: W/\"v1-gzip\"\r\n")),
+            (HttpStatusCode::Ok, "W/\"v1\"".to_string())
+        );
+        assert_eq!(
+            status_and_tag(get(
+                "Accept-Encoding: deflate\r\nIf-None-Match: W/\"v1-gzip\"\r\n"
+            )),
+            (HttpStatusCode::Ok, "W/\"v1-deflate\"".to_string())
+        );
+        // Header names are case-insensitive
+        assert_eq!(
+            status_and_tag(get("accept-encoding: gzip\r\n")),
+            (HttpStatusCode::Ok, "W/\"v1-gzip\"".to_string())
+        );
+    }
+
+    fn streamed_handler(
+        _request: &HttpRequest,
+        _params: &Params,
+        _ctx: &ServerContext,
+        _req_id: RequestId,
+    ) -> Result<HttpResponse, HttpError> {
+        let body = vec![b'a'; STREAM_MIN_SIZE as usize];
+        let headers = HashMap::from([
+            ("Content-Type".to_string(), "text/plain".to_string()),
+            ("Content-Length".to_string(), body.len().to_string()),
+        ]);
+        let status_line = ResponseStatusLine {
+            version: HttpVersion::Http1_1,
+            status: HttpStatusCode::Ok,
+        };
+        let body = HttpBody::Stream(BodyStream::from_bytes(body));
+        Ok(HttpResponse::new(status_line, headers, Some(body)))
+    }
+
+    #[test]
+    fn test_large_streams_are_compressed_as_they_are_sent() {
+        let ctx = TempRoot::new("compress-stream").context();
+        for name in ["Accept-Encoding", "accept-encoding"] {
+            let raw = format!("GET / HTTP/1.1\r\n{}: gzip\r\n\r\n", name);
+            let request = HttpRequest::parse(raw.as_bytes()).unwrap();
+            let response = Next::new(&[compress], streamed_handler)
+                .run(&request, &Params::new(), &ctx, ctx.next_request_id())
+                .unwrap();
+
+            assert_eq!(response.headers["Content-Encoding"], "gzip", "{}", name);
+            assert!(!response.headers.contains_key("Content-Length"));
+            let Some(HttpBody::Stream(stream)) = &response.body else {
+                panic!("expected a streamed body");
+            };
+            assert!(stream.encoding().is_some());
+        }
+    }
+
+    #[test]
+    fn test_vary_lists_every_negotiated_header() {
+        fn negotiated_handler(
+            request: &HttpRequest,
+            _params: &Params,
+            _ctx: &ServerContext,
+            _req_id: RequestId,
+        ) -> Result<HttpResponse, HttpError> {
+            Ok(HttpResponse::with_negotiation(
+                HttpStatusCode::Ok,
+                HttpVersion::Http1_1,
+                "",
+                "abc".repeat(1000),
+                header(&request.headers, "Accept"),
+                None,
+                "text/plain",
+            ))
+        }
+
+        let ctx = TempRoot::new("vary").context();
+        for accept_encoding in ["gzip", "identity"] {
+            let raw = format!(
+                "GET / HTTP/1.1\r\nAccept: application/json\r\nAccept-Encoding: {}\r\n\r\n",
+                accept_encoding
+            );
+            let request = HttpRequest::parse(raw.as_bytes()).unwrap();
+            let response = Next::new(&[compress], negotiated_handler)
+                .run(&request, &Params::new(), &ctx, ctx.next_request_id())
+                .unwrap();
+            assert_eq!(response.headers["Vary"], "Accept, Accept-Encoding");
+        }
+    }
+
+    #[test]
+    fn test_charset_transcodes_to_the_accepted_charset() {
+        fn text_handler(
+            request: &HttpRequest,
+            _params: &Params,
+            _ctx: &ServerContext,
+            _req_id: RequestId,
+        ) -> Result<HttpResponse, HttpError> {
+            let headers = HashMap::from([
+                ("Content-Type".to_string(), "text/plain".to_string()),
+                ("Content-Length".to_string(), "5".to_string()),
+                ("ETag".to_string(), "\"v1\"".to_string()),
+            ]);
+            let status_line = ResponseStatusLine {
+                version: HttpVersion::Http1_1,
+                status: HttpStatusCode::Ok,
+            };
+            let text = header(&request.headers, "X-Text").unwrap_or("café");
+            Ok(HttpResponse::new(
+                status_line,
+                headers,
+                Some(HttpBody::Text(text.to_string())),
+            ))
+        }
+
+        let ctx = TempRoot::new("charset").context();
+        let get = |headers: &str| {
+            let raw = format!("GET / HTTP/1.1\r\n{}\r\n", headers);
+            let request = HttpRequest::parse(raw.as_bytes()).unwrap();
+            Next::new(&[charset], text_handler)
+                .run(&request, &Params::new(), &ctx, ctx.next_request_id())
+                .unwrap()
+        };
+
+        let latin1 = get("Accept-Charset: iso-8859-1, utf-8;q=0.5\r\n");
+        assert_eq!(
+            latin1.headers["Content-Type"],
+            "text/plain; charset=iso-8859-1"
+        );
+        assert_eq!(latin1.headers["Content-Length"], "4");
+        assert!(!latin1.headers.contains_key("ETag"));
+        assert_eq!(latin1.headers["Vary"], "Accept-Charset");
+        assert!(matches!(latin1.body, Some(HttpBody::Binary(b)) if b == b"caf\xe9"));
+
+        let unmappable = get("Accept-Charset: iso-8859-1\r\nX-Text: \u{20ac}5\r\n");
+        assert_eq!(unmappable.headers["Content-Type"], "text/plain");
+        let utf8 = get("");
+        assert_eq!(utf8.headers["Content-Type"], "text/plain");
+        assert_eq!(utf8.headers["ETag"], "\"v1\"");
+    }
+
+    #[test]
+    fn test_chain_runs_outermost_first() {
+        let ctx = TempRoot::new("chain").context();
+        let request = HttpRequest::parse(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+        let response = Next::new(&[outer, inner], handler)
+            .run(&request, &Params::new(), &ctx, ctx.next_request_id())
+            .unwrap();
+        assert_eq!(response.headers["X-Trace"], "outer(inner(handler))");
+    }
+
+    #[test]
+    fn test_response_limit_checks_the_handler_output() {
+        let ctx = TempRoot::new("response-limit").context();
+        let request =
+            HttpRequest::parse(b"GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n").unwrap();
+        let run = |limit| {
+            Next::new(&[compress], tagged_handler)
+                .limit(Some(limit))
+                .run(&request, &Params::new(), &ctx, ctx.next_request_id())
+        };
+
+        // Checked before compression, which would have brought the body under the limit
+        let aborted = run(ResponseLimit::new(2000, OverLimit::Abort)).unwrap_err();
+        assert_eq!(aborted.status, HttpStatusCode::InternalServerError);
+
+        let truncated = run(ResponseLimit::new(100, OverLimit::Truncate)).unwrap();
+        assert_eq!(truncated.body.unwrap().byte_len(), 100);
+        assert!(!truncated.headers.contains_key("ETag"));
+        assert_eq!(
+            truncated.headers["Warning"],
+            "199 - \"Response truncated to 100 of 3000 bytes\""
+        );
+
+        assert!(run(ResponseLimit::new(3000, OverLimit::Abort)).is_ok());
+    }
+
+    /// What `record` saw of each written response
+    static WRITTEN: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+    fn record(
+        _request: &HttpRequest,
+        response: &WrittenResponse<'_>,
+        _ctx: &ServerContext,
+        req_id: RequestId,
+    ) {
+        let mut written = WRITTEN.lock().unwrap();
+        written.push(format!(
+            "{} {} {}",
+            response.status,
+            response.headers[crate::http::request::id::REQUEST_ID_HEADER] == req_id.token(),
+            response.headers["X-Trace"]
+        ));
+    }
+
+    #[test]
+    fn test_after_write_hooks_see_the_sent_response() {
+        let root = TempRoot::new("after-write");
+        let ctx = ServerContext::with_routes(root.config(), |router| {
+            router
+                .get("/hooked", handler)?
+                .with(outer)
+                .after_write(record)
+                .after_write(record);
+            Ok(())
+        })
+        .unwrap();
+        TestClient::new(&ctx).get("/hooked");
+
+        assert_eq!(
+            *WRITTEN.lock().unwrap(),
+            vec!["200 OK true outer(handler)"; 2]
+        );
+    }
+}
@@ -0,0 +1,2085 @@
+pub mod middleware;
+pub mod pattern;
+pub mod stats;
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{self, Write},
+    path::Path,
+    time::Instant,
+};
+
+use serde::Deserialize;
+
+use crate::http::{
+    admin,
+    config::ServerConfig,
+    errors::{self, HttpError, HttpErrorResponse},
+    files::{
+        etag,
+        mime::{self, mime_type_from_extension},
+        playlist::{generate_playlist, is_segment, DEFAULT_SEGMENT_DURATION, PLAYLIST_NAME},
+        precompressed,
+        reader::read_file_with_range,
+        types::{ByteRange, FileReadError, FileReadRequest},
+    },
+    logging::server_log,
+    metrics::{self, Counter},
+    request::{HttpMethod, HttpRequest, RequestId},
+    response::{
+        append_vary,
+        charset::{self, Charset},
+        ContentNegotiable, HttpContentType, HttpResponse, HttpStatusCode, ResponseStatusLine,
+    },
+    server,
+    shard::SHARD_HEADER,
+    site_defaults,
+    stream::ClientStream,
+    uploads, well_known,
+    writer::{
+        send_observed, write_counted, HttpBody, HttpWritable, HttpWriter, WriteReport, WriterError,
+    },
+};
+use middleware::{AfterWrite, Guard, Middleware, Next, OverLimit, ResponseLimit};
+use pattern::{Overlap, Pattern};
+use stats::{RouteStats, StatsSnapshot};
+
+/// Represents supported HTTP Encoding types
+#[derive(Debug, Clone)]
+pub enum HttpEncoding {
+    Gzip,
+    Deflate,
+    Brotli,
+    Identity,
+}
+
+impl fmt::Display for HttpEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoding_str = match self {
+            HttpEncoding::Gzip => "gzip",
+            HttpEncoding::Deflate => "deflate",
+            HttpEncoding::Brotli => "brotli",
+            HttpEncoding::Identity => "identity",
+        };
+        write!(f, "{}", encoding_str)
+    }
+}
+
+impl HttpEncoding {
+    // Translates string to HttpEncoding enum
+    pub fn from_encoding_string(s: &str) -> Option<HttpEncoding> {
+        match s.to_lowercase().as_str() {
+            "gzip" => Some(HttpEncoding::Gzip),
+            "deflate" => Some(HttpEncoding::Deflate),
+            "br" | "brotli" => Some(HttpEncoding::Brotli),
+            _ => None,
+        }
+    }
+
+    // Picks the client's most preferred supported encoding, identity when it accepts none, or
+    // nothing when it has forbidden identity as well
+    pub fn negotiate(accept_encoding: Option<&str>) -> Option<HttpEncoding> {
+        let Some(header) = accept_encoding else {
+            return Some(HttpEncoding::Identity);
+        };
+        match HttpEncoding::parse_accept_encoding(header)
+            .into_iter()
+            .next()
+        {
+            Some((encoding, _)) => Some(encoding),
+            None => HttpEncoding::identity_allowed(header).then_some(HttpEncoding::Identity),
+        }
+    }
+
+    // Checks whether an unencoded body is acceptable: an explicit `identity;q=0` forbids it, as
+    // does `*;q=0` unless identity is listed with a non-zero quality
+    fn identity_allowed(header: &str) -> bool {
+        let codings = HttpEncoding::codings(header);
+        let quality = |name: &str| {
+            codings
+                .iter()
+                .find(|(coding, _)| coding.eq_ignore_ascii_case(name))
+                .map(|(_, q)| *q)
+        };
+        quality("identity")
+            .or_else(|| quality("*"))
+            .map_or(true, |q| q > 0.0)
+    }
+
+    // Parses Accept-Encoding header and returns sorted encodings with quality values
+    pub fn parse_accept_encoding(header: &str) -> Vec<(HttpEncoding, f32)> {
+        let mut sorted_quality: Vec<(&str, f32)> = HttpEncoding::codings(header)
+            .into_iter()
+            .filter(|(_, q)| *q > 0.0)
+            .collect();
+        sorted_quality.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+        let mut encodings: Vec<(HttpEncoding, f32)> = Vec::new();
+        for (enc_str, q) in sorted_quality {
+            if let Some(enc) = HttpEncoding::from_encoding_string(enc_str) {
+                encodings.push((enc, q));
+            }
+        }
+
+        encodings
+    }
+
+    // Splits Accept-Encoding into coding names and their quality values, in header order
+    fn codings(header: &str) -> Vec<(&str, f32)> {
+        // "gzip;q=0.8, deflate;q=0.9, br;q=1.0" -> ["gzip;q=0.8", "deflate;q=0.9", "br;q=1.0"]
+        let comma_split = header.split(',').map(str::trim);
+
+        // ["gzip;q=0.8", "deflate;q=0.9", "br;q=1.0"] -> ["gzip", "q=0.8"], ["deflate", "q=0.9"]..
+        let semicolon_split =
+            comma_split.map(|s| s.split(';').map(str::trim).collect::<Vec<&str>>());
+
+        // "q=0.8" -> "0.8" or "1.0" if not present
+        let quality_split = semicolon_split.map(|parts| {
+            if parts.is_empty() || parts[0].is_empty() {
+                return ("", 0.0);
+            }
+
+            let encoding_name = parts[0];
+
+            // if q is present, parse it, else default to 1.0
+            let q_value = if parts.len() > 1 && parts[1].starts_with("q=") {
+                // (gzip, q=0.8) -> 0.8
+                parts[1][2..].parse::<f32>().unwrap_or(1.0)
+            } else {
+                1.0
+            };
+
+            (encoding_name, q_value)
+        });
+
+        quality_split.filter(|(name, _)| !name.is_empty()).collect()
+    }
+}
+
+/// Represents why a response was sent uncompressed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SkipReason {
+    Disabled,       // compression.enabled is false
+    NotAccepted,    // Accept-Encoding names no supported encoding
+    BelowMinimum,   // Body is smaller than the configured minimum for its type
+    Streamed,       // Streamed body that could not be compressed on the way: off, or not HTTP/1.1
+    Partial,        // A 206, whose ranges count bytes of the uncompressed body
+    Encoded,        // Body already has a Content-Encoding, e.g. a precompressed file
+    CompressedType, // Content-Type is compressed already, e.g. image/jpeg or application/zip
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            SkipReason::Disabled => "disabled",
+            SkipReason::NotAccepted => "not_accepted",
+            SkipReason::BelowMinimum => "below_minimum",
+            SkipReason::Streamed => "streamed",
+            SkipReason::Partial => "partial",
+            SkipReason::Encoded => "encoded",
+            SkipReason::CompressedType => "compressed_type",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// Represents what the compression middleware decided for one response
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompressionOutcome {
+    Compressed {
+        encoding: String,
+        original: usize,   // Body bytes before compression
+        compressed: usize, // Body bytes sent
+    },
+    Streaming {
+        encoding: String,
+        original: usize, // Body bytes before compression; the compressed size is never known whole
+    },
+    Skipped {
+        reason: SkipReason,
+        size: usize, // Body bytes sent as-is
+    },
+    NotAcceptable, // Client forbids identity and accepts no supported encoding; answered with 406
+}
+
+/// Represents Compression Middleware
+pub struct CompressionMiddleware;
+
+impl CompressionMiddleware {
+    // Applies compression based on the Accept-Encoding header to bodies of at least `min_size` bytes
+    pub fn apply<T: HttpWritable>(
+        response: T,
+        accept_encoding: Option<&str>,
+        min_size: usize,
+        levels: CompressionLevels,
+    ) -> CompressedResponse<T> {
+        let body = match response.body() {
+            HttpBody::Text(text) => text.into_bytes(),
+            HttpBody::Binary(bin) => bin,
+            HttpBody::Stream(stream) => stream.read_to_vec(),
+        };
+
+        let Some(encoding) = HttpEncoding::negotiate(accept_encoding) else {
+            return CompressedResponse::not_acceptable(response, body);
+        };
+
+        let skipped = match encoding {
+            HttpEncoding::Identity => Some(SkipReason::NotAccepted),
+            _ if body.len() < min_size => Some(SkipReason::BelowMinimum),
+            _ => None,
+        };
+        if let Some(reason) = skipped {
+            return CompressedResponse::uncompressed(response, body, reason);
+        }
+
+        let original = body.len();
+        let compressed_body = match encoding {
+            HttpEncoding::Gzip => Self::compress_gzip(&body, levels),
+            HttpEncoding::Deflate => Self::compress_deflate(&body, levels),
+            HttpEncoding::Brotli => Self::compress_brotli(&body, levels),
+            HttpEncoding::Identity => body,
+        };
+
+        CompressedResponse {
+            original: response,
+            encoding: encoding.to_string(),
+            outcome: CompressionOutcome::Compressed {
+                encoding: encoding.to_string(),
+                original,
+                compressed: compressed_body.len(),
+            },
+            compressed_body,
+        }
+    }
+
+    fn compress_brotli(body: &[u8], levels: CompressionLevels) -> Vec<u8> {
+        let mut encoder = levels.brotli(Vec::new());
+        io::copy(&mut &body[..], &mut encoder).unwrap();
+        encoder.into_inner()
+    }
+
+    fn compress_deflate(body: &[u8], levels: CompressionLevels) -> Vec<u8> {
+        let mut encoder = levels.deflate(Vec::new());
+        io::copy(&mut &body[..], &mut encoder).unwrap();
+        encoder.finish().into_result().unwrap()
+    }
+
+    fn compress_gzip(body: &[u8], levels: CompressionLevels) -> Vec<u8> {
+        let mut encoder = levels.gzip(Vec::new()).unwrap();
+        io::copy(&mut &body[..], &mut encoder).unwrap();
+        encoder.finish().into_result().unwrap()
+    }
+}
+
+/// How hard the encoders work, as set in `[compression]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionLevels {
+    pub gzip: u32,           // 0 stores, 1 to 9 search a growing window; deflate too
+    pub brotli_quality: u32, // 0 (fastest) to 11 (smallest)
+    pub brotli_window: u32,  // Base-2 log of the window size, 10 to 24
+}
+
+impl CompressionLevels {
+    /// LZ77 window for a gzip level: 512 bytes at level 1, doubling up to the 32 KiB maximum
+    /// that deflate allows from level 7
+    fn window_size(&self) -> u16 {
+        (256_u32 << self.gzip.clamp(1, 7)) as u16
+    }
+
+    fn gzip<W: Write>(&self, inner: W) -> io::Result<libflate::gzip::Encoder<W>> {
+        let lz77 = libflate::lz77::DefaultLz77Encoder::with_window_size(self.window_size());
+        let options = libflate::gzip::EncodeOptions::with_lz77(lz77);
+        let options = if self.gzip == 0 {
+            options.no_compression()
+        } else {
+            options
+        };
+        libflate::gzip::Encoder::with_options(inner, options)
+    }
+
+    fn deflate<W: Write>(&self, inner: W) -> libflate::deflate::Encoder<W> {
+        let lz77 = libflate::lz77::DefaultLz77Encoder::with_window_size(self.window_size());
+        let options = libflate::deflate::EncodeOptions::with_lz77(lz77);
+        let options = if self.gzip == 0 {
+            options.no_compression()
+        } else {
+            options
+        };
+        libflate::deflate::Encoder::with_options(inner, options)
+    }
+
+    fn brotli<W: Write>(&self, inner: W) -> brotli::CompressorWriter<W> {
+        brotli::CompressorWriter::new(inner, 4096, self.brotli_quality, self.brotli_window)
+    }
+}
+
+/// A compressor that encodes a body as it is written through to `W`, for bodies too large to
+/// hold whole
+pub enum StreamEncoder<W: Write> {
+    Gzip(libflate::gzip::Encoder<W, libflate::lz77::DefaultLz77Encoder>),
+    Deflate(libflate::deflate::Encoder<W, libflate::lz77::DefaultLz77Encoder>),
+    Brotli(Box<brotli::CompressorWriter<W>>),
+}
+
+impl<W: Write> StreamEncoder<W> {
+    /// Starts an encoder for `encoding` in front of `inner`; identity needs none
+    pub fn new(
+        encoding: &HttpEncoding,
+        levels: CompressionLevels,
+        inner: W,
+    ) -> io::Result<Option<Self>> {
+        Ok(Some(match encoding {
+            HttpEncoding::Gzip => StreamEncoder::Gzip(levels.gzip(inner)?),
+            HttpEncoding::Deflate => StreamEncoder::Deflate(levels.deflate(inner)),
+            HttpEncoding::Brotli => StreamEncoder::Brotli(Box::new(levels.brotli(inner))),
+            HttpEncoding::Identity => return Ok(None),
+        }))
+    }
+
+    /// Writes the end of the compressed stream and hands back the inner writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            StreamEncoder::Gzip(encoder) => encoder.finish().into_result(),
+            StreamEncoder::Deflate(encoder) => encoder.finish().into_result(),
+            StreamEncoder::Brotli(encoder) => Ok(encoder.into_inner()),
+        }
+    }
+}
+
+impl<W: Write> Write for StreamEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            StreamEncoder::Gzip(encoder) => encoder.write(buf),
+            StreamEncoder::Deflate(encoder) => encoder.write(buf),
+            StreamEncoder::Brotli(encoder) => encoder.write(buf),
+        }
+    }
+
+    /// Pushes out everything compressed so far, so the client can decode up to here
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            StreamEncoder::Gzip(encoder) => encoder.flush(),
+            StreamEncoder::Deflate(encoder) => encoder.flush(),
+            StreamEncoder::Brotli(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Represents a response with applied compression
+pub struct CompressedResponse<T: HttpWritable> {
+    original: T,
+    encoding: String,
+    compressed_body: Vec<u8>,
+    outcome: CompressionOutcome,
+}
+
+impl<T: HttpWritable> CompressedResponse<T> {
+    /// Wraps a response whose body is sent as-is
+    fn uncompressed(original: T, body: Vec<u8>, reason: SkipReason) -> Self {
+        CompressedResponse {
+            original,
+            encoding: HttpEncoding::Identity.to_string(),
+            outcome: CompressionOutcome::Skipped {
+                reason,
+                size: body.len(),
+            },
+            compressed_body: body,
+        }
+    }
+
+    /// Wraps a response that cannot be sent in any coding the client accepts
+    fn not_acceptable(original: T, body: Vec<u8>) -> Self {
+        CompressedResponse {
+            original,
+            encoding: HttpEncoding::Identity.to_string(),
+            compressed_body: body,
+            outcome: CompressionOutcome::NotAcceptable,
+        }
+    }
+
+    /// Returns whether the body was compressed, and if not, why
+    pub fn outcome(&self) -> &CompressionOutcome {
+        &self.outcome
+    }
+}
+
+impl<T: HttpWritable> HttpWritable for CompressedResponse<T> {
+    // Returns original status line
+    fn status_line(&self) -> &ResponseStatusLine {
+        self.original.status_line()
+    }
+
+    // Returns modified headers with Content-Encoding and updated Content-Length
+    fn headers(&self) -> HashMap<String, String> {
+        let mut headers = self.original.headers().clone();
+        headers.remove("Content-Length");
+
+        if self.encoding != "identity" {
+            headers.insert("Content-Encoding".to_string(), self.encoding.clone());
+        }
+        append_vary(&mut headers, "Accept-Encoding");
+        headers.insert(
+            "Content-Length".to_string(),
+            self.compressed_body.len().to_string(),
+        );
+
+        headers
+    }
+
+    // Returns compressed body
+    fn body(&self) -> HttpBody {
+        HttpBody::Binary(self.compressed_body.clone())
+    }
+}
+
+/// Path parameters captured by a route, e.g. `text` for `/echo/{text}`
+pub type Params = HashMap<String, String>;
+
+/// Fixed parameter holding a static route's directory; braces keep it apart from pattern names
+const STATIC_DIR_PARAM: &str = "{static_dir}";
+
+/// Signature shared by every route handler
+pub type Handler = fn(
+    request: &HttpRequest,
+    params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError>;
+
+/// A complete 200 response rendered once at registration and written to the socket as-is
+#[derive(Debug)]
+pub struct RawResponse {
+    bytes: Vec<u8>,  // Status line, headers, and body
+    head_len: usize, // Where the body starts in `bytes`
+    content_type: String,
+}
+
+impl RawResponse {
+    /// Renders `body` with the Content-Type for `file`'s extension and its Content-Length
+    fn render(file: &Path, body: &[u8]) -> Self {
+        let content_type = file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(mime_type_from_extension)
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            HttpStatusCode::Ok,
+            content_type,
+            body.len()
+        );
+
+        let mut bytes = head.into_bytes();
+        let head_len = bytes.len();
+        bytes.extend_from_slice(body);
+        RawResponse {
+            bytes,
+            head_len,
+            content_type,
+        }
+    }
+
+    /// Writes the rendered bytes, bypassing the response writer
+    fn send(&self, stream: &mut dyn ClientStream, req_id: RequestId) -> WriteReport {
+        metrics::incr(Counter::for_status(&HttpStatusCode::Ok));
+        let mut sent = 0;
+        let result = write_counted(stream, &self.bytes, &mut sent).inspect_err(|e| {
+            server_log!(Info, req = req_id, "Failed to send raw response: {}", e);
+        });
+        WriteReport::new(sent, result.map_err(WriterError::IoError))
+    }
+
+    /// Rebuilds the response for callers that need an `HttpResponse` rather than bytes
+    fn to_response(&self) -> HttpResponse {
+        let body = self.bytes[self.head_len..].to_vec();
+        let headers = HashMap::from([
+            ("Content-Type".to_string(), self.content_type.clone()),
+            ("Content-Length".to_string(), body.len().to_string()),
+        ]);
+        let status_line = ResponseStatusLine {
+            version: crate::http::request::HttpVersion::Http1_1,
+            status: HttpStatusCode::Ok,
+        };
+        HttpResponse::new(status_line, headers, Some(HttpBody::Binary(body)))
+    }
+}
+
+/// What a matched route answers with
+#[derive(Debug)]
+enum Target {
+    Handler(Handler), // Runs after the route's guards, inside its middleware
+    Raw(RawResponse), // Sent as-is; guards and middleware are skipped
+}
+
+/// Represents a single route
+#[derive(Debug)]
+pub struct Route {
+    method: HttpMethod,
+    pattern: Pattern, // /echo/{text}, /items/{id:u64}
+    target: Target,
+    params: Params, // Fixed at registration; captured parameters of the same name win
+    guards: Vec<Guard>, // Outermost first; run before the body is read
+    middleware: Vec<Middleware>, // Outermost first
+    after_write: Vec<AfterWrite>, // In the order attached
+    max_response: Option<ResponseLimit>, // Checked against the handler's own response
+    stats: RouteStats,
+}
+
+impl Route {
+    /// Wraps the route's handler, and any middleware already attached, in `middleware`
+    pub fn with(&mut self, middleware: Middleware) -> &mut Self {
+        self.middleware.insert(0, middleware);
+        self
+    }
+
+    /// Adds a check that runs ahead of any guards already attached, and before all middleware
+    pub fn guard(&mut self, guard: Guard) -> &mut Self {
+        self.guards.insert(0, guard);
+        self
+    }
+
+    /// Adds a hook that sees the response after it has been written, following any already attached
+    pub fn after_write(&mut self, hook: AfterWrite) -> &mut Self {
+        self.after_write.push(hook);
+        self
+    }
+
+    /// Caps the size of the body the route sends, replacing any limit already set
+    pub fn max_response(&mut self, limit: ResponseLimit) -> &mut Self {
+        self.max_response = Some(limit);
+        self
+    }
+
+    /// Runs the route's guards in order, stopping at the first refusal
+    fn check(
+        &self,
+        request: &HttpRequest,
+        params: &Params,
+        ctx: &server::ServerContext,
+        req_id: RequestId,
+    ) -> Result<(), HttpError> {
+        self.guards
+            .iter()
+            .try_for_each(|guard| guard(request, params, ctx, req_id))
+    }
+}
+
+/// The routes registered by one `Router::scope` call
+pub struct RouteGroup<'a> {
+    routes: &'a mut [Route],
+}
+
+impl RouteGroup<'_> {
+    /// Wraps every route in the group, including nested scopes, in `middleware`
+    pub fn with(&mut self, middleware: Middleware) -> &mut Self {
+        for route in self.routes.iter_mut() {
+            route.with(middleware);
+        }
+        self
+    }
+
+    /// Adds `guard` to every route in the group, including nested scopes
+    pub fn guard(&mut self, guard: Guard) -> &mut Self {
+        for route in self.routes.iter_mut() {
+            route.guard(guard);
+        }
+        self
+    }
+
+    /// Adds `hook` to every route in the group, including nested scopes
+    pub fn after_write(&mut self, hook: AfterWrite) -> &mut Self {
+        for route in self.routes.iter_mut() {
+            route.after_write(hook);
+        }
+        self
+    }
+}
+
+/// Represents why a route could not be registered
+#[derive(Debug, PartialEq)]
+pub enum RouteError {
+    Duplicate(String, String), // Same method and pattern as an existing route: (new, existing)
+    Ambiguous(String, String), // Some request paths would match both routes: (new, existing)
+    InvalidPattern(String),    // A parameter constraint could not be parsed
+    Unreadable(String, String), // A raw route's file could not be read: (file, reason)
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::Duplicate(new, existing) => {
+                write!(f, "route {} duplicates {}", new, existing)
+            }
+            RouteError::Ambiguous(new, existing) => write!(
+                f,
+                "route {} overlaps {}; the first registered would always win",
+                new, existing
+            ),
+            RouteError::InvalidPattern(msg) => write!(f, "{}", msg),
+            RouteError::Unreadable(file, reason) => {
+                write!(f, "raw route file {} is unreadable: {}", file, reason)
+            }
+        }
+    }
+}
+
+/// How a request whose path differs from a route's only by a trailing slash is handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    #[default]
+    Strict, // `/user-agent/` is a different path from `/user-agent` and gets 404
+    Ignore,   // Either form is served by the route registered with the other
+    Redirect, // Answered with a permanent redirect to the form the route was registered with
+}
+
+/// Manages routes and dispatches requests; built once per server and shared by every connection
+#[derive(Debug)]
+pub struct Router {
+    routes: Vec<Route>,
+    prefix: String, // Prepended to paths registered inside `scope`
+    trailing_slash: TrailingSlash,
+    unmatched: RouteStats, // Requests no route matched
+}
+
+impl Router {
+    /// Creates a router with the built-in routes and the configured static directories
+    pub fn new(config: &ServerConfig) -> Result<Self, RouteError> {
+        let mut router = Router {
+            routes: Vec::new(),
+            prefix: String::new(),
+            trailing_slash: config.trailing_slash,
+            unmatched: RouteStats::default(),
+        };
+        router.get("/", root_handler)?.with(middleware::charset);
+        router
+            .get("/echo/{text}", echo_handler)?
+            .with(middleware::charset)
+            .with(middleware::compress);
+        router
+            .get("/user-agent", user_agent_handler)?
+            .with(middleware::charset);
+        router.scope("/files", |files| {
+            files
+                .get("/{filename..}", file_handler)?
+                .with(middleware::charset)
+                .with(middleware::compress);
+            files
+                .post("/{filename..}", file_handler)?
+                .guard(uploads::require_token);
+            Ok(())
+        })?;
+        router.get("/chunked/{text}", chunked_handler)?;
+        router.get("/hls/{dirname}/{segment}", hls_handler)?;
+        #[cfg(feature = "thumbnails")]
+        router.get("/thumb/{filename}", thumb_handler)?;
+        router.get("/upload-token", uploads::token_handler)?;
+        if config.metrics.prometheus {
+            router.get("/metrics", metrics::prometheus_handler)?;
+        }
+        router
+            .scope("/admin", |admin| {
+                admin
+                    .get("/logs", admin::logs_handler)?
+                    .max_response(ResponseLimit::new(
+                        admin::MAX_LOGS_BYTES,
+                        OverLimit::Truncate,
+                    ));
+                admin.get("/stats", admin::stats_handler)?;
+                admin.post("/cache/purge", admin::cache_purge_handler)?;
+                Ok(())
+            })?
+            .guard(admin::require_token)
+            .with(middleware::compress)
+            .after_write(admin::audit);
+        for (prefix, dir) in &config.static_files {
+            router.static_files(prefix, dir)?;
+        }
+        for (path, file) in &config.raw_routes {
+            router.raw(path, &Path::new(&config.root).join(file))?;
+        }
+        well_known::register(&mut router, &config.well_known)?;
+        site_defaults::register(&mut router, config)?;
+
+        Ok(router)
+    }
+
+    /// Registers a GET route serving every file under `dir`, nested directories included, at
+    /// `prefix/...`; `dir` is relative to the site root, so virtual hosts each serve their own
+    pub fn static_files(&mut self, prefix: &str, dir: &str) -> Result<&mut Route, RouteError> {
+        if !prefix.starts_with('/') || prefix.ends_with('/') {
+            return Err(RouteError::InvalidPattern(format!(
+                "static prefix '{}' must start with '/' and not end with one",
+                prefix
+            )));
+        }
+        let route = self
+            .get(&format!("{}/{{path..}}", prefix), static_handler)?
+            .with(middleware::charset)
+            .with(middleware::compress);
+        route.params.insert(
+            STATIC_DIR_PARAM.to_string(),
+            dir.trim_matches('/').to_string(),
+        );
+        Ok(route)
+    }
+
+    /// Registers a GET route answered with `file` as a response rendered now and written to the
+    /// socket as-is, for hot endpoints such as tracking pixels
+    ///
+    /// Nothing is added per request: no guards, middleware, compression, Connection or
+    /// X-Request-Id headers. Later changes to the file are not picked up
+    pub fn raw(&mut self, path: &str, file: &Path) -> Result<(), RouteError> {
+        let body = fs::read(file)
+            .map_err(|e| RouteError::Unreadable(file.display().to_string(), e.to_string()))?;
+        let raw = RawResponse::render(file, &body);
+        self.register(HttpMethod::Get, path, Target::Raw(raw))?;
+        Ok(())
+    }
+
+    /// Registers a GET route for the well-known resource `name`, such as `security.txt`, under
+    /// `/.well-known/`; `name` may contain parameters, as in `acme-challenge/{token}`
+    pub fn well_known(&mut self, name: &str, handler: Handler) -> Result<&mut Route, RouteError> {
+        if name.is_empty() || name.starts_with('/') {
+            return Err(RouteError::InvalidPattern(format!(
+                "well-known name '{}' must be non-empty and not start with '/'",
+                name
+            )));
+        }
+        self.get(&format!("{}/{}", well_known::PREFIX, name), handler)
+    }
+
+    /// Registers a route for any method, returning it so middleware can be attached
+    pub fn add(
+        &mut self,
+        method: HttpMethod,
+        path: &str,
+        handler: Handler,
+    ) -> Result<&mut Route, RouteError> {
+        self.register(method, path, Target::Handler(handler))
+    }
+
+    /// Registers a POST route, returning it so middleware can be attached
+    pub fn post(&mut self, path: &str, handler: Handler) -> Result<&mut Route, RouteError> {
+        self.register(HttpMethod::Post, path, Target::Handler(handler))
+    }
+
+    /// Registers a GET route, returning it so middleware can be attached
+    pub fn get(&mut self, path: &str, handler: Handler) -> Result<&mut Route, RouteError> {
+        self.register(HttpMethod::Get, path, Target::Handler(handler))
+    }
+
+    /// Registers the routes added by `build` under `prefix`, returning them so middleware can be
+    /// attached to the whole group; scopes nest, so prefixes add up
+    pub fn scope(
+        &mut self,
+        prefix: &str,
+        build: impl FnOnce(&mut Router) -> Result<(), RouteError>,
+    ) -> Result<RouteGroup<'_>, RouteError> {
+        if !prefix.starts_with('/') || prefix.ends_with('/') {
+            return Err(RouteError::InvalidPattern(format!(
+                "scope prefix '{}' must start with '/' and not end with one",
+                prefix
+            )));
+        }
+
+        let first = self.routes.len();
+        let outer = self.prefix.clone();
+        self.prefix.push_str(prefix);
+        let built = build(self);
+        self.prefix = outer;
+        built?;
+
+        Ok(RouteGroup {
+            routes: &mut self.routes[first..],
+        })
+    }
+
+    /// Adds a route unless it duplicates or overlaps one already registered for the method
+    fn register(
+        &mut self,
+        method: HttpMethod,
+        path: &str,
+        target: Target,
+    ) -> Result<&mut Route, RouteError> {
+        // Inside a scope, "/" names the scope's own path rather than one with a trailing slash
+        let path = match path {
+            "/" if !self.prefix.is_empty() => self.prefix.clone(),
+            _ => format!("{}{}", self.prefix, path),
+        };
+        let pattern = Pattern::parse(&path).map_err(RouteError::InvalidPattern)?;
+
+        for existing in self.routes.iter().filter(|r| r.method == method) {
+            let new_route = format!("{} {}", method, pattern);
+            let existing_route = format!("{} {}", existing.method, existing.pattern);
+            match pattern.overlap(&existing.pattern) {
+                Overlap::Disjoint => {}
+                Overlap::Identical => {
+                    return Err(RouteError::Duplicate(new_route, existing_route));
+                }
+                Overlap::Ambiguous => {
+                    return Err(RouteError::Ambiguous(new_route, existing_route));
+                }
+            }
+        }
+
+        self.routes.push(Route {
+            method,
+            pattern,
+            target,
+            params: Params::new(),
+            guards: Vec::new(),
+            middleware: Vec::new(),
+            after_write: Vec::new(),
+            max_response: None,
+            stats: RouteStats::default(),
+        });
+        Ok(self.routes.last_mut().expect("route was just pushed"))
+    }
+
+    /// Finds the matching route and writes its response to the stream, returning its status and
+    /// how much of the response was delivered
+    pub fn route(
+        &self,
+        request: &HttpRequest,
+        stream: &mut dyn ClientStream,
+        ctx: &server::ServerContext,
+        req_id: RequestId,
+    ) -> (HttpStatusCode, WriteReport) {
+        let started = Instant::now();
+        let found = self.find(request);
+        let method = &request.status_line.method;
+        let (label, stats) = found
+            .as_ref()
+            .map_or((metrics::UNMATCHED_ROUTE, &self.unmatched), |(route, _)| {
+                (route.pattern.as_str(), &route.stats)
+            });
+        if let Some((route, _)) = &found {
+            if let Target::Raw(raw) = &route.target {
+                let report = raw.send(stream, req_id);
+                metrics::record_response(label, method, &HttpStatusCode::Ok, report.bytes);
+                stats.record(started.elapsed());
+                return (HttpStatusCode::Ok, report);
+            }
+        }
+
+        let hooks = found
+            .as_ref()
+            .map_or(&[][..], |(route, _)| &route.after_write[..]);
+        let response = self.respond(found, request, ctx, req_id);
+        let status = response.status_line.status.clone();
+
+        let mut report = send_observed(stream, response, req_id, |written| {
+            for hook in hooks {
+                hook(request, written, ctx, req_id);
+            }
+        });
+        if let Some(e) = report.error.take() {
+            HttpWriter::log_writer_error(e, "Router::route - sending response", req_id);
+        }
+        metrics::record_response(label, method, &status, report.bytes);
+        stats.record(started.elapsed());
+        (status, report)
+    }
+
+    /// Returns each route's hit count and latency percentiles, labelled "GET /echo/{text}", in
+    /// registration order, followed by requests no route matched
+    pub fn stats(&self) -> Vec<(String, StatsSnapshot)> {
+        self.routes
+            .iter()
+            .map(|route| {
+                let label = format!("{} {}", route.method, route.pattern);
+                (label, route.stats.snapshot())
+            })
+            .chain([(
+                metrics::UNMATCHED_ROUTE.to_string(),
+                self.unmatched.snapshot(),
+            )])
+            .collect()
+    }
+
+    /// Returns the pattern of the route that answers `request`, e.g. "/echo/{text}"
+    #[cfg(feature = "otel")]
+    pub fn pattern_for(&self, request: &HttpRequest) -> Option<&str> {
+        self.find(request).map(|(route, _)| route.pattern.as_str())
+    }
+
+    /// Builds the response `route` would send, without a socket
+    #[cfg(test)]
+    pub fn handle(
+        &self,
+        request: &HttpRequest,
+        ctx: &server::ServerContext,
+        req_id: RequestId,
+    ) -> HttpResponse {
+        self.respond(self.find(request), request, ctx, req_id)
+    }
+
+    /// Runs the found route's guards, middleware, and handler, turning errors into error responses
+    fn respond(
+        &self,
+        found: Option<(&Route, Params)>,
+        request: &HttpRequest,
+        ctx: &server::ServerContext,
+        req_id: RequestId,
+    ) -> HttpResponse {
+        let result = match found {
+            Some((route, params)) => match route.target {
+                Target::Handler(handler) => {
+                    route.check(request, &params, ctx, req_id).and_then(|()| {
+                        Next::new(&route.middleware, handler)
+                            .limit(route.max_response)
+                            .run(request, &params, ctx, req_id)
+                    })
+                }
+                Target::Raw(ref raw) => Ok(raw.to_response()),
+            },
+            None => Err(self.not_found(request)),
+        };
+
+        let mut response =
+            result.unwrap_or_else(|error| error_response(error, request, ctx, req_id));
+        // A redirect is hinted with the shard of its target, so the client goes straight there
+        let steered_path = response
+            .headers
+            .get("Location")
+            .unwrap_or(&request.status_line.path);
+        if let Some(shard) = ctx.config().shard.shard_for(steered_path) {
+            response
+                .headers
+                .insert(SHARD_HEADER.to_string(), shard.to_string());
+        }
+        response
+    }
+
+    /// Runs only the matching route's guards, so a request can be refused from its head alone
+    ///
+    /// Returns the error response to send, or `None` if the handler should receive the body
+    pub fn preflight(
+        &self,
+        request: &HttpRequest,
+        ctx: &server::ServerContext,
+        req_id: RequestId,
+    ) -> Option<HttpResponse> {
+        let result = match self.find(request) {
+            Some((route, params)) => route.check(request, &params, ctx, req_id),
+            None => Err(self.not_found(request)),
+        };
+
+        result
+            .err()
+            .map(|error| error_response(error, request, ctx, req_id))
+    }
+
+    /// Returns the first route whose method and pattern (constraints included) match the request
+    fn find(&self, request: &HttpRequest) -> Option<(&Route, Params)> {
+        let method = &request.status_line.method;
+        let path = &request.status_line.path;
+        self.find_path(method, path)
+            .or_else(|| match self.trailing_slash {
+                TrailingSlash::Ignore => self.find_path(method, &toggle_trailing_slash(path)?),
+                TrailingSlash::Strict | TrailingSlash::Redirect => None,
+            })
+    }
+
+    fn find_path(&self, method: &HttpMethod, path: &str) -> Option<(&Route, Params)> {
+        self.routes
+            .iter()
+            .filter(|route| route.method == *method)
+            .find_map(|route| {
+                let mut params = route.params.clone();
+                params.extend(route.pattern.matches(path)?);
+                Some((route, params))
+            })
+    }
+
+    /// Builds the error for a request no route matched: 404, or a redirect to the other slash form
+    fn not_found(&self, request: &HttpRequest) -> HttpError {
+        let not_found = HttpError::new(HttpStatusCode::NotFound, "Route not found");
+        if self.trailing_slash != TrailingSlash::Redirect {
+            return not_found;
+        }
+        let Some(path) = toggle_trailing_slash(&request.status_line.path) else {
+            return not_found;
+        };
+        if self.find_path(&request.status_line.method, &path).is_none() {
+            return not_found;
+        }
+
+        let location = match &request.status_line.query {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+        // 301 lets clients turn a POST into a GET; 308 makes them repeat the method and body
+        let status = match request.status_line.method {
+            HttpMethod::Get => HttpStatusCode::MovedPermanently,
+            _ => HttpStatusCode::PermanentRedirect,
+        };
+        HttpError::new(status, format!("Moved to {}", location)).with_header("Location", &location)
+    }
+}
+
+/// Adds or removes a trailing slash; the root path has no other form
+fn toggle_trailing_slash(path: &str) -> Option<String> {
+    match path.strip_suffix('/') {
+        _ if path == "/" => None,
+        Some(trimmed) => Some(trimmed.to_string()),
+        None => Some(format!("{}/", path)),
+    }
+}
+
+/// Builds the response for a failed request, using the site's error page when it has one
+fn error_response(
+    error: HttpError,
+    request: &HttpRequest,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> HttpResponse {
+    let page = ctx.error_page(request.host(), &error.status);
+    let message = errors::with_reference(&error.status, error.message, req_id);
+    let mut response =
+        HttpErrorResponse::for_request(error.status, request, message).with_page(page);
+    for (key, value) in error.headers {
+        if key.eq_ignore_ascii_case("Vary") {
+            value
+                .split(',')
+                .for_each(|field| append_vary(&mut response.headers, field.trim()));
+        } else {
+            response.headers.insert(key, value);
+        }
+    }
+
+    HttpResponse::from_writable(response)
+}
+
+/// Maps a path resolution failure to the status sent to the client
+fn resolve_status(error: server::ResolveError) -> HttpStatusCode {
+    match error {
+        server::ResolveError::Forbidden => HttpStatusCode::Forbidden,
+        server::ResolveError::NotFound => HttpStatusCode::NotFound,
+        server::ResolveError::Invalid => HttpStatusCode::NotFound,
+        server::ResolveError::Io => HttpStatusCode::InternalServerError,
+    }
+}
+
+/// Handler that handles a root path
+pub fn root_handler(
+    request: &HttpRequest,
+    _params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    server_log!(Info, req = req_id, "[root] handling /");
+
+    // A root with an index.html is treated as a static site
+    if let Some(index) = ctx.site_page(request.host(), "index.html") {
+        let headers = HashMap::from([
+            (
+                "Content-Type".to_string(),
+                HttpContentType::Html.to_string(),
+            ),
+            ("Content-Length".to_string(), index.len().to_string()),
+        ]);
+        let status_line = ResponseStatusLine {
+            version: request.status_line.version.clone(),
+            status: HttpStatusCode::Ok,
+        };
+        return Ok(HttpResponse::new(
+            status_line,
+            headers,
+            Some(HttpBody::Text(index)),
+        ));
+    }
+
+    let body = "Welcome to the Rust HTTP Server!".to_string();
+
+    let accept_type = request.headers.get("Accept").map(|s| s.as_str());
+
+    Ok(HttpResponse::with_negotiation(
+        HttpStatusCode::Ok,
+        request.status_line.version.clone(),
+        request.headers.get("Connection").map_or("", |s| s.as_str()),
+        body,
+        accept_type,
+        None,
+        HttpContentType::PlainText.to_string().as_str(),
+    ))
+}
+
+/// Basic chunked response handler
+pub fn chunked_handler(
+    request: &HttpRequest,
+    params: &Params,
+    _ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    server_log!(Info, req = req_id, "[chunked] params={:?}", params);
+    let status_line = ResponseStatusLine {
+        version: request.status_line.version.clone(),
+        status: HttpStatusCode::Ok,
+    };
+
+    let body = params
+        .get("text")
+        .map(|s| s.as_bytes())
+        .unwrap_or(b"")
+        .to_vec();
+
+    let chunked_headers: HashMap<String, String> = [
+        (
+            "Content-Type".to_string(),
+            HttpContentType::PlainText.to_string(),
+        ),
+        ("Transfer-Encoding".to_string(), "chunked".to_string()),
+        ("Connection".to_string(), "close".to_string()),
+    ]
+    .into();
+
+    Ok(HttpResponse::new(
+        status_line,
+        chunked_headers,
+        Some(HttpBody::Binary(body)),
+    ))
+}
+
+/// Handler that echoes text parameter
+pub fn echo_handler(
+    request: &HttpRequest,
+    params: &Params,
+    _ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    server_log!(Info, req = req_id, "[echo] params={:?}", params);
+    let body = params
+        .get("text")
+        .map(|s| s.as_str())
+        .unwrap_or("")
+        .to_string();
+
+    let accept_type = request.headers.get("Accept").map(|s| s.as_str());
+
+    Ok(HttpResponse::with_negotiation(
+        HttpStatusCode::Ok,
+        request.status_line.version.clone(),
+        request.headers.get("Connection").map_or("", |s| s.as_str()),
+        body,
+        accept_type,
+        None,
+        HttpContentType::PlainText.to_string().as_str(),
+    ))
+}
+
+/// Handler that returns the content of a file
+pub fn file_handler(
+    request: &HttpRequest,
+    params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    let filename = params.get("filename").map(|s| s.as_str()).unwrap_or("");
+    server_log!(
+        Info,
+        req = req_id,
+        "[file] method={} raw_path={} filename_param={:?}",
+        request.status_line.method,
+        request.status_line.path,
+        filename
+    );
+
+    let conn = request
+        .headers
+        .get("Connection")
+        .map(|s| s.as_str())
+        .unwrap_or("");
+
+    match request.status_line.method {
+        HttpMethod::Get => serve_file(request, filename, conn, ctx, req_id),
+        HttpMethod::Post => {
+            let content = request.body.as_ref().map_or("", |b| b.as_str());
+
+            let resolved = ctx
+                .resolve_path(
+                    request.host(),
+                    filename,
+                    server::AccessIntent::Write,
+                    req_id,
+                )
+                .map_err(|err| HttpError::new(resolve_status(err), "File resolution failed"))?;
+
+            fs::write(resolved.path(), content).map_err(|e| {
+                HttpError::new(
+                    HttpStatusCode::InternalServerError,
+                    format!("Failed to write file '{}': {}", filename, e),
+                )
+            })?;
+            ctx.file_cache().remove(resolved.path());
+
+            let status = if resolved.exists() {
+                HttpStatusCode::Ok
+            } else {
+                HttpStatusCode::Created
+            };
+
+            Ok(HttpResponse::for_file_error(
+                status,
+                request.status_line.version.clone(),
+                conn,
+                filename,
+                format!("File '{}' created/updated", filename),
+            ))
+        }
+        _ => Err(HttpError::new(
+            HttpStatusCode::MethodNotAllowed,
+            "Method not allowed",
+        )),
+    }
+}
+
+/// Serves a file under the site root, honoring a Range header
+fn serve_file(
+    request: &HttpRequest,
+    filename: &str,
+    conn: &str,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    let resolved = ctx
+        .resolve_path(request.host(), filename, server::AccessIntent::Read, req_id)
+        .map_err(|err| HttpError::new(resolve_status(err), "File resolution failed"))?;
+    // A directory resolves to its index page, whose name decides the Content-Type
+    let served_name = resolved.path().to_string_lossy();
+    let mime_type = Path::new(served_name.as_ref())
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(mime::lookup)
+        .or_else(|| {
+            ctx.config()
+                .sniff_types
+                .then(|| mime::sniff_file(resolved.path()))
+                .flatten()
+        })
+        .unwrap_or(mime::FALLBACK_TYPE);
+    // Text types are declared UTF-8; files stored in another charset declare that one instead,
+    // letting the charset middleware transcode them
+    let source = ctx.config().charset.source;
+    let content_type = if source != Charset::Utf8 && charset::is_text(mime_type) {
+        charset::with_charset(mime_type, source)
+    } else {
+        mime_type.to_string()
+    };
+    let file_name = resolved
+        .path()
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let range = request
+        .headers
+        .get("Range")
+        .and_then(|range_str| ByteRange::from_header(range_str));
+
+    // A range is served from the file itself, since its offsets refer to the uncompressed bytes
+    let accept_encoding = request
+        .headers
+        .get("Accept-Encoding")
+        .filter(|_| range.is_none())
+        .map(|s| s.as_str());
+    let precompressed = if ctx.config().compression.precompressed {
+        precompressed::lookup(resolved.path(), accept_encoding)
+    } else {
+        precompressed::Lookup::default()
+    };
+    let (file_path, coding) = match &precompressed.chosen {
+        Some((path, coding)) => (path.as_path(), Some(*coding)),
+        None => (resolved.path().as_path(), None),
+    };
+
+    // Sent with every answer for this file, 304s included
+    let mut cache_headers: HashMap<String, String> = ctx
+        .config()
+        .cache_control
+        .value_for(&request.status_line.path, &file_name, mime_type)
+        .map(|value| ("Cache-Control".to_string(), value.to_string()))
+        .into_iter()
+        .collect();
+    if precompressed.varies {
+        append_vary(&mut cache_headers, "Accept-Encoding");
+    }
+
+    // Cached files are tagged from their contents, so purging one re-tags content that was
+    // replaced without changing its timestamp; the rest are tagged from their metadata
+    let etag = ctx
+        .file_cache()
+        .etag(file_path)
+        .ok()
+        .flatten()
+        .or_else(|| {
+            fs::metadata(file_path)
+                .ok()
+                .and_then(|metadata| etag::weak_etag(&metadata))
+        })
+        .map(|etag| match coding {
+            Some(coding) => etag::for_encoding(&etag, coding),
+            None => etag,
+        });
+    if let Some(etag) = &etag {
+        let if_none_match = request
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("If-None-Match"))
+            .map(|(_, value)| value.as_str());
+        if if_none_match.is_some_and(|header| etag::none_match(header, etag)) {
+            server_log!(Debug, req = req_id, "[file] {} not modified", etag);
+            let status_line = ResponseStatusLine {
+                version: request.status_line.version.clone(),
+                status: HttpStatusCode::NotModified,
+            };
+            let mut headers = HashMap::from([("ETag".to_string(), etag.clone())]);
+            headers.extend(cache_headers);
+            return Ok(HttpResponse::new(status_line, headers, None));
+        }
+    }
+
+    let read_request = match range {
+        Some(range) => FileReadRequest::Range(file_path.to_path_buf(), range),
+        None => FileReadRequest::Full(file_path.to_path_buf()),
+    };
+
+    let file_result = read_file_with_range(read_request, ctx.file_cache()).map_err(|err| {
+        let status = match err {
+            FileReadError::NotFound(_) => HttpStatusCode::NotFound,
+            FileReadError::IoError(_) => HttpStatusCode::InternalServerError,
+            FileReadError::InvalidRange(file_size) => {
+                return HttpError::new(
+                    HttpStatusCode::RangeNotSatisfiable,
+                    "Range not satisfiable",
+                )
+                .with_header("Content-Range", &format!("bytes */{}", file_size));
+            }
+            _ => HttpStatusCode::InternalServerError,
+        };
+        HttpError::new(status, "Reading file content failed")
+    })?;
+
+    let Some((start, end)) = file_result.range else {
+        let mut response = HttpResponse::for_file(
+            HttpStatusCode::Ok,
+            request.status_line.version.clone(),
+            conn,
+            &served_name,
+            file_result.body,
+        );
+        response
+            .headers
+            .insert("Content-Type".to_string(), content_type);
+        if let Some(etag) = etag {
+            response.headers.insert("ETag".to_string(), etag);
+        }
+        if let Some(coding) = coding {
+            server_log!(Debug, req = req_id, "[file] sending {} variant", coding);
+            response
+                .headers
+                .insert("Content-Encoding".to_string(), coding.to_string());
+        }
+        response.headers.extend(cache_headers);
+        return Ok(response);
+    };
+
+    let status_line = ResponseStatusLine {
+        version: request.status_line.version.clone(),
+        status: HttpStatusCode::PartialContent,
+    };
+
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), content_type);
+    headers.insert(
+        "Content-Length".to_string(),
+        file_result.body.byte_len().to_string(),
+    );
+    headers.insert(
+        "Content-Range".to_string(),
+        format!("bytes {}-{}/{}", start, end, file_result.total_size),
+    );
+    headers.insert("Connection".to_string(), conn.to_string());
+    if let Some(etag) = etag {
+        headers.insert("ETag".to_string(), etag);
+    }
+    headers.extend(cache_headers);
+
+    Ok(HttpResponse::new(
+        status_line,
+        headers,
+        Some(file_result.body),
+    ))
+}
+
+/// Handler for `Router::static_files` routes: serves `{path..}` from the route's directory
+pub fn static_handler(
+    request: &HttpRequest,
+    params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    let dir = params.get(STATIC_DIR_PARAM).map_or("", |s| s.as_str());
+    let path = params.get("path").map_or("", |s| s.as_str());
+    let filename = if dir.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", dir, path)
+    };
+    server_log!(Info, req = req_id, "[static] {:?}", filename);
+
+    let conn = request.headers.get("Connection").map_or("", |s| s.as_str());
+    serve_file(request, &filename, conn, ctx, req_id)
+}
+
+/// Handler that serves a generated m3u8 playlist, or a media segment, from a directory under the root
+pub fn hls_handler(
+    request: &HttpRequest,
+    params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    let dirname = params.get("dirname").map(|s| s.as_str()).unwrap_or("");
+    let segment = params.get("segment").map(|s| s.as_str()).unwrap_or("");
+    server_log!(
+        Info,
+        req = req_id,
+        "[hls] dirname={:?} segment={:?}",
+        dirname,
+        segment
+    );
+
+    let conn = request
+        .headers
+        .get("Connection")
+        .map(|s| s.as_str())
+        .unwrap_or("");
+
+    if segment != PLAYLIST_NAME {
+        if !is_segment(segment) {
+            return Err(HttpError::new(
+                HttpStatusCode::NotFound,
+                "Not a media segment",
+            ));
+        }
+
+        // Segments are plain files, so reuse the file handler (and its range support)
+        let file_params =
+            Params::from([("filename".to_string(), format!("{}/{}", dirname, segment))]);
+        return file_handler(request, &file_params, ctx, req_id);
+    }
+
+    let dir = ctx.resolve_path(
+        request.host(),
+        dirname,
+        server::AccessIntent::Directory,
+        req_id,
+    );
+    let playlist = match dir {
+        Ok(resolved) => generate_playlist(resolved.path(), DEFAULT_SEGMENT_DURATION),
+        Err(_) => Err(io::ErrorKind::NotFound.into()),
+    };
+
+    let playlist = playlist.map_err(|e| {
+        server_log!(
+            Error,
+            req = req_id,
+            "[hls] playlist generation failed: {}",
+            e
+        );
+        let status = match e.kind() {
+            io::ErrorKind::NotFound => HttpStatusCode::NotFound,
+            _ => HttpStatusCode::InternalServerError,
+        };
+        HttpError::new(status, "Playlist generation failed")
+    })?;
+
+    Ok(HttpResponse::for_file(
+        HttpStatusCode::Ok,
+        request.status_line.version.clone(),
+        conn,
+        PLAYLIST_NAME,
+        HttpBody::Text(playlist),
+    ))
+}
+
+/// Handler that returns User-Agent header
+pub fn user_agent_handler(
+    request: &HttpRequest,
+    _params: &Params,
+    _ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    server_log!(Info, req = req_id, "[user-agent]");
+    let user_agent = request
+        .headers
+        .get("User-Agent")
+        .map(|s| s.as_str())
+        .unwrap_or("Unknown");
+
+    let body = user_agent.to_string();
+
+    let accept_type = request.headers.get("Accept").map(|s| s.as_str());
+
+    Ok(HttpResponse::with_negotiation(
+        HttpStatusCode::Ok,
+        request.status_line.version.clone(),
+        request.headers.get("Connection").map_or("", |s| s.as_str()),
+        body,
+        accept_type,
+        None,
+        HttpContentType::PlainText.to_string().as_str(),
+    ))
+}
+
+/// Handler that serves a resized copy of an image, cached under the context's cache directory
+#[cfg(feature = "thumbnails")]
+pub fn thumb_handler(
+    request: &HttpRequest,
+    params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    use crate::http::files::thumbnail::{parse_width, thumbnail_path, ThumbnailError};
+
+    let filename = params.get("filename").map(|s| s.as_str()).unwrap_or("");
+    server_log!(
+        Info,
+        req = req_id,
+        "[thumb] filename_param={:?} query={:?}",
+        filename,
+        request.status_line.query
+    );
+
+    let conn = request
+        .headers
+        .get("Connection")
+        .map(|s| s.as_str())
+        .unwrap_or("");
+
+    let result = parse_width(request.query_param("w")).and_then(|width| {
+        match ctx.resolve_path(request.host(), filename, server::AccessIntent::Read, req_id) {
+            Ok(resolved) => thumbnail_path(resolved.path(), ctx.cache_dir(), width),
+            Err(server::ResolveError::Io) => Err(ThumbnailError::IoError(io::Error::other(
+                "File resolution failed",
+            ))),
+            Err(_) => Err(ThumbnailError::IoError(io::ErrorKind::NotFound.into())),
+        }
+    });
+
+    let thumbnail = result.and_then(|path| {
+        fs::read(&path)
+            .map(|bytes| (path, bytes))
+            .map_err(ThumbnailError::from)
+    });
+
+    let (path, bytes) = thumbnail.map_err(|err| {
+        let (status, message) = match err {
+            ThumbnailError::InvalidWidth => (
+                HttpStatusCode::BadRequest,
+                "Query parameter 'w' must be between 1 and 2048",
+            ),
+            ThumbnailError::UnsupportedFormat => {
+                (HttpStatusCode::BadRequest, "Unsupported image format")
+            }
+            ThumbnailError::IoError(e) if e.kind() == io::ErrorKind::NotFound => {
+                (HttpStatusCode::NotFound, "Image not found")
+            }
+            ThumbnailError::Decode(reason) => {
+                server_log!(
+                    Error,
+                    req = req_id,
+                    "[thumb] decode/encode error: {}",
+                    reason
+                );
+                (
+                    HttpStatusCode::InternalServerError,
+                    "Thumbnail generation failed",
+                )
+            }
+            ThumbnailError::IoError(_) => (
+                HttpStatusCode::InternalServerError,
+                "Thumbnail generation failed",
+            ),
+        };
+        HttpError::new(status, message)
+    })?;
+
+    let thumb_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(filename);
+
+    let mut response = HttpResponse::for_file(
+        HttpStatusCode::Ok,
+        request.status_line.version.clone(),
+        conn,
+        thumb_name,
+        HttpBody::Binary(bytes),
+    );
+    response.headers.insert(
+        "Cache-Control".to_string(),
+        "public, max-age=31536000, immutable".to_string(),
+    );
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::test_support::{TempRoot, TestClient};
+
+    #[test]
+    fn test_register_rejects_duplicate_and_shadowed_routes() {
+        let mut router = Router::new(&ServerConfig::default()).unwrap();
+
+        assert!(matches!(
+            router.get("/files/{name..}", file_handler),
+            Err(RouteError::Duplicate(_, _))
+        ));
+        assert!(matches!(
+            router.get("/files/{dir}/{name}", file_handler),
+            Err(RouteError::Ambiguous(_, _))
+        ));
+        assert!(matches!(
+            router.get("/files/index", file_handler),
+            Err(RouteError::Ambiguous(_, _))
+        ));
+        assert!(matches!(
+            router.get("/hls/{dir}/playlist.m3u8", hls_handler),
+            Err(RouteError::Ambiguous(_, _))
+        ));
+        assert!(matches!(
+            router.get("/files/{id:u3}", file_handler),
+            Err(RouteError::InvalidPattern(_))
+        ));
+        assert!(router.post("/echo/{text}", echo_handler).is_ok());
+        assert!(router.get("/file/{dir}/{name}", file_handler).is_ok());
+    }
+
+    #[test]
+    fn test_scopes_prefix_their_routes() {
+        let mut router = Router::new(&ServerConfig::default()).unwrap();
+        router
+            .scope("/api", |api| {
+                api.get("/", root_handler)?;
+                api.scope("/v1", |v1| {
+                    v1.get("/echo/{text}", echo_handler)?;
+                    Ok(())
+                })?;
+                Ok(())
+            })
+            .unwrap();
+
+        let request = |raw: &str| HttpRequest::parse(raw.as_bytes()).unwrap();
+        let path = |raw: &str| {
+            let request = request(raw);
+            router
+                .find(&request)
+                .map(|(route, _)| route.pattern.to_string())
+        };
+        assert_eq!(path("GET /api HTTP/1.1\r\n\r\n").as_deref(), Some("/api"));
+        assert_eq!(
+            path("GET /api/v1/echo/hi HTTP/1.1\r\n\r\n").as_deref(),
+            Some("/api/v1/echo/{text}")
+        );
+        assert_eq!(
+            path("GET /admin/logs HTTP/1.1\r\n\r\n").as_deref(),
+            Some("/admin/logs")
+        );
+
+        assert!(router.scope("api/", |_| Ok(())).is_err());
+        let duplicate = router.scope("/files", |files| {
+            files.get("/{name..}", file_handler)?;
+            Ok(())
+        });
+        assert!(matches!(duplicate, Err(RouteError::Duplicate(_, _))));
+    }
+
+    #[test]
+    fn test_handle_returns_responses_without_a_stream() {
+        let root = TempRoot::new("handle");
+        let ctx = root.context();
+        let router = ctx.router();
+        let request = |raw: &str| HttpRequest::parse(raw.as_bytes()).unwrap();
+
+        let echo = router.handle(
+            &request("GET /echo/hi HTTP/1.1\r\n\r\n"),
+            &ctx,
+            ctx.next_request_id(),
+        );
+        assert_eq!(echo.status_line.status, HttpStatusCode::Ok);
+        assert_eq!(echo.headers["Content-Length"], "2");
+
+        let missing = router.handle(
+            &request("GET /nowhere HTTP/1.1\r\n\r\n"),
+            &ctx,
+            ctx.next_request_id(),
+        );
+        assert_eq!(missing.status_line.status, HttpStatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_trailing_slash_modes() {
+        let root = TempRoot::new("trailing-slash");
+        let client = |trailing_slash| {
+            let config = ServerConfig {
+                trailing_slash,
+                ..root.config()
+            };
+            let ctx = server::ServerContext::with_routes(config, |router| {
+                router.post("/echo/{text}", echo_handler).map(|_| ())
+            })
+            .unwrap();
+            TestClient::new(&ctx)
+        };
+        let slashed = "/user-agent/?v=1";
+
+        let strict = client(TrailingSlash::Strict).get(slashed);
+        assert_eq!(strict.status, HttpStatusCode::NotFound);
+
+        let ignored = client(TrailingSlash::Ignore).get(slashed);
+        assert_eq!(ignored.status, HttpStatusCode::Ok);
+
+        let redirect = client(TrailingSlash::Redirect);
+        let redirected = redirect.get(slashed);
+        assert_eq!(redirected.status, HttpStatusCode::MovedPermanently);
+        assert_eq!(redirected.header("Location"), Some("/user-agent?v=1"));
+
+        let post = HttpRequest::parse(b"POST /echo/hi/ HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(
+            redirect.send(&post).status,
+            HttpStatusCode::PermanentRedirect
+        );
+    }
+
+    #[test]
+    fn test_static_files_serve_nested_paths_from_their_directory() {
+        let root = TempRoot::new("static");
+        root.write("public/css/app.css", "body {}");
+        root.write("secret.txt", "hidden");
+
+        let mut config = root.config();
+        config
+            .static_files
+            .insert("/assets".to_string(), "public".to_string());
+        let client = TestClient::new(&server::ServerContext::from_config(config).unwrap());
+        let get = |path: &str| client.get(path).status;
+
+        assert_eq!(get("/assets/css/app.css"), HttpStatusCode::Ok);
+        assert_ne!(get("/assets/../secret.txt"), HttpStatusCode::Ok);
+        assert_eq!(get("/assets/missing.css"), HttpStatusCode::NotFound);
+
+        let mut router = Router::new(&ServerConfig::default()).unwrap();
+        assert!(router.static_files("assets/", "public").is_err());
+        assert!(router.static_files("/assets", "public").is_ok());
+    }
+
+    #[test]
+    fn test_directory_requests_serve_their_index_page() {
+        let root = TempRoot::new("index");
+        root.write("docs/index.html", "<h1>Docs</h1>");
+        fs::create_dir_all(root.join("empty")).unwrap();
+
+        let client = TestClient::new(&root.context());
+
+        let response = client.get("/files/docs");
+        assert_eq!(response.status, HttpStatusCode::Ok);
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(client.get("/files/docs/").status, HttpStatusCode::Ok);
+        assert_eq!(client.get("/files/empty").status, HttpStatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_hls_playlists_list_the_directory_segments() {
+        let root = TempRoot::new("hls");
+        root.write("show/seg1.ts", "one");
+        root.write("show/seg0.ts", "zero");
+        root.write("show/notes.txt", "not a segment");
+        let ctx = root.context();
+        let client = TestClient::new(&ctx);
+
+        let playlist = client.get("/hls/show/index.m3u8");
+        assert_eq!(playlist.status, HttpStatusCode::Ok);
+        let body = playlist.text();
+        assert!(body.starts_with("#EXTM3U\n"), "{}", body);
+        assert!(body.find("seg0.ts").unwrap() < body.find("seg1.ts").unwrap());
+        assert!(!body.contains("notes.txt"), "{}", body);
+
+        assert_eq!(client.get("/hls/show/seg1.ts").text(), "one");
+        assert_eq!(
+            client.get("/hls/missing/index.m3u8").status,
+            HttpStatusCode::NotFound
+        );
+    }
+
+    #[test]
+    fn test_error_pages_come_from_the_root() {
+        let root = TempRoot::new("pages");
+        for page in [
+            "404.html",
+            "missing.html",
+            "403.html",
+            "500.html",
+            "50x.html",
+        ] {
+            root.write(page, page);
+        }
+
+        let config = ServerConfig {
+            error_pages: std::collections::BTreeMap::from([
+                ("404".to_string(), "missing.html".to_string()),
+                ("5xx".to_string(), String::new()),
+            ]),
+            ..root.config()
+        };
+        let ctx = server::ServerContext::from_config(config).unwrap();
+        let response = TestClient::new(&ctx).get("/files/nope.txt");
+        assert_eq!(response.status, HttpStatusCode::NotFound);
+        assert_eq!(response.text(), "missing.html");
+
+        let page = |status| ctx.error_page(None, &status);
+        assert_eq!(page(HttpStatusCode::Forbidden).unwrap(), "403.html");
+        assert_eq!(
+            page(HttpStatusCode::InternalServerError).unwrap(),
+            "500.html"
+        );
+        assert_eq!(page(HttpStatusCode::ServiceUnavailable), None);
+        assert_eq!(page(HttpStatusCode::Conflict), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_windows_filename_rules_apply_only_when_asked() {
+        use server::FilenameRules;
+
+        let root = TempRoot::new("names");
+        root.write("aux.log", "log");
+        root.write("report:2024", "report");
+
+        for rules in [FilenameRules::Platform, FilenameRules::Windows] {
+            let config = ServerConfig {
+                filename_rules: rules,
+                ..root.config()
+            };
+            let client = TestClient::new(&server::ServerContext::from_config(config).unwrap());
+            let expected = match rules {
+                FilenameRules::Platform => HttpStatusCode::Ok,
+                FilenameRules::Windows => HttpStatusCode::NotFound,
+            };
+            for name in ["aux.log", "report:2024", "report%3A2024"] {
+                let status = client.get(&format!("/files/{}", name)).status;
+                assert_eq!(status, expected, "{:?} {}", rules, name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_file_etags_answer_if_none_match_with_304() {
+        let root = TempRoot::new("etag");
+        root.write("clip.mp4", vec![7u8; 64]);
+
+        let client = TestClient::new(&root.context());
+        let get = |headers: &str| {
+            let raw = format!("GET /files/clip.mp4 HTTP/1.1\r\n{}\r\n", headers);
+            client.send(&HttpRequest::parse(raw.as_bytes()).unwrap())
+        };
+
+        let first = get("");
+        assert_eq!(first.status, HttpStatusCode::Ok);
+        let etag = first.header("ETag").unwrap().to_string();
+        assert!(etag.starts_with("W/\""), "{}", etag);
+
+        // The 304 has no Content-Length, and the writer must still put it on the wire
+        let revalidated = get(&format!("If-None-Match: {}\r\n", etag));
+        assert_eq!(revalidated.status, HttpStatusCode::NotModified);
+        assert_eq!(revalidated.header("ETag"), Some(etag.as_str()));
+        assert!(revalidated.body.is_empty());
+
+        let ranged = get("Range: bytes=0-9\r\nIf-None-Match: \"stale\"\r\n");
+        assert_eq!(ranged.status, HttpStatusCode::PartialContent);
+        assert_eq!(ranged.header("ETag"), Some(etag.as_str()));
+    }
+
+    #[test]
+    fn test_cached_files_are_compressed_and_transcoded_whole() {
+        let root = TempRoot::new("cached-file");
+        root.write("notes.txt", "caf\u{e9} ".repeat(400));
+        let ctx = root.context();
+        let client = TestClient::new(&ctx);
+        let get = |headers: &str| {
+            let raw = format!("GET /files/notes.txt HTTP/1.1\r\n{}\r\n", headers);
+            client.send(&HttpRequest::parse(raw.as_bytes()).unwrap())
+        };
+
+        // The first request fills the cache; the rest are served from it
+        assert_eq!(get("").body.len(), 2400);
+        let gzip = get("Accept-Encoding: gzip\r\n");
+        assert_eq!(gzip.header("Content-Encoding"), Some("gzip"));
+        let length = gzip.body.len().to_string();
+        assert_eq!(gzip.header("Content-Length"), Some(length.as_str()));
+
+        let latin1 = get("Accept-Charset: iso-8859-1\r\n");
+        let content_type = latin1.header("Content-Type").unwrap();
+        assert!(content_type.contains("iso-8859-1"), "{}", content_type);
+        assert_eq!(latin1.body, b"caf\xe9 ".repeat(400));
+
+        let range = get("Range: bytes=6-11\r\n");
+        assert_eq!(range.status, HttpStatusCode::PartialContent);
+        assert_eq!(range.text(), "caf\u{e9} ");
+    }
+
+    #[test]
+    fn test_unsatisfiable_ranges_get_416_with_the_file_size() {
+        let root = TempRoot::new("416");
+        root.write("clip.mp4", vec![7u8; 64]);
+
+        let client = TestClient::new(&root.context());
+        let get = |range: &str| {
+            let raw = format!("GET /files/clip.mp4 HTTP/1.1\r\nRange: {}\r\n\r\n", range);
+            client.send(&HttpRequest::parse(raw.as_bytes()).unwrap())
+        };
+
+        let beyond = get("bytes=64-");
+        assert_eq!(beyond.status, HttpStatusCode::RangeNotSatisfiable);
+        assert_eq!(beyond.header("Content-Range"), Some("bytes */64"));
+        assert_eq!(
+            get("bytes=60-100").header("Content-Range"),
+            Some("bytes 60-63/64")
+        );
+    }
+
+    #[test]
+    fn test_raw_routes_send_prerendered_bytes() {
+        let root = TempRoot::new("raw");
+        root.write("pixel.gif", b"GIF89a");
+
+        let mut config = root.config();
+        config
+            .raw_routes
+            .insert("/pixel.gif".to_string(), "pixel.gif".to_string());
+        let ctx = server::ServerContext::from_config(config.clone()).unwrap();
+        let router = Router::new(ctx.config()).unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let request = HttpRequest::parse(b"GET /pixel.gif HTTP/1.1\r\n\r\n").unwrap();
+        let (status, report) = router.route(&request, &mut server, &ctx, ctx.next_request_id());
+        drop(server);
+
+        let mut sent = Vec::new();
+        io::Read::read_to_end(&mut client, &mut sent).unwrap();
+        assert_eq!(status, HttpStatusCode::Ok);
+        assert_eq!(
+            sent,
+            b"HTTP/1.1 200 OK\r\nContent-Type: image/gif\r\nContent-Length: 6\r\n\r\nGIF89a"
+        );
+        assert_eq!(report.bytes, sent.len() as u64);
+
+        config
+            .raw_routes
+            .insert("/missing".to_string(), "missing.bin".to_string());
+        assert!(matches!(
+            Router::new(&config),
+            Err(RouteError::Unreadable(..))
+        ));
+    }
+
+    #[test]
+    fn test_compression_reports_its_decision() {
+        let response = |body: &str| {
+            HttpResponse::with_negotiation(
+                HttpStatusCode::Ok,
+                crate::http::request::HttpVersion::Http1_1,
+                "",
+                body.to_string(),
+                None,
+                None,
+                "text/plain",
+            )
+        };
+        let large = "a".repeat(1024);
+        let levels = ServerConfig::default().compression.levels();
+
+        let small = CompressionMiddleware::apply(response("hi"), Some("gzip"), 1024, levels);
+        assert_eq!(
+            small.outcome(),
+            &CompressionOutcome::Skipped {
+                reason: SkipReason::BelowMinimum,
+                size: 2
+            }
+        );
+        let refused =
+            CompressionMiddleware::apply(response(&large), Some("identity"), 1024, levels);
+        assert!(matches!(
+            refused.outcome(),
+            CompressionOutcome::Skipped {
+                reason: SkipReason::NotAccepted,
+                ..
+            }
+        ));
+        for forbidden in ["identity;q=0", "zstd, *;q=0", "gzip;q=0, identity;q=0"] {
+            let apply = CompressionMiddleware::apply(response("hi"), Some(forbidden), 1024, levels);
+            assert_eq!(
+                apply.outcome(),
+                &CompressionOutcome::NotAcceptable,
+                "{}",
+                forbidden
+            );
+        }
+        assert_eq!(
+            HttpEncoding::negotiate(Some("zstd, *;q=0, identity")).map(|e| e.to_string()),
+            Some("identity".to_string())
+        );
+        let gzipped = CompressionMiddleware::apply(response(&large), Some("gzip"), 1024, levels);
+        assert!(matches!(
+            gzipped.outcome(),
+            CompressionOutcome::Compressed { encoding, original: 1024, compressed }
+                if encoding == "gzip" && *compressed < 1024
+        ));
+
+        let stored = CompressionLevels { gzip: 0, ..levels };
+        let stored = CompressionMiddleware::apply(response(&large), Some("gzip"), 1024, stored);
+        assert!(matches!(
+            stored.outcome(),
+            CompressionOutcome::Compressed { compressed, .. } if *compressed > 1024
+        ));
+        let fast = CompressionLevels {
+            brotli_quality: 0,
+            brotli_window: 10,
+            ..levels
+        };
+        let brotli = CompressionMiddleware::apply(response(&large), Some("br"), 1024, fast);
+        assert!(matches!(
+            brotli.outcome(),
+            CompressionOutcome::Compressed { compressed, .. } if *compressed < 1024
+        ));
+    }
+
+    #[test]
+    fn test_route_records_stats_per_route() {
+        let ctx = TempRoot::new("route-stats").context();
+        let client = TestClient::new(&ctx);
+        client.get("/echo/a");
+        client.get("/echo/b");
+        client.get("/nowhere");
+
+        let stats = ctx.router().stats();
+        let hits = |label: &str| {
+            stats
+                .iter()
+                .find(|(route, _)| route == label)
+                .map(|(_, snapshot)| snapshot.hits)
+        };
+        assert_eq!(hits("GET /echo/{text}"), Some(2));
+        assert_eq!(hits("GET /user-agent"), Some(0));
+        assert_eq!(hits(metrics::UNMATCHED_ROUTE), Some(1));
+    }
+}
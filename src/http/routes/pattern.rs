@@ -0,0 +1,404 @@
+use std::fmt;
+
+use super::Params;
+
+/// A parsed route pattern such as `/files/{id:u64}` or `/files/{path..}`
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+/// One `/`-separated piece of a route pattern
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),                   // Must equal the request segment
+    Param(String, Option<Constraint>), // Captures the request segment under this name
+    Rest(String),                      // Captures the rest of the path, slashes included
+}
+
+/// Restricts the values a route parameter accepts
+#[derive(Debug, Clone, PartialEq)]
+enum Constraint {
+    Integer { signed: bool, bits: u32 }, // {id:u64}, {offset:i32}
+    Class(CharClass),                    // {name:[a-z]+}, {id:\d+}
+}
+
+/// A character class with a repetition count, e.g. `[a-z0-9-]{2,16}` or `\d+`
+#[derive(Debug, Clone, PartialEq)]
+struct CharClass {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+    min: usize,
+    max: Option<usize>,
+}
+
+/// One element of a bracketed class before ranges are resolved
+#[derive(Debug)]
+enum ClassItem {
+    Char(char),             // Plain character; may be a range endpoint
+    Set(Vec<(char, char)>), // Escape: a shorthand class or a literal
+}
+
+/// How two patterns registered for the same method relate
+#[derive(Debug, PartialEq)]
+pub enum Overlap {
+    Disjoint,  // No request path matches both
+    Identical, // Same shape; only parameter names differ
+    Ambiguous, // Some request paths match both
+}
+
+impl Pattern {
+    /// Parses a route pattern, validating any `{name:constraint}` parameters
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let segments: Vec<Segment> = source
+            .split('/')
+            .map(|segment| {
+                let Some(inner) = segment
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.strip_suffix('}'))
+                else {
+                    return Ok(Segment::Literal(segment.to_string()));
+                };
+
+                if let Some(name) = inner.strip_suffix("..") {
+                    return Ok(Segment::Rest(name.to_string()));
+                }
+                match inner.split_once(':') {
+                    None => Ok(Segment::Param(inner.to_string(), None)),
+                    Some((name, constraint)) => Ok(Segment::Param(
+                        name.to_string(),
+                        Some(Constraint::parse(constraint).ok_or_else(|| {
+                            format!("invalid constraint '{}' in {}", constraint, source)
+                        })?),
+                    )),
+                }
+            })
+            .collect::<Result<_, String>>()?;
+
+        let last = segments.len() - 1;
+        if segments[..last]
+            .iter()
+            .any(|segment| matches!(segment, Segment::Rest(_)))
+        {
+            return Err(format!("wildcard in {} must be the last segment", source));
+        }
+
+        Ok(Pattern {
+            source: source.to_string(),
+            segments,
+        })
+    }
+
+    /// Matches a request path, returning the captured parameters
+    pub fn matches(&self, path: &str) -> Option<Params> {
+        let parts: Vec<&str> = path.split('/').collect();
+        let has_rest = matches!(self.segments.last(), Some(Segment::Rest(_)));
+        if parts.len() < self.segments.len() || (!has_rest && parts.len() > self.segments.len()) {
+            return None;
+        }
+
+        let mut params = Params::new();
+        for (i, (segment, value)) in self.segments.iter().zip(&parts).enumerate() {
+            match segment {
+                Segment::Literal(literal) if literal == value => {}
+                Segment::Literal(_) => return None,
+                Segment::Param(name, constraint) => {
+                    if !constraint.as_ref().map_or(true, |c| c.accepts(value)) {
+                        return None;
+                    }
+                    params.insert(name.clone(), value.to_string());
+                }
+                Segment::Rest(name) => {
+                    let rest = parts[i..].join("/");
+                    if rest.is_empty() {
+                        return None;
+                    }
+                    params.insert(name.clone(), rest);
+                }
+            }
+        }
+        Some(params)
+    }
+
+    /// Compares two patterns; differently constrained parameters are assumed not to overlap
+    pub fn overlap(&self, other: &Pattern) -> Overlap {
+        let mut identical = true;
+        for i in 0..self.segments.len().max(other.segments.len()) {
+            match (self.segments.get(i), other.segments.get(i)) {
+                (Some(Segment::Rest(_)), Some(Segment::Rest(_))) => break,
+                // A wildcard matches whatever the other pattern has left, as long as something is left
+                (Some(Segment::Rest(_)), Some(_)) | (Some(_), Some(Segment::Rest(_))) => {
+                    identical = false;
+                    break;
+                }
+                (Some(a), Some(b)) => match Self::segment_overlap(a, b) {
+                    Overlap::Disjoint => return Overlap::Disjoint,
+                    Overlap::Identical => {}
+                    Overlap::Ambiguous => identical = false,
+                },
+                _ => return Overlap::Disjoint,
+            }
+        }
+
+        if identical {
+            Overlap::Identical
+        } else {
+            Overlap::Ambiguous
+        }
+    }
+
+    /// Compares two segments that are not wildcards
+    fn segment_overlap(a: &Segment, b: &Segment) -> Overlap {
+        match (a, b) {
+            (Segment::Literal(x), Segment::Literal(y)) if x != y => Overlap::Disjoint,
+            (Segment::Literal(_), Segment::Literal(_)) => Overlap::Identical,
+            (Segment::Param(_, x), Segment::Param(_, y)) if x == y => Overlap::Identical,
+            (Segment::Param(_, Some(_)), Segment::Param(_, Some(_))) => Overlap::Disjoint,
+            (Segment::Param(_, _), Segment::Param(_, _)) => Overlap::Ambiguous,
+            (Segment::Literal(literal), Segment::Param(_, constraint))
+            | (Segment::Param(_, constraint), Segment::Literal(literal)) => {
+                if constraint.as_ref().map_or(true, |c| c.accepts(literal)) {
+                    Overlap::Ambiguous
+                } else {
+                    Overlap::Disjoint
+                }
+            }
+            (Segment::Rest(_), _) | (_, Segment::Rest(_)) => Overlap::Ambiguous,
+        }
+    }
+}
+
+impl Pattern {
+    /// Returns the pattern as registered, e.g. "/echo/{text}"
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Constraint {
+    /// Parses `u8`..`u64`, `i8`..`i64`, or a character class like `[a-z]+` or `\w+`
+    fn parse(raw: &str) -> Option<Self> {
+        let integer = |signed, bits: &str| {
+            let bits = bits.parse().ok().filter(|b| [8, 16, 32, 64].contains(b))?;
+            Some(Constraint::Integer { signed, bits })
+        };
+
+        if let Some(bits) = raw.strip_prefix('u') {
+            integer(false, bits)
+        } else if let Some(bits) = raw.strip_prefix('i') {
+            integer(true, bits)
+        } else {
+            CharClass::parse(raw).map(Constraint::Class)
+        }
+    }
+
+    /// Checks whether a request segment satisfies the constraint
+    fn accepts(&self, value: &str) -> bool {
+        match self {
+            Constraint::Integer { signed, bits } => {
+                let Ok(n) = value.parse::<i128>() else {
+                    return false;
+                };
+                let (min, max) = if *signed {
+                    (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+                } else {
+                    (0, (1i128 << bits) - 1)
+                };
+                (min..=max).contains(&n)
+            }
+            Constraint::Class(class) => class.accepts(value),
+        }
+    }
+}
+
+impl CharClass {
+    /// Parses `[...]` or a `\d`/`\w` shorthand, followed by an optional `+`, `*`, `?`, `{n}`,
+    /// `{n,}`, or `{n,m}`
+    fn parse(raw: &str) -> Option<Self> {
+        let (negated, (ranges, quantifier)) = match raw.strip_prefix('\\') {
+            Some(rest) => {
+                let mut chars = rest.chars();
+                let ranges = Self::shorthand(chars.next()?)?;
+                (false, (ranges, chars.as_str()))
+            }
+            None => {
+                let class = raw.strip_prefix('[')?;
+                match class.strip_prefix('^') {
+                    Some(rest) => (true, Self::parse_brackets(rest)?),
+                    None => (false, Self::parse_brackets(class)?),
+                }
+            }
+        };
+
+        let (min, max) = match quantifier {
+            "" => (1, Some(1)),
+            "+" => (1, None),
+            "*" => (0, None),
+            "?" => (0, Some(1)),
+            _ => {
+                let counts = quantifier.strip_prefix('{')?.strip_suffix('}')?;
+                match counts.split_once(',') {
+                    None => {
+                        let n = counts.parse().ok()?;
+                        (n, Some(n))
+                    }
+                    Some((min, "")) => (min.parse().ok()?, None),
+                    Some((min, max)) => {
+                        let (min, max) = (min.parse().ok()?, max.parse().ok()?);
+                        if min > max {
+                            return None;
+                        }
+                        (min, Some(max))
+                    }
+                }
+            }
+        };
+
+        Some(CharClass {
+            ranges,
+            negated,
+            min,
+            max,
+        })
+    }
+
+    /// Parses the inside of `[...]` after any `^`, returning what follows the closing bracket
+    fn parse_brackets(body: &str) -> Option<(Vec<(char, char)>, &str)> {
+        // Escapes become sets, so an escaped '-' or ']' is never an operator
+        let mut items: Vec<ClassItem> = Vec::new();
+        let mut chars = body.char_indices();
+        let rest = loop {
+            match chars.next()? {
+                (i, ']') => break &body[i + 1..],
+                (_, '\\') => {
+                    let escaped = chars.next()?.1;
+                    let ranges = Self::shorthand(escaped).unwrap_or(vec![(escaped, escaped)]);
+                    items.push(ClassItem::Set(ranges));
+                }
+                (_, c) => items.push(ClassItem::Char(c)),
+            }
+        };
+        if items.is_empty() {
+            return None;
+        }
+
+        let mut ranges = Vec::new();
+        let mut i = 0;
+        while i < items.len() {
+            // A '-' is a literal at either end of the class, and a range operator in between
+            match (&items[i], items.get(i + 1), items.get(i + 2)) {
+                (ClassItem::Char(lo), Some(ClassItem::Char('-')), Some(ClassItem::Char(hi))) => {
+                    if lo > hi {
+                        return None;
+                    }
+                    ranges.push((*lo, *hi));
+                    i += 3;
+                }
+                (ClassItem::Char(c), _, _) => {
+                    ranges.push((*c, *c));
+                    i += 1;
+                }
+                (ClassItem::Set(set), _, _) => {
+                    ranges.extend_from_slice(set);
+                    i += 1;
+                }
+            }
+        }
+        Some((ranges, rest))
+    }
+
+    /// Expands `\d` (digits) and `\w` (letters, digits, and `_`)
+    fn shorthand(c: char) -> Option<Vec<(char, char)>> {
+        match c {
+            'd' => Some(vec![('0', '9')]),
+            'w' => Some(vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')]),
+            _ => None,
+        }
+    }
+
+    fn accepts(&self, value: &str) -> bool {
+        let count = value.chars().count();
+        count >= self.min
+            && self.max.map_or(true, |max| count <= max)
+            && value.chars().all(|c| {
+                let in_class = self.ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c));
+                in_class != self.negated
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constrained_params_match_only_valid_values() {
+        let by_id = Pattern::parse("/items/{id:u8}").unwrap();
+        assert_eq!(by_id.matches("/items/42").unwrap()["id"], "42");
+        assert!(by_id.matches("/items/256").is_none());
+        assert!(by_id.matches("/items/-1").is_none());
+
+        let by_slug = Pattern::parse("/items/{slug:[a-z0-9-]{2,8}}").unwrap();
+        assert!(by_slug.matches("/items/red-hat").is_some());
+        assert!(by_slug.matches("/items/x").is_none());
+        assert!(by_slug.matches("/items/Red").is_none());
+
+        let by_digits = Pattern::parse("/users/{id:\\d+}").unwrap();
+        assert!(by_digits.matches("/users/0042").is_some());
+        assert!(by_digits.matches("/users/42a").is_none());
+
+        let by_name = Pattern::parse("/files/{name:[\\w.\\-]+}").unwrap();
+        assert!(by_name.matches("/files/app_v2-min.js").is_some());
+        assert!(by_name.matches("/files/a%20b").is_none());
+
+        assert!(Pattern::parse("/items/{id:\\q+}").is_err());
+        assert!(Pattern::parse("/items/{id:[a-z}").is_err());
+        assert!(Pattern::parse("/items/{id:u7}").is_err());
+        assert!(Pattern::parse("/items/{id:[z-a]+}").is_err());
+        assert!(Pattern::parse("/items/{id:[a-z]{3,1}}").is_err());
+    }
+
+    #[test]
+    fn test_overlap_considers_constraints() {
+        let p = |s: &str| Pattern::parse(s).unwrap();
+
+        assert_eq!(p("/a/{x}").overlap(&p("/a/{y}")), Overlap::Identical);
+        assert_eq!(p("/a/{x}").overlap(&p("/a/list")), Overlap::Ambiguous);
+        assert_eq!(p("/a/{x:u64}").overlap(&p("/a/list")), Overlap::Disjoint);
+        assert_eq!(p("/a/{x:u64}").overlap(&p("/a/7")), Overlap::Ambiguous);
+        assert_eq!(
+            p("/a/{x:u64}").overlap(&p("/a/{y:u64}")),
+            Overlap::Identical
+        );
+        assert_eq!(
+            p("/a/{x:u64}").overlap(&p("/a/{y:[a-z]+}")),
+            Overlap::Disjoint
+        );
+        assert_eq!(p("/a/{x:u64}").overlap(&p("/a/{y}")), Overlap::Ambiguous);
+        assert_eq!(p("/a/{x..}").overlap(&p("/a/{y..}")), Overlap::Identical);
+        assert_eq!(p("/a/{x..}").overlap(&p("/a/b/{y}")), Overlap::Ambiguous);
+        assert_eq!(p("/a/{x..}").overlap(&p("/a")), Overlap::Disjoint);
+        assert_eq!(p("/a/{x..}").overlap(&p("/b/{y..}")), Overlap::Disjoint);
+    }
+
+    #[test]
+    fn test_wildcard_captures_the_rest_of_the_path() {
+        let files = Pattern::parse("/files/{path..}").unwrap();
+        assert_eq!(
+            files.matches("/files/css/app.css").unwrap()["path"],
+            "css/app.css"
+        );
+        assert_eq!(files.matches("/files/a.txt").unwrap()["path"], "a.txt");
+        assert!(files.matches("/files/").is_none());
+        assert!(files.matches("/files").is_none());
+
+        assert!(Pattern::parse("/{path..}/edit").is_err());
+    }
+}
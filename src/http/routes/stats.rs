@@ -0,0 +1,110 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Latency samples kept per route; percentiles describe the most recent requests
+pub const LATENCY_SAMPLES: usize = 1024;
+
+/// Hit count and recent latencies of one route
+#[derive(Debug, Default)]
+pub struct RouteStats {
+    hits: AtomicU64,
+    recent: Mutex<Samples>,
+}
+
+/// Ring of the latest latencies, overwriting the oldest once full
+#[derive(Debug, Default)]
+struct Samples {
+    durations: Vec<Duration>,
+    next: usize, // Slot the next sample overwrites once the ring is full
+}
+
+/// Point-in-time view of a route's statistics; percentiles are `None` before the first request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatsSnapshot {
+    pub hits: u64,
+    pub p50: Option<Duration>,
+    pub p95: Option<Duration>,
+    pub p99: Option<Duration>,
+}
+
+impl RouteStats {
+    /// Counts a request that took `elapsed` from routing to the end of its response
+    pub fn record(&self, elapsed: Duration) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+
+        // A poisoned lock only means another thread panicked mid-update; the samples are still usable
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.durations.len() < LATENCY_SAMPLES {
+            recent.durations.push(elapsed);
+        } else {
+            let slot = recent.next;
+            recent.durations[slot] = elapsed;
+            recent.next = (slot + 1) % LATENCY_SAMPLES;
+        }
+    }
+
+    /// Returns the hit count and the latency percentiles over the recent samples
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let mut sorted = self
+            .recent
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .durations
+            .clone();
+        sorted.sort_unstable();
+
+        StatsSnapshot {
+            hits: self.hits.load(Ordering::Relaxed),
+            p50: percentile(&sorted, 50),
+            p95: percentile(&sorted, 95),
+            p99: percentile(&sorted, 99),
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], p: usize) -> Option<Duration> {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted.get(rank - 1).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_reports_percentiles_of_recent_samples() {
+        let stats = RouteStats::default();
+        assert_eq!(
+            stats.snapshot(),
+            StatsSnapshot {
+                hits: 0,
+                p50: None,
+                p95: None,
+                p99: None
+            }
+        );
+
+        for ms in (1..=100).rev() {
+            stats.record(Duration::from_millis(ms));
+        }
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.hits, 100);
+        assert_eq!(snapshot.p50, Some(Duration::from_millis(50)));
+        assert_eq!(snapshot.p95, Some(Duration::from_millis(95)));
+        assert_eq!(snapshot.p99, Some(Duration::from_millis(99)));
+
+        // Once the ring is full, new samples replace the oldest ones
+        for _ in 0..LATENCY_SAMPLES {
+            stats.record(Duration::from_millis(7));
+        }
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.hits, 100 + LATENCY_SAMPLES as u64);
+        assert_eq!(snapshot.p99, Some(Duration::from_millis(7)));
+    }
+}
@@ -0,0 +1,265 @@
+use std::{borrow::Cow, collections::HashMap, io, time::Instant};
+
+use crate::http::{
+    logging::server_log,
+    request::{HttpRequest, HttpVersion, RequestId},
+    response::HttpStatusCode,
+    stream::ClientStream,
+    writer,
+};
+
+use super::{is_timeout, read_timeout, send_error, RequestHead, ServerContext};
+
+/// Maximum size for a request body (16MB), checked against Content-Length before reading
+pub const MAX_REQUEST_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Reads the part of a request's body that did not arrive with its head
+///
+/// Nothing is read until the request passes the body size limit and, when the client sent
+/// `Expect: 100-continue`, its route's guards; only then is `100 Continue` sent, so a refused
+/// client never uploads its body. The body must then arrive within `timeouts.body_secs`, and the
+/// whole request within `timeouts.request_secs` of its first byte, however the bytes trickle in.
+/// Returns `None` once the client has been answered and the connection must close: the request
+/// was refused, or its body never fully arrived
+pub fn complete<'a>(
+    request_head: &'a RequestHead,
+    stream: &mut dyn ClientStream,
+    ctx: &ServerContext,
+    req_id: RequestId,
+) -> Option<Cow<'a, [u8]>> {
+    let head = request_head.bytes();
+    // Malformed heads are left for `dispatch` to reject
+    let Ok(request) = HttpRequest::parse(head) else {
+        return Some(Cow::Borrowed(head));
+    };
+    let declared = content_length(&request);
+    let received = head
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map_or(0, |boundary| head.len() - boundary - 4);
+    if received >= declared {
+        return Some(Cow::Borrowed(head));
+    }
+
+    if declared > MAX_REQUEST_BODY_SIZE {
+        server_log!(
+            Warn,
+            req = req_id,
+            "declared body of {} bytes exceeds {}, sending 413",
+            declared,
+            MAX_REQUEST_BODY_SIZE
+        );
+        send_error(
+            stream,
+            HttpStatusCode::PayloadTooLarge,
+            "Request body too large",
+            req_id,
+        );
+        return None;
+    }
+
+    if expects_continue(&request) {
+        if let Some(mut refusal) = ctx.router.preflight(&request, ctx, req_id) {
+            server_log!(
+                Info,
+                req = req_id,
+                "refused before the body was sent: {}",
+                refusal.status_line.status
+            );
+            // The body the client never sent cannot be told apart from a next request, so close
+            refusal
+                .headers
+                .retain(|k, _| !k.eq_ignore_ascii_case("Connection"));
+            refusal
+                .headers
+                .insert("Connection".to_string(), "close".to_string());
+            writer::send_response(stream, refusal, req_id).unwrap_or_else(|e| {
+                server_log!(Error, req = req_id, "Failed to send refusal: {:?}", e);
+            });
+            return None;
+        }
+        let sent = writer::HttpWriter::new(stream).write_interim(
+            HttpVersion::Http1_1,
+            HttpStatusCode::Continue,
+            &HashMap::new(),
+        );
+        if let Err(e) = sent {
+            server_log!(Info, req = req_id, "failed to send 100 Continue: {:?}", e);
+            return None;
+        }
+    }
+
+    let timeouts = &ctx.config.timeouts;
+    let mut deadline = Instant::now() + timeouts.body();
+    if let (Some(limit), Some(started)) = (timeouts.request(), request_head.started()) {
+        deadline = deadline.min(started + limit);
+    }
+
+    let header_len = head.len() - received;
+    let total = header_len + declared;
+    let mut bytes = head.to_vec();
+    let mut buffer = [0; 8192];
+    while bytes.len() < total {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            server_log!(
+                Warn,
+                req = req_id,
+                "body incomplete at its deadline ({} of {} bytes), sending 408",
+                bytes.len() - header_len,
+                declared
+            );
+            send_error(
+                stream,
+                HttpStatusCode::RequestTimeout,
+                "Request timed out",
+                req_id,
+            );
+            return None;
+        }
+        read_timeout(stream, timeouts.read().min(remaining));
+
+        let want = (total - bytes.len()).min(buffer.len());
+        match stream.read(&mut buffer[..want]) {
+            Ok(0) => {
+                server_log!(
+                    Info,
+                    req = req_id,
+                    "peer closed after {} of {} body bytes",
+                    bytes.len() - header_len,
+                    declared
+                );
+                return None;
+            }
+            Ok(n) => bytes.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            // A stalled read ends up here too once the deadline it was capped by passes
+            Err(e) if is_timeout(&e) => {}
+            Err(e) => {
+                server_log!(Info, req = req_id, "failed to read request body: {}", e);
+                return None;
+            }
+        }
+    }
+    Some(Cow::Owned(bytes))
+}
+
+/// Checks whether an HTTP/1.1 client is waiting for `100 Continue` before sending its body
+fn expects_continue(request: &HttpRequest) -> bool {
+    request.status_line.version == HttpVersion::Http1_1
+        && request.headers.iter().any(|(key, value)| {
+            key.eq_ignore_ascii_case("Expect") && value.eq_ignore_ascii_case("100-continue")
+        })
+}
+
+/// Returns the declared body length; a missing or invalid Content-Length means no body
+pub fn content_length(request: &HttpRequest) -> usize {
+    request
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{
+        server::{dispatch, ConnectionAction, ConnectionStats},
+        test_support::TempRoot,
+    };
+    use std::{
+        fs,
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    /// Sends `head` to a server running `dispatch`, then `body` only if it answers 100 Continue
+    fn exchange(ctx: &ServerContext, head: &str, body: &str) -> (ConnectionAction, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let server_ctx = ctx.clone();
+        let mut request_head = RequestHead::new(ctx.config().timeouts.header());
+        request_head.push(head.as_bytes());
+        let served = thread::spawn(move || {
+            let req_id = server_ctx.next_request_id();
+            let action = dispatch(
+                &request_head,
+                &mut server,
+                &server_ctx,
+                req_id,
+                &mut ConnectionStats::default(),
+            );
+            drop(server);
+            action
+        });
+
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut status = String::new();
+        reader.read_line(&mut status).unwrap();
+        let mut rest = String::new();
+        if status.starts_with("HTTP/1.1 100") {
+            reader.read_line(&mut String::new()).unwrap();
+            client.write_all(body.as_bytes()).unwrap();
+            client.shutdown(std::net::Shutdown::Write).unwrap();
+            status.clear();
+            reader.read_line(&mut status).unwrap();
+            rest = "continued\n".to_string();
+        }
+        reader.read_to_string(&mut rest).unwrap();
+        (served.join().unwrap(), status + &rest)
+    }
+
+    #[test]
+    fn test_guards_run_before_100_continue() {
+        let root = TempRoot::new("expect");
+        let mut config = root.config();
+        config.uploads.secret = Some("k".to_string());
+        let ctx = ServerContext::from_config(config).unwrap();
+        let head = |length: usize| {
+            format!(
+                "POST /files/note.txt HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: {}\r\n\r\n",
+                length
+            )
+        };
+
+        let (action, response) = exchange(&ctx, &head(5), "hello");
+        assert_eq!(action, ConnectionAction::Close);
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        assert!(!response.contains("continued"));
+
+        let (_, response) = exchange(&ctx, &head(MAX_REQUEST_BODY_SIZE + 1), "");
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+
+        let mut config = ctx.config().clone();
+        config.uploads.secret = None;
+        let ctx = ServerContext::from_config(config).unwrap();
+        let (_, response) = exchange(&ctx, &head(5), "hello");
+        assert!(response.starts_with("HTTP/1.1 201"), "{}", response);
+        assert!(response.contains("continued"));
+        assert_eq!(fs::read_to_string(root.join("note.txt")).unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_slow_body_hits_the_body_deadline() {
+        let root = TempRoot::new("slow-body");
+        let mut config = root.config();
+        config.timeouts.body_secs = 1;
+        let ctx = ServerContext::from_config(config).unwrap();
+
+        // Half the body arrives with the head and the rest never does
+        let started = Instant::now();
+        let (action, response) = exchange(
+            &ctx,
+            "POST /files/slow.txt HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello",
+            "",
+        );
+        assert_eq!(action, ConnectionAction::Close);
+        assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::http::{
+    logging::server_log, request::RequestId, response::HttpStatusCode, stream::ClientStream,
+};
+
+use super::{
+    dispatch, is_disconnect, is_timeout, read_timeout, reaper::Registration, reject_timeout,
+    send_error, write_timeout, ConnectionAction, ConnectionStats, HeadProgress, RequestHead,
+    ServerContext,
+};
+
+/// Bytes read from the socket per call while a request head arrives
+const READ_BUFFER_SIZE: usize = 1024;
+
+/// A client connection on the threaded backend, from accept until it closes
+///
+/// Holds everything that outlives a single request: the socket, the read buffer, the keep-alive
+/// counters, and the decision to close
+pub struct Connection<S: ClientStream> {
+    stream: S,
+    ctx: ServerContext,
+    buffer: [u8; READ_BUFFER_SIZE],
+    stats: ConnectionStats,
+    registration: Registration, // Lets the reaper close the socket while it idles
+    closing: bool,              // Set once a response announced the connection's end
+}
+
+impl<S: ClientStream> Connection<S> {
+    /// Takes over an accepted socket, registering it with the idle reaper
+    pub fn new(mut stream: S, ctx: ServerContext) -> Self {
+        write_timeout(&mut stream, &ctx);
+        let registration = ctx.registry().register(&stream);
+        Connection {
+            stream,
+            ctx,
+            buffer: [0; READ_BUFFER_SIZE],
+            stats: ConnectionStats::default(),
+            registration,
+            closing: false,
+        }
+    }
+
+    /// Waits for the next request head, within the keep-alive idle timeout
+    ///
+    /// Returns `Ok(None)` when the connection ended quietly: the peer closed it or it idled out.
+    /// A head that is too large or too slow has been answered with the returned error status
+    pub fn read_request(
+        &mut self,
+        req_id: RequestId,
+    ) -> Result<Option<RequestHead>, HttpStatusCode> {
+        let config = self.ctx.config();
+        let mut head = RequestHead::new(config.timeouts.header());
+        read_timeout(&mut self.stream, config.keep_alive.idle());
+        self.registration.idle();
+
+        loop {
+            match self.stream.read(&mut self.buffer) {
+                Ok(0) => break, // Connection closed
+                Ok(n) => {
+                    self.registration.busy();
+                    match head.push(&self.buffer[..n]) {
+                        HeadProgress::Complete => break,
+                        HeadProgress::Incomplete(remaining) => {
+                            read_timeout(&mut self.stream, config.timeouts.read().min(remaining));
+                        }
+                        HeadProgress::TooLarge => {
+                            send_error(
+                                &mut self.stream,
+                                HttpStatusCode::BadRequest,
+                                "Request header too large",
+                                req_id,
+                            );
+                            return Err(HttpStatusCode::BadRequest);
+                        }
+                        HeadProgress::Expired => {
+                            reject_timeout(&mut self.stream, &self.ctx, &head, req_id);
+                            return Err(HttpStatusCode::RequestTimeout);
+                        }
+                    }
+                }
+                Err(e) if head.is_empty() && is_timeout(&e) => {
+                    server_log!(
+                        Info,
+                        req = req_id,
+                        "keep-alive idle timeout reached, closing connection"
+                    );
+                    return Ok(None);
+                }
+                Err(e) if is_timeout(&e) => {
+                    // The client started a request but stalled before finishing the headers
+                    reject_timeout(&mut self.stream, &self.ctx, &head, req_id);
+                    return Err(HttpStatusCode::RequestTimeout);
+                }
+                Err(e) if is_disconnect(&e) => {
+                    server_log!(Info, req = req_id, "peer disconnected: {}", e);
+                    return Ok(None);
+                }
+                Err(e) => {
+                    send_error(
+                        &mut self.stream,
+                        HttpStatusCode::InternalServerError,
+                        &format!("Failed to read request: {}", e),
+                        req_id,
+                    );
+                    return Ok(None);
+                }
+            }
+        }
+
+        // If the peer closed the connection without sending bytes, stop gracefully
+        if head.is_empty() {
+            server_log!(Info, req = req_id, "peer closed connection (no bytes)");
+            return Ok(None);
+        }
+        Ok(Some(head))
+    }
+
+    /// Reads the request's body, routes it, and records whether the connection must close
+    pub fn serve(&mut self, head: &RequestHead, req_id: RequestId) {
+        let action = dispatch(head, &mut self.stream, &self.ctx, req_id, &mut self.stats);
+        self.closing = action == ConnectionAction::Close;
+    }
+
+    /// Checks whether the last request ended the connection
+    pub fn should_close(&self) -> bool {
+        self.closing
+    }
+
+    /// Returns the keep-alive counters so far
+    #[cfg(test)]
+    pub fn stats(&self) -> &ConnectionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::test_support::{duplex, TempRoot};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_serves_requests_until_one_closes() {
+        let (mut client, server) = duplex();
+        let root = TempRoot::new("connection");
+        let ctx = root.context();
+
+        let mut connection = Connection::new(server, ctx.clone());
+        for request in [
+            "GET / HTTP/1.1\r\n\r\n",
+            "GET /missing HTTP/1.1\r\nConnection: close\r\n\r\n",
+        ] {
+            assert!(!connection.should_close());
+            client.write_all(request.as_bytes()).unwrap();
+            let req_id = ctx.next_request_id();
+            let head = connection.read_request(req_id).unwrap().unwrap();
+            connection.serve(&head, req_id);
+        }
+        assert!(connection.should_close());
+        assert_eq!(connection.stats().served, 2);
+        assert_eq!(connection.stats().errors, 1);
+        drop(connection);
+        let mut sent = String::new();
+        client.read_to_string(&mut sent).unwrap();
+        assert!(sent.starts_with("HTTP/1.1 200"), "{}", sent);
+        assert!(sent.contains("HTTP/1.1 404"), "{}", sent);
+    }
+}
@@ -0,0 +1,554 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    net::{TcpListener as StdTcpListener, TcpStream as StdTcpStream},
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
+
+use mio::{
+    net::{TcpListener, TcpStream},
+    Events, Interest, Poll, Registry, Token, Waker,
+};
+use threadpool::ThreadPool;
+
+use super::{
+    dispatch, is_disconnect, reject_overloaded, reject_timeout, send_error, write_timeout,
+    ConnectionAction, ConnectionSlot, ConnectionStats, HeadProgress, RequestHead, ServerContext,
+};
+use crate::http::{
+    logging::server_log,
+    metrics::{self, Counter},
+    request::RequestId,
+    response::HttpStatusCode,
+};
+
+/// Token the worker threads use to wake the event loop when a request finishes
+const WAKER: Token = Token(usize::MAX);
+
+/// Upper bound on readiness events handled per poll
+const MAX_EVENTS: usize = 1024;
+
+/// Longest the loop sleeps before re-checking deadlines and the shutdown flag
+const MAX_POLL_WAIT: Duration = Duration::from_millis(100);
+
+/// A connection owned by the event loop while it waits for (the rest of) a request head
+struct Connection {
+    stream: TcpStream,
+    slot: ConnectionSlot,
+    req_id: RequestId,
+    head: RequestHead,
+    stats: ConnectionStats,
+    deadline: Instant, // Keep-alive idle deadline until the first byte, then the read deadline
+}
+
+/// A connection handed back by a worker after its request was served
+struct Finished {
+    stream: StdTcpStream,
+    slot: ConnectionSlot,
+    stats: ConnectionStats,
+    pipelined: Vec<u8>, // Bytes of the next request that arrived with this one
+}
+
+impl Connection {
+    /// Starts waiting for the next request on a connection
+    fn new(
+        stream: TcpStream,
+        slot: ConnectionSlot,
+        stats: ConnectionStats,
+        ctx: &ServerContext,
+    ) -> Self {
+        Connection {
+            stream,
+            slot,
+            req_id: ctx.next_request_id(),
+            head: RequestHead::new(ctx.config().timeouts.header()),
+            stats,
+            deadline: Instant::now() + ctx.config().keep_alive.idle(),
+        }
+    }
+}
+
+/// What the loop should do with a connection after draining its readable bytes
+enum ReadOutcome {
+    Pending,                              // Wait for more readiness
+    Dispatch,                             // The request head is complete (or the peer half-closed)
+    Reject(HttpStatusCode, &'static str), // Answer with an error and close
+    Close,                                // Drop the connection silently
+}
+
+/// Runs a single-threaded readiness loop over every listener until shutdown is requested
+///
+/// Accepting, keep-alive waits, and request head reads are driven by `mio`; each complete request
+/// is handed to a worker thread so routing and response writing reuse the blocking handlers
+pub fn serve(listeners: Vec<StdTcpListener>, ctx: ServerContext) -> io::Result<()> {
+    let mut poll = Poll::new()?;
+    let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+    let pool = ThreadPool::new(ctx.config().workers);
+    let (finished_tx, finished_rx) = mpsc::channel::<Finished>();
+
+    let mut listeners: Vec<TcpListener> = listeners
+        .into_iter()
+        .map(|listener| {
+            listener.set_nonblocking(true)?;
+            Ok(TcpListener::from_std(listener))
+        })
+        .collect::<io::Result<_>>()?;
+    for (i, listener) in listeners.iter_mut().enumerate() {
+        poll.registry()
+            .register(listener, Token(i), Interest::READABLE)?;
+    }
+
+    let mut connections: HashMap<Token, Connection> = HashMap::new();
+    let mut next_token = listeners.len();
+    let mut events = Events::with_capacity(MAX_EVENTS);
+
+    while !ctx.is_shutting_down() {
+        let wait = connections
+            .values()
+            .map(|conn| conn.deadline.saturating_duration_since(Instant::now()))
+            .min()
+            .map_or(MAX_POLL_WAIT, |nearest| nearest.min(MAX_POLL_WAIT));
+
+        if let Err(e) = poll.poll(&mut events, Some(wait)) {
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+
+        for event in events.iter() {
+            match event.token() {
+                WAKER => {}
+                Token(i) if i < listeners.len() => {
+                    for (stream, token) in accept_ready(&listeners[i], &ctx, &pool, &mut next_token)
+                    {
+                        let mut conn = stream;
+                        poll.registry()
+                            .register(&mut conn.stream, token, Interest::READABLE)?;
+                        connections.insert(token, conn);
+                    }
+                }
+                token => {
+                    let Some(conn) = connections.get_mut(&token) else {
+                        continue;
+                    };
+                    match read_ready(conn, &ctx) {
+                        ReadOutcome::Pending => {}
+                        outcome => {
+                            let conn = connections
+                                .remove(&token)
+                                .expect("connection was just looked up");
+                            hand_off(
+                                conn,
+                                outcome,
+                                poll.registry(),
+                                &ctx,
+                                &pool,
+                                &finished_tx,
+                                &waker,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        // Requests served on the pool come back here to wait for their next request
+        while let Ok(finished) = finished_rx.try_recv() {
+            let token = Token(next_token);
+            next_token += 1;
+            match resume(finished, poll.registry(), token, &ctx) {
+                Ok((conn, ReadOutcome::Pending)) => {
+                    connections.insert(token, conn);
+                }
+                // A pipelined request arrived whole with the previous one, so no readiness
+                // event will announce it
+                Ok((conn, outcome)) => hand_off(
+                    conn,
+                    outcome,
+                    poll.registry(),
+                    &ctx,
+                    &pool,
+                    &finished_tx,
+                    &waker,
+                ),
+                Err(e) => server_log!(Error, "Failed to resume connection: {}", e),
+            }
+        }
+
+        expire_deadlines(
+            &mut connections,
+            poll.registry(),
+            &ctx,
+            &pool,
+            &finished_tx,
+            &waker,
+        );
+    }
+
+    // Idle keep-alive connections are closed now; requests still on the pool finish on their own
+    server_log!(
+        Info,
+        "Closing {} idle connections for shutdown",
+        connections.len()
+    );
+    Ok(())
+}
+
+/// Accepts every pending connection on a ready listener
+fn accept_ready(
+    listener: &TcpListener,
+    ctx: &ServerContext,
+    pool: &ThreadPool,
+    next_token: &mut usize,
+) -> Vec<(Connection, Token)> {
+    let log_connections = ctx.config().logging.connections;
+    let mut accepted = Vec::new();
+
+    loop {
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                if log_connections {
+                    server_log!(Info, "Accepted Connection: {}", addr);
+                }
+                let Some(slot) = ctx.try_acquire_connection() else {
+                    metrics::incr(Counter::ConnectionsRejected);
+                    let ctx = ctx.clone();
+                    pool.execute(move || match into_blocking(stream) {
+                        Ok(stream) => reject_overloaded(stream, &ctx),
+                        Err(e) => server_log!(Error, "Failed to reject connection: {}", e),
+                    });
+                    continue;
+                };
+                metrics::incr(Counter::ConnectionsAccepted);
+                if let Err(e) = stream.set_nodelay(ctx.config().socket.nodelay) {
+                    server_log!(Warn, "Failed to set TCP_NODELAY: {}", e);
+                }
+
+                let token = Token(*next_token);
+                *next_token += 1;
+                accepted.push((
+                    Connection::new(stream, slot, ConnectionStats::default(), ctx),
+                    token,
+                ));
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                server_log!(Info, "error: {}", e);
+                break;
+            }
+        }
+    }
+
+    accepted
+}
+
+/// Reads everything currently available on a connection into its request head
+fn read_ready(conn: &mut Connection, ctx: &ServerContext) -> ReadOutcome {
+    let mut buffer = [0; 1024];
+
+    loop {
+        match conn.stream.read(&mut buffer) {
+            Ok(0) if conn.head.is_empty() => {
+                server_log!(Info, req = conn.req_id, "peer closed connection (no bytes)");
+                return ReadOutcome::Close;
+            }
+            Ok(0) => return ReadOutcome::Dispatch, // Half-closed mid-request; parse what arrived
+            Ok(n) => match push(conn, &buffer[..n], ctx) {
+                ReadOutcome::Pending => {}
+                outcome => return outcome,
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return ReadOutcome::Pending,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) if is_disconnect(&e) => {
+                server_log!(Info, req = conn.req_id, "peer disconnected: {}", e);
+                return ReadOutcome::Close;
+            }
+            Err(e) => {
+                server_log!(Error, req = conn.req_id, "Failed to read request: {}", e);
+                return ReadOutcome::Reject(
+                    HttpStatusCode::InternalServerError,
+                    "Failed to read request",
+                );
+            }
+        }
+    }
+}
+
+/// Adds bytes to a connection's request head and decides what happens next
+fn push(conn: &mut Connection, bytes: &[u8], ctx: &ServerContext) -> ReadOutcome {
+    match conn.head.push(bytes) {
+        HeadProgress::Complete => ReadOutcome::Dispatch,
+        HeadProgress::Incomplete(remaining) => {
+            conn.deadline = Instant::now() + ctx.config().timeouts.read().min(remaining);
+            ReadOutcome::Pending
+        }
+        HeadProgress::TooLarge => {
+            ReadOutcome::Reject(HttpStatusCode::BadRequest, "Request header too large")
+        }
+        HeadProgress::Expired => ReadOutcome::Reject(HttpStatusCode::RequestTimeout, ""),
+    }
+}
+
+/// Moves a connection off the event loop to a worker that serves or rejects its request
+fn hand_off(
+    mut conn: Connection,
+    outcome: ReadOutcome,
+    registry: &Registry,
+    ctx: &ServerContext,
+    pool: &ThreadPool,
+    finished_tx: &mpsc::Sender<Finished>,
+    waker: &Arc<Waker>,
+) {
+    let _ = registry.deregister(&mut conn.stream);
+    let Connection {
+        stream,
+        slot,
+        req_id,
+        mut head,
+        mut stats,
+        ..
+    } = conn;
+    let pipelined = match outcome {
+        ReadOutcome::Dispatch => head.split_pipelined(),
+        _ => Vec::new(),
+    };
+
+    let mut stream = match into_blocking(stream) {
+        Ok(stream) => stream,
+        Err(e) => {
+            server_log!(Error, req = req_id, "connection handoff failed: {}", e);
+            return;
+        }
+    };
+
+    let ctx = ctx.clone();
+    let finished_tx = finished_tx.clone();
+    let waker = Arc::clone(waker);
+    let log_connections = ctx.config().logging.connections;
+
+    pool.execute(move || {
+        let status = match outcome {
+            ReadOutcome::Dispatch => {
+                write_timeout(&mut stream, &ctx);
+                match dispatch(&head, &mut stream, &ctx, req_id, &mut stats) {
+                    ConnectionAction::KeepAlive => {
+                        let _ = finished_tx.send(Finished {
+                            stream,
+                            slot,
+                            stats,
+                            pipelined,
+                        });
+                        let _ = waker.wake();
+                        return;
+                    }
+                    ConnectionAction::Close => None,
+                }
+            }
+            ReadOutcome::Reject(HttpStatusCode::RequestTimeout, _) => {
+                reject_timeout(&mut stream, &ctx, &head, req_id);
+                Some(HttpStatusCode::RequestTimeout)
+            }
+            ReadOutcome::Reject(status, message) => {
+                send_error(&mut stream, status.clone(), message, req_id);
+                Some(status)
+            }
+            ReadOutcome::Pending | ReadOutcome::Close => None,
+        };
+
+        match status {
+            Some(status_code) => {
+                server_log!(Info, "Connection closed with status code {}", status_code);
+            }
+            None if log_connections => server_log!(Info, "Connection closed"),
+            None => {}
+        }
+        drop(slot);
+    });
+}
+
+/// Registers a connection returned by a worker so it can wait for its next request, starting
+/// from any pipelined bytes that arrived with the last one
+fn resume(
+    finished: Finished,
+    registry: &Registry,
+    token: Token,
+    ctx: &ServerContext,
+) -> io::Result<(Connection, ReadOutcome)> {
+    finished.stream.set_nonblocking(true)?;
+    let mut conn = Connection::new(
+        TcpStream::from_std(finished.stream),
+        finished.slot,
+        finished.stats,
+        ctx,
+    );
+    registry.register(&mut conn.stream, token, Interest::READABLE)?;
+    let outcome = if finished.pipelined.is_empty() {
+        ReadOutcome::Pending
+    } else {
+        push(&mut conn, &finished.pipelined, ctx)
+    };
+    Ok((conn, outcome))
+}
+
+/// Closes idle connections past their keep-alive deadline and answers stalled requests with 408
+fn expire_deadlines(
+    connections: &mut HashMap<Token, Connection>,
+    registry: &Registry,
+    ctx: &ServerContext,
+    pool: &ThreadPool,
+    finished_tx: &mpsc::Sender<Finished>,
+    waker: &Arc<Waker>,
+) {
+    let now = Instant::now();
+    let expired: Vec<Token> = connections
+        .iter()
+        .filter(|(_, conn)| conn.deadline <= now)
+        .map(|(token, _)| *token)
+        .collect();
+
+    for token in expired {
+        let Some(mut conn) = connections.remove(&token) else {
+            continue;
+        };
+        if conn.head.is_empty() {
+            server_log!(
+                Info,
+                req = conn.req_id,
+                "keep-alive idle timeout reached, closing connection"
+            );
+            let _ = registry.deregister(&mut conn.stream);
+            if ctx.config().logging.connections {
+                server_log!(Info, "Connection closed");
+            }
+        } else {
+            // The client started a request but stalled before finishing the headers
+            hand_off(
+                conn,
+                ReadOutcome::Reject(HttpStatusCode::RequestTimeout, ""),
+                registry,
+                ctx,
+                pool,
+                finished_tx,
+                waker,
+            );
+        }
+    }
+}
+
+/// Converts a nonblocking mio stream into a blocking std stream for the handlers
+fn into_blocking(stream: TcpStream) -> io::Result<StdTcpStream> {
+    let stream = StdTcpStream::from(stream);
+    stream.set_nonblocking(false)?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{
+        response::HttpStatusCode,
+        server::run::Server,
+        test_support::{spawn, RunningServer, TempRoot, TestResponse},
+    };
+    use std::{
+        io::{BufReader, Write},
+        net::TcpStream,
+        thread,
+        time::Duration,
+    };
+
+    /// Spawns an event-loop server over a fresh root with `configure` applied to its config
+    fn serve(
+        name: &str,
+        configure: impl FnOnce(&mut crate::http::config::ServerConfig),
+    ) -> (TempRoot, RunningServer) {
+        let root = TempRoot::new(name);
+        let mut config = root.config();
+        configure(&mut config);
+        let server = spawn(Server::builder().config(config).workers(2)).unwrap();
+        (root, server)
+    }
+
+    /// Opens a connection along with a reader over its responses
+    fn open(server: &RunningServer) -> (TcpStream, BufReader<TcpStream>) {
+        let stream = server.connect();
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        (stream, reader)
+    }
+
+    #[test]
+    fn test_keep_alive_serves_requests_in_turn_on_one_connection() {
+        let (_root, server) = serve("event-loop-keep-alive", |_| {});
+        let (mut stream, mut reader) = open(&server);
+
+        for word in ["one", "two", "three"] {
+            write!(
+                stream,
+                "GET /echo/{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                word
+            )
+            .unwrap();
+            let response = TestResponse::read_from(&mut reader).unwrap();
+            assert_eq!(response.text(), word);
+            assert_eq!(response.header("Connection"), Some("keep-alive"));
+        }
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_head_split_across_reads_is_reassembled() {
+        let (_root, server) = serve("event-loop-split", |_| {});
+        let (mut stream, mut reader) = open(&server);
+
+        for part in [
+            "GET /echo/spl",
+            "it HTTP/1.1\r\nHo",
+            "st: localhost\r\n",
+            "\r\n",
+        ] {
+            stream.write_all(part.as_bytes()).unwrap();
+            stream.flush().unwrap();
+            thread::sleep(Duration::from_millis(50));
+        }
+        let response = TestResponse::read_from(&mut reader).unwrap();
+        assert_eq!(response.status, HttpStatusCode::Ok);
+        assert_eq!(response.text(), "split");
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_pipelined_requests_are_answered_in_order() {
+        let (_root, server) = serve("event-loop-pipelining", |_| {});
+        let (mut stream, mut reader) = open(&server);
+
+        stream
+            .write_all(
+                b"GET /echo/first HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbody\
+                  GET /echo/second HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                  GET /echo/third HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let texts: Vec<String> = (0..3)
+            .map(|_| TestResponse::read_from(&mut reader).unwrap().text())
+            .collect();
+        assert_eq!(texts, ["first", "second", "third"]);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_stalled_head_gets_408_at_the_header_timeout() {
+        let (_root, server) = serve("event-loop-header-timeout", |config| {
+            config.timeouts.header_secs = 1;
+        });
+        let (mut stream, mut reader) = open(&server);
+
+        stream
+            .write_all(b"GET /echo/slow HTTP/1.1\r\nHost: loc")
+            .unwrap();
+        let response = TestResponse::read_from(&mut reader).unwrap();
+        assert_eq!(response.status, HttpStatusCode::RequestTimeout);
+        server.shutdown().unwrap();
+    }
+}
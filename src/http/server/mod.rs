@@ -0,0 +1,1077 @@
+pub mod body;
+#[cfg(not(any(feature = "async", feature = "event-loop")))]
+pub mod connection;
+#[cfg(feature = "event-loop")]
+pub mod event_loop;
+#[cfg(not(any(feature = "async", feature = "event-loop")))]
+pub mod reaper;
+pub mod run;
+#[cfg(feature = "async")]
+pub mod tokio_backend;
+
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    path::{self, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::http::{
+    config::{ServerConfig, SocketConfig},
+    crash,
+    errors::{self, HttpErrorResponse},
+    files::cache::FileCache,
+    logging::{access, server_log, Fields},
+    metrics::{self, Counter},
+    mirror, quarantine,
+    request::{HttpRequest, HttpVersion, RequestId, RequestIdGenerator},
+    response::HttpStatusCode,
+    routes,
+    stream::ClientStream,
+    writer::{self, HttpWriter},
+};
+
+/// Maximum size for HTTP request headers (16KB)
+/// Prevents memory exhaustion from malicious clients sending unbounded data
+const MAX_REQUEST_HEADER_SIZE: usize = 16 * 1024;
+
+/// Write timeout for 503 responses sent from the accept thread when over the connection limit
+const OVERLOAD_WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Retry-After hint sent with connection-limit 503 responses
+const OVERLOAD_RETRY_AFTER_SECS: u64 = 1;
+
+/// File served in place of a directory that is requested for reading
+const DIRECTORY_INDEX: &str = "index.html";
+
+/// Error pages looked up at the top of a root unless `error_pages` overrides them, by status
+/// code or class
+const DEFAULT_ERROR_PAGES: [(&str, &str); 4] = [
+    ("403", "403.html"),
+    ("404", "404.html"),
+    ("500", "500.html"),
+    ("5xx", "50x.html"),
+];
+
+/// List of reserved Windows filenames
+const RESERVED_NAMES: &[&str] = &[
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+#[derive(Debug, Clone)]
+/// Server context holding configuration and state
+pub struct ServerContext {
+    site: SiteRoot,
+    vhosts: Arc<HashMap<String, SiteRoot>>, // Keyed by lowercase host name without port
+    router: Arc<routes::Router>,
+    file_cache: Arc<FileCache>,
+    config: Arc<ServerConfig>,
+    request_ids: Arc<RequestIdGenerator>,
+    connections: Arc<AtomicUsize>,
+    mirror: Option<mirror::Mirror>, // Parsed once from config.mirror
+    #[cfg(not(any(feature = "async", feature = "event-loop")))]
+    registry: Arc<reaper::ConnectionRegistry>, // Open connections, for the idle reaper
+    shutdown: Arc<AtomicBool>,
+}
+
+/// A directory files are served from, with its canonical form for containment checks
+#[derive(Debug, Clone)]
+struct SiteRoot {
+    root_path: PathBuf,
+    canon_path: PathBuf,
+}
+
+impl SiteRoot {
+    /// Opens a serving directory, which must already exist
+    fn open(root: &str) -> Result<Self, InitError> {
+        let root_path = PathBuf::from(root);
+        let canon_path = fs::canonicalize(&root_path).map_err(|_| InitError::RootUnavailable)?;
+
+        if !canon_path.is_dir() {
+            return Err(InitError::MissingOrNotDir);
+        }
+
+        Ok(SiteRoot {
+            root_path,
+            canon_path,
+        })
+    }
+}
+
+/// Holds one of the server's connection slots; the slot is released on drop
+pub struct ConnectionSlot {
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Enum representing access intent for path resolution
+#[derive(Debug, Clone, Copy)]
+pub enum AccessIntent {
+    Read,      // An existing file; a directory resolves to its index page
+    Directory, // An existing directory, to list its entries
+    Write,
+}
+
+/// Which platform's filename restrictions requested paths are held to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilenameRules {
+    #[default]
+    Platform, // Windows' rules only when the server runs on Windows
+    Windows, // Windows' rules everywhere, for roots also served from Windows
+}
+
+impl FilenameRules {
+    /// Checks whether reserved device names, `<>:"|?*`, and trailing dots or spaces are refused
+    pub fn windows(self) -> bool {
+        match self {
+            FilenameRules::Platform => cfg!(windows),
+            FilenameRules::Windows => true,
+        }
+    }
+}
+
+/// Result type for path resolution
+pub enum ResolveError {
+    Forbidden,
+    NotFound,
+    Invalid,
+    Io,
+}
+
+/// Result type for server context initialization
+#[derive(Debug)]
+pub enum InitError {
+    RootUnavailable,
+    MissingOrNotDir,
+    VhostUnavailable, // A virtual host's directory is missing or not a directory
+    InvalidRoutes,    // Two routes conflict; the error is logged when the router is built
+    InvalidMirror,    // The mirror url does not parse; the error is logged
+}
+
+/// Result of path resolution
+pub struct ResolvedPath {
+    path: PathBuf,
+    exists: bool,
+}
+
+impl ResolvedPath {
+    /// Gets the resolved absolute path
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Checks if the resolved path exists
+    pub fn exists(&self) -> bool {
+        self.exists
+    }
+}
+
+impl ServerContext {
+    /// Creates a new ServerContext serving the configured root directory and virtual hosts
+    pub fn from_config(config: ServerConfig) -> Result<Self, InitError> {
+        ServerContext::with_routes(config, |_| Ok(()))
+    }
+
+    /// Creates a ServerContext whose router also has the routes `add` registers, after the
+    /// built-in ones
+    pub fn with_routes(
+        config: ServerConfig,
+        add: impl FnOnce(&mut routes::Router) -> Result<(), routes::RouteError>,
+    ) -> Result<Self, InitError> {
+        let site = SiteRoot::open(&config.root)?;
+        server_log!(Info, "Serving files from: {}", site.canon_path.display());
+
+        let mut vhosts = HashMap::new();
+        for (host, root) in &config.vhosts {
+            let vhost = SiteRoot::open(root).map_err(|_| {
+                server_log!(Error, "Directory for vhost {} is unavailable: {}", host, root);
+                InitError::VhostUnavailable
+            })?;
+            server_log!(
+                Info,
+                "Serving {} from: {}",
+                host,
+                vhost.canon_path.display()
+            );
+            vhosts.insert(host_key(host), vhost);
+        }
+
+        let context = ServerContext {
+            site,
+            vhosts: Arc::new(vhosts),
+            router: Arc::new(
+                routes::Router::new(&config)
+                    .and_then(|mut router| add(&mut router).map(|()| router))
+                    .map_err(|e| {
+                        server_log!(Error, "Invalid route table: {}", e);
+                        InitError::InvalidRoutes
+                    })?,
+            ),
+            file_cache: Arc::new(FileCache::new(&config.file_cache)),
+            mirror: mirror::Mirror::from_config(&config.mirror).map_err(|e| {
+                server_log!(Error, "Invalid mirror: {}", e);
+                InitError::InvalidMirror
+            })?,
+            config: Arc::new(config),
+            request_ids: Arc::new(RequestIdGenerator::new()),
+            connections: Arc::new(AtomicUsize::new(0)),
+            #[cfg(not(any(feature = "async", feature = "event-loop")))]
+            registry: Arc::new(reaper::ConnectionRegistry::default()),
+            shutdown: Arc::new(AtomicBool::new(false)),
+        };
+
+        Ok(context)
+    }
+
+    /// Returns the configuration the context was built from
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Returns the routes requests are dispatched to
+    pub fn router(&self) -> &routes::Router {
+        &self.router
+    }
+
+    /// Returns the directory used for generated artifacts (kept outside the serving root)
+    pub fn cache_dir(&self) -> &Path {
+        Path::new(&self.config.cache_dir)
+    }
+
+    /// Returns the in-memory cache of small files
+    pub fn file_cache(&self) -> &FileCache {
+        &self.file_cache
+    }
+
+    /// Returns the flag that signal handlers set to request a graceful shutdown
+    pub fn shutdown_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.shutdown)
+    }
+
+    /// Checks whether a graceful shutdown has been requested
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+
+    /// Claims a connection slot, or returns None when `max_connections` are already open
+    pub fn try_acquire_connection(&self) -> Option<ConnectionSlot> {
+        let max = self.config.max_connections;
+        self.connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+                (open < max).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| ConnectionSlot {
+                connections: Arc::clone(&self.connections),
+            })
+    }
+
+    /// Returns the number of connections currently holding a slot
+    pub fn open_connections(&self) -> usize {
+        self.connections.load(Ordering::Acquire)
+    }
+
+    /// Returns the registry of open connections the idle reaper watches
+    #[cfg(not(any(feature = "async", feature = "event-loop")))]
+    pub fn registry(&self) -> &Arc<reaper::ConnectionRegistry> {
+        &self.registry
+    }
+
+    /// Returns the next request id: a sequence number for logs plus a unique token
+    pub fn next_request_id(&self) -> RequestId {
+        self.request_ids.next()
+    }
+
+    /// Returns the serving root for a Host header, falling back to the default root
+    fn site_for(&self, host: Option<&str>) -> &SiteRoot {
+        host.and_then(|host| self.vhosts.get(&host_key(host)))
+            .unwrap_or(&self.site)
+    }
+
+    /// Reads a page such as `index.html` from the top of the Host's serving directory
+    pub fn site_page(&self, host: Option<&str>, name: &str) -> Option<String> {
+        fs::read_to_string(self.site_for(host).canon_path.join(name)).ok()
+    }
+
+    /// Returns the Host's custom page for an error status: the one configured for the exact code
+    /// (`403.html`, `404.html`, and `500.html` by default), else the one for its class (`50x.html`)
+    pub fn error_page(&self, host: Option<&str>, status: &HttpStatusCode) -> Option<String> {
+        let code = status.code();
+        [code.to_string(), format!("{}xx", code / 100)]
+            .iter()
+            .filter_map(|key| {
+                self.config
+                    .error_pages
+                    .get(key)
+                    .map(String::as_str)
+                    .or_else(|| {
+                        DEFAULT_ERROR_PAGES
+                            .iter()
+                            .find(|(default, _)| default == key)
+                            .map(|(_, page)| *page)
+                    })
+            })
+            .filter(|page| !page.is_empty())
+            .find_map(|page| self.site_page(host, page))
+    }
+
+    /// Resolves a requested path to an absolute path within the Host's serving directory
+    pub fn resolve_path(
+        &self,
+        host: Option<&str>,
+        req_path: &str,
+        intent: AccessIntent,
+        req_id: RequestId,
+    ) -> Result<ResolvedPath, ResolveError> {
+        server_log!(
+            Debug,
+            req = req_id,
+            "[resolve_path] start: intent={:?} raw='{}'",
+            intent,
+            req_path
+        );
+
+        let decoded = match percent_decode(req_path) {
+            Ok(s) => s,
+            Err(_) => {
+                server_log!(
+                    Debug,
+                    req = req_id,
+                    "[resolve_path] invalid: bad percent-encoding"
+                );
+                return Err(ResolveError::Invalid);
+            }
+        };
+        if decoded.is_empty() {
+            server_log!(
+                Debug,
+                req = req_id,
+                "[resolve_path] invalid: empty after decode"
+            );
+            return Err(ResolveError::Invalid);
+        }
+
+        if decoded.chars().any(|c| c.is_ascii_control()) {
+            server_log!(
+                Debug,
+                req = req_id,
+                "[resolve_path] invalid: contains ASCII control characters"
+            );
+            return Err(ResolveError::Invalid);
+        }
+
+        // Legitimate Unix names such as `aux.log` or `report:2024` only break on Windows
+        let windows_rules = self.config.filename_rules.windows();
+        let invalid_win_chars = ['<', '>', ':', '"', '|', '?', '*'];
+        if decoded.contains('\\')
+            || (windows_rules && decoded.chars().any(|c| invalid_win_chars.contains(&c)))
+        {
+            server_log!(
+                Debug,
+                req = req_id,
+                "[resolve_path] invalid: contains Windows-invalid characters"
+            );
+            return Err(ResolveError::Invalid);
+        }
+
+        let path_obj = PathBuf::from(&decoded);
+        if path_obj
+            .components()
+            .any(|comp| matches!(comp, path::Component::RootDir | path::Component::Prefix(_)))
+        {
+            server_log!(
+                Debug,
+                req = req_id,
+                "[resolve_path] forbidden: absolute or drive-prefixed path"
+            );
+            return Err(ResolveError::Forbidden);
+        }
+
+        if path_obj
+            .components()
+            .any(|c| matches!(c, path::Component::CurDir | path::Component::ParentDir))
+        {
+            server_log!(
+                Debug,
+                req = req_id,
+                "[resolve_path] forbidden: contains . or .. segments"
+            );
+            return Err(ResolveError::Forbidden);
+        }
+
+        if req_path.contains('\\') {
+            server_log!(
+                Debug,
+                req = req_id,
+                "[resolve_path] invalid: raw path contains backslash"
+            );
+            return Err(ResolveError::Invalid);
+        }
+        if req_path
+            .as_bytes()
+            .windows(3)
+            .any(|w| w == b"%2F" || w == b"%2f" || w == b"%5C" || w == b"%5c")
+        {
+            server_log!(
+                Debug,
+                req = req_id,
+                "[resolve_path] invalid: percent-encoded path separator"
+            );
+            return Err(ResolveError::Invalid);
+        }
+
+        let last_name = path_obj.file_name().ok_or_else(|| {
+            server_log!(
+                Debug,
+                req = req_id,
+                "[resolve_path] invalid: no terminal filename component"
+            );
+            ResolveError::Invalid
+        })?;
+        let last = last_name.to_string_lossy();
+        if windows_rules && (last.ends_with('.') || last.ends_with(' ')) {
+            server_log!(
+                Debug,
+                req = req_id,
+                "[resolve_path] invalid: trailing dot or space in filename"
+            );
+            return Err(ResolveError::Invalid);
+        }
+        let base = last.split('.').next().unwrap_or("").to_ascii_lowercase();
+        let is_reserved = windows_rules && RESERVED_NAMES.contains(&base.as_str());
+        if is_reserved {
+            server_log!(
+                Debug,
+                req = req_id,
+                "[resolve_path] invalid: reserved Windows name '{}'",
+                base
+            );
+            return Err(ResolveError::Invalid);
+        }
+
+        let site = self.site_for(host);
+        let candidate = site.root_path.join(&decoded);
+        server_log!(
+            Debug,
+            req = req_id,
+            "[resolve_path] root={} canon_root={} candidate={}",
+            site.root_path.display(),
+            site.canon_path.display(),
+            candidate.display()
+        );
+
+        match intent {
+            AccessIntent::Read | AccessIntent::Directory => {
+                // Canonicalize the target itself; must exist for reads
+                let canonicalize = |path: &Path| {
+                    fs::canonicalize(path).map_err(|e| match e.kind() {
+                        std::io::ErrorKind::NotFound => ResolveError::NotFound,
+                        _ => ResolveError::Io,
+                    })
+                };
+                let mut canon_candidate = canonicalize(&candidate)?;
+                if matches!(intent, AccessIntent::Directory) {
+                    if !canon_candidate.is_dir() {
+                        return Err(ResolveError::NotFound);
+                    }
+                } else if canon_candidate.is_dir() {
+                    // A directory is served through its index page, or not at all
+                    server_log!(
+                        Debug,
+                        req = req_id,
+                        "[resolve_path] directory: looking for {}",
+                        DIRECTORY_INDEX
+                    );
+                    canon_candidate = canonicalize(&canon_candidate.join(DIRECTORY_INDEX))?;
+                    if !canon_candidate.is_file() {
+                        return Err(ResolveError::NotFound);
+                    }
+                }
+
+                if !canon_candidate.starts_with(&site.canon_path) {
+                    server_log!(
+                        Debug,
+                        req = req_id,
+                        "[resolve_path] forbidden: outside root after canonicalize"
+                    );
+                    return Err(ResolveError::Forbidden);
+                }
+
+                Ok(ResolvedPath {
+                    path: canon_candidate,
+                    exists: true,
+                })
+            }
+            AccessIntent::Write => {
+                // Canonicalize the parent; a file may not exist yet
+                let parent = candidate.parent().ok_or_else(|| {
+                    server_log!(
+                        Debug,
+                        req = req_id,
+                        "[resolve_path] invalid: missing parent directory"
+                    );
+                    ResolveError::Invalid
+                })?;
+                let canon_parent = fs::canonicalize(parent).map_err(|e| match e.kind() {
+                    std::io::ErrorKind::NotFound => ResolveError::NotFound,
+                    _ => ResolveError::Io,
+                })?;
+                if !canon_parent.starts_with(&site.canon_path) {
+                    server_log!(
+                        Debug,
+                        req = req_id,
+                        "[resolve_path] forbidden: parent outside root after canonicalize"
+                    );
+                    return Err(ResolveError::Forbidden);
+                }
+
+                let exists = candidate.exists();
+                let file_name = last_name.to_os_string();
+                Ok(ResolvedPath {
+                    path: canon_parent.join(file_name),
+                    exists,
+                })
+            }
+        }
+    }
+}
+
+/// Binds a listening socket with the configured SO_REUSEADDR and backlog
+pub fn bind_listener(address: &str, options: &SocketConfig) -> io::Result<TcpListener> {
+    let addr = address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "address did not resolve")
+    })?;
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(options.reuse_address)?;
+    socket.bind(&addr.into())?;
+    socket.listen(options.backlog)?;
+    Ok(socket.into())
+}
+
+/// Normalizes a host name for vhost lookup: lowercase, without port or trailing dot
+pub fn host_key(host: &str) -> String {
+    let host = host.trim();
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').map_or(rest, |(addr, _)| addr),
+        None => host.split_once(':').map_or(host, |(name, _)| name),
+    };
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Percent-decodes a path segment. Returns Err on malformed sequences.
+fn percent_decode(input: &str) -> Result<String, ()> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                if i + 2 >= bytes.len() {
+                    return Err(());
+                }
+                let high_char = bytes[i + 1] as char;
+                let low_char = bytes[i + 2] as char;
+                let high_nibble = high_char.to_digit(16).ok_or(())? as u8;
+                let low_nibble = low_char.to_digit(16).ok_or(())? as u8;
+                let byte = (high_nibble << 4) | low_nibble;
+                out.push(byte);
+                i += 3;
+            }
+            ch => {
+                out.push(ch);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).map_err(|_| ())
+}
+
+/// Accumulates the bytes of one request head and enforces its size and time limits
+pub struct RequestHead {
+    bytes: Vec<u8>,
+    started: Option<Instant>, // When the first byte arrived
+    header_timeout: Duration,
+}
+
+/// Where a request head stands after more bytes arrive
+#[derive(Debug, PartialEq)]
+pub enum HeadProgress {
+    Incomplete(Duration), // Keep reading; the header deadline is this far away
+    Complete,             // The blank line ending the headers has arrived
+    TooLarge,             // More than MAX_REQUEST_HEADER_SIZE bytes without finishing
+    Expired,              // The header deadline passed before the headers finished
+}
+
+/// Per-connection counters carried from one request to the next
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    pub served: u64, // Requests routed on this connection
+    pub errors: u32, // Requests that failed to parse or were answered with a 4xx
+    #[cfg(feature = "otel")]
+    pub span: crate::http::otel::ConnectionSpan, // Parent of this connection's request spans
+}
+
+/// Whether a connection stays open after serving a request
+#[derive(Debug, PartialEq)]
+pub enum ConnectionAction {
+    KeepAlive,
+    Close,
+}
+
+impl RequestHead {
+    /// Creates an empty request head; the deadline starts with the first byte
+    pub fn new(header_timeout: Duration) -> Self {
+        RequestHead {
+            bytes: Vec::new(),
+            started: None,
+            header_timeout,
+        }
+    }
+
+    /// Appends bytes read from the client and reports whether the head is complete
+    pub fn push(&mut self, chunk: &[u8]) -> HeadProgress {
+        // The whole header section must arrive within the deadline, however the bytes trickle in
+        let deadline = *self.started.get_or_insert_with(Instant::now) + self.header_timeout;
+        self.bytes.extend_from_slice(chunk);
+
+        if self.bytes.len() > MAX_REQUEST_HEADER_SIZE {
+            return HeadProgress::TooLarge;
+        }
+        if self.bytes.windows(4).any(|window| window == b"\r\n\r\n")
+            || HttpRequest::is_simple_request(&self.bytes)
+        {
+            return HeadProgress::Complete;
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            HeadProgress::Expired
+        } else {
+            HeadProgress::Incomplete(remaining)
+        }
+    }
+
+    /// Checks whether no bytes have arrived yet
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Checks whether the header deadline has passed
+    pub fn is_expired(&self) -> bool {
+        self.started
+            .is_some_and(|started| Instant::now() >= started + self.header_timeout)
+    }
+
+    /// Returns when the first byte of the request arrived
+    pub fn started(&self) -> Option<Instant> {
+        self.started
+    }
+
+    /// Returns the bytes received so far
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Splits off any bytes past this request's head and declared body, which a pipelining
+    /// client sent as the start of its next request
+    pub fn split_pipelined(&mut self) -> Vec<u8> {
+        let Some(boundary) = self
+            .bytes
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+        else {
+            return Vec::new();
+        };
+        let Ok(request) = HttpRequest::parse(&self.bytes[..boundary + 4]) else {
+            return Vec::new();
+        };
+        let end = boundary + 4 + body::content_length(&request);
+        if end < self.bytes.len() {
+            self.bytes.split_off(end)
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Handles incoming client connections
+#[cfg(not(any(feature = "async", feature = "event-loop")))]
+pub fn handle_client(stream: impl ClientStream, ctx: ServerContext) -> Result<(), HttpStatusCode> {
+    let mut connection = connection::Connection::new(stream, ctx.clone());
+
+    while !connection.should_close() {
+        let req_id = ctx.next_request_id();
+        let Some(head) = connection.read_request(req_id)? else {
+            return Ok(());
+        };
+        connection.serve(&head, req_id);
+    }
+    Ok(())
+}
+
+/// Logs why a partially received request timed out and answers it with 408
+fn reject_timeout(
+    stream: &mut dyn ClientStream,
+    ctx: &ServerContext,
+    head: &RequestHead,
+    req_id: RequestId,
+) {
+    if head.is_expired() {
+        server_log!(
+            Warn,
+            req = req_id,
+            "headers incomplete after {}s, sending 408",
+            ctx.config.timeouts.header().as_secs()
+        );
+    } else {
+        server_log!(
+            Warn,
+            req = req_id,
+            "read timeout after {} bytes, sending 408",
+            head.bytes().len()
+        );
+    }
+    send_error(
+        stream,
+        HttpStatusCode::RequestTimeout,
+        "Request timed out",
+        req_id,
+    );
+}
+
+/// Reads the rest of the request's body, routes it, and decides whether the connection stays open
+pub fn dispatch(
+    head: &RequestHead,
+    stream: &mut dyn ClientStream,
+    ctx: &ServerContext,
+    req_id: RequestId,
+    stats: &mut ConnectionStats,
+) -> ConnectionAction {
+    let Some(request_bytes) = body::complete(head, stream, ctx, req_id) else {
+        return ConnectionAction::Close;
+    };
+
+    match HttpRequest::parse(&request_bytes) {
+        Ok(mut parse_ok) => {
+            let _active = crash::ActiveRequest::track(req_id);
+            let _in_flight = metrics::InFlight::start();
+            let started = Instant::now();
+            metrics::incr(Counter::Requests);
+            stats.served += 1;
+            let max_requests = ctx.config.keep_alive.max_requests;
+            if max_requests > 0 && stats.served >= max_requests {
+                server_log!(
+                    Info,
+                    req = req_id,
+                    "max requests per connection ({}) reached",
+                    max_requests
+                );
+                // Handlers echo the request's Connection header, so this makes them announce the close
+                parse_ok
+                    .headers
+                    .retain(|k, _| !k.eq_ignore_ascii_case("Connection"));
+                parse_ok
+                    .headers
+                    .insert("Connection".to_string(), "close".to_string());
+            }
+
+            let method = parse_ok.status_line.method.to_string();
+            let path = parse_ok.status_line.path.clone();
+            server_log!(
+                Info,
+                req = req_id,
+                fields = Fields {
+                    event: Some("request"),
+                    method: Some(method.clone()),
+                    path: Some(path.clone()),
+                    ..Fields::default()
+                },
+                "{} {} (id {})",
+                method,
+                path,
+                req_id.token()
+            );
+            mirror::maybe_mirror(ctx.mirror.as_ref(), &parse_ok, req_id);
+
+            #[cfg(feature = "chaos")]
+            match crate::http::chaos::inject(&ctx.config.chaos, &parse_ok, stream, req_id) {
+                crate::http::chaos::ChaosOutcome::Continue => {}
+                crate::http::chaos::ChaosOutcome::Closed => return ConnectionAction::Close,
+            }
+
+            let peer = stream.tcp().and_then(|tcp| tcp.peer_addr().ok());
+            #[cfg(feature = "otel")]
+            let span = crate::http::otel::RequestSpan::start(&mut stats.span, &parse_ok, peer);
+            let (status, report) = ctx.router.route(&parse_ok, stream, ctx, req_id);
+            let elapsed = started.elapsed();
+            metrics::time_request(elapsed);
+            #[cfg(feature = "otel")]
+            span.end(&status, report.bytes, ctx.router.pattern_for(&parse_ok));
+            access::log(&parse_ok, peer, &status, &report, elapsed, req_id);
+            server_log!(
+                Info,
+                req = req_id,
+                fields = Fields {
+                    event: Some("response"),
+                    method: Some(method),
+                    path: Some(path),
+                    status: Some(status.code()),
+                    duration: Some(elapsed),
+                    bytes: Some(report.bytes),
+                },
+                "{} answered, {} bytes in {:.1?}",
+                status,
+                report.bytes,
+                elapsed
+            );
+            if status.closes_connection() {
+                server_log!(Info, req = req_id, "{} sent, closing connection", status);
+                stream.shutdown(Shutdown::Both).unwrap_or_else(|e| {
+                    server_log!(Error, req = req_id, "Failed to shutdown: {:?}", e);
+                });
+                return ConnectionAction::Close;
+            }
+            if (400..500).contains(&status.code()) && count_error(stats, ctx, req_id) {
+                stream.shutdown(Shutdown::Both).unwrap_or_else(|e| {
+                    server_log!(Error, req = req_id, "Failed to shutdown: {:?}", e);
+                });
+                return ConnectionAction::Close;
+            }
+            if ctx.is_shutting_down() {
+                server_log!(
+                    Info,
+                    req = req_id,
+                    "Server shutting down, closing connection."
+                );
+                stream.shutdown(Shutdown::Both).unwrap_or_else(|e| {
+                    server_log!(Error, req = req_id, "Failed to shutdown: {:?}", e);
+                });
+                return ConnectionAction::Close;
+            }
+            if parse_ok
+                .headers
+                .get("Connection")
+                .is_some_and(|v| v.eq_ignore_ascii_case("close"))
+            {
+                server_log!(
+                    Info,
+                    req = req_id,
+                    "Connection: close header found, shutting down."
+                );
+                stream.shutdown(Shutdown::Both).unwrap_or_else(|e| {
+                    server_log!(Error, req = req_id, "Failed to shutdown: {:?}", e);
+                });
+                return ConnectionAction::Close;
+            }
+            ConnectionAction::KeepAlive
+        }
+        Err(parse_error) => {
+            let fields = Fields {
+                event: Some("parse_error"),
+                status: Some(parse_error.status.code()),
+                ..Fields::default()
+            };
+            match quarantine::store(&request_bytes, req_id) {
+                Some(path) => server_log!(
+                    Warn,
+                    req = req_id,
+                    fields = fields,
+                    "parse error: {} — sending error response (raw request saved to {})",
+                    parse_error,
+                    path.display()
+                ),
+                None => server_log!(
+                    Warn,
+                    req = req_id,
+                    fields = fields,
+                    "parse error: {} — sending error response",
+                    parse_error
+                ),
+            }
+            // Whatever follows a malformed request cannot be trusted to start the next one
+            let error_response = HttpErrorResponse::new(
+                parse_error.status,
+                parse_error.version,
+                "close",
+                parse_error.headers.get("Accept").map(|s| s.as_str()),
+                parse_error.detail.unwrap_or("Parsing failed").to_string(),
+            );
+            writer::send_response(stream, error_response, req_id).unwrap_or_else(|e| {
+                HttpWriter::log_writer_error(e, "dispatch - answering a malformed request", req_id);
+            });
+            ConnectionAction::Close
+        }
+    }
+}
+
+/// Counts a failed request, returning true once the connection has reached `keep_alive.max_errors`
+fn count_error(stats: &mut ConnectionStats, ctx: &ServerContext, req_id: RequestId) -> bool {
+    stats.errors += 1;
+    let max_errors = ctx.config.keep_alive.max_errors;
+    if max_errors == 0 || stats.errors < max_errors {
+        return false;
+    }
+    server_log!(
+        Warn,
+        req = req_id,
+        "{} failed requests on this connection, closing it",
+        stats.errors
+    );
+    true
+}
+
+/// Turns away a connection accepted while the server is at its connection limit
+pub fn reject_overloaded(mut stream: TcpStream, ctx: &ServerContext) {
+    // Runs on the accept thread, so never let a slow client hold it up
+    stream
+        .set_write_timeout(Some(OVERLOAD_WRITE_TIMEOUT))
+        .unwrap_or_else(|e| server_log!(Error, "Failed to set write timeout: {:?}", e));
+
+    let mut error_response = HttpErrorResponse::new(
+        HttpStatusCode::ServiceUnavailable,
+        HttpVersion::Http1_1,
+        "close",
+        None,
+        "Server is at its connection limit".to_string(),
+    );
+    error_response
+        .headers
+        .insert("Retry-After".to_string(), OVERLOAD_RETRY_AFTER_SECS.to_string());
+
+    let req_id = ctx.next_request_id();
+    server_log!(
+        Warn,
+        req = req_id,
+        "connection limit ({}) reached, sending 503",
+        ctx.config.max_connections
+    );
+    writer::send_response(&mut stream, error_response, req_id).unwrap_or_else(|e| {
+        HttpWriter::log_writer_error(e, "reject_overloaded - sending 503", req_id);
+    });
+}
+
+/// Sets the write timeouts for a TCP stream.
+fn write_timeout(stream: &mut dyn ClientStream, ctx: &ServerContext) {
+    stream
+        .set_write_timeout(Some(ctx.config.timeouts.write()))
+        .unwrap_or_else(|e| server_log!(Error, "Failed to set write timeout: {:?}", e));
+}
+
+/// Checks whether a read error was caused by a socket timeout.
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Checks whether a read error means the peer went away, so there is no one to answer.
+fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Sends an error response that closes the connection, logging if the write fails.
+fn send_error(
+    stream: &mut dyn ClientStream,
+    status: HttpStatusCode,
+    message: &str,
+    req_id: RequestId,
+) {
+    let message = errors::with_reference(&status, message.to_string(), req_id);
+    let error_response =
+        HttpErrorResponse::new(status, HttpVersion::Http1_1, "close", None, message);
+    writer::send_response(stream, error_response, req_id).unwrap_or_else(|e| {
+        HttpWriter::log_writer_error(e, "send_error - sending error response", req_id);
+    });
+}
+
+/// Sets the read timeout for a TCP stream.
+fn read_timeout(stream: &mut dyn ClientStream, timeout: Duration) {
+    stream
+        .set_read_timeout(Some(timeout))
+        .unwrap_or_else(|e| server_log!(Error, "Failed to set read timeout: {:?}", e));
+}
+
+#[cfg(all(test, not(any(feature = "async", feature = "event-loop"))))]
+mod tests {
+    use super::*;
+    use crate::http::{
+        stream::ClientStream,
+        test_support::{duplex, TempRoot},
+    };
+    use std::{
+        io::{Read, Write},
+        thread,
+    };
+
+    /// Runs `handle_client` on one end of an in-memory connection, sending each of `requests`
+    /// once the server has gone quiet, and returns its result and everything it wrote back
+    fn serve(requests: &[&str]) -> (Result<(), HttpStatusCode>, String) {
+        let root = TempRoot::new("handle-client");
+        let ctx = root.context();
+        let (mut client, server) = duplex();
+        let handled = thread::spawn(move || handle_client(server, ctx));
+
+        let mut sent = Vec::new();
+        let mut buf = [0; 1024];
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        for request in requests {
+            client.write_all(request.as_bytes()).unwrap();
+            while let Ok(n @ 1..) = client.read(&mut buf) {
+                sent.extend_from_slice(&buf[..n]);
+            }
+        }
+        client.set_read_timeout(None).unwrap();
+        client.read_to_end(&mut sent).unwrap();
+        (handled.join().unwrap(), String::from_utf8(sent).unwrap())
+    }
+
+    #[test]
+    fn test_handle_client_keeps_alive_and_closes_on_errors() {
+        let (result, sent) = serve(&[
+            "GET /echo/one HTTP/1.1\r\n\r\n",
+            "GET /echo/two HTTP/1.1\r\nConnection: close\r\n\r\n",
+        ]);
+        assert_eq!(result, Ok(()));
+        assert_eq!(sent.matches("HTTP/1.1 200 OK").count(), 2, "{}", sent);
+        assert!(sent.ends_with("two"), "{}", sent);
+
+        let (result, sent) = serve(&["GET /echo/one HTTP/1.1\r\n\r\n", "NONSENSE\r\n\r\n"]);
+        assert_eq!(result, Ok(()));
+        assert!(sent.starts_with("HTTP/1.1 200 OK"), "{}", sent);
+        assert!(sent.contains("HTTP/1.0 400 Bad Request"), "{}", sent);
+
+        let oversized = format!(
+            "GET / HTTP/1.1\r\nX-Pad: {}",
+            "a".repeat(MAX_REQUEST_HEADER_SIZE)
+        );
+        let (result, sent) = serve(&[&oversized]);
+        assert_eq!(result, Err(HttpStatusCode::BadRequest));
+        assert!(sent.starts_with("HTTP/1.1 400 Bad Request"), "{}", sent);
+    }
+}
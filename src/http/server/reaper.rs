@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::http::{
+    logging::server_log,
+    metrics::{self, Counter},
+    stream::ClientStream,
+};
+
+use super::ServerContext;
+
+/// How often the reaper looks for connections that have been idle too long
+const REAP_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Open connections and when each went idle, so stale ones can be closed from another thread
+///
+/// A worker blocked reading a silent keep-alive connection is held until its socket timeout
+/// fires; shutting the socket down from here ends that read at once
+#[derive(Debug, Default)]
+pub struct ConnectionRegistry {
+    next_id: AtomicU64,
+    entries: Mutex<HashMap<u64, Entry>>,
+}
+
+/// One registered connection
+#[derive(Debug)]
+struct Entry {
+    stream: Option<TcpStream>, // A clone used only to shut the socket down; None if cloning failed or it is no socket
+    idle_since: Option<Instant>, // None while a request is being read or served
+}
+
+/// A connection's place in the registry; dropping it removes the connection
+pub struct Registration {
+    registry: Arc<ConnectionRegistry>,
+    id: u64,
+}
+
+impl ConnectionRegistry {
+    /// Adds a connection, idle from now until its first request arrives
+    pub fn register(self: &Arc<Self>, stream: &dyn ClientStream) -> Registration {
+        let stream = stream.tcp().and_then(|tcp| {
+            tcp.try_clone()
+                .inspect_err(|e| server_log!(Warn, "Connection cannot be reaped: {}", e))
+                .ok()
+        });
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.lock().insert(
+            id,
+            Entry {
+                stream,
+                idle_since: Some(Instant::now()),
+            },
+        );
+        Registration {
+            registry: Arc::clone(self),
+            id,
+        }
+    }
+
+    /// Shuts down every connection idle for at least `max_idle`, returning how many were closed
+    pub fn reap(&self, max_idle: Duration) -> usize {
+        let now = Instant::now();
+        let mut reaped = 0;
+        for entry in self.lock().values_mut() {
+            let stale = entry
+                .idle_since
+                .is_some_and(|since| now.saturating_duration_since(since) >= max_idle);
+            if !stale {
+                continue;
+            }
+            // Cleared so the next pass does not shut it down again before its worker notices
+            entry.idle_since = None;
+            if let Some(stream) = &entry.stream {
+                let _ = stream.shutdown(Shutdown::Both);
+                reaped += 1;
+            }
+        }
+        reaped
+    }
+
+    fn set_idle(&self, id: u64, idle_since: Option<Instant>) {
+        if let Some(entry) = self.lock().get_mut(&id) {
+            entry.idle_since = idle_since;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Registration {
+    /// Marks the connection as waiting for its next request
+    pub fn idle(&self) {
+        self.registry.set_idle(self.id, Some(Instant::now()));
+    }
+
+    /// Marks the connection as reading or serving a request, which the reaper leaves alone
+    pub fn busy(&self) {
+        self.registry.set_idle(self.id, None);
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.id);
+    }
+}
+
+/// Closes connections idle past the keep-alive timeout; once shutdown starts, every idle one
+///
+/// Runs until shutdown has started and every connection has closed
+pub fn run(ctx: ServerContext) {
+    let idle_limit = ctx.config().keep_alive.idle();
+    loop {
+        thread::sleep(REAP_INTERVAL);
+        let max_idle = if ctx.is_shutting_down() {
+            Duration::ZERO
+        } else {
+            idle_limit
+        };
+
+        let reaped = ctx.registry().reap(max_idle);
+        if reaped > 0 {
+            server_log!(Info, "Reaped {} idle connections", reaped);
+            for _ in 0..reaped {
+                metrics::incr(Counter::ConnectionsReaped);
+            }
+        }
+        if ctx.is_shutting_down() && ctx.open_connections() == 0 {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read, net::TcpListener};
+
+    #[test]
+    fn test_only_idle_connections_are_reaped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let registry = Arc::new(ConnectionRegistry::default());
+        let connect = || {
+            let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (server, _) = listener.accept().unwrap();
+            (client, server)
+        };
+
+        let (mut idle_client, idle_server) = connect();
+        let (_busy_client, busy_server) = connect();
+        let idle = registry.register(&idle_server);
+        let busy = registry.register(&busy_server);
+        busy.busy();
+
+        assert_eq!(registry.reap(Duration::from_secs(60)), 0);
+        assert_eq!(registry.reap(Duration::ZERO), 1);
+        assert_eq!(idle_client.read(&mut [0; 1]).unwrap(), 0);
+        // Already shut down, so not counted again
+        assert_eq!(registry.reap(Duration::ZERO), 0);
+
+        drop(idle);
+        busy.idle();
+        assert_eq!(registry.reap(Duration::ZERO), 1);
+        assert_eq!(registry.lock().len(), 1);
+    }
+}
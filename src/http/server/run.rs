@@ -0,0 +1,383 @@
+use std::{
+    fmt,
+    fs::create_dir_all,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(not(any(feature = "async", feature = "event-loop")))]
+use threadpool::ThreadPool;
+
+use crate::http::{
+    config::{ConfigError, ServerConfig},
+    files::mime,
+    logging::server_log,
+    metrics, quarantine,
+    request::HttpMethod,
+    routes::{Handler, RouteError, Router},
+    writer,
+};
+
+use super::{bind_listener, InitError, ServerContext};
+
+/// How often the shutdown watcher and the drain loop check for progress
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A server with its routes built and its listeners bound, ready to run
+pub struct Server {
+    context: ServerContext,
+    listeners: Vec<TcpListener>,
+}
+
+/// Adds routes to a router being built, after the built-in ones
+type AddRoutes = Box<dyn FnOnce(&mut Router) -> Result<(), RouteError>>;
+
+/// Collects a server's settings and routes; `build` binds the listeners
+///
+/// `Server::builder().bind("0.0.0.0:8080").root("./www").workers(32).build()?.run()`
+#[derive(Default)]
+pub struct ServerBuilder {
+    config: ServerConfig,
+    bind: Vec<String>, // Replaces the configured addresses when any is given
+    routes: Vec<AddRoutes>,
+}
+
+/// Represents why a server could not start or stopped serving
+#[derive(Debug)]
+pub enum ServerError {
+    Config(ConfigError),           // The settings cannot be used
+    CreateRoot(String, io::Error), // The root directory could not be created: (root, error)
+    Context(InitError),            // A root, a virtual host, or the route table is unusable
+    Bind(String, io::Error),       // A listener could not be bound: (address, error)
+    Serve(io::Error),              // The async runtime or the event loop failed
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Config(e) => write!(f, "{}", e),
+            ServerError::CreateRoot(root, e) => {
+                write!(f, "Failed to create directory {}: {:?}", root, e)
+            }
+            ServerError::Context(e) => write!(f, "Failed to initialize server context: {:?}", e),
+            ServerError::Bind(address, e) => write!(f, "Failed to bind {}: {}", address, e),
+            ServerError::Serve(e) => write!(f, "Failed to serve: {}", e),
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// Starts from `config`, e.g. one loaded from a file, in place of the defaults
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Adds an address to listen on, such as `0.0.0.0:8080`; port 0 picks a free port
+    pub fn bind(mut self, address: &str) -> Self {
+        self.bind.push(address.to_string());
+        self
+    }
+
+    /// Sets the directory files are served from
+    pub fn root(mut self, root: &str) -> Self {
+        self.config.root = root.to_string();
+        self
+    }
+
+    /// Sets how many requests are handled at once
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.config.workers = workers;
+        self
+    }
+
+    /// Adds a route answered by `handler`
+    pub fn route(self, method: HttpMethod, path: &str, handler: Handler) -> Self {
+        let path = path.to_string();
+        self.routes(move |router| router.add(method, &path, handler).map(|_| ()))
+    }
+
+    /// Adds the routes `add` registers, for routes that need guards, middleware, or scopes
+    pub fn routes(
+        mut self,
+        add: impl FnOnce(&mut Router) -> Result<(), RouteError> + 'static,
+    ) -> Self {
+        self.routes.push(Box::new(add));
+        self
+    }
+
+    /// Checks the settings, builds the router, and binds every address
+    pub fn build(self) -> Result<Server, ServerError> {
+        let mut config = self.config;
+        if !self.bind.is_empty() {
+            config.bind = self.bind;
+        }
+        config.validate().map_err(ServerError::Config)?;
+
+        let routes = self.routes;
+        Server::start(config, |router| {
+            routes.into_iter().try_for_each(|add| add(router))
+        })
+    }
+}
+
+impl Server {
+    /// Starts building a server from the default configuration
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Builds a server whose router also has the routes `add` registers, after the built-in ones,
+    /// and binds every configured address
+    fn start(
+        config: ServerConfig,
+        add: impl FnOnce(&mut Router) -> Result<(), RouteError>,
+    ) -> Result<Self, ServerError> {
+        metrics::init(&config.metrics);
+        #[cfg(feature = "otel")]
+        crate::http::otel::init(&config.otel);
+        quarantine::init(&config.quarantine);
+        writer::init(config.max_response_header_bytes);
+        mime::init(&config.mime_types);
+        server_log!(Info, "Using directory: {}", config.root);
+
+        create_dir_all(&config.root)
+            .map_err(|e| ServerError::CreateRoot(config.root.clone(), e))?;
+
+        let context = ServerContext::with_routes(config, add).map_err(ServerError::Context)?;
+        server_log!(Info, "Cache directory: {}", context.cache_dir().display());
+
+        let config = context.config();
+        let mut listeners = Vec::new();
+        for address in &config.bind {
+            let listener = bind_listener(address, &config.socket)
+                .map_err(|e| ServerError::Bind(address.clone(), e))?;
+            listeners.push(listener);
+        }
+        server_log!(
+            Info,
+            "Listening on {} with {} workers",
+            config.bind.join(", "),
+            config.workers
+        );
+
+        Ok(Server { context, listeners })
+    }
+
+    /// Returns the context every connection is served with
+    pub fn context(&self) -> &ServerContext {
+        &self.context
+    }
+
+    /// Returns the addresses the listeners are bound to, with the ports chosen for any port 0
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|l| l.local_addr().ok())
+            .collect()
+    }
+
+    /// Serves connections until SIGINT or SIGTERM, then waits for open connections to finish, up
+    /// to the shutdown timeout
+    pub fn run(self) -> Result<(), ServerError> {
+        for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+            if let Err(e) = signal_hook::flag::register(signal, self.context.shutdown_flag()) {
+                server_log!(
+                    Error,
+                    "Failed to register handler for signal {}: {}",
+                    signal,
+                    e
+                );
+            }
+        }
+        self.serve()
+    }
+
+    /// Serves connections until the context's shutdown flag is set, then waits for open
+    /// connections to finish, up to the shutdown timeout; unlike `run`, signals are left alone
+    pub fn serve(self) -> Result<(), ServerError> {
+        let wake_addrs = self.local_addrs();
+        let watcher_ctx = self.context.clone();
+        thread::spawn(move || wake_on_shutdown(watcher_ctx, wake_addrs));
+
+        let Server { context, listeners } = self;
+        // The runtime is kept alive until draining finishes so in-flight connections can complete
+        #[cfg(feature = "async")]
+        let _runtime =
+            super::tokio_backend::serve(listeners, context.clone()).map_err(ServerError::Serve)?;
+        #[cfg(feature = "event-loop")]
+        super::event_loop::serve(listeners, context.clone()).map_err(ServerError::Serve)?;
+        #[cfg(not(any(feature = "async", feature = "event-loop")))]
+        serve_threaded(listeners, &context, context.config().workers);
+
+        drain(&context, context.config().timeouts.shutdown());
+        // Spans of the connections just drained are still queued for export
+        #[cfg(feature = "otel")]
+        crate::http::otel::flush();
+        Ok(())
+    }
+}
+
+/// Serves every listener with a blocking accept loop feeding a fixed thread pool, until shutdown
+#[cfg(not(any(feature = "async", feature = "event-loop")))]
+fn serve_threaded(mut listeners: Vec<TcpListener>, context: &ServerContext, workers: usize) {
+    let pool = ThreadPool::new(workers);
+    let reaper_ctx = context.clone();
+    thread::spawn(move || super::reaper::run(reaper_ctx));
+
+    // Every listener but the last gets its own accept thread; the last runs on the calling thread
+    let last = listeners
+        .pop()
+        .expect("config validation guarantees a bind address");
+    let mut accept_threads = Vec::new();
+    for listener in listeners {
+        let ctx = context.clone();
+        let pool = pool.clone();
+        accept_threads.push(thread::spawn(move || accept_loop(listener, ctx, pool)));
+    }
+    accept_loop(last, context.clone(), pool);
+    for handle in accept_threads {
+        let _ = handle.join();
+    }
+}
+
+/// Waits for queued and in-flight connections to finish, up to the deadline
+fn drain(context: &ServerContext, deadline: Duration) {
+    server_log!(
+        Info,
+        "Shutdown requested: draining {} open connections (deadline {:?})",
+        context.open_connections(),
+        deadline
+    );
+
+    let started = Instant::now();
+    while context.open_connections() > 0 {
+        if started.elapsed() >= deadline {
+            server_log!(
+                Error,
+                "Shutdown deadline reached with {} connections still open, exiting",
+                context.open_connections()
+            );
+            return;
+        }
+        thread::sleep(SHUTDOWN_POLL_INTERVAL);
+    }
+
+    server_log!(Info, "All connections drained, exiting");
+}
+
+/// Waits for a shutdown request, then connects to each listener so blocked accept calls return
+fn wake_on_shutdown(context: ServerContext, addrs: Vec<SocketAddr>) {
+    while !context.is_shutting_down() {
+        thread::sleep(SHUTDOWN_POLL_INTERVAL);
+    }
+
+    for mut addr in addrs {
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        let _ = TcpStream::connect_timeout(&addr, SHUTDOWN_POLL_INTERVAL);
+    }
+}
+
+/// Accepts connections on a listener and hands them to the thread pool until shutdown
+#[cfg(not(any(feature = "async", feature = "event-loop")))]
+fn accept_loop(listener: TcpListener, context: ServerContext, pool: ThreadPool) {
+    let log_connections = context.config().logging.connections;
+
+    for stream in listener.incoming() {
+        if context.is_shutting_down() {
+            break;
+        }
+
+        match stream {
+            Ok(stream) => {
+                if log_connections {
+                    match stream.peer_addr() {
+                        Ok(addr) => server_log!(Info, "Accepted Connection: {}", addr),
+                        Err(_) => server_log!(Info, "Accepted Connection: unknown"),
+                    }
+                }
+                // Bounded by max_connections, so the pool's queue cannot grow without limit
+                let Some(slot) = context.try_acquire_connection() else {
+                    metrics::incr(metrics::Counter::ConnectionsRejected);
+                    super::reject_overloaded(stream, &context);
+                    continue;
+                };
+                metrics::incr(metrics::Counter::ConnectionsAccepted);
+                if let Err(e) = stream.set_nodelay(context.config().socket.nodelay) {
+                    server_log!(Warn, "Failed to set TCP_NODELAY: {}", e);
+                }
+                let ctx = context.clone();
+                pool.execute(move || {
+                    let _slot = slot;
+                    match super::handle_client(stream, ctx) {
+                        Ok(()) => {
+                            if log_connections {
+                                server_log!(Info, "Connection closed");
+                            }
+                        }
+                        Err(status_code) => {
+                            server_log!(Info, "Connection closed with status code {}", status_code);
+                        }
+                    }
+                });
+            }
+
+            Err(e) => {
+                server_log!(Info, "error: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{
+        errors::HttpError,
+        request::{HttpRequest, RequestId},
+        response::HttpResponse,
+        routes::Params,
+        test_support::TempRoot,
+    };
+
+    fn ping(
+        _: &HttpRequest,
+        _: &Params,
+        _: &ServerContext,
+        _: RequestId,
+    ) -> Result<HttpResponse, HttpError> {
+        Ok(HttpResponse::builder().text_body("pong").build()?)
+    }
+
+    #[test]
+    fn test_builder_applies_settings_and_routes() {
+        let root = TempRoot::new("builder");
+        let builder = || {
+            Server::builder()
+                .root(root.path().to_str().unwrap())
+                .bind("127.0.0.1:0")
+                .workers(2)
+                .route(HttpMethod::Get, "/ping", ping)
+        };
+
+        let server = builder().build().unwrap();
+        assert_eq!(server.context().config().workers, 2);
+        assert_eq!(server.context().config().bind, ["127.0.0.1:0"]);
+        assert_ne!(server.local_addrs()[0].port(), 0);
+
+        assert!(matches!(
+            builder().route(HttpMethod::Get, "/ping", ping).build(),
+            Err(ServerError::Context(InitError::InvalidRoutes))
+        ));
+        assert!(matches!(
+            builder().workers(0).build(),
+            Err(ServerError::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    io,
+    net::{TcpListener as StdTcpListener, TcpStream as StdTcpStream},
+    time::Duration,
+};
+
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+    runtime::{Builder, Runtime},
+    task, time,
+};
+
+use super::{
+    dispatch, is_disconnect, reject_overloaded, reject_timeout, send_error, write_timeout,
+    ConnectionAction, ConnectionStats, HeadProgress, RequestHead, ServerContext,
+};
+use crate::http::{
+    logging::server_log,
+    metrics::{self, Counter},
+    request::RequestId,
+    response::HttpStatusCode,
+};
+
+/// How often an idle keep-alive wait wakes to check whether shutdown has started
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Builds a tokio runtime and accepts on every listener until shutdown is requested
+///
+/// Returns the runtime so connections still in flight keep running while the caller drains them
+pub fn serve(listeners: Vec<StdTcpListener>, ctx: ServerContext) -> io::Result<Runtime> {
+    // Handlers and response writes block, so `workers` bounds how many requests are routed and
+    // written at once; only accepts, head reads, and keep-alive waits are asynchronous
+    let runtime = Builder::new_multi_thread()
+        .enable_all()
+        .max_blocking_threads(ctx.config().workers)
+        .thread_name("rusttp-async")
+        .build()?;
+
+    runtime.block_on(async {
+        let mut accept_tasks = Vec::new();
+        for listener in listeners {
+            listener.set_nonblocking(true)?;
+            let listener = TcpListener::from_std(listener)?;
+            accept_tasks.push(tokio::spawn(accept_loop(listener, ctx.clone())));
+        }
+        for accept_task in accept_tasks {
+            let _ = accept_task.await;
+        }
+        Ok::<(), io::Error>(())
+    })?;
+
+    Ok(runtime)
+}
+
+/// Accepts connections and spawns a task per connection until shutdown
+async fn accept_loop(listener: TcpListener, ctx: ServerContext) {
+    let log_connections = ctx.config().logging.connections;
+
+    loop {
+        let accepted = listener.accept().await;
+        if ctx.is_shutting_down() {
+            break;
+        }
+
+        match accepted {
+            Ok((stream, addr)) => {
+                if log_connections {
+                    server_log!(Info, "Accepted Connection: {}", addr);
+                }
+                let Some(slot) = ctx.try_acquire_connection() else {
+                    metrics::incr(Counter::ConnectionsRejected);
+                    let ctx = ctx.clone();
+                    task::spawn_blocking(move || match into_blocking(stream) {
+                        Ok(stream) => reject_overloaded(stream, &ctx),
+                        Err(e) => server_log!(Error, "Failed to reject connection: {}", e),
+                    });
+                    continue;
+                };
+                metrics::incr(Counter::ConnectionsAccepted);
+                if let Err(e) = stream.set_nodelay(ctx.config().socket.nodelay) {
+                    server_log!(Warn, "Failed to set TCP_NODELAY: {}", e);
+                }
+
+                let ctx = ctx.clone();
+                tokio::spawn(async move {
+                    let _slot = slot;
+                    match handle_client(stream, ctx).await {
+                        Ok(()) => {
+                            if log_connections {
+                                server_log!(Info, "Connection closed");
+                            }
+                        }
+                        Err(status_code) => {
+                            server_log!(Info, "Connection closed with status code {}", status_code);
+                        }
+                    }
+                });
+            }
+            Err(e) => {
+                server_log!(Info, "error: {}", e);
+            }
+        }
+    }
+}
+
+/// Handles a connection: request heads and keep-alive waits are read asynchronously, while each
+/// parsed request is routed and its response written on the blocking pool, so a slow reader of
+/// a large response holds one of the `workers` threads until it is sent
+pub async fn handle_client(mut stream: TcpStream, ctx: ServerContext) -> Result<(), HttpStatusCode> {
+    let mut stats = ConnectionStats::default();
+
+    loop {
+        let req_id = ctx.next_request_id();
+        let mut head = RequestHead::new(ctx.config().timeouts.header());
+        let mut buffer = [0; 1024];
+
+        // Wait for the next request with the keep-alive idle timeout, waking while idle so a
+        // shutdown closes the connection instead of waiting out the timeout
+        let idle_deadline = time::Instant::now() + ctx.config().keep_alive.idle();
+        let mut wait = ctx.config().timeouts.read();
+
+        loop {
+            if head.is_empty() {
+                wait = idle_deadline
+                    .saturating_duration_since(time::Instant::now())
+                    .min(SHUTDOWN_CHECK_INTERVAL);
+            }
+            match time::timeout(wait, stream.read(&mut buffer)).await {
+                Ok(Ok(0)) => break, // Connection closed
+                Ok(Ok(n)) => match head.push(&buffer[..n]) {
+                    HeadProgress::Complete => break,
+                    HeadProgress::Incomplete(remaining) => {
+                        wait = ctx.config().timeouts.read().min(remaining);
+                    }
+                    HeadProgress::TooLarge => {
+                        run_blocking(stream, move |stream| {
+                            send_error(
+                                stream,
+                                HttpStatusCode::BadRequest,
+                                "Request header too large",
+                                req_id,
+                            )
+                        })
+                        .await
+                        .map_err(|e| connection_error(e, req_id))?;
+                        return Err(HttpStatusCode::BadRequest);
+                    }
+                    HeadProgress::Expired => {
+                        let ctx = ctx.clone();
+                        run_blocking(stream, move |stream| {
+                            reject_timeout(stream, &ctx, &head, req_id)
+                        })
+                        .await
+                        .map_err(|e| connection_error(e, req_id))?;
+                        return Err(HttpStatusCode::RequestTimeout);
+                    }
+                },
+                Err(_) if head.is_empty() && ctx.is_shutting_down() => {
+                    server_log!(
+                        Info,
+                        req = req_id,
+                        "closing idle keep-alive connection for shutdown"
+                    );
+                    return Ok(());
+                }
+                Err(_) if head.is_empty() && time::Instant::now() < idle_deadline => {}
+                Err(_) if head.is_empty() => {
+                    server_log!(
+                        Info,
+                        req = req_id,
+                        "keep-alive idle timeout reached, closing connection"
+                    );
+                    return Ok(());
+                }
+                Err(_) => {
+                    // The client started a request but stalled before finishing the headers
+                    let ctx = ctx.clone();
+                    run_blocking(stream, move |stream| {
+                        reject_timeout(stream, &ctx, &head, req_id)
+                    })
+                    .await
+                    .map_err(|e| connection_error(e, req_id))?;
+                    return Err(HttpStatusCode::RequestTimeout);
+                }
+                Ok(Err(e)) if is_disconnect(&e) => {
+                    server_log!(Info, req = req_id, "peer disconnected: {}", e);
+                    return Ok(());
+                }
+                Ok(Err(e)) => {
+                    run_blocking(stream, move |stream| {
+                        send_error(
+                            stream,
+                            HttpStatusCode::InternalServerError,
+                            &format!("Failed to read request: {}", e),
+                            req_id,
+                        )
+                    })
+                    .await
+                    .map_err(|e| connection_error(e, req_id))?;
+                    return Ok(());
+                }
+            }
+        }
+
+        // If the peer closed the connection without sending bytes, stop gracefully
+        if head.is_empty() {
+            server_log!(Info, req = req_id, "peer closed connection (no bytes)");
+            return Ok(());
+        }
+
+        let request_ctx = ctx.clone();
+        let (std_stream, (action, stats_now)) = run_blocking(stream, move |stream| {
+            write_timeout(stream, &request_ctx);
+            let action = dispatch(&head, stream, &request_ctx, req_id, &mut stats);
+            (action, stats)
+        })
+        .await
+        .map_err(|e| connection_error(e, req_id))?;
+
+        if action == ConnectionAction::Close {
+            return Ok(());
+        }
+        stats = stats_now;
+        stream = into_async(std_stream).map_err(|e| connection_error(e, req_id))?;
+    }
+}
+
+/// Runs a blocking closure against the connection on the runtime's blocking pool
+async fn run_blocking<T, F>(stream: TcpStream, f: F) -> io::Result<(StdTcpStream, T)>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut StdTcpStream) -> T + Send + 'static,
+{
+    let mut stream = into_blocking(stream)?;
+    task::spawn_blocking(move || {
+        let output = f(&mut stream);
+        (stream, output)
+    })
+    .await
+    .map_err(io::Error::other)
+}
+
+/// Converts a tokio stream into a blocking std stream
+fn into_blocking(stream: TcpStream) -> io::Result<StdTcpStream> {
+    let stream = stream.into_std()?;
+    stream.set_nonblocking(false)?;
+    Ok(stream)
+}
+
+/// Converts a blocking std stream back into a tokio stream
+fn into_async(stream: StdTcpStream) -> io::Result<TcpStream> {
+    stream.set_nonblocking(true)?;
+    TcpStream::from_std(stream)
+}
+
+/// Logs a failure to move the connection between the async and blocking halves
+fn connection_error(error: io::Error, req_id: RequestId) -> HttpStatusCode {
+    server_log!(Error, req = req_id, "connection handoff failed: {}", error);
+    HttpStatusCode::InternalServerError
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{
+        server::run::Server,
+        test_support::{spawn, TempRoot, TestResponse},
+    };
+    use std::{
+        io::{BufReader, Read, Write},
+        time::Instant,
+    };
+
+    #[test]
+    fn test_keep_alive_exchange_then_shutdown_closes_the_idle_connection() {
+        let root = TempRoot::new("tokio-backend");
+        let mut config = root.config();
+        config.keep_alive.idle_secs = 60;
+        let server = spawn(Server::builder().config(config).workers(2)).unwrap();
+
+        let mut stream = server.connect();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        for word in ["one", "two"] {
+            write!(
+                stream,
+                "GET /echo/{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                word
+            )
+            .unwrap();
+            let response = TestResponse::read_from(&mut reader).unwrap();
+            assert_eq!(response.text(), word);
+            assert_eq!(response.header("Connection"), Some("keep-alive"));
+        }
+
+        // Let the connection settle into its keep-alive wait, with most of the timeout left
+        std::thread::sleep(Duration::from_millis(300));
+        let started = Instant::now();
+        server.shutdown().unwrap();
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "{:?}",
+            started.elapsed()
+        );
+        assert_eq!(reader.read(&mut [0; 16]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,70 @@
+use serde::Deserialize;
+
+/// Response header naming the instance a path belongs to
+pub const SHARD_HEADER: &str = "X-Shard";
+
+/// Consistent-hash sharding hints for deployments that front several instances
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShardConfig {
+    /// Instances behind the proxy; 0 sends no hint
+    pub count: u32,
+}
+
+impl ShardConfig {
+    /// Returns the shard, in `0..count`, that serves a path; `None` when sharding is off
+    ///
+    /// Uses jump consistent hashing, so growing `count` by one moves only about 1/count of paths
+    pub fn shard_for(&self, path: &str) -> Option<u32> {
+        if self.count == 0 {
+            return None;
+        }
+        let path = path.split_once('?').map_or(path, |(path, _)| path);
+        Some(jump_hash(fnv1a(path.as_bytes()), self.count))
+    }
+}
+
+/// 64-bit FNV-1a, stable across releases and platforms unlike the std hasher
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Lamping and Veach's jump consistent hash of `key` into `buckets` buckets
+fn jump_hash(mut key: u64, buckets: u32) -> u32 {
+    let (mut bucket, mut jump) = (-1_i64, 0_i64);
+    while jump < i64::from(buckets) {
+        bucket = jump;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        jump = ((bucket + 1) as f64 * ((1_u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    bucket as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shards_are_stable_and_move_little_when_growing() {
+        assert_eq!(ShardConfig { count: 0 }.shard_for("/index.html"), None);
+
+        let four = ShardConfig { count: 4 };
+        let five = ShardConfig { count: 5 };
+        let paths: Vec<String> = (0..1000).map(|i| format!("/files/{}.txt", i)).collect();
+        let mut per_shard = [0; 4];
+        let mut moved = 0;
+        for path in &paths {
+            let shard = four.shard_for(path).unwrap();
+            per_shard[shard as usize] += 1;
+            assert_eq!(four.shard_for(&format!("{}?v=2", path)), Some(shard));
+            match five.shard_for(path).unwrap() {
+                4 => moved += 1,
+                other => assert_eq!(other, shard, "{} changed shard", path),
+            }
+        }
+        assert!(per_shard.iter().all(|&n| n > 150), "{:?}", per_shard);
+        assert!((100..300).contains(&moved), "{} moved", moved);
+    }
+}
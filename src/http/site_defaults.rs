@@ -0,0 +1,212 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+};
+
+use serde::Deserialize;
+
+use crate::http::{
+    config::ServerConfig,
+    errors::HttpError,
+    logging::server_log,
+    request::{HttpRequest, RequestId},
+    response::{HttpContentType, HttpResponse, HttpStatusCode, ResponseStatusLine},
+    routes::{self, Params, RouteError, Router},
+    server::{self, host_key},
+    writer::HttpBody,
+};
+
+/// Same meaning as having no robots.txt at all: every crawler may fetch everything
+const DEFAULT_ROBOTS_TXT: &str = "User-agent: *\nDisallow:\n";
+
+/// Answers for `/robots.txt` and `/favicon.ico` when the site root has no such file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteDefaultsConfig {
+    /// Body of the default robots.txt
+    pub robots_txt: String,
+    /// Icon file served as the default favicon; without one it gets 204 No Content
+    pub favicon: Option<String>,
+    /// Overrides for individual hosts, keyed like vhosts
+    pub hosts: BTreeMap<String, HostDefaults>, // "staging.example.com" -> robots_txt = "..."
+}
+
+/// Per-host replacements for the site-wide defaults; unset fields fall back to them
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostDefaults {
+    pub robots_txt: Option<String>,
+    pub favicon: Option<String>,
+}
+
+impl Default for SiteDefaultsConfig {
+    fn default() -> Self {
+        SiteDefaultsConfig {
+            robots_txt: DEFAULT_ROBOTS_TXT.to_string(),
+            favicon: None,
+            hosts: BTreeMap::new(),
+        }
+    }
+}
+
+impl SiteDefaultsConfig {
+    /// Returns the overrides for a request's Host, if any
+    fn for_host(&self, host: Option<&str>) -> Option<&HostDefaults> {
+        let key = host_key(host?);
+        self.hosts
+            .iter()
+            .find(|(name, _)| host_key(name) == key)
+            .map(|(_, defaults)| defaults)
+    }
+}
+
+/// Registers `/robots.txt` and `/favicon.ico`, except where a raw route already answers them
+pub fn register(router: &mut Router, config: &ServerConfig) -> Result<(), RouteError> {
+    for (path, handler) in [
+        ("/robots.txt", robots_handler as routes::Handler),
+        ("/favicon.ico", favicon_handler),
+    ] {
+        if !config.raw_routes.contains_key(path) {
+            router.get(path, handler)?;
+        }
+    }
+    Ok(())
+}
+
+/// Handler for `/robots.txt`: the site's own file, or the configured default
+pub fn robots_handler(
+    request: &HttpRequest,
+    _params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    if let Some(response) = site_file(request, "robots.txt", ctx, req_id) {
+        return response;
+    }
+
+    let config = &ctx.config().site_defaults;
+    let body = config
+        .for_host(request.host())
+        .and_then(|host| host.robots_txt.clone())
+        .unwrap_or_else(|| config.robots_txt.clone());
+    Ok(response(
+        request,
+        HttpStatusCode::Ok,
+        &HttpContentType::PlainText.to_string(),
+        HttpBody::Text(body),
+    ))
+}
+
+/// Handler for `/favicon.ico`: the site's own icon, the configured one, or 204 No Content
+pub fn favicon_handler(
+    request: &HttpRequest,
+    _params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    if let Some(response) = site_file(request, "favicon.ico", ctx, req_id) {
+        return response;
+    }
+
+    let config = &ctx.config().site_defaults;
+    let favicon = config
+        .for_host(request.host())
+        .and_then(|host| host.favicon.as_ref())
+        .or(config.favicon.as_ref());
+    let Some(path) = favicon else {
+        return Ok(response(
+            request,
+            HttpStatusCode::NoContent,
+            "image/x-icon",
+            HttpBody::Binary(Vec::new()),
+        ));
+    };
+
+    let icon = fs::read(path).map_err(|e| {
+        server_log!(Warn, req = req_id, "[favicon] cannot read {}: {}", path, e);
+        HttpError::new(HttpStatusCode::NotFound, "File not found")
+    })?;
+    Ok(response(
+        request,
+        HttpStatusCode::Ok,
+        "image/x-icon",
+        HttpBody::Binary(icon),
+    ))
+}
+
+/// Serves `name` from the top of the Host's directory, as `/files/{name}` would, if it exists
+fn site_file(
+    request: &HttpRequest,
+    name: &str,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Option<Result<HttpResponse, HttpError>> {
+    ctx.resolve_path(request.host(), name, server::AccessIntent::Read, req_id)
+        .ok()?;
+    let params = Params::from([("filename".to_string(), name.to_string())]);
+    Some(routes::file_handler(request, &params, ctx, req_id))
+}
+
+/// Builds a default response; clients may keep it for a day
+fn response(
+    request: &HttpRequest,
+    status: HttpStatusCode,
+    content_type: &str,
+    body: HttpBody,
+) -> HttpResponse {
+    let mut headers = HashMap::from([("Cache-Control".to_string(), "max-age=86400".to_string())]);
+    if status != HttpStatusCode::NoContent {
+        headers.insert("Content-Type".to_string(), content_type.to_string());
+        headers.insert("Content-Length".to_string(), body.byte_len().to_string());
+    }
+    let status_line = ResponseStatusLine {
+        version: request.status_line.version.clone(),
+        status,
+    };
+    HttpResponse::new(status_line, headers, Some(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::test_support::{TempRoot, TestClient};
+
+    #[test]
+    fn test_defaults_yield_to_host_settings_and_real_files() {
+        let root = TempRoot::new("defaults");
+        let mut config = root.config();
+        config.site_defaults.hosts.insert(
+            "staging.example.com".to_string(),
+            HostDefaults {
+                robots_txt: Some("User-agent: *\nDisallow: /\n".to_string()),
+                favicon: None,
+            },
+        );
+        let client = TestClient::new(&server::ServerContext::from_config(config).unwrap());
+        let get = |path: &str, host: &str| {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, host);
+            let response = client.send(&HttpRequest::parse(raw.as_bytes()).unwrap());
+            let body = response.text();
+            (response.status, body)
+        };
+
+        assert_eq!(
+            get("/robots.txt", "example.com"),
+            (HttpStatusCode::Ok, DEFAULT_ROBOTS_TXT.to_string())
+        );
+        assert_eq!(
+            get("/robots.txt", "Staging.Example.com:8080").1,
+            "User-agent: *\nDisallow: /\n"
+        );
+        assert_eq!(
+            get("/favicon.ico", "example.com").0,
+            HttpStatusCode::NoContent
+        );
+
+        root.write("robots.txt", "User-agent: bot\nDisallow: /private\n");
+        assert_eq!(
+            get("/robots.txt", "staging.example.com").1,
+            "User-agent: bot\nDisallow: /private\n"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    time::Duration,
+};
+
+/// A client connection requests are read from and responses written to: a TCP socket in the
+/// server, or an in-memory stream in tests
+pub trait ClientStream: Read + Write {
+    /// Bounds how long a read may block; `None` blocks until data arrives
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Bounds how long a write may block; `None` blocks until the peer takes the data
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Shuts down reading, writing, or both, as `TcpStream::shutdown` does
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+
+    /// Returns the socket underneath, for sendfile and the idle reaper; `None` when there is none
+    fn tcp(&self) -> Option<&TcpStream>;
+}
+
+impl ClientStream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+
+    fn tcp(&self) -> Option<&TcpStream> {
+        Some(self)
+    }
+}
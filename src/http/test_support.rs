@@ -0,0 +1,511 @@
+use std::{
+    cell::Cell,
+    collections::{HashMap, VecDeque},
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    panic,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::http::{
+    config::ServerConfig,
+    request::HttpRequest,
+    response::HttpStatusCode,
+    server::{
+        run::{ServerBuilder, ServerError},
+        ServerContext,
+    },
+    stream::ClientStream,
+};
+
+/// How long `RunningServer::connect` streams wait for the server before a read fails
+const CLIENT_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One end of an in-memory connection made by `duplex`: it reads what the other end writes, and
+/// the other end reads what it writes
+///
+/// Reads block like a socket's, honoring the read timeout with `WouldBlock`, and see end of
+/// stream once the other end shuts down writing or is dropped. Writes never block
+pub struct MemoryStream {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    read_timeout: Cell<Option<Duration>>,
+}
+
+/// Bytes travelling one way between the two ends
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    ready: Condvar, // Signalled when bytes arrive or the pipe closes
+}
+
+#[derive(Default)]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    closed: bool, // No more bytes will be written
+}
+
+impl Pipe {
+    fn lock(&self) -> MutexGuard<'_, PipeState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// Creates a connected pair of in-memory streams, e.g. a client end and a server end
+pub fn duplex() -> (MemoryStream, MemoryStream) {
+    let (a_to_b, b_to_a) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+    let end = |incoming, outgoing| MemoryStream {
+        incoming,
+        outgoing,
+        read_timeout: Cell::new(None),
+    };
+    (
+        end(Arc::clone(&b_to_a), Arc::clone(&a_to_b)),
+        end(a_to_b, b_to_a),
+    )
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = self
+            .read_timeout
+            .get()
+            .map(|timeout| Instant::now() + timeout);
+        let mut state = self.incoming.lock();
+        loop {
+            if !state.bytes.is_empty() || buf.is_empty() {
+                let n = buf.len().min(state.bytes.len());
+                for (slot, byte) in buf.iter_mut().zip(state.bytes.drain(..n)) {
+                    *slot = byte;
+                }
+                return Ok(n);
+            }
+            if state.closed {
+                return Ok(0);
+            }
+            state = match deadline {
+                None => self
+                    .incoming
+                    .ready
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                        return Err(io::ErrorKind::WouldBlock.into());
+                    };
+                    self.incoming
+                        .ready
+                        .wait_timeout(state, left)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.outgoing.lock();
+        if state.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        state.bytes.extend(buf);
+        self.outgoing.ready.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ClientStream for MemoryStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            self.incoming.close();
+        }
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            self.outgoing.close();
+        }
+        Ok(())
+    }
+
+    fn tcp(&self) -> Option<&TcpStream> {
+        None
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+/// Sends requests through a server's router in memory and parses the responses it writes, so
+/// handlers can be tested without a socket
+pub struct TestClient {
+    ctx: ServerContext,
+}
+
+/// A response read back by `TestClient`, with any chunked body already decoded
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: HttpStatusCode,
+    pub headers: HashMap<String, String>, // As sent, e.g. "Content-Type"
+    pub body: Vec<u8>,
+}
+
+impl TestClient {
+    /// Creates a client for the routes of a built server, e.g. `Server::context`
+    pub fn new(ctx: &ServerContext) -> Self {
+        TestClient { ctx: ctx.clone() }
+    }
+
+    /// Sends `GET path` over HTTP/1.1
+    pub fn get(&self, path: &str) -> TestResponse {
+        let head = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        let request = HttpRequest::parse(head.as_bytes()).expect("GET request should parse");
+        self.send(&request)
+    }
+
+    /// Routes `request` as a connection would and parses what the router writes back
+    pub fn send(&self, request: &HttpRequest) -> TestResponse {
+        let (client, mut server) = duplex();
+        let req_id = self.ctx.next_request_id();
+        self.ctx
+            .router()
+            .route(request, &mut server, &self.ctx, req_id);
+        drop(server);
+
+        TestResponse::read_from(&mut BufReader::new(client)).expect("response should be complete")
+    }
+}
+
+impl TestResponse {
+    /// Reads one response off a connection, leaving any that follow it on keep-alive
+    ///
+    /// The body runs for its Content-Length, through the last chunk of a chunked body (trailers
+    /// are dropped), or to the end of the stream when neither is sent
+    pub fn read_from(reader: &mut impl BufRead) -> io::Result<TestResponse> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+        let status_line = read_line(reader)?;
+        let code = status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| invalid("response should start with a status line"))?;
+
+        let mut headers = HashMap::new();
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("header line should hold a colon"))?;
+            headers.insert(name.to_string(), value.trim().to_string());
+        }
+
+        let mut response = TestResponse {
+            status: HttpStatusCode::from(code),
+            headers,
+            body: Vec::new(),
+        };
+        let chunked = response
+            .header("Transfer-Encoding")
+            .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"));
+        let length = response
+            .header("Content-Length")
+            .map(|len| {
+                len.parse::<usize>()
+                    .map_err(|_| invalid("bad Content-Length"))
+            })
+            .transpose()?;
+        if !response.status.allows_body() {
+            return Ok(response);
+        }
+        if chunked {
+            loop {
+                let size_line = read_line(reader)?;
+                let size_hex = size_line.split(';').next().unwrap_or_default().trim();
+                let size = usize::from_str_radix(size_hex, 16)
+                    .map_err(|_| invalid("chunk size should be hex"))?;
+                if size == 0 {
+                    while !read_line(reader)?.is_empty() {}
+                    break;
+                }
+                let start = response.body.len();
+                response.body.resize(start + size, 0);
+                reader.read_exact(&mut response.body[start..])?;
+                read_line(reader)?;
+            }
+        } else if let Some(length) = length {
+            response.body.resize(length, 0);
+            reader.read_exact(&mut response.body)?;
+        } else {
+            reader.read_to_end(&mut response.body)?;
+        }
+        Ok(response)
+    }
+
+    /// Returns a header's value, looked up case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the body as text, with invalid UTF-8 replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Reads a line ending in CRLF, without the line ending; end of stream is an error
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// A server started by `spawn`, serving on background threads until it is shut down or dropped
+pub struct RunningServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    serving: Option<JoinHandle<Result<(), ServerError>>>,
+}
+
+/// Starts the server `builder` describes, also listening on a free port of 127.0.0.1 so tests
+/// running in parallel never collide; signal handlers are not installed
+pub fn spawn(builder: ServerBuilder) -> Result<RunningServer, ServerError> {
+    let server = builder.bind("127.0.0.1:0").build()?;
+    let addr = *server
+        .local_addrs()
+        .last()
+        .expect("the server listens on the address just added");
+    let shutdown = server.context().shutdown_flag();
+    let serving = thread::spawn(move || server.serve());
+    Ok(RunningServer {
+        addr,
+        shutdown,
+        serving: Some(serving),
+    })
+}
+
+impl RunningServer {
+    /// Returns the address the server listens on, with the port it was given
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Opens a connection to the server, with reads that fail rather than hang a broken test
+    pub fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(self.addr).expect("server should accept connections");
+        stream
+            .set_read_timeout(Some(CLIENT_READ_TIMEOUT))
+            .expect("read timeout should be settable");
+        stream
+    }
+
+    /// Sends `GET path` on a connection of its own and reads the response
+    pub fn get(&self, path: &str) -> TestResponse {
+        let mut stream = self.connect();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        )
+        .expect("request should be sent");
+        TestResponse::read_from(&mut BufReader::new(stream)).expect("response should be complete")
+    }
+
+    /// Stops accepting connections, waits for open ones to finish, and returns how serving ended
+    pub fn shutdown(mut self) -> Result<(), ServerError> {
+        match self.stop() {
+            Some(Err(panic)) => panic::resume_unwind(panic),
+            Some(Ok(result)) => result,
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the shutdown flag and joins the serving thread, unless that was done already
+    fn stop(&mut self) -> Option<thread::Result<Result<(), ServerError>>> {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.serving.take().map(JoinHandle::join)
+    }
+}
+
+impl Drop for RunningServer {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// A directory under the system temp dir for a test's files; it is deleted on drop, so it goes
+/// away even when an assertion fails first
+pub struct TempRoot {
+    path: PathBuf,
+}
+
+impl TempRoot {
+    /// Creates an empty directory named after `name`, which must be unique among the tests
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("rusttp-{}-{}", name, process::id()));
+        // Left over by a run that was killed before it could clean up
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("temp root should be creatable");
+        TempRoot { path }
+    }
+
+    /// Returns the directory itself
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path of `relative` inside the directory
+    pub fn join(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.path.join(relative)
+    }
+
+    /// Writes a file inside the directory, creating the directories leading to it
+    pub fn write(&self, relative: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("temp root subdirectory should be creatable");
+        }
+        fs::write(&path, contents).expect("temp root file should be writable");
+        path
+    }
+
+    /// Returns the default configuration, serving files from this directory
+    pub fn config(&self) -> ServerConfig {
+        ServerConfig {
+            root: self.path.to_string_lossy().into_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns a context for a server with the default configuration, serving this directory
+    pub fn context(&self) -> ServerContext {
+        ServerContext::from_config(self.config()).expect("temp root should be a valid root")
+    }
+}
+
+impl Drop for TempRoot {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::server::run::Server;
+
+    #[test]
+    fn test_duplex_carries_bytes_both_ways_until_closed() {
+        let (mut client, mut server) = duplex();
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(server.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"ping");
+
+        server
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        let idle = server.read(&mut buf).unwrap_err();
+        assert_eq!(idle.kind(), io::ErrorKind::WouldBlock);
+
+        server.write_all(b"pong").unwrap();
+        server.shutdown(Shutdown::Write).unwrap();
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "pong");
+
+        drop(server);
+        let gone = client.write_all(b"more").unwrap_err();
+        assert_eq!(gone.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_client_reads_back_routed_responses() {
+        let root = TempRoot::new("client");
+        let client = TestClient::new(&root.context());
+
+        let echo = client.get("/echo/hello");
+        assert_eq!(echo.status, HttpStatusCode::Ok);
+        assert_eq!(
+            echo.header("content-type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(echo.text(), "hello");
+
+        let chunked = client.get("/chunked/streamed");
+        assert_eq!(chunked.header("Transfer-Encoding"), Some("chunked"));
+        assert!(chunked.text().contains("streamed"), "{}", chunked.text());
+
+        let request =
+            HttpRequest::parse(b"GET /user-agent HTTP/1.1\r\nUser-Agent: probe/1.0\r\n\r\n")
+                .unwrap();
+        assert_eq!(client.send(&request).text(), "probe/1.0");
+        assert_eq!(client.get("/nope").status, HttpStatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_spawned_server_serves_keep_alive_chunked_and_ranges() {
+        let root = TempRoot::new("spawn");
+        root.write("digits.txt", "0123456789");
+        let builder = Server::builder().root(root.path().to_str().unwrap());
+        let server = spawn(builder.workers(2)).unwrap();
+        assert_ne!(server.addr().port(), 0);
+
+        let mut stream = server.connect();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        stream.write_all(b"GET /echo/one HTTP/1.1\r\n\r\n").unwrap();
+        let first = TestResponse::read_from(&mut reader).unwrap();
+        assert_eq!(first.header("Connection"), Some("keep-alive"));
+        stream
+            .write_all(b"GET /files/digits.txt HTTP/1.1\r\nRange: bytes=2-5\r\n\r\n")
+            .unwrap();
+        let partial = TestResponse::read_from(&mut reader).unwrap();
+        assert_eq!(partial.status, HttpStatusCode::PartialContent);
+        assert_eq!(partial.text(), "2345");
+        drop((stream, reader));
+
+        let chunked = server.get("/chunked/streamed");
+        assert_eq!(chunked.header("Transfer-Encoding"), Some("chunked"));
+        assert!(chunked.text().contains("streamed"), "{}", chunked.text());
+
+        let addr = server.addr();
+        server.shutdown().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::http::{
+    errors::HttpError,
+    logging::server_log,
+    request::{HttpRequest, RequestId},
+    response::{HttpResponse, HttpStatusCode},
+    routes::Params,
+    server,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const DEFAULT_TOKEN_TTL_SECS: u64 = 300;
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Header that carries an upload token; `?token=` is accepted for plain HTML forms
+pub const UPLOAD_TOKEN_HEADER: &str = "X-Upload-Token";
+
+/// Upload token settings; uploads need no token unless a secret is set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+    /// HMAC key for upload tokens; setting it makes POST /files require a token
+    pub secret: Option<String>,
+    /// How long a minted token stays valid
+    pub token_ttl_secs: u64,
+    /// Largest size limit a token may grant
+    pub max_bytes: u64,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            secret: None,
+            token_ttl_secs: DEFAULT_TOKEN_TTL_SECS,
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+}
+
+/// Represents why an upload token was refused
+#[derive(Debug, PartialEq)]
+pub enum UploadTokenError {
+    Missing,      // Token required but not sent
+    Malformed,    // Not "<expires>.<max_bytes>.<signature>"
+    BadSignature, // Signed with another secret or for another path
+    Expired,      // Past its expiry time
+    TooLarge,     // Body exceeds the size the token allows
+}
+
+impl From<UploadTokenError> for HttpError {
+    fn from(error: UploadTokenError) -> Self {
+        match error {
+            UploadTokenError::Missing => {
+                HttpError::new(HttpStatusCode::Forbidden, "Upload token required")
+            }
+            UploadTokenError::Malformed | UploadTokenError::BadSignature => {
+                HttpError::new(HttpStatusCode::Forbidden, "Invalid upload token")
+            }
+            UploadTokenError::Expired => {
+                HttpError::new(HttpStatusCode::Forbidden, "Upload token expired")
+            }
+            UploadTokenError::TooLarge => HttpError::new(
+                HttpStatusCode::PayloadTooLarge,
+                "Upload exceeds the token's size limit",
+            ),
+        }
+    }
+}
+
+/// Mints a token allowing one path to receive uploads of up to `max_bytes` until `expires`
+pub fn mint(secret: &str, path: &str, max_bytes: u64, expires: u64) -> String {
+    format!(
+        "{}.{}.{}",
+        expires,
+        max_bytes,
+        to_hex(&sign(secret, path, max_bytes, expires))
+    )
+}
+
+/// Checks a token against the upload's path and body size at time `now`
+pub fn verify(
+    secret: &str,
+    token: &str,
+    path: &str,
+    body_len: u64,
+    now: u64,
+) -> Result<(), UploadTokenError> {
+    let mut parts = token.splitn(3, '.');
+    let (Some(expires), Some(max_bytes), Some(signature)) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(UploadTokenError::Malformed);
+    };
+    let expires: u64 = expires.parse().map_err(|_| UploadTokenError::Malformed)?;
+    let max_bytes: u64 = max_bytes.parse().map_err(|_| UploadTokenError::Malformed)?;
+    let signature = from_hex(signature).ok_or(UploadTokenError::Malformed)?;
+
+    mac(secret, path, max_bytes, expires)
+        .verify_slice(&signature)
+        .map_err(|_| UploadTokenError::BadSignature)?;
+
+    if now >= expires {
+        return Err(UploadTokenError::Expired);
+    }
+    if body_len > max_bytes {
+        return Err(UploadTokenError::TooLarge);
+    }
+    Ok(())
+}
+
+/// Validates the upload token on a POST to `path`, if upload tokens are enabled
+pub fn authorize_upload(
+    config: &UploadConfig,
+    request: &HttpRequest,
+    path: &str,
+) -> Result<(), UploadTokenError> {
+    let Some(secret) = &config.secret else {
+        return Ok(());
+    };
+
+    let token = request
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(UPLOAD_TOKEN_HEADER))
+        .map(|(_, value)| value.as_str())
+        .or_else(|| request.query_param("token"))
+        .ok_or(UploadTokenError::Missing)?;
+
+    // Trust whichever is larger so a short Content-Length cannot sneak a bigger body through
+    let declared = request
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, value)| value.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    let actual = request.body.as_ref().map_or(0, |b| b.len() as u64);
+
+    verify(secret, token, path, declared.max(actual), unix_now())
+}
+
+/// Guard for upload routes: validates the upload token for the `{filename}` being written
+pub fn require_token(
+    request: &HttpRequest,
+    params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<(), HttpError> {
+    let filename = params.get("filename").map(|s| s.as_str()).unwrap_or("");
+    authorize_upload(&ctx.config().uploads, request, filename).map_err(|e| {
+        server_log!(Warn, req = req_id, "[upload] refused: {:?}", e);
+        HttpError::from(e)
+    })
+}
+
+/// Handler that mints an upload token for `?path=...&max_bytes=...`
+pub fn token_handler(
+    request: &HttpRequest,
+    _params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    let config = &ctx.config().uploads;
+    let path = request.query_param("path").unwrap_or("");
+    server_log!(Info, req = req_id, "[upload-token] path={:?}", path);
+
+    let Some(secret) = &config.secret else {
+        return Err(HttpError::new(HttpStatusCode::NotFound, "Route not found"));
+    };
+    if is_cross_site(request) {
+        return Err(HttpError::new(
+            HttpStatusCode::Forbidden,
+            "Upload tokens are only issued to same-origin pages",
+        ));
+    }
+    if path.is_empty() {
+        return Err(HttpError::new(
+            HttpStatusCode::BadRequest,
+            "Missing path parameter",
+        ));
+    }
+
+    let max_bytes = match request.query_param("max_bytes").map(str::parse::<u64>) {
+        None => config.max_bytes,
+        Some(Ok(requested)) => requested.min(config.max_bytes),
+        Some(Err(_)) => {
+            return Err(HttpError::new(
+                HttpStatusCode::BadRequest,
+                "Invalid max_bytes parameter",
+            ));
+        }
+    };
+
+    let expires = unix_now() + config.token_ttl_secs;
+    let body = mint(secret, path, max_bytes, expires);
+
+    Ok(HttpResponse::builder()
+        .version(request.status_line.version.clone())
+        .header("Cache-Control", "no-store")
+        .text_body(body)
+        .build()?)
+}
+
+/// Checks whether the browser says the token request came from another site
+///
+/// Browsers send `Sec-Fetch-Site` and `Origin` on cross-site fetches; a page on another origin
+/// could not read the token anyway, but refusing outright keeps tokens out of its reach entirely
+fn is_cross_site(request: &HttpRequest) -> bool {
+    let header = |name: &str| {
+        request
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+
+    if header("Sec-Fetch-Site").is_some_and(|site| site.eq_ignore_ascii_case("cross-site")) {
+        return true;
+    }
+
+    match (header("Origin"), header("Host")) {
+        (Some(origin), Some(host)) => {
+            let origin_host = origin.split_once("://").map_or(origin, |(_, rest)| rest);
+            !origin_host.eq_ignore_ascii_case(host)
+        }
+        _ => false,
+    }
+}
+
+fn mac(secret: &str, path: &str, max_bytes: u64, expires: u64) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(
+        format!(
+            "{}\n{}\n{}",
+            path.trim_start_matches('/'),
+            expires,
+            max_bytes
+        )
+        .as_bytes(),
+    );
+    mac
+}
+
+fn sign(secret: &str, path: &str, max_bytes: u64, expires: u64) -> Vec<u8> {
+    mac(secret, path, max_bytes, expires)
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_round_trip_and_bindings() {
+        let token = mint("s3cret", "notes.txt", 100, 1_000);
+
+        assert_eq!(verify("s3cret", &token, "notes.txt", 100, 999), Ok(()));
+        assert_eq!(verify("s3cret", &token, "/notes.txt", 10, 999), Ok(()));
+        assert_eq!(
+            verify("s3cret", &token, "other.txt", 10, 999),
+            Err(UploadTokenError::BadSignature)
+        );
+        assert_eq!(
+            verify("different", &token, "notes.txt", 10, 999),
+            Err(UploadTokenError::BadSignature)
+        );
+        assert_eq!(
+            verify("s3cret", &token, "notes.txt", 101, 999),
+            Err(UploadTokenError::TooLarge)
+        );
+        assert_eq!(
+            verify("s3cret", &token, "notes.txt", 10, 1_000),
+            Err(UploadTokenError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_tampered_token_is_rejected() {
+        let token = mint("s3cret", "notes.txt", 100, 1_000);
+        let raised = token.replacen(".100.", ".999999.", 1);
+
+        assert_eq!(
+            verify("s3cret", &raised, "notes.txt", 500, 999),
+            Err(UploadTokenError::BadSignature)
+        );
+        assert_eq!(
+            verify("s3cret", "garbage", "notes.txt", 1, 0),
+            Err(UploadTokenError::Malformed)
+        );
+    }
+}
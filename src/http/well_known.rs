@@ -0,0 +1,225 @@
+use std::{fs, io, path::Path};
+
+use serde::Deserialize;
+
+use crate::http::{
+    errors::HttpError,
+    logging::server_log,
+    request::{HttpRequest, RequestId},
+    response::{HttpResponse, HttpStatusCode},
+    routes::{Params, RouteError, Router},
+    server,
+    writer::HttpBody,
+};
+
+/// Path under which well-known resources live (RFC 8615)
+pub const PREFIX: &str = "/.well-known";
+
+/// Built-in `/.well-known/` resources; each is served only once it is configured
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WellKnownConfig {
+    /// `Contact:` lines of security.txt, e.g. "mailto:security@example.com"
+    pub security_contacts: Vec<String>,
+    /// `Expires:` line of security.txt as an RFC 3339 timestamp; required with contacts
+    pub security_expires: Option<String>,
+    /// Page that `/.well-known/change-password` sends password managers to
+    pub change_password: Option<String>,
+    /// Directory an ACME client writes HTTP-01 challenge files to
+    pub acme_challenge_dir: Option<String>,
+}
+
+impl WellKnownConfig {
+    /// Checks that every configured resource can be served
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.security_contacts.is_empty() && self.security_expires.is_none() {
+            return Err(
+                "well_known.security_expires is required with security_contacts".to_string(),
+            );
+        }
+        if self.change_password.as_deref().is_some_and(str::is_empty) {
+            return Err("well_known.change_password must not be empty".to_string());
+        }
+        if self
+            .acme_challenge_dir
+            .as_deref()
+            .is_some_and(str::is_empty)
+        {
+            return Err("well_known.acme_challenge_dir must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Registers the built-in resources enabled in `config`
+pub fn register(router: &mut Router, config: &WellKnownConfig) -> Result<(), RouteError> {
+    if !config.security_contacts.is_empty() {
+        router.well_known("security.txt", security_txt_handler)?;
+    }
+    if config.change_password.is_some() {
+        router.well_known("change-password", change_password_handler)?;
+    }
+    if config.acme_challenge_dir.is_some() {
+        // Tokens are base64url, so the constraint alone keeps paths inside the directory
+        router.well_known("acme-challenge/{token:[\\w-]+}", acme_challenge_handler)?;
+    }
+    Ok(())
+}
+
+/// Handler for `/.well-known/security.txt` (RFC 9116), built from the configured fields
+pub fn security_txt_handler(
+    request: &HttpRequest,
+    _params: &Params,
+    ctx: &server::ServerContext,
+    _req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    let config = &ctx.config().well_known;
+    let mut body: String = config
+        .security_contacts
+        .iter()
+        .map(|contact| format!("Contact: {}\n", contact))
+        .collect();
+    if let Some(expires) = &config.security_expires {
+        body.push_str(&format!("Expires: {}\n", expires));
+    }
+
+    response(
+        request,
+        HttpStatusCode::Ok,
+        "text/plain; charset=utf-8",
+        HttpBody::Text(body),
+    )
+}
+
+/// Handler for `/.well-known/change-password`: redirects to the configured page
+pub fn change_password_handler(
+    _request: &HttpRequest,
+    _params: &Params,
+    ctx: &server::ServerContext,
+    _req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    let location = ctx
+        .config()
+        .well_known
+        .change_password
+        .as_deref()
+        .unwrap_or("/");
+
+    Ok(HttpResponse::redirect(HttpStatusCode::Found, location))
+}
+
+/// Handler for `/.well-known/acme-challenge/{token}`: serves the file an ACME client left for
+/// the CA to fetch
+pub fn acme_challenge_handler(
+    request: &HttpRequest,
+    params: &Params,
+    ctx: &server::ServerContext,
+    req_id: RequestId,
+) -> Result<HttpResponse, HttpError> {
+    let dir = ctx.config().well_known.acme_challenge_dir.as_deref();
+    let token = params.get("token").map_or("", |s| s.as_str());
+    server_log!(Info, req = req_id, "[well-known] acme challenge {}", token);
+
+    let contents =
+        fs::read(Path::new(dir.unwrap_or(".")).join(token)).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => HttpError::new(HttpStatusCode::NotFound, "Unknown token"),
+            _ => HttpError::new(
+                HttpStatusCode::InternalServerError,
+                "Reading challenge failed",
+            ),
+        })?;
+
+    response(
+        request,
+        HttpStatusCode::Ok,
+        "application/octet-stream",
+        HttpBody::Binary(contents),
+    )
+}
+
+/// Builds an uncacheable response; well-known resources change without notice
+fn response(
+    request: &HttpRequest,
+    status: HttpStatusCode,
+    content_type: &str,
+    body: HttpBody,
+) -> Result<HttpResponse, HttpError> {
+    Ok(HttpResponse::builder()
+        .version(request.status_line.version.clone())
+        .status(status)
+        .header("Content-Type", content_type)
+        .header("Cache-Control", "no-store")
+        .body(body)
+        .build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::test_support::{TempRoot, TestClient};
+
+    fn nodeinfo_handler(
+        request: &HttpRequest,
+        _params: &Params,
+        _ctx: &server::ServerContext,
+        _req_id: RequestId,
+    ) -> Result<HttpResponse, HttpError> {
+        response(
+            request,
+            HttpStatusCode::Ok,
+            "application/json",
+            HttpBody::Text("{}".to_string()),
+        )
+    }
+
+    #[test]
+    fn test_builtin_and_custom_resources() {
+        let acme = TempRoot::new("acme");
+        acme.write("tok_en-1", "tok_en-1.thumbprint");
+
+        let root = TempRoot::new("well-known");
+        let mut config = root.config();
+        config.well_known = WellKnownConfig {
+            security_contacts: vec!["mailto:security@example.com".to_string()],
+            security_expires: Some("2030-01-01T00:00:00Z".to_string()),
+            change_password: Some("/account/password".to_string()),
+            acme_challenge_dir: Some(acme.path().to_string_lossy().into_owned()),
+        };
+        let ctx = server::ServerContext::with_routes(config, |router| {
+            assert!(router.well_known("/nodeinfo", nodeinfo_handler).is_err());
+            router.well_known("nodeinfo", nodeinfo_handler).map(|_| ())
+        })
+        .unwrap();
+        let client = TestClient::new(&ctx);
+
+        let security = client.get("/.well-known/security.txt");
+        assert_eq!(security.status, HttpStatusCode::Ok);
+        assert_eq!(
+            security.text(),
+            "Contact: mailto:security@example.com\nExpires: 2030-01-01T00:00:00Z\n"
+        );
+        let change = client.get("/.well-known/change-password");
+        assert_eq!(change.status, HttpStatusCode::Found);
+        assert_eq!(change.header("Location"), Some("/account/password"));
+        assert_eq!(change.header("Cache-Control"), Some("no-cache"));
+        assert_eq!(
+            change.header("Content-Length"),
+            Some(change.body.len().to_string().as_str())
+        );
+        let challenge = client.get("/.well-known/acme-challenge/tok_en-1");
+        assert_eq!(challenge.status, HttpStatusCode::Ok);
+        assert_eq!(challenge.text(), "tok_en-1.thumbprint");
+        assert_eq!(
+            client.get("/.well-known/acme-challenge/missing").status,
+            HttpStatusCode::NotFound
+        );
+        assert_eq!(
+            client.get("/.well-known/acme-challenge/..%2Fsecret").status,
+            HttpStatusCode::NotFound
+        );
+        assert_eq!(
+            client.get("/.well-known/nodeinfo").status,
+            HttpStatusCode::Ok
+        );
+    }
+}
@@ -1,31 +1,53 @@
-use std::{collections::HashMap, io::Write, net::TcpStream};
+use std::{
+    collections::HashMap,
+    io::{self, IoSlice, Write},
+};
 
 use titlecase::Titlecase;
 
-use super::types::{WriterError, WriterState};
-use crate::http::{request::HttpVersion, response::HttpStatusCode};
+use super::standard::check_header_size;
+use super::types::{validate_header, BodyStream, WriterError, WriterState, STREAM_CHUNK_SIZE};
+use crate::http::{
+    request::HttpVersion,
+    response::HttpStatusCode,
+    routes::{CompressionLevels, HttpEncoding, StreamEncoder},
+    stream::ClientStream,
+};
 
 /// A writer for HTTP responses that uses chunked transfer encoding.
 pub struct ChunkedWriter<'a> {
-    stream: &'a mut TcpStream,
+    stream: &'a mut dyn ClientStream,
     state: WriterState,
     status_line: Option<String>,
     headers: HashMap<String, String>,
     body: Option<Vec<u8>>,
+    streamed: Option<BodyStream>, // Sent one chunk per read from its source
+    sent: u64,                    // Bytes the socket has accepted so far
+    head_only: bool,              // Answering HEAD: the head is sent, but no chunks
 }
 
 impl<'a> ChunkedWriter<'a> {
-    /// Create a new ChunkedWriter with the given TcpStream
-    pub fn new(stream: &'a mut TcpStream) -> Self {
+    /// Create a new ChunkedWriter with the given stream
+    pub fn new(stream: &'a mut dyn ClientStream) -> Self {
         ChunkedWriter {
             stream,
             state: WriterState::Initial,
             status_line: None,
             headers: HashMap::new(),
             body: None,
+            streamed: None,
+            sent: 0,
+            head_only: false,
         }
     }
 
+    /// Create a ChunkedWriter for a HEAD response, which sends the head GET would get but no chunks
+    pub fn for_head(stream: &'a mut dyn ClientStream) -> Self {
+        let mut writer = ChunkedWriter::new(stream);
+        writer.head_only = true;
+        writer
+    }
+
     /// Write the status line of the HTTP response. This can only be called once.
     pub fn write_status_line(
         &mut self,
@@ -56,6 +78,10 @@ impl<'a> ChunkedWriter<'a> {
                 "[request {req_id}][send_response] Cannot write headers in current state".into(),
             ));
         }
+        if let Err(e) = validate_header(&key, &value) {
+            self.state = WriterState::Failed;
+            return Err(e);
+        }
 
         self.state = WriterState::HeadersOpen;
 
@@ -100,16 +126,116 @@ impl<'a> ChunkedWriter<'a> {
         Ok(())
     }
 
-    /// Complete the writing process by sending the status line, headers, and body in chunked transfer encoding
-    pub fn complete_write(self) -> Result<(), WriterError> {
+    /// Write a body that is read from its source as it is sent. This can only be called after headers are finished.
+    pub fn write_stream(&mut self, body: BodyStream) -> Result<(), WriterError> {
+        if self.state != WriterState::HeadersClosed {
+            self.state = WriterState::Failed;
+
+            return Err(WriterError::InvalidState(
+                "[request {req_id}][send_response] Cannot write body in current state".into(),
+            ));
+        }
+
+        self.streamed = Some(body);
+        self.state = WriterState::BodyWritten;
+
+        Ok(())
+    }
+
+    /// Send `data` as a chunk right away. Call it after headers are finished as often as needed, then `complete_write` to end the body.
+    ///
+    /// The first call sends the status line and headers. Empty data sends nothing, since an empty chunk would end the body.
+    pub fn stream_chunk(&mut self, data: &[u8]) -> Result<(), WriterError> {
+        match self.state {
+            WriterState::HeadersClosed => self.start_streaming()?,
+            WriterState::Streaming => {}
+            _ => {
+                self.state = WriterState::Failed;
+                return Err(WriterError::InvalidState(
+                    "[request {req_id}][send_response] Cannot stream a chunk in current state"
+                        .into(),
+                ));
+            }
+        }
+        if data.is_empty() || self.head_only {
+            return Ok(());
+        }
+
+        let chunk_header = format!("{:x}\r\n", data.len());
+        let parts: [&[u8]; 3] = [chunk_header.as_bytes(), data, b"\r\n"];
+        write_all_vectored(self.stream, &parts, &mut self.sent).map_err(|e| {
+            self.state = WriterState::Failed;
+            WriterError::IoError(e)
+        })
+    }
+
+    /// Complete the writing process by sending whatever is left of the response in chunked transfer encoding, then the last chunk
+    pub fn complete_write(&mut self) -> Result<(), WriterError> {
         // Empty body allowed in chunked encoding
-        if self.state != WriterState::BodyWritten && self.state != WriterState::HeadersClosed {
+        if !matches!(
+            self.state,
+            WriterState::BodyWritten | WriterState::HeadersClosed | WriterState::Streaming
+        ) {
             return Err(WriterError::InvalidState(
                 "[request {req_id}][send_response] Cannot complete write in current state".into(),
             ));
         }
 
-        let status_line = self.status_line.ok_or_else(|| {
+        if self.head_only {
+            if self.state != WriterState::Streaming {
+                self.start_streaming()?;
+            }
+            self.stream.flush().map_err(WriterError::IoError)?;
+            return Ok(());
+        }
+
+        if self.state != WriterState::Streaming {
+            let Some(streamed) = self.streamed.take() else {
+                // The head, the body's only chunk, and the last-chunk go out in as few writes as
+                // the socket allows, instead of one per line
+                let head = self.head()?;
+                let body = self.body.as_deref().unwrap_or_default();
+                let chunk_header = format!("{:x}\r\n", body.len());
+                let mut parts: Vec<&[u8]> = vec![head.as_bytes()];
+                if !body.is_empty() {
+                    parts.extend([chunk_header.as_bytes(), body, b"\r\n"]);
+                }
+                parts.push(b"0\r\n\r\n");
+                write_all_vectored(self.stream, &parts, &mut self.sent)
+                    .map_err(WriterError::IoError)?;
+                self.stream.flush().map_err(WriterError::IoError)?;
+                return Ok(());
+            };
+
+            // A streamed body follows the head as one chunk per read from its source
+            self.start_streaming()?;
+            match streamed.encoding() {
+                Some((encoding, levels)) => {
+                    write_encoded(self.stream, &streamed, encoding, levels, &mut self.sent)?
+                }
+                None => streamed.for_each_chunk(|chunk| self.stream_chunk(chunk))?,
+            }
+        }
+
+        write_all_vectored(self.stream, &[b"0\r\n\r\n"], &mut self.sent)
+            .map_err(WriterError::IoError)?;
+        self.stream.flush().map_err(WriterError::IoError)?;
+
+        Ok(())
+    }
+
+    /// Sends the status line and headers, after which the body can only go out chunk by chunk
+    fn start_streaming(&mut self) -> Result<(), WriterError> {
+        let head = self.head()?;
+        write_all_vectored(self.stream, &[head.as_bytes()], &mut self.sent)
+            .map_err(WriterError::IoError)?;
+        self.state = WriterState::Streaming;
+        Ok(())
+    }
+
+    /// Checks the status line and headers allow chunked framing, and renders them
+    fn head(&mut self) -> Result<String, WriterError> {
+        let status_line = self.status_line.take().ok_or_else(|| {
             WriterError::InvalidState(
                 "[request {req_id}][send_response] Status line must be set before completing write"
                     .into(),
@@ -137,36 +263,235 @@ impl<'a> ChunkedWriter<'a> {
             ));
         }
 
-        write!(self.stream, "{}", status_line).map_err(WriterError::IoError)?;
+        check_header_size(&status_line, &self.headers)?;
 
+        let mut head = status_line;
         for (key, value) in &self.headers {
-            write!(self.stream, "{}: {}\r\n", key, value).map_err(WriterError::IoError)?;
+            head.push_str(&format!("{}: {}\r\n", key, value));
         }
-        write!(self.stream, "\r\n").map_err(WriterError::IoError)?;
+        head.push_str("\r\n");
+        Ok(head)
+    }
+
+    /// Returns how many bytes of the response the socket has accepted, head and framing included
+    pub fn bytes_sent(&self) -> u64 {
+        self.sent
+    }
+}
 
-        let body_opt = self.body.clone();
-        if let Some(body) = body_opt {
-            Self::write_chunk(self.stream, &body)?;
+/// Compresses a streamed body as it is read; each read's output is flushed as its own chunk, so
+/// the compressed body is never held whole and the client can decode what has arrived
+fn write_encoded(
+    stream: &mut dyn ClientStream,
+    body: &BodyStream,
+    encoding: &HttpEncoding,
+    levels: CompressionLevels,
+    sent: &mut u64,
+) -> Result<(), WriterError> {
+    let sink = ChunkSink::new(stream, sent);
+    let Some(mut encoder) = StreamEncoder::new(encoding, levels, sink)? else {
+        return Err(WriterError::InvalidState(
+            "A streamed body cannot be encoded as identity".into(),
+        ));
+    };
+    body.for_each_chunk(|chunk| {
+        encoder.write_all(chunk)?;
+        Ok(encoder.flush()?)
+    })?;
+    Ok(encoder.finish()?.flush()?)
+}
+
+/// Frames what an encoder writes into chunks, collecting its many small writes until it flushes
+struct ChunkSink<'s> {
+    stream: &'s mut dyn ClientStream,
+    sent: &'s mut u64,
+    pending: Vec<u8>, // Encoded bytes not yet sent as a chunk
+}
+
+impl<'s> ChunkSink<'s> {
+    fn new(stream: &'s mut dyn ClientStream, sent: &'s mut u64) -> Self {
+        ChunkSink {
+            stream,
+            sent,
+            pending: Vec::new(),
         }
+    }
+}
 
-        write!(self.stream, "0\r\n\r\n").map_err(WriterError::IoError)?;
-        self.stream.flush().map_err(WriterError::IoError)?;
+impl Write for ChunkSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        if self.pending.len() >= STREAM_CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
 
+    /// Sends what is pending as one chunk; an empty chunk would end the body, so none is sent
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let chunk_header = format!("{:x}\r\n", self.pending.len());
+        let parts: [&[u8]; 3] = [chunk_header.as_bytes(), &self.pending, b"\r\n"];
+        write_all_vectored(self.stream, &parts, self.sent)?;
+        self.pending.clear();
         Ok(())
     }
+}
 
-    /// Write a chunk of data in chunked transfer encoding
-    fn write_chunk(stream: &mut TcpStream, data: &[u8]) -> Result<(), WriterError> {
-        let chunk_size = data.len();
-        let chunk_header = format!("{:x}\r\n", chunk_size);
-        stream
-            .write_all(chunk_header.as_bytes())
-            .map_err(WriterError::IoError)?;
+/// Writes every part in order, resuming after short writes; adds what was written to `sent`
+fn write_all_vectored(
+    stream: &mut dyn ClientStream,
+    mut parts: &[&[u8]],
+    sent: &mut u64,
+) -> io::Result<()> {
+    let mut offset = 0; // Bytes of parts[0] already written
+    while let Some(first) = parts.first() {
+        let slices: Vec<IoSlice> = std::iter::once(IoSlice::new(&first[offset..]))
+            .chain(parts[1..].iter().map(|part| IoSlice::new(part)))
+            .collect();
+        let mut written = match stream.write_vectored(&slices) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                *sent += n as u64;
+                n
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        while let Some(first) = parts.first() {
+            let left = first.len() - offset;
+            if written < left {
+                offset += written;
+                break;
+            }
+            written -= left;
+            offset = 0;
+            parts = &parts[1..];
+        }
+    }
+    Ok(())
+}
 
-        let chunk_data = &data[..chunk_size];
-        stream.write_all(chunk_data).map_err(WriterError::IoError)?;
-        stream.write_all(b"\r\n").map_err(WriterError::IoError)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::config::ServerConfig;
+    use std::{
+        io::Read,
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    #[test]
+    fn test_vectored_write_survives_short_writes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        // Larger than a socket buffer, so the kernel accepts it a piece at a time
+        let body = vec![b'x'; 4 * 1024 * 1024];
+        let expected_len = body.len();
+        let sender = thread::spawn(move || {
+            let mut writer = ChunkedWriter::new(&mut server);
+            writer
+                .write_status_line(HttpVersion::Http1_1, HttpStatusCode::Ok)
+                .unwrap();
+            writer
+                .write_header("Transfer-Encoding".into(), "chunked".into())
+                .unwrap();
+            writer.finish_headers().unwrap();
+            writer.write_body(&body).unwrap();
+            writer.complete_write().unwrap();
+        });
+
+        let mut sent = Vec::new();
+        client.read_to_end(&mut sent).unwrap();
+        sender.join().unwrap();
+
+        let head = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n400000\r\n";
+        assert!(sent.starts_with(head));
+        assert!(sent.ends_with(b"x\r\n0\r\n\r\n"));
+        assert_eq!(sent.len(), head.len() + expected_len + 7);
+    }
 
-        Ok(())
+    /// Sends a body through a ChunkedWriter with `send`, and returns the chunks that arrived
+    fn send_chunks(send: impl FnOnce(&mut ChunkedWriter<'_>) + Send + 'static) -> Vec<Vec<u8>> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let sender = thread::spawn(move || {
+            let mut writer = ChunkedWriter::new(&mut server);
+            writer
+                .write_status_line(HttpVersion::Http1_1, HttpStatusCode::Ok)
+                .unwrap();
+            writer
+                .write_header("Transfer-Encoding".into(), "chunked".into())
+                .unwrap();
+            writer.finish_headers().unwrap();
+            send(&mut writer);
+            writer.complete_write().unwrap();
+        });
+
+        let mut sent = Vec::new();
+        client.read_to_end(&mut sent).unwrap();
+        sender.join().unwrap();
+
+        let head = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert!(sent.starts_with(head));
+        let (mut rest, mut chunks) = (&sent[head.len()..], Vec::new());
+        loop {
+            let line_end = rest.windows(2).position(|w| w == b"\r\n").unwrap();
+            let size = std::str::from_utf8(&rest[..line_end]).unwrap();
+            let size = usize::from_str_radix(size, 16).unwrap();
+            rest = &rest[line_end + 2..];
+            if size == 0 {
+                assert_eq!(rest, b"\r\n");
+                return chunks;
+            }
+            chunks.push(rest[..size].to_vec());
+            rest = &rest[size + 2..];
+        }
+    }
+
+    #[test]
+    fn test_encoded_stream_is_compressed_chunk_by_chunk() {
+        let text: Vec<u8> = (0..3 * STREAM_CHUNK_SIZE)
+            .map(|i| b'a' + (i % 7) as u8)
+            .collect();
+
+        for encoding in [HttpEncoding::Gzip, HttpEncoding::Brotli] {
+            let levels = ServerConfig::default().compression.levels();
+            let body = BodyStream::from_bytes(text.clone()).encoded(encoding.clone(), levels);
+            let chunks = send_chunks(move |writer| writer.write_stream(body).unwrap());
+            // One flush per read of the source, then the end of the compressed stream
+            assert!(chunks.len() > 3, "{}: {} chunks", encoding, chunks.len());
+
+            let compressed = chunks.concat();
+            let mut decoded = Vec::new();
+            match encoding {
+                HttpEncoding::Gzip => libflate::gzip::Decoder::new(&compressed[..])
+                    .unwrap()
+                    .read_to_end(&mut decoded),
+                _ => brotli::Decompressor::new(&compressed[..], 4096).read_to_end(&mut decoded),
+            }
+            .unwrap();
+            assert_eq!(decoded, text, "{}", encoding);
+        }
+    }
+
+    #[test]
+    fn test_stream_chunk_sends_each_chunk_as_it_comes() {
+        let chunks = send_chunks(|writer| {
+            for event in ["data: one\n\n", "", "data: two\n\n"] {
+                writer.stream_chunk(event.as_bytes()).unwrap();
+            }
+        });
+        assert_eq!(
+            chunks,
+            [b"data: one\n\n".to_vec(), b"data: two\n\n".to_vec()]
+        );
     }
 }
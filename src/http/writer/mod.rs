@@ -1,8 +1,14 @@
 pub mod chunked;
+#[cfg(target_os = "linux")]
+pub mod sendfile;
 pub mod traits;
 pub mod types;
 pub mod standard;
 
 pub use traits::HttpWritable;
-pub use types::{HttpBody};
-pub use standard::{send_response, HttpWriter};
\ No newline at end of file
+pub use types::{
+    validate_header, write_counted, BodyStream, HttpBody, WriteReport, WriterError, WrittenResponse,
+};
+pub use standard::{
+    init, send_head_observed, send_observed, send_response, HttpWriter, DEFAULT_MAX_HEADER_BYTES,
+};
\ No newline at end of file
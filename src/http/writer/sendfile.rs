@@ -0,0 +1,72 @@
+use std::{fs::File, io, net::TcpStream, os::fd::AsRawFd};
+
+/// Most bytes asked of a single sendfile call; Linux stops just short of 2 GiB per call anyway
+const MAX_PER_CALL: u64 = 1 << 30;
+
+/// Copies up to `len` bytes from the file's current position to the socket without passing
+/// them through userspace, stopping early if the file runs out
+///
+/// Each byte sent is added to `sent` as it goes, so a failed copy still reports its progress
+pub fn copy(file: &File, socket: &TcpStream, len: u64, sent: &mut u64) -> io::Result<()> {
+    let mut copied_total = 0;
+    while copied_total < len {
+        let count = (len - copied_total).min(MAX_PER_CALL) as usize;
+        // SAFETY: both descriptors are borrowed from handles that stay open for the call, and a
+        // null offset makes the kernel read from, and advance, the file's own position
+        let copied = unsafe {
+            libc::sendfile(
+                socket.as_raw_fd(),
+                file.as_raw_fd(),
+                std::ptr::null_mut(),
+                count,
+            )
+        };
+        match copied {
+            0 => break, // The file is shorter than it was when the response was built
+            copied if copied > 0 => {
+                copied_total += copied as u64;
+                *sent += copied as u64;
+            }
+            _ => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::test_support::TempRoot;
+    use std::{
+        io::{Read, Seek, SeekFrom},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn test_copies_from_the_file_position() {
+        let root = TempRoot::new("sendfile");
+        let path = root.write("digits.txt", b"0123456789");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let mut file = File::open(&path).unwrap();
+        file.seek(SeekFrom::Start(3)).unwrap();
+        let mut sent = 0;
+        copy(&file, &server, 4, &mut sent).unwrap();
+        assert_eq!(sent, 4);
+        // Past the end of the file, only what is left is sent
+        copy(&file, &server, 10, &mut sent).unwrap();
+        assert_eq!(sent, 7);
+        drop(server);
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "3456789");
+    }
+}
@@ -1,34 +1,129 @@
 use std::collections::HashMap;
-use std::io::Write;
-use std::net::TcpStream;
+use std::sync::OnceLock;
+use std::time::SystemTime;
 use titlecase::Titlecase;
 
 use super::chunked::ChunkedWriter;
 use super::traits::HttpWritable;
-use super::types::{ChunkedDecision, HttpBody, WriterError, WriterState};
-use crate::http::request::HttpVersion;
+use super::types::{
+    validate_header, write_counted, BodyStream, ChunkedDecision, HttpBody, WriteReport,
+    WriterError, WriterState, WrittenResponse,
+};
+use crate::http::date;
+use crate::http::errors::{self, HttpErrorResponse};
+use crate::http::logging::{server_log, Fields};
+use crate::http::metrics::{self, Counter};
+use crate::http::request::{id::REQUEST_ID_HEADER, HttpVersion, RequestId};
 use crate::http::response::HttpStatusCode;
+use crate::http::stream::ClientStream;
+
+/// Default cap on a response's serialized status line and headers (64KB)
+pub const DEFAULT_MAX_HEADER_BYTES: usize = 64 * 1024;
+
+static MAX_HEADER_BYTES: OnceLock<usize> = OnceLock::new();
+
+/// Sets the response header size cap; without this call the default applies
+pub fn init(max_header_bytes: usize) {
+    let _ = MAX_HEADER_BYTES.set(max_header_bytes);
+}
+
+/// Fails with `HeadersTooLarge` if the serialized head would exceed the configured cap
+pub(super) fn check_header_size(
+    status_line: &str,
+    headers: &HashMap<String, String>,
+) -> Result<(), WriterError> {
+    let limit = *MAX_HEADER_BYTES.get().unwrap_or(&DEFAULT_MAX_HEADER_BYTES);
+    let size = status_line.len()
+        + headers
+            .iter()
+            .map(|(key, value)| key.len() + value.len() + 4) // ": " and CRLF
+            .sum::<usize>()
+        + 2;
+    if size > limit {
+        return Err(WriterError::HeadersTooLarge { size, limit });
+    }
+    Ok(())
+}
 
 /// Represents an HTTP response writer
 pub struct HttpWriter<'a> {
-    stream: &'a mut TcpStream,
+    stream: &'a mut dyn ClientStream,
     state: WriterState,
     status_line: Option<String>,
     headers: HashMap<String, String>,
+    body_allowed: bool, // False for 204 and 304, which are sent without Content-Length
+    streamed: Option<BodyStream>, // Copied from its source once the head is written
     body: Option<Vec<u8>>,
     // TODO: Trailers eventually
+    sent: u64,       // Bytes the socket has accepted so far
+    head_only: bool, // Answering HEAD: the head describes the GET response, but no body is sent
 }
 
 impl<'a> HttpWriter<'a> {
     /// Creates a new HttpWriter
-    pub fn new(stream: &'a mut TcpStream) -> Self {
+    pub fn new(stream: &'a mut dyn ClientStream) -> Self {
         HttpWriter {
             stream,
             state: WriterState::Initial,
             status_line: None,
             headers: HashMap::new(),
+            body_allowed: true,
             body: None,
+            streamed: None,
+            sent: 0,
+            head_only: false,
+        }
+    }
+
+    /// Creates a writer for a HEAD response, which sends the head GET would get but no body
+    ///
+    /// Content-Length is sent as set, without checking it against the body
+    pub fn for_head(stream: &'a mut dyn ClientStream) -> Self {
+        let mut writer = HttpWriter::new(stream);
+        writer.head_only = true;
+        writer
+    }
+
+    /// Sends a 1xx interim response, such as 100 Continue or 103 Early Hints, right away
+    ///
+    /// Any number may precede the status line. HTTP/1.0 clients do not expect them, so nothing is
+    /// sent to those
+    pub fn write_interim(
+        &mut self,
+        version: HttpVersion,
+        status: HttpStatusCode,
+        headers: &HashMap<String, String>,
+    ) -> Result<(), WriterError> {
+        if !matches!(self.state, WriterState::Initial | WriterState::InterimSent) {
+            self.state = WriterState::Failed;
+            return Err(WriterError::InvalidState(
+                "Can only write interim responses before the Status Line".to_string(),
+            ));
+        }
+        // 101 Switching Protocols ends HTTP on the connection, so it is a final response
+        if !(100..200).contains(&status.code()) || status.code() == 101 {
+            self.state = WriterState::Failed;
+            return Err(WriterError::InvalidState(format!(
+                "{} is not an interim status",
+                status
+            )));
         }
+        if version == HttpVersion::Http1_0 {
+            return Ok(());
+        }
+
+        let mut head = format!("{} {}\r\n", version, status);
+        check_header_size(&head, headers)?;
+        for (key, value) in headers {
+            validate_header(key, value)?;
+            head.push_str(&format!("{}: {}\r\n", key.titlecase(), value));
+        }
+        head.push_str("\r\n");
+        write_counted(self.stream, head.as_bytes(), &mut self.sent)?;
+        self.stream.flush()?;
+        self.state = WriterState::InterimSent;
+
+        Ok(())
     }
 
     /// Writes the status line to the HTTP response
@@ -37,7 +132,7 @@ impl<'a> HttpWriter<'a> {
         version: HttpVersion,
         status: HttpStatusCode,
     ) -> Result<(), WriterError> {
-        if self.state != WriterState::Initial {
+        if !matches!(self.state, WriterState::Initial | WriterState::InterimSent) {
             self.state = WriterState::Failed;
 
             return Err(WriterError::InvalidState(
@@ -45,6 +140,7 @@ impl<'a> HttpWriter<'a> {
             ));
         }
 
+        self.body_allowed = status.allows_body();
         let status_line = format!("{} {}\r\n", version, status);
         self.status_line = Some(status_line);
 
@@ -61,6 +157,10 @@ impl<'a> HttpWriter<'a> {
                 "Can only write headers in StatusWritten or HeadersOpen state".to_string(),
             ));
         }
+        if let Err(e) = validate_header(&a, &b) {
+            self.state = WriterState::Failed;
+            return Err(e);
+        }
         self.state = WriterState::HeadersOpen;
 
         let normalized_key = a.titlecase();
@@ -101,8 +201,24 @@ impl<'a> HttpWriter<'a> {
         Ok(())
     }
 
+    /// Sets a body that is copied from its source in fixed-size chunks when the response completes
+    pub fn write_stream(&mut self, body: BodyStream) -> Result<(), WriterError> {
+        if self.state != WriterState::HeadersClosed {
+            self.state = WriterState::Failed;
+            return Err(WriterError::InvalidState(
+                "Can only write body in HeadersClosed state".to_string(),
+            ));
+        }
+
+        self.streamed = Some(body);
+
+        self.state = WriterState::BodyWritten;
+
+        Ok(())
+    }
+
     /// Completes the HTTP response writing, ensuring all parts are valid and written
-    pub fn complete_write(self) -> Result<(), WriterError> {
+    pub fn complete_write(&mut self) -> Result<(), WriterError> {
         if self.state != WriterState::BodyWritten && self.state != WriterState::HeadersClosed {
             return Err(WriterError::InvalidState(
                 "Can only complete in BodyWritten state".to_string(),
@@ -115,92 +231,259 @@ impl<'a> HttpWriter<'a> {
             ));
         }
 
-        if self.headers.contains_key("Content-Length") {
-            let body_len: usize = self.body.as_ref().map_or(0, |b| b.len());
-            let content_length = self
-                .headers
-                .get("Content-Length")
-                .unwrap()
-                .parse::<usize>()
-                .map_err(|_| {
-                    WriterError::InvalidHeader("Content-Length must be a valid number".to_string())
-                })?;
-
-            if content_length != body_len {
+        let body_len: usize = match &self.streamed {
+            Some(streamed) => streamed.byte_len() as usize,
+            None => self.body.as_ref().map_or(0, |b| b.len()),
+        };
+        if !self.body_allowed {
+            if body_len > 0 {
+                return Err(WriterError::InvalidState(
+                    "204 and 304 responses cannot carry a body".to_string(),
+                ));
+            }
+        } else if let Some(content_length) = self.headers.get("Content-Length") {
+            let content_length = content_length.parse::<usize>().map_err(|_| {
+                WriterError::InvalidHeader("Content-Length must be a valid number".to_string())
+            })?;
+
+            if content_length != body_len && !self.head_only {
                 return Err(WriterError::ContentLengthMismatch {
                     declared: content_length,
                     actual: body_len,
                 });
             }
+        } else {
+            return Err(WriterError::MissingHeader(
+                "Content-Length header is required".to_string(),
+            ));
+        }
 
-            self.stream
-                .write_all(self.status_line.as_ref().unwrap().as_bytes())?;
-            for (key, value) in &self.headers {
-                self.stream
-                    .write_all(format!("{}: {}\r\n", key, value).as_bytes())?;
-            }
-
-            self.stream.write_all(b"\r\n")?;
-            if self.body.is_some() {
-                self.stream
-                    .write_all(self.body.as_ref().unwrap().as_slice())?;
-            }
+        check_header_size(self.status_line.as_ref().unwrap(), &self.headers)?;
+
+        let sent = &mut self.sent;
+        write_counted(
+            self.stream,
+            self.status_line.as_ref().unwrap().as_bytes(),
+            sent,
+        )?;
+        for (key, value) in &self.headers {
+            write_counted(
+                self.stream,
+                format!("{}: {}\r\n", key, value).as_bytes(),
+                sent,
+            )?;
+        }
 
+        write_counted(self.stream, b"\r\n", sent)?;
+        if self.head_only {
             self.stream.flush()?;
-
-            Ok(())
-        } else {
-            Err(WriterError::MissingHeader(
-                "Content-Length header is required".to_string(),
-            ))
+            return Ok(());
+        }
+        if let Some(body) = &self.body {
+            write_counted(self.stream, body.as_slice(), sent)?;
         }
+        if let Some(streamed) = &self.streamed {
+            streamed.write_to(self.stream, sent)?;
+        }
+
+        self.stream.flush()?;
+
+        Ok(())
+    }
+
+    /// Returns how many bytes of the response the socket has accepted, head included
+    pub fn bytes_sent(&self) -> u64 {
+        self.sent
     }
 
     /// Logs WriterError with specific context for each error variant
-    pub fn log_writer_error(error: WriterError, context: &str) {
-        match error {
-            WriterError::InvalidState(msg) => {
-                eprintln!("[{}] State machine violation: {}", context, msg);
-            }
-            WriterError::ContentLengthMismatch { declared, actual } => {
-                eprintln!("[{}] Content-Length mismatch! Declared: {}, Actual: {} - Response will be malformed!",
-                    context, declared, actual);
-            }
-            WriterError::MissingHeader(header) => {
-                eprintln!("[{}] Required header missing: {}", context, header);
-            }
+    pub fn log_writer_error(error: WriterError, context: &str, req_id: RequestId) {
+        let detail = match error {
+            WriterError::InvalidState(msg) => format!("State machine violation: {}", msg),
+            WriterError::ContentLengthMismatch { declared, actual } => format!(
+                "Content-Length mismatch! Declared: {}, Actual: {} - Response will be malformed!",
+                declared, actual
+            ),
+            WriterError::MissingHeader(header) => format!("Required header missing: {}", header),
             WriterError::IoError(io_err) => {
-                eprintln!(
-                    "[{}] Network/IO error: {} - Connection may be broken",
-                    context, io_err
-                );
-            }
-            WriterError::InvalidHeader(msg) => {
-                eprintln!("[{}] Invalid header format: {}", context, msg);
+                format!("Network/IO error: {} - Connection may be broken", io_err)
             }
-        }
+            WriterError::InvalidHeader(msg) => format!("Invalid header format: {}", msg),
+            WriterError::HeadersTooLarge { size, limit } => format!(
+                "Response headers are {} bytes, over the {} byte limit",
+                size, limit
+            ),
+        };
+        let fields = Fields {
+            event: Some("writer_error"),
+            ..Fields::default()
+        };
+        server_log!(
+            Error,
+            req = req_id,
+            fields = fields,
+            "[{}] {}",
+            context,
+            detail
+        );
     }
 }
 
-/// Sends an HTTP response over the given TcpStream
+/// Sends an HTTP response over the given stream
+///
+/// A response whose headers exceed the size cap, or hold one `validate_header` refuses, is replaced
+/// by a plain 500, so a runaway handler or header configuration cannot flood or split the response
 pub fn send_response<T: HttpWritable>(
-    stream: &mut TcpStream,
+    stream: &mut dyn ClientStream,
     response: T,
-    req_id: u64,
+    req_id: RequestId,
 ) -> Result<(), WriterError> {
+    send_observed(stream, response, req_id, |_| {}).into_result()
+}
+
+/// Adds the headers every response carries: the request id, and a Date unless one was set
+fn stamp(headers: &mut HashMap<String, String>, req_id: RequestId) {
+    headers.insert(REQUEST_ID_HEADER.to_string(), req_id.token());
+    if get_header_ci(headers, "Date").is_none() {
+        headers.insert("Date".to_string(), date::http_date(SystemTime::now()));
+    }
+}
+
+/// Sends a response as `send_response` does, then hands what was written to `observe`
+///
+/// `observe` only runs once the response was written in full; for an oversized or invalid response
+/// it sees the 500 that replaced it. The report says how much reached the socket either way
+pub fn send_observed<T: HttpWritable>(
+    stream: &mut dyn ClientStream,
+    response: T,
+    req_id: RequestId,
+    observe: impl FnOnce(&WrittenResponse<'_>),
+) -> WriteReport {
+    send_with(stream, response, req_id, false, observe)
+}
+
+/// Answers a HEAD request as `send_observed` does, sending the response's head without its body
+///
+/// The response is the one GET would get, or one with the same headers and an empty body
+pub fn send_head_observed<T: HttpWritable>(
+    stream: &mut dyn ClientStream,
+    response: T,
+    req_id: RequestId,
+    observe: impl FnOnce(&WrittenResponse<'_>),
+) -> WriteReport {
+    send_with(stream, response, req_id, true, observe)
+}
+
+fn send_with<T: HttpWritable>(
+    stream: &mut dyn ClientStream,
+    response: T,
+    req_id: RequestId,
+    head_only: bool,
+    observe: impl FnOnce(&WrittenResponse<'_>),
+) -> WriteReport {
     let version = response.status_line().version.clone();
     let status = response.status_line().status.clone();
-    let headers = response.headers();
+    let mut headers = response.headers();
+    stamp(&mut headers, req_id);
+    let connection = get_header_ci(&headers, "Connection")
+        .unwrap_or_default()
+        .to_string();
+
+    let body = response.body();
+    let report = write_response(
+        stream,
+        version.clone(),
+        status.clone(),
+        &headers,
+        &body,
+        req_id,
+        head_only,
+    );
+    let reason = match &report.error {
+        Some(WriterError::HeadersTooLarge { size, limit }) => {
+            server_log!(
+                Error,
+                req = req_id,
+                "[send_response] {} response has {} bytes of headers, over the {} byte limit; sending 500",
+                status,
+                size,
+                limit
+            );
+            Some("Response headers too large")
+        }
+        Some(WriterError::InvalidHeader(msg)) if report.bytes == 0 => {
+            server_log!(
+                Error,
+                req = req_id,
+                "[send_response] {} response has an invalid header ({}); sending 500",
+                status,
+                msg
+            );
+            Some("Response header invalid")
+        }
+        _ => None,
+    };
+    match reason {
+        Some(reason) => {
+            let status = HttpStatusCode::InternalServerError;
+            let message = errors::with_reference(&status, reason.to_string(), req_id);
+            let fallback =
+                HttpErrorResponse::new(status.clone(), version.clone(), &connection, None, message);
+            let mut headers = fallback.headers();
+            stamp(&mut headers, req_id);
+            metrics::incr(Counter::for_status(&status));
+            let body = fallback.body();
+            let report = write_response(
+                stream,
+                version,
+                status.clone(),
+                &headers,
+                &body,
+                req_id,
+                head_only,
+            );
+            if report.error.is_none() {
+                observe(&WrittenResponse {
+                    status: &status,
+                    headers: &headers,
+                    body: &body,
+                });
+            }
+            report
+        }
+        _ => {
+            metrics::incr(Counter::for_status(&status));
+            if report.error.is_none() {
+                observe(&WrittenResponse {
+                    status: &status,
+                    headers: &headers,
+                    body: &body,
+                });
+            }
+            report
+        }
+    }
+}
 
-    let decision = decide_chunking(&version, &headers);
+/// Writes a response with the writer its framing calls for; `head_only` leaves out the body
+fn write_response(
+    stream: &mut dyn ClientStream,
+    version: HttpVersion,
+    status: HttpStatusCode,
+    headers: &HashMap<String, String>,
+    body: &HttpBody,
+    req_id: RequestId,
+    head_only: bool,
+) -> WriteReport {
+    let decision = decide_chunking(&version, headers);
     if let Some(msg) = &decision.warning {
-        eprintln!("[request {}][send_response] {}", req_id, msg);
+        server_log!(Warn, req = req_id, "[send_response] {}", msg);
     }
 
     if decision.use_chunked {
         let mut effective: HashMap<String, String> = HashMap::new();
         let mut transfer_tokens: Vec<String> = Vec::new();
-        for (k, v) in &headers {
+        for (k, v) in headers {
             if k.eq_ignore_ascii_case("Content-Length") {
                 continue;
             }
@@ -219,45 +502,71 @@ pub fn send_response<T: HttpWritable>(
 
         effective.insert("Transfer-Encoding".to_string(), transfer_tokens.join(", "));
 
-        let mut writer = ChunkedWriter::new(stream);
-
-        writer.write_status_line(version, status)?;
-
-        for (k, v) in effective {
-            writer.write_header(k, v)?;
-        }
-        writer.finish_headers()?;
+        let mut writer = if head_only {
+            ChunkedWriter::for_head(stream)
+        } else {
+            ChunkedWriter::new(stream)
+        };
+        let result = write_chunked(&mut writer, version, status, effective, body);
+        WriteReport::new(writer.bytes_sent(), result)
+    } else {
+        let mut writer = if head_only {
+            HttpWriter::for_head(stream)
+        } else {
+            HttpWriter::new(stream)
+        };
+        let result = write_plain(&mut writer, version, status, headers, body);
+        WriteReport::new(writer.bytes_sent(), result)
+    }
+}
 
-        match response.body() {
-            HttpBody::Text(text) => writer.write_body(text.as_bytes())?,
-            HttpBody::Binary(bytes) => writer.write_body(&bytes)?,
-        }
+fn write_chunked(
+    writer: &mut ChunkedWriter<'_>,
+    version: HttpVersion,
+    status: HttpStatusCode,
+    headers: HashMap<String, String>,
+    body: &HttpBody,
+) -> Result<(), WriterError> {
+    writer.write_status_line(version, status)?;
 
-        writer.complete_write()?;
+    for (k, v) in headers {
+        writer.write_header(k, v)?;
+    }
+    writer.finish_headers()?;
 
-        Ok(())
-    } else {
-        let mut writer = HttpWriter::new(stream);
+    match body {
+        HttpBody::Text(text) => writer.write_body(text.as_bytes())?,
+        HttpBody::Binary(bytes) => writer.write_body(bytes)?,
+        HttpBody::Stream(streamed) => writer.write_stream(streamed.clone())?,
+    }
 
-        writer.write_status_line(version, status)?;
+    writer.complete_write()
+}
 
-        for (k, v) in &headers {
-            if k.eq_ignore_ascii_case("Transfer-Encoding") {
-                continue;
-            }
-            writer.write_header(k.clone(), v.clone())?;
-        }
-        writer.finish_headers()?;
+fn write_plain(
+    writer: &mut HttpWriter<'_>,
+    version: HttpVersion,
+    status: HttpStatusCode,
+    headers: &HashMap<String, String>,
+    body: &HttpBody,
+) -> Result<(), WriterError> {
+    writer.write_status_line(version, status)?;
 
-        match response.body() {
-            HttpBody::Text(text) => writer.write_body(text.as_bytes())?,
-            HttpBody::Binary(bytes) => writer.write_body(&bytes)?,
+    for (k, v) in headers {
+        if k.eq_ignore_ascii_case("Transfer-Encoding") {
+            continue;
         }
+        writer.write_header(k.clone(), v.clone())?;
+    }
+    writer.finish_headers()?;
 
-        writer.complete_write()?;
-
-        Ok(())
+    match body {
+        HttpBody::Text(text) => writer.write_body(text.as_bytes())?,
+        HttpBody::Binary(bytes) => writer.write_body(bytes)?,
+        HttpBody::Stream(streamed) => writer.write_stream(streamed.clone())?,
     }
+
+    writer.complete_write()
 }
 
 /// Gets a header value by key, case-insensitively
@@ -323,3 +632,188 @@ fn decide_chunking(version: &HttpVersion, headers: &HashMap<String, String>) ->
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{
+        request::{RequestId, RequestIdGenerator},
+        response::{HttpResponse, ResponseStatusLine},
+        test_support::{duplex, MemoryStream},
+        writer::types::STREAM_CHUNK_SIZE,
+    };
+    use std::io::Read;
+
+    /// Runs `send` against the server end of an in-memory connection and returns every byte
+    /// the client end received
+    fn sent_by(send: impl FnOnce(&mut MemoryStream, RequestId)) -> Vec<u8> {
+        let (mut client, mut server) = duplex();
+        send(&mut server, RequestIdGenerator::new().next());
+        drop(server);
+
+        let mut sent = Vec::new();
+        client.read_to_end(&mut sent).unwrap();
+        sent
+    }
+
+    /// Sends `response` with `send_response` and returns what arrived, as text
+    fn sent_text(response: HttpResponse) -> String {
+        let sent = sent_by(|stream, req_id| send_response(stream, response, req_id).unwrap());
+        String::from_utf8(sent).unwrap()
+    }
+
+    fn response(
+        status: HttpStatusCode,
+        headers: HashMap<String, String>,
+        body: Option<HttpBody>,
+    ) -> HttpResponse {
+        let status_line = ResponseStatusLine {
+            version: HttpVersion::Http1_1,
+            status,
+        };
+        HttpResponse::new(status_line, headers, body)
+    }
+
+    #[test]
+    fn test_oversized_headers_are_replaced_by_500() {
+        let headers = HashMap::from([
+            ("Content-Length".to_string(), "2".to_string()),
+            ("X-Bloat".to_string(), "a".repeat(DEFAULT_MAX_HEADER_BYTES)),
+        ]);
+        let body = Some(HttpBody::Text("ok".into()));
+        let sent = sent_text(response(HttpStatusCode::Ok, headers, body));
+
+        assert!(sent.starts_with("HTTP/1.1 500"), "{}", sent);
+        assert!(!sent.contains("X-Bloat"));
+        assert!(
+            sent.contains("\r\nDate: ") && sent.contains(" GMT\r\n"),
+            "{}",
+            sent
+        );
+        assert!(sent.len() < 1024);
+    }
+
+    #[test]
+    fn test_headers_that_would_split_the_response_are_replaced_by_500() {
+        let cases = [
+            ("X-Note", "Content-Length", "2"),
+            ("X Note", "Transfer-Encoding", "chunked"),
+        ];
+        for (name, framing, framing_value) in cases {
+            let headers = HashMap::from([
+                (framing.to_string(), framing_value.to_string()),
+                (
+                    name.to_string(),
+                    "a\r\nSet-Cookie: session=evil".to_string(),
+                ),
+            ]);
+            let body = Some(HttpBody::Text("ok".into()));
+            let sent = sent_text(response(HttpStatusCode::Ok, headers, body));
+
+            assert!(sent.starts_with("HTTP/1.1 500"), "{}", sent);
+            assert!(!sent.contains("Set-Cookie"), "{}", sent);
+        }
+        assert!(matches!(
+            validate_header("X-Note", "a\0b"),
+            Err(WriterError::InvalidHeader(_))
+        ));
+        assert!(validate_header("X-Note", "a, b; q=\"c\"").is_ok());
+    }
+
+    #[test]
+    fn test_bodiless_statuses_are_sent_without_content_length() {
+        for status in [HttpStatusCode::NotModified, HttpStatusCode::NoContent] {
+            let headers = HashMap::from([("ETag".to_string(), "W/\"40-1\"".to_string())]);
+            let sent = sent_text(response(status.clone(), headers, None));
+
+            let expected = format!("HTTP/1.1 {}\r\n", status);
+            assert!(sent.starts_with(&expected), "{}", sent);
+            assert!(sent.contains("\r\nETag: W/\"40-1\"\r\n"), "{}", sent);
+            assert!(!sent.contains("Content-Length"), "{}", sent);
+            assert!(sent.ends_with("\r\n\r\n"), "{}", sent);
+        }
+    }
+
+    #[test]
+    fn test_head_responses_keep_the_get_framing_without_a_body() {
+        let cases = [
+            ("Content-Length", "11", HttpBody::Text(String::new())),
+            ("Content-Length", "11", HttpBody::Text("hello world".into())),
+            (
+                "Transfer-Encoding",
+                "chunked",
+                HttpBody::Text("hello world".into()),
+            ),
+        ];
+        for (framing, value, body) in cases {
+            let headers = HashMap::from([(framing.to_string(), value.to_string())]);
+            let response = response(HttpStatusCode::Ok, headers, Some(body));
+            let sent = sent_by(|stream, req_id| {
+                let report = send_head_observed(stream, response, req_id, |_| {});
+                assert!(report.error.is_none(), "{:?}", report.error);
+            });
+            let sent = String::from_utf8(sent).unwrap();
+
+            assert!(sent.starts_with("HTTP/1.1 200"), "{}", sent);
+            assert!(sent.contains(&format!("\r\n{}: {}\r\n", framing, value)));
+            assert!(
+                sent.ends_with("\r\n\r\n") && !sent.contains("hello"),
+                "{}",
+                sent
+            );
+        }
+    }
+
+    #[test]
+    fn test_interim_responses_precede_the_final_one() {
+        let hints = HashMap::from([(
+            "link".to_string(),
+            "</app.css>; rel=preload; as=style".to_string(),
+        )]);
+        let sent = sent_by(|stream, _| {
+            let mut writer = HttpWriter::new(&mut *stream);
+            let v1_1 = || HttpVersion::Http1_1;
+            writer
+                .write_interim(v1_1(), HttpStatusCode::EarlyHints, &hints)
+                .unwrap();
+            writer
+                .write_interim(HttpVersion::Http1_0, HttpStatusCode::EarlyHints, &hints)
+                .unwrap();
+            writer
+                .write_status_line(v1_1(), HttpStatusCode::Ok)
+                .unwrap();
+            writer
+                .write_header("Content-Length".into(), "0".into())
+                .unwrap();
+            writer.finish_headers().unwrap();
+            writer.complete_write().unwrap();
+            assert!(matches!(
+                writer.write_interim(v1_1(), HttpStatusCode::Continue, &HashMap::new()),
+                Err(WriterError::InvalidState(_))
+            ));
+            assert!(matches!(
+                HttpWriter::new(stream).write_interim(v1_1(), HttpStatusCode::Ok, &HashMap::new()),
+                Err(WriterError::InvalidState(_))
+            ));
+        });
+
+        assert_eq!(
+            String::from_utf8(sent).unwrap(),
+            "HTTP/1.1 103 Early Hints\r\nLink: </app.css>; rel=preload; as=style\r\n\r\n\
+             HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_streamed_body_is_copied_from_its_source() {
+        let content: Vec<u8> = (0..3 * STREAM_CHUNK_SIZE + 7).map(|i| i as u8).collect();
+        let headers = HashMap::from([("Content-Length".to_string(), content.len().to_string())]);
+        let body = BodyStream::new(std::io::Cursor::new(content.clone()), content.len() as u64);
+        let response = response(HttpStatusCode::Ok, headers, Some(HttpBody::Stream(body)));
+        let sent = sent_by(|stream, req_id| send_response(stream, response, req_id).unwrap());
+
+        let head_end = sent.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert!(sent.starts_with(b"HTTP/1.1 200"));
+        assert_eq!(&sent[head_end..], &content[..]);
+    }
+}
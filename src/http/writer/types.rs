@@ -1,5 +1,17 @@
 #![allow(dead_code)]
-use std::{fmt, io};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, Read},
+    sync::{Arc, Mutex},
+};
+
+use crate::http::{
+    response::HttpStatusCode,
+    routes::{CompressionLevels, HttpEncoding},
+    stream::ClientStream,
+};
 
 // Represents whether to use chunked transfer encoding or not
 pub struct ChunkedDecision {
@@ -8,11 +20,15 @@ pub struct ChunkedDecision {
     pub warning: Option<String>,
 }
 
+/// Bytes read from a streamed body per write to the socket
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
 /// Represents an HTTP body with a text or binary content
 #[derive(Debug, Clone)]
 pub enum HttpBody {
     Text(String),
     Binary(Vec<u8>),
+    Stream(BodyStream), // Read from its source as it is written, e.g. a large file
 }
 
 impl fmt::Display for HttpBody {
@@ -20,6 +36,7 @@ impl fmt::Display for HttpBody {
         match self {
             HttpBody::Text(content) => write!(f, "{}", content),
             HttpBody::Binary(content) => write!(f, "{:?}", content),
+            HttpBody::Stream(stream) => write!(f, "<stream of {} bytes>", stream.byte_len()),
         }
     }
 }
@@ -30,18 +47,293 @@ impl HttpBody {
         match self {
             HttpBody::Text(text) => text.len(),
             HttpBody::Binary(bytes) => bytes.len(),
+            HttpBody::Stream(stream) => stream.byte_len() as usize,
+        }
+    }
+
+    /// Cuts the body to at most `max` bytes; text is cut back to a character boundary
+    pub fn truncate(&mut self, max: usize) {
+        match self {
+            HttpBody::Text(text) => {
+                let end = (0..=max.min(text.len()))
+                    .rev()
+                    .find(|&end| text.is_char_boundary(end))
+                    .unwrap_or(0);
+                text.truncate(end);
+            }
+            HttpBody::Binary(bytes) => bytes.truncate(max),
+            HttpBody::Stream(stream) => stream.len = stream.len.min(max as u64),
+        }
+    }
+}
+
+/// A body of known length whose bytes stay in their source until the writer copies them out
+///
+/// Clones share the source, which only the first writer to take it can send
+#[derive(Clone)]
+pub struct BodyStream {
+    source: Arc<Mutex<Option<Source>>>,
+    len: u64,
+    encoding: Option<(HttpEncoding, CompressionLevels)>, // Compressed on the way to the socket
+}
+
+/// Where a streamed body's bytes come from
+enum Source {
+    Reader(Box<dyn Read + Send>),
+    Bytes(Box<dyn AsRef<[u8]> + Send>), // Already addressable, e.g. a memory map; written in place
+    File(File), // Positioned at the first byte to send; its descriptor can go to sendfile(2)
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyStream")
+            .field("len", &self.len)
+            .field("encoding", &self.encoding)
+            .finish()
+    }
+}
+
+impl BodyStream {
+    /// Wraps a source that yields exactly `len` bytes
+    pub fn new(source: impl Read + Send + 'static, len: u64) -> Self {
+        BodyStream {
+            source: Arc::new(Mutex::new(Some(Source::Reader(Box::new(source))))),
+            len,
+            encoding: None,
+        }
+    }
+
+    /// Wraps bytes that are already in memory, such as a mapped file, so they are written
+    /// straight from there instead of through a read buffer
+    pub fn from_bytes(bytes: impl AsRef<[u8]> + Send + 'static) -> Self {
+        let len = bytes.as_ref().len() as u64;
+        BodyStream {
+            source: Arc::new(Mutex::new(Some(Source::Bytes(Box::new(bytes))))),
+            len,
+            encoding: None,
+        }
+    }
+
+    /// Wraps an open file positioned at the first of the `len` bytes to send, so on Linux the
+    /// writer can have the kernel copy it to the socket
+    pub fn from_file(file: File, len: u64) -> Self {
+        BodyStream {
+            source: Arc::new(Mutex::new(Some(Source::File(file)))),
+            len,
+            encoding: None,
+        }
+    }
+
+    /// Compresses the body with `encoding` as it is sent; its compressed length is unknown up
+    /// front, so it can only go out with chunked transfer encoding
+    pub fn encoded(mut self, encoding: HttpEncoding, levels: CompressionLevels) -> Self {
+        self.encoding = Some((encoding, levels));
+        self
+    }
+
+    /// Returns the number of bytes the source yields
+    pub fn byte_len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the encoding the body is compressed with while it is sent, and how hard, if any
+    pub fn encoding(&self) -> Option<(&HttpEncoding, CompressionLevels)> {
+        self.encoding
+            .as_ref()
+            .map(|(encoding, levels)| (encoding, *levels))
+    }
+
+    /// Writes the body to the stream: a file with sendfile(2) when the stream is a socket on
+    /// Linux, anything else through `for_each_chunk`. Bytes the stream accepts are added to
+    /// `sent`, even if writing fails
+    pub fn write_to(
+        &self,
+        stream: &mut dyn ClientStream,
+        sent: &mut u64,
+    ) -> Result<(), WriterError> {
+        let source = self.take()?;
+        #[cfg(target_os = "linux")]
+        if let (Source::File(file), Some(socket)) = (&source, stream.tcp()) {
+            let before = *sent;
+            super::sendfile::copy(file, socket, self.len, sent)?;
+            if *sent - before < self.len {
+                // Content-Length already promised the full length, so the response is broken
+                return Err(WriterError::ContentLengthMismatch {
+                    declared: self.len as usize,
+                    actual: (*sent - before) as usize,
+                });
+            }
+            return Ok(());
+        }
+        self.copy_chunks(source, |chunk| Ok(write_counted(stream, chunk, sent)?))
+    }
+
+    /// Reads the source in chunks of at most STREAM_CHUNK_SIZE bytes, passing each to `write`
+    pub fn for_each_chunk(
+        &self,
+        write: impl FnMut(&[u8]) -> Result<(), WriterError>,
+    ) -> Result<(), WriterError> {
+        self.copy_chunks(self.take()?, write)
+    }
+
+    /// Takes the source out, so the body is sent at most once
+    fn take(&self) -> Result<Source, WriterError> {
+        self.source
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .ok_or_else(|| WriterError::InvalidState("Streamed body was already sent".into()))
+    }
+
+    fn copy_chunks(
+        &self,
+        source: Source,
+        mut write: impl FnMut(&[u8]) -> Result<(), WriterError>,
+    ) -> Result<(), WriterError> {
+        let mut source: Box<dyn Read> = match source {
+            Source::Reader(reader) => reader,
+            Source::File(file) => Box::new(file),
+            Source::Bytes(bytes) => {
+                return (*bytes).as_ref()[..self.len as usize]
+                    .chunks(STREAM_CHUNK_SIZE)
+                    .try_for_each(write);
+            }
+        };
+
+        let mut buffer = vec![0; STREAM_CHUNK_SIZE.min(self.len as usize)];
+        let mut remaining = self.len;
+        while remaining > 0 {
+            let want = remaining.min(buffer.len() as u64) as usize;
+            let read = match source.read(&mut buffer[..want]) {
+                Ok(0) => {
+                    // Content-Length already promised the full length, so the response is broken
+                    return Err(WriterError::ContentLengthMismatch {
+                        declared: self.len as usize,
+                        actual: (self.len - remaining) as usize,
+                    });
+                }
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(WriterError::IoError(e)),
+            };
+            write(&buffer[..read])?;
+            remaining -= read as u64;
+        }
+        Ok(())
+    }
+
+    /// Reads the whole source into memory, for consumers that need the bytes themselves
+    pub fn read_to_vec(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len as usize);
+        let _ = self.for_each_chunk(|chunk| {
+            bytes.extend_from_slice(chunk);
+            Ok(())
+        });
+        bytes
+    }
+}
+
+/// Writes all of `bytes` as `write_all` does, adding what the socket accepted to `sent` as it goes
+pub fn write_counted(
+    stream: &mut dyn ClientStream,
+    mut bytes: &[u8],
+    sent: &mut u64,
+) -> io::Result<()> {
+    while !bytes.is_empty() {
+        match stream.write(bytes) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                *sent += n as u64;
+                bytes = &bytes[n..];
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// How much of a response reached the client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delivery {
+    Complete,
+    ClientAbort, // The client disconnected or stopped reading before it was all written
+    ServerError, // The server could not produce the rest, e.g. a file shorter than announced
+}
+
+impl fmt::Display for Delivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let delivery = match self {
+            Delivery::Complete => "complete",
+            Delivery::ClientAbort => "truncated-client-abort",
+            Delivery::ServerError => "truncated-server-error",
+        };
+        write!(f, "{}", delivery)
+    }
+}
+
+/// What writing one response achieved
+#[derive(Debug)]
+pub struct WriteReport {
+    pub delivery: Delivery,
+    pub bytes: u64,                 // Handed to the socket, head and framing included
+    pub error: Option<WriterError>, // Why writing stopped, unless it completed
+}
+
+impl WriteReport {
+    /// Builds the report for a write that ended with `result` after `bytes` bytes
+    pub fn new(bytes: u64, result: Result<(), WriterError>) -> Self {
+        let delivery = match &result {
+            Ok(()) => Delivery::Complete,
+            Err(WriterError::IoError(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::BrokenPipe
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::WriteZero
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                Delivery::ClientAbort
+            }
+            Err(_) => Delivery::ServerError,
+        };
+        WriteReport {
+            delivery,
+            bytes,
+            error: result.err(),
         }
     }
+
+    /// Converts the report back into the plain result of the write
+    pub fn into_result(self) -> Result<(), WriterError> {
+        self.error.map_or(Ok(()), Err)
+    }
+}
+
+/// A response that was written in full, as handed to the writer
+///
+/// The headers include `X-Request-Id`; the writer's framing may still have swapped
+/// Content-Length for `Transfer-Encoding: chunked`. A streamed body has already been consumed,
+/// so only its length is left
+pub struct WrittenResponse<'a> {
+    pub status: &'a HttpStatusCode,
+    pub headers: &'a HashMap<String, String>,
+    pub body: &'a HttpBody,
 }
 
 /// Represents the state of the writer
 #[derive(Debug, Clone, PartialEq)]
 pub(super) enum WriterState {
     Initial,       // Can only write status
+    InterimSent,   // 1xx responses sent; more may follow before the status line
     StatusWritten, // Can only write headers
     HeadersOpen,   // Can write/replace headers
     HeadersClosed, // Headers done, can only write body
     BodyWritten,   // Body written, can only complete
+    Streaming,     // Head sent, chunks go out as they are written until completed
     Failed,        // Error occurred, no operations allowed
 }
 
@@ -51,8 +343,9 @@ pub enum WriterError {
     InvalidState(String),
     IoError(io::Error),
     MissingHeader(String),
-    InvalidHeader(String),
+    InvalidHeader(String), // Nothing was written when a header was refused by `validate_header`
     ContentLengthMismatch { declared: usize, actual: usize },
+    HeadersTooLarge { size: usize, limit: usize }, // Nothing was written to the stream
 }
 
 impl From<io::Error> for WriterError {
@@ -60,3 +353,25 @@ impl From<io::Error> for WriterError {
         WriterError::IoError(error)
     }
 }
+
+/// Checks that a header can be written as is: the name must be an RFC 7230 token, and the value
+/// must not hold CR, LF, or NUL, which would let it end the header and inject others
+pub fn validate_header(name: &str, value: &str) -> Result<(), WriterError> {
+    let is_token = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if !is_token {
+        return Err(WriterError::InvalidHeader(format!(
+            "header name {:?} is not a token",
+            name
+        )));
+    }
+    if value.contains(['\r', '\n', '\0']) {
+        return Err(WriterError::InvalidHeader(format!(
+            "value of header {} contains CR, LF, or NUL",
+            name
+        )));
+    }
+    Ok(())
+}
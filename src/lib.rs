@@ -0,0 +1,11 @@
+pub mod http;
+
+pub use http::{
+    request::HttpRequest,
+    response::HttpResponse,
+    routes::Router,
+    server::{run::Server, ServerContext},
+};
+
+#[cfg(all(feature = "async", feature = "event-loop"))]
+compile_error!("the \"async\" and \"event-loop\" features are mutually exclusive");
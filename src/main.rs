@@ -1,82 +1,133 @@
-use crate::http::server;
-use std::{env, fs::create_dir_all, net::TcpListener, process};
-use threadpool::ThreadPool;
-
-mod http;
-
-const DEFAULT_DIR: &str = "./www";
+use rusttp::{
+    http::{
+        config::{ServerConfig, DEFAULT_CONFIG_FILE},
+        crash,
+        logging::{self, server_log, Level},
+    },
+    Server,
+};
+use std::{env, path::Path, process};
 
 /// Entry point for the HTTP server
 fn main() {
     let args = parse_command_line();
-    let flag_dir = extract_directory(&args);
-    let root_dir = flag_dir.clone().unwrap_or_else(|| DEFAULT_DIR.to_string());
-    if flag_dir.is_none() {
-        println!(
-            "No directory specified. Using default directory: {}",
-            DEFAULT_DIR
-        );
-    } else {
-        println!("Using specified directory: {}", root_dir);
-    }
+    let config = load_config(&args);
 
-    if let Err(e) = create_dir_all(&root_dir) {
-        eprintln!("Failed to create directory {}: {:?}", root_dir, e);
+    if let Err(e) = logging::init(&config.logging) {
+        let path = config
+            .logging
+            .access_log_file
+            .as_deref()
+            .unwrap_or_default();
+        server_log!(Error, "Failed to open access log {}: {}", path, e);
         process::exit(1);
     }
+    crash::install(&config);
+
+    server_log!(Info, "Configuration: {}", config.summary());
+    let server = Server::builder()
+        .config(config)
+        .build()
+        .unwrap_or_else(|e| crash::fatal(&e.to_string()));
+    if let Err(e) = server.run() {
+        crash::fatal(&e.to_string());
+    }
+    logging::flush();
+}
+
+/// Parses command line arguments into a vector of strings
+fn parse_command_line() -> Vec<String> {
+    env::args().collect()
+}
 
-    let context = match server::ServerContext::new(&root_dir) {
-        Ok(ctx) => ctx,
-        Err(e) => {
-            eprintln!("Failed to initialize server context: {:?}", e);
+/// Builds the configuration from the config file, then applies environment and flag overrides
+fn load_config(args: &[String]) -> ServerConfig {
+    let mut config = match extract_setting(args, "--config", "RUSTTP_CONFIG") {
+        Some(path) => ServerConfig::load(Path::new(&path)).unwrap_or_else(|e| {
+            server_log!(Error, "{}: {}", path, e);
             process::exit(1);
+        }),
+        None if Path::new(DEFAULT_CONFIG_FILE).is_file() => {
+            ServerConfig::load(Path::new(DEFAULT_CONFIG_FILE)).unwrap_or_else(|e| {
+                server_log!(Error, "{}: {}", DEFAULT_CONFIG_FILE, e);
+                process::exit(1);
+            })
         }
+        None => ServerConfig::default(),
     };
 
-    let pool = ThreadPool::new(100);
-
-    let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                match stream.peer_addr() {
-                    Ok(addr) => println!("\nAccepted Connection: {}", addr),
-                    Err(_) => println!("\nAccepted Connection: unknown"),
-                }
-                let ctx = context.clone();
-                pool.execute(move || {
-                    match server::handle_client(stream, ctx) {
-                        Ok(()) => {
-                            println!("Connection closed");
-                        }
-                        Err(status_code) => {
-                            println!("Connection closed with status code {}", status_code);
-                        }
-                    }
-                });
-            }
-
-            Err(e) => {
-                println!("error: {}", e);
-            }
+    if let Some(dir) = extract_directory(args) {
+        config.root = dir;
+    }
+    for spec in extract_flags(args, "--vhost") {
+        if let Err(e) = config.add_vhost(&spec) {
+            server_log!(Error, "{}", e);
+            process::exit(1);
         }
     }
+    config.override_listen(
+        extract_setting(args, "--bind", "RUSTTP_BIND"),
+        parse_setting(args, "--port", "RUSTTP_PORT"),
+    );
+    if let Some(workers) = parse_setting(args, "--workers", "RUSTTP_WORKERS") {
+        config.workers = workers;
+    }
+    if let Some(cache_dir) = extract_setting(args, "--cache-dir", "RUSTTP_CACHE_DIR") {
+        config.cache_dir = cache_dir;
+    }
+    if let Some(raw) = extract_setting(args, "--log-level", "RUSTTP_LOG_LEVEL") {
+        config.logging.level = Level::parse(&raw).unwrap_or_else(|| {
+            server_log!(
+                Error,
+                "Invalid value for --log-level / RUSTTP_LOG_LEVEL: '{}'",
+                raw
+            );
+            process::exit(1);
+        });
+    }
 
-    pool.join();
-}
+    if let Err(e) = config.validate() {
+        server_log!(Error, "{}", e);
+        process::exit(1);
+    }
 
-/// Parses command line arguments into a vector of strings
-fn parse_command_line() -> Vec<String> {
-    env::args().collect()
+    config
 }
 
 /// Extracts the directory path from command line arguments
 fn extract_directory(args: &[String]) -> Option<String> {
+    extract_flag(args, "--directory")
+}
+
+/// Extracts the value following a flag from command line arguments
+fn extract_flag(args: &[String], flag: &str) -> Option<String> {
     for i in 0..args.len() {
-        if args[i] == "--directory" && i + 1 < args.len() {
+        if args[i] == flag && i + 1 < args.len() {
             return Some(args[i + 1].clone());
         }
     }
     None
 }
+
+/// Extracts the values of every occurrence of a repeatable flag, in order
+fn extract_flags(args: &[String], flag: &str) -> Vec<String> {
+    args.windows(2)
+        .filter(|pair| pair[0] == flag)
+        .map(|pair| pair[1].clone())
+        .collect()
+}
+
+/// Extracts a setting from a command line flag, falling back to an environment variable
+fn extract_setting(args: &[String], flag: &str, env_var: &str) -> Option<String> {
+    extract_flag(args, flag).or_else(|| env::var(env_var).ok().filter(|v| !v.is_empty()))
+}
+
+/// Parses a numeric setting, exiting with an error message if the value is malformed
+fn parse_setting<T: std::str::FromStr>(args: &[String], flag: &str, env_var: &str) -> Option<T> {
+    extract_setting(args, flag, env_var).map(|raw| {
+        raw.parse::<T>().unwrap_or_else(|_| {
+            server_log!(Error, "Invalid value for {} / {}: '{}'", flag, env_var, raw);
+            process::exit(1);
+        })
+    })
+}